@@ -0,0 +1,228 @@
+//! [clap] parsers for option groups, enabled by the `clap` feature
+//!
+//! [FlakeArgs], [EvaluationArgs], [SourceArgs] and [BuildArgs] implement [clap::Args],
+//! so that CLIs built on runix can accept the same flags as nix
+//! and pass them on to the commands they run:
+//!
+//! ```
+//! # use clap::Parser;
+//! # use runix::arguments::eval::EvaluationArgs;
+//! # use runix::arguments::flake::FlakeArgs;
+//! # use runix::command::Build;
+//! # use runix::command_line::NixCliCommand;
+//! #[derive(Parser)]
+//! struct Cli {
+//!     #[command(flatten)]
+//!     flake: FlakeArgs,
+//!     #[command(flatten)]
+//!     eval: EvaluationArgs,
+//! }
+//!
+//! let cli = Cli::parse_from([
+//!     "cli",
+//!     "--impure",
+//!     "--override-input",
+//!     "nixpkgs",
+//!     "github:nixos/nixpkgs",
+//! ]);
+//! let build = Build {
+//!     flake: cli.flake,
+//!     eval: cli.eval,
+//!     ..Default::default()
+//! };
+//! assert_eq!(build.args(), [
+//!     "--override-input",
+//!     "nixpkgs",
+//!     "github:nixos/nixpkgs",
+//!     "--impure"
+//! ]);
+//! ```
+//!
+//! The flags are parsed by private structs deriving [clap::Args],
+//! which are converted into the option groups.
+
+use std::path::PathBuf;
+
+use clap::error::ErrorKind;
+use clap::{ArgMatches, Args, Command, Error, FromArgMatches};
+
+use super::eval::EvaluationArgs;
+use super::flake::{FlakeArgs, OverrideInput};
+use super::source::SourceArgs;
+use super::BuildArgs;
+
+/// Implement [clap::Args] for `$args` by parsing `$cli` and converting it with [TryFrom]
+macro_rules! impl_args {
+    ($args:ty, $cli:ty) => {
+        impl FromArgMatches for $args {
+            fn from_arg_matches(matches: &ArgMatches) -> Result<Self, Error> {
+                <$cli>::from_arg_matches(matches)?.try_into()
+            }
+
+            fn update_from_arg_matches(&mut self, matches: &ArgMatches) -> Result<(), Error> {
+                *self = Self::from_arg_matches(matches)?;
+                Ok(())
+            }
+        }
+
+        impl Args for $args {
+            fn augment_args(cmd: Command) -> Command {
+                <$cli>::augment_args(cmd)
+            }
+
+            fn augment_args_for_update(cmd: Command) -> Command {
+                <$cli>::augment_args_for_update(cmd)
+            }
+        }
+    };
+}
+
+#[derive(clap::Args)]
+struct FlakeCli {
+    /// Override a specific flake input (e.g. `dwarffs/nixpkgs`)
+    #[arg(long, num_args = 2, value_names = ["INPUT_PATH", "FLAKE_URL"])]
+    override_input: Vec<String>,
+    /// Do not write the flake's newly generated lock file
+    #[arg(long)]
+    no_write_lock_file: bool,
+}
+
+impl TryFrom<FlakeCli> for FlakeArgs {
+    type Error = Error;
+
+    fn try_from(cli: FlakeCli) -> Result<Self, Error> {
+        let override_inputs = cli
+            .override_input
+            .chunks(2)
+            .map(|input| {
+                let to = input[1]
+                    .parse()
+                    .map_err(|e| Error::raw(ErrorKind::ValueValidation, e))?;
+                Ok(OverrideInput::new(input[0].clone(), to))
+            })
+            .collect::<Result<_, Error>>()?;
+
+        Ok(FlakeArgs {
+            override_inputs,
+            no_write_lock_file: cli.no_write_lock_file.into(),
+        })
+    }
+}
+impl_args!(FlakeArgs, FlakeCli);
+
+#[derive(clap::Args)]
+struct EvaluationCli {
+    /// The URL of the Nix store to use for evaluation
+    #[arg(long, value_name = "STORE_URL")]
+    eval_store: Option<String>,
+    /// Allow access to mutable paths and repositories
+    #[arg(long)]
+    impure: bool,
+}
+
+impl TryFrom<EvaluationCli> for EvaluationArgs {
+    type Error = Error;
+
+    fn try_from(cli: EvaluationCli) -> Result<Self, Error> {
+        Ok(EvaluationArgs {
+            eval_store: cli.eval_store.map(Into::into),
+            impure: cli.impure.into(),
+        })
+    }
+}
+impl_args!(EvaluationArgs, EvaluationCli);
+
+#[derive(clap::Args)]
+struct SourceCli {
+    /// Interpret installables as attribute paths relative to the Nix expression
+    #[arg(long)]
+    expr: Option<String>,
+}
+
+impl TryFrom<SourceCli> for SourceArgs {
+    type Error = Error;
+
+    fn try_from(cli: SourceCli) -> Result<Self, Error> {
+        Ok(SourceArgs {
+            expr: cli.expr.map(Into::into),
+        })
+    }
+}
+impl_args!(SourceArgs, SourceCli);
+
+#[derive(clap::Args)]
+struct BuildCli {
+    /// Use PATH as prefix for the symlinks to the build results
+    #[arg(long, short, value_name = "PATH")]
+    out_link: Option<PathBuf>,
+    /// Do not create symlinks to the build results
+    #[arg(long)]
+    no_link: bool,
+}
+
+impl TryFrom<BuildCli> for BuildArgs {
+    type Error = Error;
+
+    fn try_from(cli: BuildCli) -> Result<Self, Error> {
+        Ok(BuildArgs {
+            out_link: cli.out_link.map(Into::into),
+            no_link: cli.no_link.then(|| true.into()),
+        })
+    }
+}
+impl_args!(BuildArgs, BuildCli);
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::command_line::ToArgs;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        flake: FlakeArgs,
+        #[command(flatten)]
+        eval: EvaluationArgs,
+        #[command(flatten)]
+        source: SourceArgs,
+        #[command(flatten)]
+        build: BuildArgs,
+    }
+
+    #[test]
+    fn pass_through() {
+        let args = [
+            "--override-input",
+            "nixpkgs",
+            "github:nixos/nixpkgs/nixos-unstable",
+            "--no-write-lock-file",
+            "--eval-store",
+            "auto",
+            "--impure",
+            "--expr",
+            "import <nixpkgs> {}",
+            "--out-link",
+            "result-dev",
+            "--no-link",
+        ];
+        let cli = Cli::try_parse_from(["cli"].into_iter().chain(args)).unwrap();
+
+        let passed = [
+            cli.flake.to_args(),
+            cli.eval.to_args(),
+            cli.source.to_args(),
+            cli.build.to_args(),
+        ]
+        .concat();
+        assert_eq!(passed, args);
+
+        let cli = Cli::try_parse_from(["cli"]).unwrap();
+        assert!(cli.flake.to_args().is_empty());
+        assert!(cli.build.to_args().is_empty());
+
+        assert!(Cli::try_parse_from(["cli", "--override-input", "nixpkgs"]).is_err());
+        assert!(Cli::try_parse_from(["cli", "--override-input", "nixpkgs", "::"]).is_err());
+    }
+}
@@ -3,6 +3,7 @@
 use derive_more::{Deref, From};
 use runix_derive::ToArgs;
 
+use crate::arguments::config::ShowTrace;
 use crate::command_line::flag::{Flag, FlagType};
 use crate::command_line::ToArgs;
 
@@ -14,6 +15,7 @@ use crate::command_line::ToArgs;
 #[derive(Clone, Default, Debug, ToArgs)]
 pub struct NixCommonArgs {
     pub store: Option<Store>,
+    pub log_format: Option<LogFormat>,
 }
 
 #[derive(Clone, From, Debug, Deref, Default)]
@@ -22,3 +24,79 @@ impl Flag for Store {
     const FLAG: &'static str = "--store";
     const FLAG_TYPE: FlagType<Self> = FlagType::arg();
 }
+
+/// `--log-format` option
+///
+/// One of `raw`, `raw-with-logs`, `internal-json`, `bar` or `bar-with-logs`
+#[derive(Clone, From, Debug, Deref, Default)]
+#[from(forward)]
+pub struct LogFormat(String);
+impl LogFormat {
+    /// Structured logs, see [crate::log_event]
+    pub fn internal_json() -> Self {
+        LogFormat("internal-json".to_string())
+    }
+}
+impl Flag for LogFormat {
+    const FLAG: &'static str = "--log-format";
+    const FLAG_TYPE: FlagType<Self> = FlagType::arg();
+}
+
+/// Verbosity of the logs of nix and whether to print traces of evaluation errors
+///
+/// ```
+/// # use runix::arguments::common::LoggingArgs;
+/// # use runix::command_line::ToArgs;
+/// let logging = LoggingArgs {
+///     verbose: 2.into(),
+///     ..LoggingArgs::trace()
+/// };
+/// assert_eq!(logging.to_args(), [
+///     "--verbose",
+///     "--verbose",
+///     "--show-trace"
+/// ]);
+/// ```
+#[derive(Clone, Default, Debug, ToArgs)]
+pub struct LoggingArgs {
+    pub quiet: Quiet,
+    pub verbose: Verbose,
+    pub debug: DebugLog,
+    pub show_trace: ShowTrace,
+}
+
+impl LoggingArgs {
+    /// Print traces of evaluation errors, see [crate::command_line::NixCommandLine::trace_failures]
+    pub fn trace() -> Self {
+        LoggingArgs {
+            show_trace: true.into(),
+            ..Default::default()
+        }
+    }
+}
+
+/// `--quiet` flag, repeated to decrease the verbosity further
+#[derive(Clone, From, Debug, Deref, Default)]
+pub struct Quiet(u8);
+impl Flag for Quiet {
+    const FLAG: &'static str = "--quiet";
+    const FLAG_TYPE: FlagType<Self> =
+        FlagType::Custom(|s| vec![Self::FLAG.to_string(); s.0.into()]);
+}
+
+/// `--verbose` flag, repeated to increase the verbosity further
+#[derive(Clone, From, Debug, Deref, Default)]
+pub struct Verbose(u8);
+impl Flag for Verbose {
+    const FLAG: &'static str = "--verbose";
+    const FLAG_TYPE: FlagType<Self> =
+        FlagType::Custom(|s| vec![Self::FLAG.to_string(); s.0.into()]);
+}
+
+/// `--debug` flag, sets the verbosity to debug
+#[derive(Clone, From, Debug, Deref, Default)]
+pub struct DebugLog(bool);
+impl Flag for DebugLog {
+    const FLAG: &'static str = "--debug";
+    const FLAG_TYPE: FlagType<Self> = FlagType::bool();
+}
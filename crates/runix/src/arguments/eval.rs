@@ -2,6 +2,7 @@
 
 use derive_more::{Deref, From};
 use runix_derive::ToArgs;
+use serde::{Deserialize, Serialize};
 
 use crate::command_line::ToArgs;
 use crate::default::flag::{Flag, FlagType};
@@ -9,20 +10,21 @@ use crate::default::flag::{Flag, FlagType};
 /// Evaluation related arguments
 /// Corresponding to the arguments defined in
 /// [libcmd/common-eval-args.cc](https://github.com/NixOS/nix/blob/a6239eb5700ebb85b47bb5f12366404448361f8d/src/libcmd/common-eval-args.cc#L14-L74)
-#[derive(Clone, Default, Debug, ToArgs)]
+#[derive(Clone, Default, Debug, ToArgs, Serialize, Deserialize)]
+#[serde(default)]
 pub struct EvaluationArgs {
     pub eval_store: Option<EvalStore>,
     pub impure: Impure,
 }
 
-#[derive(Clone, From, Debug, Deref, Default)]
+#[derive(Clone, From, Debug, Deref, Default, Serialize, Deserialize)]
 pub struct EvalStore(String);
 impl Flag for EvalStore {
     const FLAG: &'static str = "--eval-store";
     const FLAG_TYPE: FlagType<Self> = FlagType::arg();
 }
 
-#[derive(Clone, From, Debug, Deref, Default)]
+#[derive(Clone, From, Debug, Deref, Default, Serialize, Deserialize)]
 pub struct Impure(bool);
 impl Flag for Impure {
     const FLAG: &'static str = "--impure";
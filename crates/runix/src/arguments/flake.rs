@@ -2,6 +2,7 @@
 
 use derive_more::{Constructor, Deref, From};
 use runix_derive::ToArgs;
+use serde::{Deserialize, Serialize};
 
 use crate::command_line::flag::{Flag, FlagType};
 use crate::command_line::ToArgs;
@@ -10,14 +11,15 @@ use crate::flake_ref::FlakeRef;
 /// Flake related arguments
 /// Corresponding to the arguments defined in
 /// [libcmd/installables.cc](https://github.com/NixOS/nix/blob/84cc7ad77c6faf1cda8f8a10f7c12a939b61fe35/src/libcmd/installables.cc#L26-L126)
-#[derive(Clone, Default, Debug, ToArgs)]
+#[derive(Clone, Default, Debug, ToArgs, Serialize, Deserialize)]
+#[serde(default)]
 pub struct FlakeArgs {
     pub override_inputs: Vec<OverrideInput>,
     pub no_write_lock_file: NoWriteLockFile,
 }
 
 /// Tuple like override inputs flag
-#[derive(Clone, Debug, From, Constructor)]
+#[derive(Clone, Debug, From, Constructor, Serialize, Deserialize)]
 pub struct OverrideInput {
     pub from: String,
     pub to: FlakeRef,
@@ -33,7 +35,7 @@ impl OverrideInput {
 }
 
 /// Flag for no-write-lock-file
-#[derive(Clone, From, Debug, Deref, Default)]
+#[derive(Clone, From, Debug, Deref, Default, Serialize, Deserialize)]
 pub struct NoWriteLockFile(bool);
 impl Flag for NoWriteLockFile {
     const FLAG: &'static str = "--no-write-lock-file";
@@ -1,16 +1,26 @@
 //! Command's own arguments, Option groups and [InstallableArg]s
 
+use std::collections::BTreeMap;
+use std::fmt;
 use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
 
 use derive_more::{Deref, From};
 use runix_derive::ToArgs;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncRead;
+use tokio_util::sync::CancellationToken;
 
-use self::common::NixCommonArgs;
+use self::common::{LoggingArgs, NixCommonArgs};
 use self::config::NixConfigArgs;
 use crate::command_line::ToArgs;
 use crate::default::flag::{Flag, FlagType};
-use crate::installable::{FlakeAttribute, Installable};
+use crate::installable::{DerivationOutputs, FlakeAttribute, Installable};
+use crate::store_path::StorePath;
 
+#[cfg(feature = "clap")]
+pub mod cli;
 pub mod common;
 pub mod config;
 pub mod eval;
@@ -24,32 +34,127 @@ pub struct NixArgs {
     /// Configure the cwd for nix actions.
     ///
     /// Relevant for instance for init and relative installables
-    pub cwd: Option<PathBuf>,
+    pub current_dir: Option<PathBuf>,
+
+    /// Environment variables set for nix
+    ///
+    /// Override variables of the same name set by the backend's defaults.
+    pub env: BTreeMap<String, String>,
+
+    /// Environment variables removed from the inherited environment
+    pub env_remove: Vec<String>,
+
+    /// Do not inherit the environment of the current process
+    ///
+    /// Only [NixArgs::env] and the backend's default environment are passed to nix.
+    pub clear_env: bool,
 
     /// Common arguments to the nix command
     pub common: NixCommonArgs,
 
     /// Nix configuration (overrides nix.conf)
     pub config: NixConfigArgs,
+
+    /// Verbosity of nix for this invocation
+    pub logging: LoggingArgs,
+
+    /// Stop nix if it did not exit within the given time
+    pub timeout: Option<Duration>,
+
+    /// Stop nix once the token is cancelled
+    pub cancel: Option<CancellationToken>,
+
+    /// Input passed to nix, inherited from the current process if unset
+    pub stdin: Option<Stdin>,
+}
+
+/// Input piped to the stdin of nix, see [NixArgs::stdin]
+///
+/// ```
+/// # use runix::arguments::{NixArgs, Stdin};
+/// let nix_args = NixArgs {
+///     stdin: Some(Stdin::from("{ hello = 1; }")),
+///     ..Default::default()
+/// };
+/// ```
+pub enum Stdin {
+    /// Bytes written to stdin, can be used for any number of invocations
+    Bytes(Vec<u8>),
+    /// A reader copied to stdin, can only be used for a single invocation
+    Reader(Mutex<Option<Box<dyn AsyncRead + Send + Unpin>>>),
+}
+
+impl Stdin {
+    /// Pipe `reader` to stdin
+    pub fn reader(reader: impl AsyncRead + Send + Unpin + 'static) -> Self {
+        Stdin::Reader(Mutex::new(Some(Box::new(reader))))
+    }
+
+    /// Whether the input can be passed to another invocation, i.e. is not a [Stdin::Reader]
+    pub(crate) fn is_replayable(&self) -> bool {
+        matches!(self, Stdin::Bytes(_))
+    }
+
+    /// A reader of the input, [None] if the [Stdin::Reader] has already been consumed
+    pub(crate) fn take_reader(&self) -> Option<Box<dyn AsyncRead + Send + Unpin>> {
+        match self {
+            Stdin::Bytes(bytes) => Some(Box::new(std::io::Cursor::new(bytes.clone()))),
+            Stdin::Reader(reader) => reader
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .take(),
+        }
+    }
+}
+
+impl fmt::Debug for Stdin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stdin::Bytes(bytes) => f.debug_tuple("Bytes").field(&bytes.len()).finish(),
+            Stdin::Reader(_) => f.debug_tuple("Reader").finish(),
+        }
+    }
+}
+
+impl From<Vec<u8>> for Stdin {
+    fn from(bytes: Vec<u8>) -> Self {
+        Stdin::Bytes(bytes)
+    }
+}
+
+impl From<String> for Stdin {
+    fn from(string: String) -> Self {
+        Stdin::Bytes(string.into_bytes())
+    }
+}
+
+impl From<&str> for Stdin {
+    fn from(string: &str) -> Self {
+        Stdin::Bytes(string.as_bytes().to_vec())
+    }
 }
 
 impl ToArgs for NixArgs {
     fn to_args(&self) -> Vec<String> {
-        [self.config.to_args(), self.common.to_args()]
-            .into_iter()
-            .flatten()
-            .collect()
+        [
+            self.config.to_args(),
+            self.common.to_args(),
+            self.logging.to_args(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
     }
 }
 
 /// Installable argument for commands taking a single Installable
 /// ([approximately](https://github.com/NixOS/nix/search?q=InstallablesCommand)
-#[derive(From, Clone, Default, Debug)]
+#[derive(From, Clone, Default, Debug, Serialize, Deserialize)]
 #[from(forward)]
 pub struct InstallableArg(Option<Installable>);
 impl ToArgs for InstallableArg {
     fn to_args(&self) -> Vec<String> {
-        self.0.iter().map(|i| i.to_string()).collect()
+        self.0.iter().flat_map(|i| i.to_args()).collect()
     }
 }
 
@@ -59,19 +164,57 @@ impl From<FlakeAttribute> for InstallableArg {
     }
 }
 
+impl From<StorePath> for InstallableArg {
+    fn from(store_path: StorePath) -> Self {
+        Self(Some(store_path.into()))
+    }
+}
+
+impl From<DerivationOutputs> for InstallableArg {
+    fn from(derivation_outputs: DerivationOutputs) -> Self {
+        Self(Some(derivation_outputs.into()))
+    }
+}
+
 /// Installable argument for commands taking multiple Installables
 /// ([approximately](https://github.com/NixOS/nix/search?q=InstallablesCommand)
-#[derive(Debug, From, Default, Clone)]
+#[derive(Debug, From, Default, Clone, Serialize, Deserialize)]
 #[from(forward)]
 pub struct InstallablesArgs(Vec<Installable>);
 impl ToArgs for InstallablesArgs {
     fn to_args(&self) -> Vec<String> {
-        self.0.iter().map(|i| i.to_string()).collect()
+        self.0.iter().flat_map(|i| i.to_args()).collect()
+    }
+}
+
+/// Collect any kind of installable, e.g. already built [StorePath]s
+///
+/// ```
+/// # use runix::arguments::InstallablesArgs;
+/// # use runix::command::PathInfo;
+/// # use runix::command_line::NixCliCommand;
+/// # use runix::store_path::StorePath;
+/// let paths = [
+///     "/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-python3-3.10.10",
+///     "/nix/store/3dsk2kh0m6xyi5rhjqzwxd4jc4hy6r2s-hello-2.12.1",
+/// ];
+/// let path_info = PathInfo {
+///     installables: paths
+///         .iter()
+///         .map(|path| StorePath::from_path(path).unwrap())
+///         .collect(),
+///     ..Default::default()
+/// };
+/// assert_eq!(path_info.args(), paths);
+/// ```
+impl<I: Into<Installable>> FromIterator<I> for InstallablesArgs {
+    fn from_iter<T: IntoIterator<Item = I>>(iter: T) -> Self {
+        Self(iter.into_iter().map(Into::into).collect())
     }
 }
 
 /// `nix --out-path <path>` option
-#[derive(Clone, From, Deref, Debug)]
+#[derive(Clone, From, Deref, Debug, Serialize, Deserialize)]
 #[from(forward)]
 pub struct OutLink(PathBuf);
 impl Flag for OutLink {
@@ -80,7 +223,7 @@ impl Flag for OutLink {
 }
 
 /// `nix build --no-link` flag
-#[derive(Clone, From, Deref, Debug)]
+#[derive(Clone, From, Deref, Debug, Serialize, Deserialize)]
 #[from(forward)]
 pub struct NoLink(bool);
 impl Flag for NoLink {
@@ -89,18 +232,19 @@ impl Flag for NoLink {
 }
 
 /// `nix build` options
-#[derive(Debug, Default, Clone, ToArgs)]
+#[derive(Debug, Default, Clone, ToArgs, Serialize, Deserialize)]
+#[serde(default)]
 pub struct BuildArgs {
     pub out_link: Option<OutLink>,
-    pub no_link: Option<Bundler>,
+    pub no_link: Option<NoLink>,
 }
 
 /// `nix develop` options
-#[derive(Debug, Default, Clone, ToArgs)]
+#[derive(Debug, Default, Clone, ToArgs, Serialize, Deserialize)]
 pub struct DevelopArgs {}
 
 /// `nix bundle --bundler <bundler>` option
-#[derive(Clone, From, Deref, Debug)]
+#[derive(Clone, From, Deref, Debug, Serialize, Deserialize)]
 #[from(forward)]
 pub struct Bundler(Installable);
 impl Flag for Bundler {
@@ -109,13 +253,14 @@ impl Flag for Bundler {
 }
 
 /// `nix bundle` options
-#[derive(Debug, Default, Clone, ToArgs)]
+#[derive(Debug, Default, Clone, ToArgs, Serialize, Deserialize)]
+#[serde(default)]
 pub struct BundleArgs {
     pub bundler: Option<Bundler>,
 }
 
 /// `nix eval --apply <expr>` option
-#[derive(Clone, From, Deref, Debug, Default)]
+#[derive(Clone, From, Deref, Debug, Default, Serialize, Deserialize)]
 #[from(forward)]
 pub struct Apply(String);
 impl Flag for Apply {
@@ -124,14 +269,15 @@ impl Flag for Apply {
 }
 
 /// [`nix eval`](https://github.com/NixOS/nix/blob/a6239eb5700ebb85b47bb5f12366404448361f8d/src/nix/eval.cc#LL21-40) options
-#[derive(Debug, Default, Clone, ToArgs)]
+#[derive(Debug, Default, Clone, ToArgs, Serialize, Deserialize)]
+#[serde(default)]
 pub struct EvalArgs {
     pub apply: Option<Apply>,
     pub installable: Option<InstallableArg>,
 }
 
 /// `nix store gc --dry-run` flag
-#[derive(Clone, From, Deref, Debug)]
+#[derive(Clone, From, Deref, Debug, Serialize, Deserialize)]
 #[from(forward)]
 pub struct DryRun(bool);
 impl Flag for DryRun {
@@ -140,7 +286,7 @@ impl Flag for DryRun {
 }
 
 /// `nix store gc --max <n>` option
-#[derive(Clone, From, Deref, Debug, Default)]
+#[derive(Clone, From, Deref, Debug, Default, Serialize, Deserialize)]
 #[from(forward)]
 pub struct Max(u32);
 impl Flag for Max {
@@ -149,14 +295,16 @@ impl Flag for Max {
 }
 
 /// `nix store gc` options
-#[derive(Debug, Default, Clone, ToArgs)]
+#[derive(Debug, Default, Clone, ToArgs, Serialize, Deserialize)]
+#[serde(default)]
 pub struct StoreGcArgs {
     pub dry_run: Option<DryRun>,
     pub max: Option<Max>,
 }
 
 /// `nix copy` options
-#[derive(Debug, Default, Clone, ToArgs)]
+#[derive(Debug, Default, Clone, ToArgs, Serialize, Deserialize)]
+#[serde(default)]
 pub struct CopyArgs {
     // TODO --no-check-sigs, --substitute-on-destination
     pub from: Option<CopyFrom>,
@@ -164,7 +312,7 @@ pub struct CopyArgs {
 }
 
 /// `nix copy --from` option
-#[derive(Debug, Clone, Deref, Default, From)]
+#[derive(Debug, Clone, Deref, Default, From, Serialize, Deserialize)]
 #[from(forward)]
 pub struct CopyFrom(String);
 impl Flag for CopyFrom {
@@ -173,7 +321,7 @@ impl Flag for CopyFrom {
 }
 
 /// `nix copy --to` option
-#[derive(Debug, Clone, Deref, Default, From)]
+#[derive(Debug, Clone, Deref, Default, From, Serialize, Deserialize)]
 #[from(forward)]
 pub struct CopyTo(String);
 impl Flag for CopyTo {
@@ -182,7 +330,7 @@ impl Flag for CopyTo {
 }
 
 /// `nix path-info --closure-size` flag
-#[derive(Clone, From, Deref, Debug)]
+#[derive(Clone, From, Deref, Debug, Serialize, Deserialize)]
 #[from(forward)]
 pub struct ClosureSize(bool);
 impl Flag for ClosureSize {
@@ -191,7 +339,7 @@ impl Flag for ClosureSize {
 }
 
 /// `nix path-info --human-readable` flag
-#[derive(Clone, From, Deref, Debug)]
+#[derive(Clone, From, Deref, Debug, Serialize, Deserialize)]
 #[from(forward)]
 pub struct HumanReadable(bool);
 impl Flag for HumanReadable {
@@ -200,7 +348,7 @@ impl Flag for HumanReadable {
 }
 
 /// `nix path-info --sigs` flag
-#[derive(Clone, From, Deref, Debug)]
+#[derive(Clone, From, Deref, Debug, Serialize, Deserialize)]
 #[from(forward)]
 pub struct Sigs(bool);
 impl Flag for Sigs {
@@ -209,7 +357,7 @@ impl Flag for Sigs {
 }
 
 /// `nix path-info --size` flag
-#[derive(Clone, From, Deref, Debug)]
+#[derive(Clone, From, Deref, Debug, Serialize, Deserialize)]
 #[from(forward)]
 pub struct Size(bool);
 impl Flag for Size {
@@ -218,18 +366,45 @@ impl Flag for Size {
 }
 
 /// `nix path-info` options
-#[derive(Debug, Default, Clone, ToArgs)]
+#[derive(Debug, Default, Clone, ToArgs, Serialize, Deserialize)]
+#[serde(default)]
 pub struct PathInfoArgs {
     pub closure_size: Option<ClosureSize>,
     pub human_readable: Option<HumanReadable>,
+    pub recursive: Option<Recursive>,
     pub sigs: Option<Sigs>,
     pub size: Option<Size>,
 }
 
+/// `nix store sign --all` flag
+///
+/// Technically an extended installable flag
+/// that applies the command to all valid paths in the store
+#[derive(Clone, From, Deref, Debug, Serialize, Deserialize)]
+#[from(forward)]
+pub struct All(bool);
+impl Flag for All {
+    const FLAG: &'static str = "--all";
+    const FLAG_TYPE: FlagType<Self> = FlagType::switch(false);
+}
+
+/// `nix store sign --derivation` flag
+///
+/// Technically an extended installable flag
+/// that operates on the store derivation rather than its outputs
+#[derive(Clone, From, Deref, Debug, Serialize, Deserialize)]
+#[from(forward)]
+pub struct Derivation(bool);
+impl Flag for Derivation {
+    const FLAG: &'static str = "--derivation";
+    const FLAG_TYPE: FlagType<Self> = FlagType::switch(false);
+}
+
 /// `nix store sign --recursive` flag
 ///
 /// Technically an extended installable flag
-#[derive(Clone, From, Deref, Debug)]
+/// that applies the command to the closure of the installables
+#[derive(Clone, From, Deref, Debug, Serialize, Deserialize)]
 #[from(forward)]
 pub struct Recursive(bool);
 impl Flag for Recursive {
@@ -237,8 +412,129 @@ impl Flag for Recursive {
     const FLAG_TYPE: FlagType<Self> = FlagType::switch(false);
 }
 
+/// `nix flake check --no-build` flag
+#[derive(Clone, From, Deref, Debug, Serialize, Deserialize)]
+#[from(forward)]
+pub struct NoBuild(bool);
+impl Flag for NoBuild {
+    const FLAG: &'static str = "--no-build";
+    const FLAG_TYPE: FlagType<Self> = FlagType::switch(false);
+}
+
+/// `nix flake check --all-systems` flag
+///
+/// Check the outputs of all systems rather than only those of the current system
+#[derive(Clone, From, Deref, Debug, Serialize, Deserialize)]
+#[from(forward)]
+pub struct AllSystems(bool);
+impl Flag for AllSystems {
+    const FLAG: &'static str = "--all-systems";
+    const FLAG_TYPE: FlagType<Self> = FlagType::switch(false);
+}
+
+/// `nix flake check` options
+#[derive(Debug, Default, Clone, ToArgs, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FlakeCheckArgs {
+    pub no_build: Option<NoBuild>,
+    pub all_systems: Option<AllSystems>,
+}
+
+/// `nix derivation show` options
+#[derive(Debug, Default, Clone, ToArgs, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DerivationShowArgs {
+    pub recursive: Option<Recursive>,
+}
+
+/// `nix search --exclude <REGEX>` option, repeated for every regex
+#[derive(Clone, From, Deref, Debug, Serialize, Deserialize)]
+#[from(forward)]
+pub struct Exclude(Vec<String>);
+impl Flag for Exclude {
+    const FLAG: &'static str = "--exclude";
+    const FLAG_TYPE: FlagType<Self> = FlagType::Custom(|s| {
+        s.0.iter()
+            .flat_map(|regex| [Self::FLAG.to_string(), regex.clone()])
+            .collect()
+    });
+}
+
+/// `nix search <INSTALLABLE> <REGEX>...` regexes
+///
+/// Packages have to match all of them, `^` matches any package.
+#[derive(Clone, From, Deref, Debug, Serialize, Deserialize)]
+#[from(forward)]
+pub struct SearchRegexes(Vec<String>);
+impl Flag for SearchRegexes {
+    const FLAG: &'static str = "";
+    const FLAG_TYPE: FlagType<Self> = FlagType::Custom(|s| s.0.clone());
+}
+
+/// `nix search` options
+///
+/// ```
+/// # use runix::arguments::SearchArgs;
+/// # use runix::command::Search;
+/// # use runix::command_line::NixCliCommand;
+/// let search = Search {
+///     search: SearchArgs {
+///         exclude: Some(vec!["python2".to_string()].into()),
+///         regexes: Some(vec!["requests".to_string()].into()),
+///     },
+///     ..Default::default()
+/// };
+/// assert_eq!(search.args(), ["--exclude", "python2", "requests"]);
+/// ```
+#[derive(Debug, Default, Clone, ToArgs, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SearchArgs {
+    pub exclude: Option<Exclude>,
+    pub regexes: Option<SearchRegexes>,
+}
+
+/// `nix profile --profile <PATH>` option
+///
+/// Defaults to the profile of the user, e.g. `~/.nix-profile`
+#[derive(Clone, From, Deref, Debug, Serialize, Deserialize)]
+#[from(forward)]
+pub struct ProfileFlag(PathBuf);
+impl Flag for ProfileFlag {
+    const FLAG: &'static str = "--profile";
+    const FLAG_TYPE: FlagType<Self> = FlagType::os_str_arg();
+}
+
+/// `nix profile` options
+#[derive(Debug, Default, Clone, ToArgs, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProfileArgs {
+    pub profile: Option<ProfileFlag>,
+}
+
+/// `nix why-depends --precise` flag
+///
+/// Show the files containing the references
+#[derive(Clone, From, Deref, Debug, Serialize, Deserialize)]
+#[from(forward)]
+pub struct Precise(bool);
+impl Flag for Precise {
+    const FLAG: &'static str = "--precise";
+    const FLAG_TYPE: FlagType<Self> = FlagType::switch(false);
+}
+
+/// `nix why-depends` options
+#[derive(Debug, Default, Clone, ToArgs, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WhyDependsArgs {
+    /// Show all chains of references rather than a shortest one
+    pub all: Option<All>,
+    pub precise: Option<Precise>,
+    /// Show why the derivation, rather than its outputs, depends on the dependency
+    pub derivation: Option<Derivation>,
+}
+
 /// `nix store sign --key-file <FILE>` option
-#[derive(Clone, From, Deref, Debug)]
+#[derive(Clone, From, Deref, Debug, Serialize, Deserialize)]
 #[from(forward)]
 pub struct KeyFile(PathBuf);
 impl Flag for KeyFile {
@@ -247,8 +543,61 @@ impl Flag for KeyFile {
 }
 
 /// `nix store sign` options
-#[derive(Debug, Clone, ToArgs)]
+#[derive(Debug, Clone, ToArgs, Serialize, Deserialize)]
 pub struct StoreSignArgs {
     pub key_file: KeyFile,
+    pub all: Option<All>,
+    pub derivation: Option<Derivation>,
+    pub recursive: Option<Recursive>,
+}
+
+impl StoreSignArgs {
+    /// Sign the selected store paths with the secret key at `key_file`
+    pub fn new(key_file: impl Into<PathBuf>) -> Self {
+        Self {
+            key_file: KeyFile(key_file.into()),
+            all: None,
+            derivation: None,
+            recursive: None,
+        }
+    }
+}
+
+/// `nix store verify --sigs-needed <n>` option
+#[derive(Clone, From, Deref, Debug, Default, Serialize, Deserialize)]
+#[from(forward)]
+pub struct SigsNeeded(u32);
+impl Flag for SigsNeeded {
+    const FLAG: &'static str = "--sigs-needed";
+    const FLAG_TYPE: FlagType<Self> = FlagType::number_arg();
+}
+
+/// `nix store verify --no-contents` flag
+#[derive(Clone, From, Deref, Debug, Default, Serialize, Deserialize)]
+#[from(forward)]
+pub struct NoContents(bool);
+impl Flag for NoContents {
+    const FLAG: &'static str = "--no-contents";
+    const FLAG_TYPE: FlagType<Self> = FlagType::switch(false);
+}
+
+/// `nix store verify --no-trust` flag
+#[derive(Clone, From, Deref, Debug, Default, Serialize, Deserialize)]
+#[from(forward)]
+pub struct NoTrust(bool);
+impl Flag for NoTrust {
+    const FLAG: &'static str = "--no-trust";
+    const FLAG_TYPE: FlagType<Self> = FlagType::switch(false);
+}
+
+/// `nix store verify` options
+#[derive(Debug, Default, Clone, ToArgs, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StoreVerifyArgs {
+    pub all: Option<All>,
+    pub derivation: Option<Derivation>,
     pub recursive: Option<Recursive>,
+    pub no_contents: Option<NoContents>,
+    pub no_trust: Option<NoTrust>,
+    pub sigs_needed: Option<SigsNeeded>,
 }
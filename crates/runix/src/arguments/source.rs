@@ -2,6 +2,7 @@
 
 use derive_more::{Deref, From};
 use runix_derive::ToArgs;
+use serde::{Deserialize, Serialize};
 
 use crate::command_line::ToArgs;
 use crate::default::flag::{Flag, FlagType};
@@ -9,12 +10,13 @@ use crate::default::flag::{Flag, FlagType};
 /// Source installable related arguments
 /// Corresponding to the arguments defined in
 /// [libcmd/installables.cc](https://github.com/NixOS/nix/blob/a6239eb5700ebb85b47bb5f12366404448361f8d/src/libcmd/installables.cc#L146-L186)
-#[derive(Clone, Default, Debug, ToArgs)]
+#[derive(Clone, Default, Debug, ToArgs, Serialize, Deserialize)]
+#[serde(default)]
 pub struct SourceArgs {
     pub expr: Option<Expr>,
 }
 
-#[derive(Clone, From, Deref, Debug, Default)]
+#[derive(Clone, From, Deref, Debug, Default, Serialize, Deserialize)]
 #[from(forward)]
 pub struct Expr(String);
 impl Flag for Expr {
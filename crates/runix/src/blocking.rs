@@ -0,0 +1,132 @@
+//! Synchronous versions of the [Run] traits
+//!
+//! Allows applications without an async runtime, e.g. build scripts or simple CLIs,
+//! to run commands.
+//! Each call runs the command to completion on a private single threaded runtime,
+//! callers do not need to set up a runtime themselves.
+//!
+//! The traits are implemented for all commands implementing the respective async trait:
+//!
+//! ```no_run
+//! # use runix::arguments::NixArgs;
+//! # use runix::blocking::RunTypedBlocking;
+//! # use runix::command::FlakeMetadata;
+//! # use runix::command_line::NixCommandLine;
+//! fn main() {
+//!     let metadata = FlakeMetadata::default()
+//!         .run_typed_blocking(&NixCommandLine::default(), &NixArgs::default())
+//!         .unwrap();
+//!     println!("{}", metadata.resolved_url);
+//! }
+//! ```
+//!
+//! **Note**: the methods panic if called from within an async runtime,
+//! async code should use the [Run] traits directly.
+
+use std::future::Future;
+
+use serde_json::Value;
+
+use crate::arguments::NixArgs;
+use crate::{NixBackend, Run, RunJson, RunTyped};
+
+/// Run `future` to completion on a new single threaded runtime
+fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to create a runtime")
+        .block_on(future)
+}
+
+/// Synchronous version of [Run]
+pub trait RunBlocking<B: NixBackend>: Run<B> {
+    fn run_blocking(&self, backend: &B, nix_args: &NixArgs) -> Result<(), Self::Error>;
+}
+
+impl<B: NixBackend, C: Run<B>> RunBlocking<B> for C {
+    fn run_blocking(&self, backend: &B, nix_args: &NixArgs) -> Result<(), Self::Error> {
+        block_on(self.run(backend, nix_args))
+    }
+}
+
+/// Synchronous version of [RunJson]
+pub trait RunJsonBlocking<B: NixBackend>: RunJson<B> {
+    fn run_json_blocking(&self, backend: &B, nix_args: &NixArgs) -> Result<Value, Self::JsonError>;
+}
+
+impl<B: NixBackend, C: RunJson<B>> RunJsonBlocking<B> for C {
+    fn run_json_blocking(&self, backend: &B, nix_args: &NixArgs) -> Result<Value, Self::JsonError> {
+        block_on(self.run_json(backend, nix_args))
+    }
+}
+
+/// Synchronous version of [RunTyped]
+pub trait RunTypedBlocking<B: NixBackend>: RunTyped<B> {
+    fn run_typed_blocking(
+        &self,
+        backend: &B,
+        nix_args: &NixArgs,
+    ) -> Result<Self::Output, Self::TypedError>;
+}
+
+impl<B: NixBackend, C: RunTyped<B>> RunTypedBlocking<B> for C {
+    fn run_typed_blocking(
+        &self,
+        backend: &B,
+        nix_args: &NixArgs,
+    ) -> Result<Self::Output, Self::TypedError> {
+        block_on(self.run_typed(backend, nix_args))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+
+    use super::*;
+
+    struct EchoBackend;
+    impl NixBackend for EchoBackend {}
+
+    /// A command returning the timeout passed to it
+    struct Echo;
+
+    #[async_trait]
+    impl Run<EchoBackend> for Echo {
+        type Error = std::io::Error;
+
+        async fn run(&self, _: &EchoBackend, _: &NixArgs) -> Result<(), Self::Error> {
+            tokio::task::yield_now().await;
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl RunTyped<EchoBackend> for Echo {
+        type Output = Option<std::time::Duration>;
+        type TypedError = std::io::Error;
+
+        async fn run_typed(
+            &self,
+            _: &EchoBackend,
+            nix_args: &NixArgs,
+        ) -> Result<Self::Output, Self::TypedError> {
+            tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+            Ok(nix_args.timeout)
+        }
+    }
+
+    #[test]
+    fn run_blocking() {
+        let nix_args = NixArgs {
+            timeout: Some(std::time::Duration::from_secs(1)),
+            ..Default::default()
+        };
+        Echo.run_blocking(&EchoBackend, &nix_args).unwrap();
+        assert_eq!(
+            Echo.run_typed_blocking(&EchoBackend, &nix_args).unwrap(),
+            nix_args.timeout
+        );
+    }
+}
@@ -0,0 +1,225 @@
+//! Which store paths a build produced locally, substituted or found in the store
+//!
+//! [BuildReporter] follows the [LogEvent]s of a build like [crate::progress::ProgressTracker]
+//! and summarizes them in a [BuildReport], e.g. to track the cache hit rate of CI jobs.
+//! Use [crate::command::Build::report] to run a build and report on it.
+//!
+//! ```
+//! # use runix::build_report::BuildReporter;
+//! # use runix::command::BuildOut;
+//! # use runix::log_event::LogEvent;
+//! let mut reporter = BuildReporter::default();
+//! for line in [
+//!     r#"@nix {"action":"start","id":1,"level":3,"type":105,"fields":["/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-hello-2.12.1.drv","",1,1]}"#,
+//!     r#"@nix {"action":"start","id":2,"level":4,"type":108,"fields":["/nix/store/9l06v7fc38c1x3r2iydl15ksgz0ysb82-glibc-2.37-8","https://cache.nixos.org"]}"#,
+//! ] {
+//!     reporter.handle(&line.parse::<LogEvent>().unwrap());
+//! }
+//!
+//! let out: BuildOut = serde_json::from_str(
+//!     r#"[
+//!         {
+//!             "drvPath": "/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-hello-2.12.1.drv",
+//!             "outputs": { "out": "/nix/store/3dsk2kh0m6xyi5rhjqzwxd4jc4hy6r2s-hello-2.12.1" }
+//!         },
+//!         {
+//!             "drvPath": "/nix/store/1ai6m9a6bz9r6zz2i3c0mlz2y6lswdzx-jq-1.7.drv",
+//!             "outputs": { "bin": "/nix/store/0qpvcd2k0cj7ahms0bff93hyw8ycjm2w-jq-1.7-bin" }
+//!         }
+//!     ]"#,
+//! )
+//! .unwrap();
+//!
+//! let report = reporter.finish(&out);
+//! assert_eq!(report.built_locally().count(), 1);
+//! assert_eq!(report.substituted[0].substituter, "https://cache.nixos.org");
+//! assert_eq!(report.present[0].to_string(), "/nix/store/0qpvcd2k0cj7ahms0bff93hyw8ycjm2w-jq-1.7-bin");
+//! assert_eq!(report.hit_rate(), Some(2.0 / 3.0));
+//! ```
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::command::BuildOut;
+use crate::log_event::{ActivityType, Field, LogEvent};
+use crate::store_path::{DrvPath, StorePath};
+
+/// A derivation built during a build
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BuiltDerivation {
+    pub drv_path: DrvPath,
+    /// The remote builder that built the derivation, [None] if built locally
+    pub machine: Option<String>,
+}
+
+/// A store path downloaded from a binary cache instead of being built
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Substitution {
+    pub path: StorePath,
+    /// The URL of the binary cache, e.g. `https://cache.nixos.org`
+    pub substituter: String,
+}
+
+/// Summary of the work done by a build
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildReport {
+    /// Derivations built, including dependencies of the requested installables
+    pub built: Vec<BuiltDerivation>,
+    /// Store paths substituted, including dependencies of the requested installables
+    pub substituted: Vec<Substitution>,
+    /// Requested outputs that were already present in the store
+    pub present: Vec<StorePath>,
+}
+
+impl BuildReport {
+    /// The derivations built on the local machine
+    pub fn built_locally(&self) -> impl Iterator<Item = &DrvPath> {
+        self.built
+            .iter()
+            .filter(|built| built.machine.is_none())
+            .map(|built| &built.drv_path)
+    }
+
+    /// The store paths substituted from `substituter`
+    pub fn substituted_from<'a>(
+        &'a self,
+        substituter: &'a str,
+    ) -> impl Iterator<Item = &'a StorePath> {
+        self.substituted
+            .iter()
+            .filter(move |substitution| substitution.substituter == substituter)
+            .map(|substitution| &substitution.path)
+    }
+
+    /// The fraction of store paths that did not have to be built,
+    /// [None] if nothing was built, substituted or present
+    pub fn hit_rate(&self) -> Option<f64> {
+        let hits = self.substituted.len() + self.present.len();
+        let total = hits + self.built.len();
+        (total > 0).then(|| hits as f64 / total as f64)
+    }
+}
+
+/// Collects a [BuildReport] from [LogEvent]s
+#[derive(Debug, Default)]
+pub struct BuildReporter {
+    built: BTreeMap<DrvPath, Option<String>>,
+    /// The substituter of every path, nix falls back to the next one if a download fails
+    substituted: BTreeMap<StorePath, String>,
+}
+
+impl BuildReporter {
+    /// Record the builds and substitutions started by `event`
+    pub fn handle(&mut self, event: &LogEvent) {
+        let LogEvent::Start {
+            activity, fields, ..
+        } = event
+        else {
+            return;
+        };
+
+        match activity {
+            ActivityType::Build => {
+                let Some(drv_path) = field_str(fields, 0).and_then(|path| path.parse().ok()) else {
+                    return;
+                };
+                let machine = field_str(fields, 1)
+                    .filter(|machine| !machine.is_empty())
+                    .map(ToString::to_string);
+                self.built.insert(drv_path, machine);
+            },
+            ActivityType::Substitute => {
+                let (Some(path), Some(substituter)) = (
+                    field_str(fields, 0).and_then(|path| path.parse().ok()),
+                    field_str(fields, 1),
+                ) else {
+                    return;
+                };
+                self.substituted.insert(path, substituter.to_string());
+            },
+            _ => {},
+        }
+    }
+
+    /// Complete the report with the requested `outputs`
+    ///
+    /// Outputs of derivations that were neither built nor substituted
+    /// were already present in the store.
+    pub fn finish(self, outputs: &BuildOut) -> BuildReport {
+        let present = outputs
+            .iter()
+            .filter(|entry| !self.built.contains_key(&entry.drv_path))
+            .flat_map(|entry| {
+                let mut paths: Vec<_> = entry.outputs.values().collect();
+                paths.sort();
+                paths
+            })
+            .filter(|path| !self.substituted.contains_key(path))
+            .cloned()
+            .collect();
+
+        BuildReport {
+            built: self
+                .built
+                .into_iter()
+                .map(|(drv_path, machine)| BuiltDerivation { drv_path, machine })
+                .collect(),
+            substituted: self
+                .substituted
+                .into_iter()
+                .map(|(path, substituter)| Substitution { path, substituter })
+                .collect(),
+            present,
+        }
+    }
+}
+
+fn field_str(fields: &[Field], n: usize) -> Option<&str> {
+    fields.get(n).and_then(Field::as_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_builds() {
+        let drv = "/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-hello-2.12.1.drv";
+        let glibc = "/nix/store/9l06v7fc38c1x3r2iydl15ksgz0ysb82-glibc-2.37-8";
+        let mut reporter = BuildReporter::default();
+        for line in [
+            format!(
+                r#"@nix {{"action":"start","id":1,"level":3,"type":105,"fields":["{drv}","ssh://builder",1,1]}}"#
+            ),
+            format!(
+                r#"@nix {{"action":"start","id":2,"level":4,"type":108,"fields":["{glibc}","https://cache.example.org"]}}"#
+            ),
+            format!(
+                r#"@nix {{"action":"start","id":3,"level":4,"type":108,"fields":["{glibc}","https://cache.nixos.org"]}}"#
+            ),
+            r#"@nix {"action":"start","id":4,"level":4,"type":101,"fields":["https://cache.nixos.org/nar/a.nar.xz"]}"#.to_string(),
+        ] {
+            reporter.handle(&line.parse().unwrap());
+        }
+
+        let report = reporter.finish(&vec![]);
+        assert_eq!(report.built, [BuiltDerivation {
+            drv_path: drv.parse().unwrap(),
+            machine: Some("ssh://builder".to_string()),
+        }]);
+        assert_eq!(report.built_locally().count(), 0);
+        assert_eq!(
+            report
+                .substituted_from("https://cache.nixos.org")
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            [glibc]
+        );
+        assert_eq!(report.substituted.len(), 1);
+        assert!(report.present.is_empty());
+        assert_eq!(report.hit_rate(), Some(0.5));
+        assert_eq!(BuildReport::default().hit_rate(), None);
+    }
+}
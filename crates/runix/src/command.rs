@@ -1,9 +1,12 @@
 //! Backened independent Command implementations
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
 
 use derive_more::{Deref, From};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error;
 
 use crate::arguments::eval::EvaluationArgs;
 use crate::arguments::flake::FlakeArgs;
@@ -12,22 +15,72 @@ use crate::arguments::{
     BuildArgs,
     BundleArgs,
     CopyArgs,
+    DerivationShowArgs,
     DevelopArgs,
     EvalArgs,
+    FlakeCheckArgs,
     InstallableArg,
     InstallablesArgs,
+    NixArgs,
     PathInfoArgs,
+    ProfileArgs,
+    SearchArgs,
     StoreGcArgs,
     StoreSignArgs,
+    StoreVerifyArgs,
+    WhyDependsArgs,
 };
 use crate::command_line::flag::{Flag, FlagType};
+use crate::command_line::json_stream::JsonItem;
+use crate::command_line::version::{Dialect, NixVersion};
 use crate::command_line::{Group, JsonCommand, NixCliCommand, TypedCommand};
-use crate::flake_ref::FlakeRef;
+use crate::derivation::Derivation;
+use crate::flake_check::FlakeCheckReport;
+use crate::flake_ref::lock::NarHash;
+use crate::flake_ref::{FlakeRef, Timestamp};
 use crate::installable::Installable;
 use crate::narinfo::Narinfo;
+use crate::nix_config::NixConfigValues;
+use crate::profile::ProfileManifest;
+use crate::registry::{RegistryEntry, RegistryError};
+use crate::search::SearchResult;
+use crate::store_path::{DrvPath, StorePath};
+use crate::why_depends::WhyDependsGraph;
+use crate::{store_info, NixBackend, OutputLine, Run as RunCommand, RunStreaming};
 
 /// `nix build` Command
-#[derive(Debug, Default, Clone)]
+///
+/// Like all commands, it (de)serializes to store or queue invocations,
+/// omitted fields take their default:
+///
+/// ```
+/// # use runix::command::Build;
+/// # use runix::command_line::NixCliCommand;
+/// let build: Build = serde_json::from_str(
+///     r#"{
+///         "installables": [
+///             "github:flox/runix#runix",
+///             "/nix/store/3dsk2kh0m6xyi5rhjqzwxd4jc4hy6r2s-hello-2.12.1"
+///         ],
+///         "eval": { "impure": true },
+///         "build": { "out_link": "result-runix" }
+///     }"#,
+/// )
+/// .unwrap();
+/// assert_eq!(build.args(), [
+///     "--impure",
+///     "github:flox/runix#runix",
+///     "/nix/store/3dsk2kh0m6xyi5rhjqzwxd4jc4hy6r2s-hello-2.12.1",
+///     "--out-link",
+///     "result-runix"
+/// ]);
+///
+/// let json = serde_json::to_string(&build).unwrap();
+/// let replayed: Build = serde_json::from_str(&json).unwrap();
+/// assert_eq!(replayed.args(), build.args());
+/// ```
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Build {
     pub flake: FlakeArgs,
     pub eval: EvaluationArgs,
@@ -47,13 +100,44 @@ impl NixCliCommand for Build {
     const SUBCOMMAND: &'static [&'static str] = &["build"];
 }
 impl JsonCommand for Build {}
-#[derive(Deserialize, Clone, Debug)]
 
 /// Type for an element in the output of `nix build --json`
+///
+/// ```
+/// # use runix::command::BuildOut;
+/// let out: BuildOut = serde_json::from_str(
+///     r#"[{
+///         "drvPath": "/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-hello-2.12.1.drv",
+///         "outputs": { "out": "/nix/store/0qpvcd2k0cj7ahms0bff93hyw8ycjm2w-hello-2.12.1" },
+///         "startTime": 1688730350,
+///         "stopTime": 1688730362
+///     }]"#,
+/// )
+/// .unwrap();
+/// assert!(out[0].outputs["out"].to_string().ends_with("-hello-2.12.1"));
+/// assert_eq!(out[0].duration().unwrap().num_seconds(), 12);
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
 pub struct BuildOutEntry {
-    #[serde(rename = "drvPath")]
-    pub drv_path: String,
-    pub outputs: HashMap<String, String>,
+    pub drv_path: DrvPath,
+    pub outputs: HashMap<String, StorePath>,
+    /// When the build started, only reported by newer versions of nix
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_time: Option<Timestamp>,
+    /// When the build finished, only reported by newer versions of nix
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_time: Option<Timestamp>,
+}
+
+impl BuildOutEntry {
+    /// How long building took, if reported by nix
+    pub fn duration(&self) -> Option<chrono::Duration> {
+        match (&self.start_time, &self.stop_time) {
+            (Some(start), Some(stop)) => Some(stop.signed_duration_since(start)),
+            _ => None,
+        }
+    }
 }
 
 /// The output of `nix build --json`
@@ -63,7 +147,8 @@ impl TypedCommand for Build {
 }
 
 /// `nix flake init` Command
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct FlakeInit {
     pub flake: FlakeArgs,
     pub eval: EvaluationArgs,
@@ -73,7 +158,7 @@ pub struct FlakeInit {
 }
 
 /// `nix flake init --template <TEMPLATE>` flag
-#[derive(Deref, Debug, Clone, From)]
+#[derive(Deref, Debug, Clone, From, Serialize, Deserialize)]
 #[from(forward)]
 pub struct TemplateFlag(Installable);
 impl Flag for TemplateFlag {
@@ -92,7 +177,7 @@ impl NixCliCommand for FlakeInit {
 }
 
 /// `nix flake metadata <FLAKE_REF>` flag
-#[derive(Deref, Debug, Clone, From)]
+#[derive(Deref, Debug, Clone, From, Serialize, Deserialize)]
 #[from(forward)]
 pub struct FlakeRefArg(FlakeRef);
 impl Flag for FlakeRefArg {
@@ -101,7 +186,8 @@ impl Flag for FlakeRefArg {
 }
 
 /// `nix flake metadata` Command
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct FlakeMetadata {
     pub eval: EvaluationArgs,
     pub flake: FlakeArgs,
@@ -121,8 +207,87 @@ impl TypedCommand for FlakeMetadata {
     type Output = crate::flake_metadata::FlakeMetadata;
 }
 
+/// `nix flake check` Command
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FlakeCheck {
+    pub eval: EvaluationArgs,
+    pub flake: FlakeArgs,
+    pub flake_ref: Option<FlakeRefArg>,
+    pub flake_check: FlakeCheckArgs,
+}
+
+impl NixCliCommand for FlakeCheck {
+    type Own = (Option<FlakeRefArg>, FlakeCheckArgs);
+
+    const EVAL_ARGS: Group<Self, EvaluationArgs> = Some(|d| d.eval.clone());
+    const FLAKE_ARGS: Group<Self, FlakeArgs> = Some(|d| d.flake.clone());
+    const OWN_ARGS: Group<Self, Self::Own> = Some(|d| (d.flake_ref.clone(), d.flake_check.clone()));
+    const SUBCOMMAND: &'static [&'static str] = &["flake", "check"];
+}
+
+impl FlakeCheck {
+    /// Run the checks and collect the diagnostics printed by nix
+    ///
+    /// Failed checks are reported as errors in the [FlakeCheckReport] rather than as `Err`,
+    /// which is only returned if nix failed without reporting an error.
+    pub async fn check<B, E>(&self, backend: &B, nix_args: &NixArgs) -> Result<FlakeCheckReport, E>
+    where
+        B: NixBackend + Sync,
+        FlakeCheck: RunStreaming<B, StreamingError = E>,
+    {
+        let mut stderr = String::new();
+        let result = self
+            .run_streaming(backend, nix_args, &mut |line| {
+                if let OutputLine::Stderr(line) = line {
+                    stderr.push_str(&line);
+                    stderr.push('\n');
+                }
+            })
+            .await;
+
+        let report = FlakeCheckReport::from_stderr(&stderr);
+        match result {
+            Err(e) if report.is_ok() => Err(e),
+            _ => Ok(report),
+        }
+    }
+}
+
+/// `nix flake prefetch` Command
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FlakePrefetch {
+    pub eval: EvaluationArgs,
+    pub flake: FlakeArgs,
+    pub flake_ref: Option<FlakeRefArg>,
+}
+
+impl NixCliCommand for FlakePrefetch {
+    type Own = Option<FlakeRefArg>;
+
+    const EVAL_ARGS: Group<Self, EvaluationArgs> = Some(|d| d.eval.clone());
+    const FLAKE_ARGS: Group<Self, FlakeArgs> = Some(|d| d.flake.clone());
+    const OWN_ARGS: Group<Self, Self::Own> = Some(|d| d.flake_ref.clone());
+    const SUBCOMMAND: &'static [&'static str] = &["flake", "prefetch"];
+}
+impl JsonCommand for FlakePrefetch {}
+
+/// The output of `nix flake prefetch --json`
+#[derive(Deserialize, Clone, Debug)]
+pub struct FlakePrefetchOut {
+    /// The narHash of the fetched source
+    pub hash: NarHash,
+    #[serde(rename = "storePath")]
+    pub store_path: PathBuf,
+}
+impl TypedCommand for FlakePrefetch {
+    type Output = FlakePrefetchOut;
+}
+
 /// `nix develop` Command
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Develop {
     pub flake: FlakeArgs,
     pub eval: EvaluationArgs,
@@ -142,8 +307,36 @@ impl NixCliCommand for Develop {
     const SUBCOMMAND: &'static [&'static str] = &["develop"];
 }
 
+/// `nix print-dev-env` Command
+///
+/// Prints the environment of `nix develop` rather than starting a shell,
+/// see [crate::dev_env::DevEnv] to run programs in it.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PrintDevEnv {
+    pub flake: FlakeArgs,
+    pub eval: EvaluationArgs,
+    pub source: SourceArgs,
+    pub installable: InstallableArg,
+}
+
+impl NixCliCommand for PrintDevEnv {
+    type Own = ();
+
+    const EVAL_ARGS: Group<Self, EvaluationArgs> = Some(|d| d.eval.clone());
+    const FLAKE_ARGS: Group<Self, FlakeArgs> = Some(|d| d.flake.clone());
+    const INSTALLABLE: Group<Self, InstallableArg> = Some(|d| d.installable.clone());
+    const SOURCE_ARGS: Group<Self, SourceArgs> = Some(|d| d.source.clone());
+    const SUBCOMMAND: &'static [&'static str] = &["print-dev-env"];
+}
+impl JsonCommand for PrintDevEnv {}
+impl TypedCommand for PrintDevEnv {
+    type Output = crate::dev_env::DevEnv;
+}
+
 /// `nix eval` Command
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Eval {
     pub flake: FlakeArgs,
     pub eval: EvaluationArgs,
@@ -162,8 +355,35 @@ impl NixCliCommand for Eval {
 }
 impl JsonCommand for Eval {}
 
+/// `nix search` Command
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Search {
+    pub flake: FlakeArgs,
+    pub eval: EvaluationArgs,
+    pub source: SourceArgs,
+    pub installable: InstallableArg,
+    pub search: SearchArgs,
+}
+
+impl NixCliCommand for Search {
+    type Own = SearchArgs;
+
+    const EVAL_ARGS: Group<Self, EvaluationArgs> = Some(|d| d.eval.clone());
+    const FLAKE_ARGS: Group<Self, FlakeArgs> = Some(|d| d.flake.clone());
+    const INSTALLABLE: Group<Self, InstallableArg> = Some(|d| d.installable.clone());
+    const OWN_ARGS: Group<Self, SearchArgs> = Some(|d| d.search.clone());
+    const SOURCE_ARGS: Group<Self, SourceArgs> = Some(|d| d.source.clone());
+    const SUBCOMMAND: &'static [&'static str] = &["search"];
+}
+impl JsonCommand for Search {}
+impl TypedCommand for Search {
+    type Output = SearchResult;
+}
+
 /// `nix run` Command
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Run {
     pub flake: FlakeArgs,
     pub eval: EvaluationArgs,
@@ -186,7 +406,8 @@ impl TypedCommand for Run {
 }
 
 /// `nix shell` Command
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Shell {
     pub flake: FlakeArgs,
     pub eval: EvaluationArgs,
@@ -208,8 +429,31 @@ impl TypedCommand for Shell {
     type Output = ();
 }
 
+/// `nix repl` Command
+///
+/// Interactive, see [crate::RunInteractive].
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Repl {
+    pub flake: FlakeArgs,
+    pub eval: EvaluationArgs,
+    pub source: SourceArgs,
+    pub installables: InstallablesArgs,
+}
+
+impl NixCliCommand for Repl {
+    type Own = ();
+
+    const EVAL_ARGS: Group<Self, EvaluationArgs> = Some(|d| d.eval.clone());
+    const FLAKE_ARGS: Group<Self, FlakeArgs> = Some(|d| d.flake.clone());
+    const INSTALLABLES: Group<Self, InstallablesArgs> = Some(|d| d.installables.clone());
+    const SOURCE_ARGS: Group<Self, SourceArgs> = Some(|d| d.source.clone());
+    const SUBCOMMAND: &'static [&'static str] = &["repl"];
+}
+
 /// `nix bundle` Command
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Bundle {
     pub flake: FlakeArgs,
     pub eval: EvaluationArgs,
@@ -234,7 +478,8 @@ impl TypedCommand for Bundle {
 }
 
 /// `nix store gc` Command
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct StoreGc {
     // store gc doesn't accept any args other than its own and global Nix args
     pub store_gc: StoreGcArgs,
@@ -244,13 +489,127 @@ impl NixCliCommand for StoreGc {
     type Own = StoreGcArgs;
 
     const OWN_ARGS: Group<Self, Self::Own> = Some(|d| d.store_gc.clone());
+    const PRIVILEGED: bool = true;
     const SUBCOMMAND: &'static [&'static str] = &["store", "gc"];
 }
 
+/// `nix store optimise` Command
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct StoreOptimise {}
+
+impl NixCliCommand for StoreOptimise {
+    type Own = ();
+
+    const PRIVILEGED: bool = true;
+    const SUBCOMMAND: &'static [&'static str] = &["store", "optimise"];
+}
+
+/// `nix store dump-path` Command
+///
+/// Prints the NAR serialisation of a store path,
+/// see [crate::RunOutput] to handle large paths.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StoreDumpPath {
+    pub flake: FlakeArgs,
+    pub eval: EvaluationArgs,
+    pub installable: InstallableArg,
+}
+
+impl NixCliCommand for StoreDumpPath {
+    type Own = ();
+
+    const EVAL_ARGS: Group<Self, EvaluationArgs> = Some(|d| d.eval.clone());
+    const FLAKE_ARGS: Group<Self, FlakeArgs> = Some(|d| d.flake.clone());
+    const INSTALLABLE: Group<Self, InstallableArg> = Some(|d| d.installable.clone());
+    const SUBCOMMAND: &'static [&'static str] = &["store", "dump-path"];
+}
+
+/// `nix derivation show` Command
+///
+/// Invoked as `nix show-derivation` on versions of Nix prior to its rename in 2.15,
+/// if [crate::command_line::NixCommandLine::version] is set.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DerivationShow {
+    pub flake: FlakeArgs,
+    pub eval: EvaluationArgs,
+    pub installables: InstallablesArgs,
+    pub derivation_show: DerivationShowArgs,
+}
+
+impl NixCliCommand for DerivationShow {
+    type Own = DerivationShowArgs;
+
+    const EVAL_ARGS: Group<Self, EvaluationArgs> = Some(|d| d.eval.clone());
+    const FLAKE_ARGS: Group<Self, FlakeArgs> = Some(|d| d.flake.clone());
+    const INSTALLABLES: Group<Self, InstallablesArgs> = Some(|d| d.installables.clone());
+    const OWN_ARGS: Group<Self, Self::Own> = Some(|d| d.derivation_show.clone());
+    const SUBCOMMAND: &'static [&'static str] = &["derivation", "show"];
+
+    fn subcommand(dialect: Option<&Dialect>) -> &'static [&'static str] {
+        match dialect {
+            Some(dialect) if dialect.nix_version() < NixVersion::new(2, 15, 0) => {
+                &["show-derivation"]
+            },
+            _ => Self::SUBCOMMAND,
+        }
+    }
+}
+impl JsonCommand for DerivationShow {
+    const JSON_ARGS: &'static [&'static str] = &[];
+}
+impl TypedCommand for DerivationShow {
+    type Output = DerivationShowOut;
+}
+
+/// The output of [DerivationShow], the derivations by their path
+pub type DerivationShowOut = BTreeMap<DrvPath, Derivation>;
+
+/// `nix derivation add` Command
+///
+/// Adds the derivation passed to stdin to the store and prints its path:
+///
+/// ```no_run
+/// # use runix::arguments::{NixArgs, Stdin};
+/// # use runix::command::DerivationAdd;
+/// # use runix::command_line::NixCommandLine;
+/// # use runix::derivation::Derivation;
+/// # use runix::store_path::DrvPath;
+/// # use runix::RunOutput;
+/// # use tokio::io::AsyncReadExt;
+/// # #[tokio::main]
+/// # async fn main() {
+/// # let derivation: Derivation = todo!();
+/// let nix_args = NixArgs {
+///     stdin: Some(Stdin::try_from(&derivation).unwrap()),
+///     ..Default::default()
+/// };
+/// let mut output = DerivationAdd::default()
+///     .run_output(&NixCommandLine::default(), &nix_args, 1024)
+///     .await
+///     .unwrap();
+///
+/// let mut drv_path = String::new();
+/// output.read_to_string(&mut drv_path).await.unwrap();
+/// let drv_path: DrvPath = drv_path.trim().parse().unwrap();
+/// # }
+/// ```
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct DerivationAdd {}
+
+impl NixCliCommand for DerivationAdd {
+    type Own = ();
+
+    const MIN_VERSION: Option<NixVersion> = Some(NixVersion::new(2, 15, 0));
+    const SUBCOMMAND: &'static [&'static str] = &["derivation", "add"];
+}
+
 /// `nix copy` Command
 ///
 /// Called `NixCopy` instead of `Copy` to avoid confusion with the `Copy` trait
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct NixCopy {
     pub copy_args: CopyArgs,
     pub eval: EvaluationArgs,
@@ -269,7 +628,8 @@ impl NixCliCommand for NixCopy {
 }
 
 /// `nix path-info` Command
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct PathInfo {
     pub eval: EvaluationArgs,
     pub flake: FlakeArgs,
@@ -288,17 +648,61 @@ impl NixCliCommand for PathInfo {
     const SOURCE_ARGS: Group<Self, SourceArgs> = Some(|d| d.source.clone());
     const SUBCOMMAND: &'static [&'static str] = &["path-info"];
 }
-impl JsonCommand for PathInfo {}
+impl JsonCommand for PathInfo {
+    /// Convert the object keyed by path printed since Nix 2.19 to a list of [Narinfo]
+    ///
+    /// ```
+    /// # use runix::command::PathInfo;
+    /// # use runix::command_line::JsonCommand;
+    /// # use serde_json::json;
+    /// let path = "/nix/store/0ib1gbh0k3mj3wssbyc8p8q8xq2r2x3c-hello-2.12.1";
+    /// let json = PathInfo::normalize_json(json!({ path: null }), None);
+    /// assert_eq!(json, json!([{ "path": path, "valid": false }]));
+    /// ```
+    fn normalize_json(json: Value, dialect: Option<&Dialect>) -> Value {
+        let Value::Object(infos) = json else {
+            return json;
+        };
+
+        infos
+            .into_iter()
+            .map(|(path, info)| {
+                Self::normalize_json_item(
+                    JsonItem {
+                        key: Some(path),
+                        value: info,
+                    },
+                    dialect,
+                )
+            })
+            .collect()
+    }
+
+    /// Convert entries keyed by path to [Narinfo], see [PathInfo::normalize_json]
+    fn normalize_json_item(item: JsonItem, _dialect: Option<&Dialect>) -> Value {
+        let Some(path) = item.key else {
+            return item.value;
+        };
+
+        match item.value {
+            Value::Object(mut info) => {
+                info.insert("path".to_string(), Value::String(path));
+                Value::Object(info)
+            },
+            _ => serde_json::json!({ "path": path, "valid": false }),
+        }
+    }
+}
 impl TypedCommand for PathInfo {
     type Output = Vec<Narinfo>;
 }
 
 /// `nix store sign` Command
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StoreSign {
     /// `store sign` (and some other commands) support additional installable options,
     /// `--all`, `--derivation` and `--recursive`,
-    /// which are not yet formalized and thus tentatively included in [StoreSignArgs].
+    /// which are included in [StoreSignArgs].
     pub store_sign: StoreSignArgs,
     pub installables: InstallablesArgs,
     pub eval: EvaluationArgs,
@@ -314,3 +718,231 @@ impl NixCliCommand for StoreSign {
     const OWN_ARGS: Group<Self, Self::Own> = Some(|d| d.store_sign.clone());
     const SUBCOMMAND: &'static [&'static str] = &["store", "sign"];
 }
+
+impl StoreSign {
+    /// Sign the selected store paths and verify the signatures afterwards
+    ///
+    /// Verification runs `nix store verify` on the same installables,
+    /// requiring at least one valid signature per path.
+    /// The public key matching [StoreSignArgs::key_file] has to be trusted,
+    /// e.g. by adding it to [crate::arguments::config::NixConfigArgs::extra_trusted_public_keys].
+    pub async fn sign_and_verify<B, E>(&self, backend: &B, nix_args: &NixArgs) -> Result<(), E>
+    where
+        B: NixBackend + Sync,
+        StoreSign: RunCommand<B, Error = E>,
+        StoreVerify: RunCommand<B, Error = E>,
+    {
+        self.run(backend, nix_args).await?;
+        self.verify().run(backend, nix_args).await
+    }
+
+    /// A [StoreVerify] command checking the signatures of the paths selected by this command
+    pub fn verify(&self) -> StoreVerify {
+        StoreVerify {
+            store_verify: StoreVerifyArgs {
+                all: self.store_sign.all.clone(),
+                derivation: self.store_sign.derivation.clone(),
+                recursive: self.store_sign.recursive.clone(),
+                no_contents: Some(true.into()),
+                no_trust: None,
+                sigs_needed: Some(1u32.into()),
+            },
+            installables: self.installables.clone(),
+            eval: self.eval.clone(),
+            flake: self.flake.clone(),
+        }
+    }
+}
+
+/// `nix store verify` Command
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StoreVerify {
+    pub store_verify: StoreVerifyArgs,
+    pub installables: InstallablesArgs,
+    pub eval: EvaluationArgs,
+    pub flake: FlakeArgs,
+}
+
+impl NixCliCommand for StoreVerify {
+    type Own = StoreVerifyArgs;
+
+    const EVAL_ARGS: Group<Self, EvaluationArgs> = Some(|d| d.eval.clone());
+    const FLAKE_ARGS: Group<Self, FlakeArgs> = Some(|d| d.flake.clone());
+    const INSTALLABLES: Group<Self, InstallablesArgs> = Some(|d| d.installables.clone());
+    const OWN_ARGS: Group<Self, Self::Own> = Some(|d| d.store_verify.clone());
+    const SUBCOMMAND: &'static [&'static str] = &["store", "verify"];
+}
+
+/// `nix profile list` Command
+///
+/// Lists the elements of the profile as JSON since Nix 2.17.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProfileList {
+    pub profile: ProfileArgs,
+}
+
+impl NixCliCommand for ProfileList {
+    type Own = ProfileArgs;
+
+    const MIN_VERSION: Option<NixVersion> = Some(NixVersion::new(2, 17, 0));
+    const OWN_ARGS: Group<Self, ProfileArgs> = Some(|d| d.profile.clone());
+    const SUBCOMMAND: &'static [&'static str] = &["profile", "list"];
+}
+impl JsonCommand for ProfileList {}
+impl TypedCommand for ProfileList {
+    type Output = ProfileManifest;
+}
+
+/// `nix why-depends` Command
+///
+/// [WhyDepends::installables] are the package followed by its dependency.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WhyDepends {
+    pub eval: EvaluationArgs,
+    pub flake: FlakeArgs,
+    pub source: SourceArgs,
+    pub installables: InstallablesArgs,
+    pub why_depends: WhyDependsArgs,
+}
+
+impl NixCliCommand for WhyDepends {
+    type Own = WhyDependsArgs;
+
+    const EVAL_ARGS: Group<Self, EvaluationArgs> = Some(|d| d.eval.clone());
+    const FLAKE_ARGS: Group<Self, FlakeArgs> = Some(|d| d.flake.clone());
+    const INSTALLABLES: Group<Self, InstallablesArgs> = Some(|d| d.installables.clone());
+    const OWN_ARGS: Group<Self, WhyDependsArgs> = Some(|d| d.why_depends.clone());
+    const SOURCE_ARGS: Group<Self, SourceArgs> = Some(|d| d.source.clone());
+    const SUBCOMMAND: &'static [&'static str] = &["why-depends"];
+}
+
+impl WhyDepends {
+    /// Run the command and parse the printed chains of references
+    pub async fn graph<B, E>(&self, backend: &B, nix_args: &NixArgs) -> Result<WhyDependsGraph, E>
+    where
+        B: NixBackend + Sync,
+        WhyDepends: RunStreaming<B, StreamingError = E>,
+    {
+        let mut stdout = String::new();
+        self.run_streaming(backend, nix_args, &mut |line| {
+            if let OutputLine::Stdout(line) = line {
+                stdout.push_str(&line);
+                stdout.push('\n');
+            }
+        })
+        .await?;
+
+        Ok(WhyDependsGraph::from_stdout(&stdout))
+    }
+}
+
+/// `nix store info` Command
+///
+/// Invoked as `nix store ping` on versions of Nix prior to its rename in 2.19 and on Lix,
+/// if [crate::command_line::NixCommandLine::version] is set.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct StoreInfo {}
+
+impl NixCliCommand for StoreInfo {
+    type Own = ();
+
+    const SUBCOMMAND: &'static [&'static str] = &["store", "info"];
+
+    fn subcommand(dialect: Option<&Dialect>) -> &'static [&'static str] {
+        match dialect {
+            Some(dialect) if dialect.nix_version() < NixVersion::new(2, 19, 0) => {
+                &["store", "ping"]
+            },
+            _ => Self::SUBCOMMAND,
+        }
+    }
+}
+impl JsonCommand for StoreInfo {}
+impl TypedCommand for StoreInfo {
+    type Output = StoreInfoOut;
+}
+
+/// `nix store ping` Command, the name of [StoreInfo] prior to Nix 2.19
+pub type StorePing = StoreInfo;
+
+/// `nix registry list` Command
+///
+/// Nix lists the registries as text, see [RegistryList::list] to parse it.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct RegistryList {}
+
+impl NixCliCommand for RegistryList {
+    type Own = ();
+
+    const SUBCOMMAND: &'static [&'static str] = &["registry", "list"];
+}
+
+/// Failure of [RegistryList::list]
+#[derive(Debug, Error)]
+pub enum RegistryListError<E> {
+    #[error(transparent)]
+    Run(E),
+    #[error(transparent)]
+    Parse(RegistryError),
+}
+
+impl RegistryList {
+    /// Run the command and parse the entries of all registries, in order of precedence
+    pub async fn list<B, E>(
+        &self,
+        backend: &B,
+        nix_args: &NixArgs,
+    ) -> Result<Vec<RegistryEntry>, RegistryListError<E>>
+    where
+        B: NixBackend + Sync,
+        RegistryList: RunStreaming<B, StreamingError = E>,
+    {
+        let mut stdout = Vec::new();
+        self.run_streaming(backend, nix_args, &mut |line| {
+            if let OutputLine::Stdout(line) = line {
+                stdout.push(line);
+            }
+        })
+        .await
+        .map_err(RegistryListError::Run)?;
+
+        stdout
+            .iter()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| RegistryEntry::from_list_line(line))
+            .collect::<Result<_, _>>()
+            .map_err(RegistryListError::Parse)
+    }
+}
+
+/// `nix config show` Command
+///
+/// Invoked as `nix show-config` on versions of Nix prior to its rename in 2.20,
+/// if [crate::command_line::NixCommandLine::version] is set.
+/// See [crate::command_line::NixCommandLine::nix_config] to probe the configuration
+/// without the defaults of the backend.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ConfigShow {}
+
+impl NixCliCommand for ConfigShow {
+    type Own = ();
+
+    const SUBCOMMAND: &'static [&'static str] = &["config", "show"];
+
+    fn subcommand(dialect: Option<&Dialect>) -> &'static [&'static str] {
+        match dialect {
+            Some(dialect) if dialect.nix_version() < NixVersion::new(2, 20, 0) => &["show-config"],
+            _ => Self::SUBCOMMAND,
+        }
+    }
+}
+impl JsonCommand for ConfigShow {}
+impl TypedCommand for ConfigShow {
+    type Output = NixConfigValues;
+}
+
+/// The output of [StoreInfo]
+pub type StoreInfoOut = store_info::StoreInfo;
@@ -0,0 +1,221 @@
+//! Configuring the defaults of a [NixCommandLine], see [NixCommandLineBuilder]
+
+use super::{DefaultArgs, NixCommandLine};
+use crate::arguments::common::NixCommonArgs;
+use crate::arguments::config::NixConfigArgs;
+use crate::arguments::eval::EvaluationArgs;
+use crate::arguments::flake::FlakeArgs;
+
+/// Builds a [NixCommandLine] applying a policy to every command
+///
+/// Defaults are issued before the arguments of each command,
+/// so that arguments set per command take precedence where nix allows to override them,
+/// i.e. for options taking a value.
+/// Flags enabled by default (e.g. `--impure`) can not be disabled per command.
+///
+/// ```
+/// # use runix::arguments::NixArgs;
+/// # use runix::command::Build;
+/// # use runix::command_line::{NixCliCommand, NixCommandLine};
+/// let backend = NixCommandLine::builder()
+///     .experimental_features(["nix-command", "flakes"])
+///     .accept_flake_config(true)
+///     .substituters(["https://cache.flox.dev"])
+///     .build();
+///
+/// let command_line = Build::default().to_command_line(&backend, &NixArgs::default());
+/// let shell = command_line.to_string();
+/// assert!(shell.contains(" --accept-flake-config "));
+/// assert!(shell.contains(" --extra-experimental-features 'nix-command flakes' "));
+/// assert!(shell.contains(" --extra-substituters 'https://cache.flox.dev' "));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct NixCommandLineBuilder {
+    backend: NixCommandLine,
+}
+
+impl NixCommandLine {
+    pub fn builder() -> NixCommandLineBuilder {
+        NixCommandLineBuilder::default()
+    }
+}
+
+impl NixCommandLineBuilder {
+    /// The nix binary to run, `nix` from the `PATH` by default
+    pub fn nix_bin(mut self, nix_bin: impl Into<String>) -> Self {
+        self.backend.nix_bin = Some(nix_bin.into());
+        self
+    }
+
+    /// Replace all defaults
+    pub fn defaults(mut self, defaults: DefaultArgs) -> Self {
+        self.backend.defaults = defaults;
+        self
+    }
+
+    /// Set an environment variable for every command
+    pub fn env(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.backend
+            .defaults
+            .environment
+            .insert(name.into(), value.into());
+        self
+    }
+
+    pub fn common_args(mut self, common_args: NixCommonArgs) -> Self {
+        self.backend.defaults.common_args = common_args;
+        self
+    }
+
+    pub fn config_args(mut self, config_args: NixConfigArgs) -> Self {
+        self.backend.defaults.config_args = config_args;
+        self
+    }
+
+    /// Flake arguments for commands that accept them
+    pub fn flake_args(mut self, flake_args: FlakeArgs) -> Self {
+        self.backend.defaults.flake_args = flake_args;
+        self
+    }
+
+    /// Evaluation arguments for commands that accept them
+    pub fn eval_args(mut self, eval_args: EvaluationArgs) -> Self {
+        self.backend.defaults.eval_args = eval_args;
+        self
+    }
+
+    /// Arguments appended to every command
+    pub fn extra_args<I>(mut self, args: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.backend
+            .defaults
+            .extra_args
+            .extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Enable experimental features in addition to those enabled already
+    pub fn experimental_features<I>(mut self, features: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        let config = &mut self.backend.defaults.config_args;
+        config.extra_experimental_features =
+            extend(config.extra_experimental_features.to_vec(), features).into();
+        self
+    }
+
+    /// Whether to accept the `nixConfig` of flakes without prompting
+    pub fn accept_flake_config(mut self, accept: bool) -> Self {
+        self.backend.defaults.config_args.accept_flake_config = accept.into();
+        self
+    }
+
+    /// Use substituters in addition to those configured already
+    pub fn substituters<I>(mut self, substituters: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        let config = &mut self.backend.defaults.config_args;
+        config.extra_substituters = extend(config.extra_substituters.to_vec(), substituters).into();
+        self
+    }
+
+    /// Trust keys in addition to those configured already, e.g. to use [Self::substituters]
+    pub fn trusted_public_keys<I>(mut self, keys: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        let config = &mut self.backend.defaults.config_args;
+        config.extra_trusted_public_keys =
+            extend(config.extra_trusted_public_keys.to_vec(), keys).into();
+        self
+    }
+
+    pub fn build(self) -> NixCommandLine {
+        self.backend
+    }
+}
+
+/// Append `items` to `values`, skipping duplicates
+fn extend<I>(mut values: Vec<String>, items: I) -> Vec<String>
+where
+    I: IntoIterator,
+    I::Item: Into<String>,
+{
+    for item in items.into_iter().map(Into::into) {
+        if !values.contains(&item) {
+            values.push(item);
+        }
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arguments::eval::EvaluationArgs;
+    use crate::arguments::NixArgs;
+    use crate::command::Eval;
+    use crate::command_line::NixCliCommand;
+
+    #[test]
+    fn layering() {
+        let backend = NixCommandLine::builder()
+            .config_args(NixConfigArgs {
+                connect_timeout: 5.into(),
+                ..Default::default()
+            })
+            .experimental_features(["flakes"])
+            .experimental_features(["nix-command", "flakes"])
+            .accept_flake_config(true)
+            .eval_args(EvaluationArgs {
+                eval_store: Some("auto".to_string().into()),
+                ..Default::default()
+            })
+            .env("NIX_CONFIG", "warn-dirty = false")
+            .build();
+
+        let config = &backend.defaults.config_args;
+        assert_eq!(*config.extra_experimental_features, [
+            "flakes",
+            "nix-command"
+        ]);
+        assert_eq!(*config.connect_timeout, 5);
+        assert!(*config.accept_flake_config);
+
+        let nix_args = NixArgs {
+            config: NixConfigArgs {
+                connect_timeout: 10.into(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let command = Eval {
+            eval: EvaluationArgs {
+                eval_store: Some("daemon".to_string().into()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let command_line = command.to_command_line(&backend, &nix_args);
+        let position = |args: [&str; 2]| {
+            command_line
+                .args
+                .windows(2)
+                .position(|window| window == args)
+                .unwrap()
+        };
+
+        // the last occurence takes effect
+        assert!(position(["--connect-timeout", "5"]) < position(["--connect-timeout", "10"]));
+        assert!(position(["--eval-store", "auto"]) < position(["--eval-store", "daemon"]));
+        assert_eq!(command_line.env["NIX_CONFIG"], "warn-dirty = false");
+    }
+}
@@ -0,0 +1,157 @@
+//! Running privileged commands as root
+//!
+//! On multi-user installations, some operations such as `nix store gc`
+//! or `nix store optimise` require root.
+//! See [super::NixCommandLine::escalation] and [super::NixCliCommand::PRIVILEGED].
+
+use std::io::IsTerminal;
+
+use super::CommandLine;
+
+/// A program running commands as root, such as `sudo` or `doas`
+///
+/// ```
+/// # use runix::arguments::NixArgs;
+/// # use runix::command::StoreGc;
+/// # use runix::command_line::escalation::Escalation;
+/// # use runix::command_line::{NixCliCommand, NixCommandLine};
+/// let backend = NixCommandLine {
+///     escalation: Some(Escalation::doas()),
+///     ..Default::default()
+/// };
+///
+/// let command_line = StoreGc::default().to_command_line(&backend, &NixArgs::default());
+/// if Escalation::is_required() {
+///     assert_eq!(command_line.program, "doas");
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Escalation {
+    /// The program prefixed to the nix invocation
+    pub program: String,
+    /// Arguments passed to [Escalation::program] before the nix invocation
+    pub args: Vec<String>,
+    /// Argument making [Escalation::program] fail rather than prompt for a password,
+    /// added if stdin is not a terminal
+    pub non_interactive_arg: Option<String>,
+}
+
+impl Escalation {
+    pub fn sudo() -> Self {
+        Escalation {
+            program: "sudo".to_string(),
+            args: vec![],
+            non_interactive_arg: Some("-n".to_string()),
+        }
+    }
+
+    pub fn doas() -> Self {
+        Escalation {
+            program: "doas".to_string(),
+            args: vec![],
+            non_interactive_arg: Some("-n".to_string()),
+        }
+    }
+
+    /// Whether escalation is required, i.e. the current user is not root
+    pub fn is_required() -> bool {
+        // SAFETY: geteuid has no preconditions
+        unsafe { libc::geteuid() != 0 }
+    }
+
+    /// Run `command_line` through [Escalation::program]
+    ///
+    /// As `sudo` and `doas` reset the environment,
+    /// changes to it are applied using `env(1)` after escalation.
+    /// The values of variables are thereby visible in the process table,
+    /// they are redacted when the invocation is logged or recorded.
+    pub fn wrap(&self, command_line: CommandLine) -> CommandLine {
+        self.wrap_with(command_line, std::io::stdin().is_terminal())
+    }
+
+    fn wrap_with(&self, command_line: CommandLine, interactive: bool) -> CommandLine {
+        let mut args = self.args.clone();
+        if !interactive {
+            args.extend(self.non_interactive_arg.clone());
+        }
+
+        if command_line.clear_env
+            || !command_line.env_remove.is_empty()
+            || !command_line.env.is_empty()
+        {
+            args.push("env".to_string());
+            if command_line.clear_env {
+                args.push("-i".to_string());
+            }
+            for name in command_line.env_remove {
+                args.push("-u".to_string());
+                args.push(name);
+            }
+            for (name, value) in command_line.env {
+                args.push(format!("{name}={value}"));
+            }
+        }
+
+        args.push(command_line.program);
+        args.extend(command_line.args);
+
+        CommandLine {
+            program: self.program.clone(),
+            args,
+            env: Default::default(),
+            env_remove: vec![],
+            clear_env: false,
+            current_dir: command_line.current_dir,
+        }
+    }
+}
+
+impl Default for Escalation {
+    fn default() -> Self {
+        Self::sudo()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrap() {
+        let command_line = CommandLine {
+            program: "nix".to_string(),
+            args: vec!["store".to_string(), "gc".to_string()],
+            env: [("NIX_CONFIG".to_string(), "cores = 1".to_string())].into(),
+            env_remove: vec!["NIX_PATH".to_string()],
+            clear_env: false,
+            current_dir: Some("/tmp".into()),
+        };
+
+        let wrapped = Escalation::sudo().wrap_with(command_line.clone(), false);
+        assert_eq!(wrapped.program, "sudo");
+        assert_eq!(wrapped.args, [
+            "-n",
+            "env",
+            "-u",
+            "NIX_PATH",
+            "NIX_CONFIG=cores = 1",
+            "nix",
+            "store",
+            "gc"
+        ]);
+        assert!(wrapped.env.is_empty() && wrapped.env_remove.is_empty());
+        assert_eq!(wrapped.current_dir, command_line.current_dir);
+        assert_eq!(
+            wrapped.to_string(),
+            "sudo -n env -u NIX_PATH 'NIX_CONFIG=***' nix store gc"
+        );
+
+        let command_line = CommandLine {
+            env: Default::default(),
+            env_remove: vec![],
+            ..command_line
+        };
+        let wrapped = Escalation::doas().wrap_with(command_line, true);
+        assert_eq!(wrapped.to_string(), "doas nix store gc");
+    }
+}
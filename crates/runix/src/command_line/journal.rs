@@ -0,0 +1,229 @@
+//! Recording invocations of nix and replaying them, see [Journal]
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{redact_arg, CommandLine};
+use crate::flake_ref::protocol::REDACTED;
+
+#[derive(Debug, Error)]
+pub enum JournalError {
+    #[error("Could not access journal {0:?}: {1}")]
+    Io(PathBuf, io::Error),
+    #[error("Invalid journal entry in {0:?}: {1}")]
+    Json(PathBuf, serde_json::Error),
+    #[error("No recorded invocation of '{0}' left to replay")]
+    NotRecorded(String),
+    #[error("Output of '{0}' was not recorded, it cannot be replayed")]
+    NoStdout(String),
+}
+
+/// An invocation of nix and its result
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub program: String,
+    /// The arguments, with credentials redacted
+    pub args: Vec<String>,
+    /// The variables set for nix, with their values redacted
+    pub env: BTreeMap<String, String>,
+    pub current_dir: Option<PathBuf>,
+    /// The output of nix, if captured by the mode it ran in
+    pub stdout: Option<String>,
+    pub stderr: String,
+    /// The exit code, [None] if nix was killed by a signal
+    pub status: Option<i32>,
+    pub duration: Duration,
+}
+
+impl JournalEntry {
+    fn new(command_line: &CommandLine, recorded: Recorded, duration: Duration) -> Self {
+        JournalEntry {
+            program: command_line.program.clone(),
+            args: redacted_args(command_line),
+            env: redacted_env(command_line),
+            current_dir: command_line.current_dir.clone(),
+            stdout: recorded.stdout,
+            stderr: recorded.stderr,
+            status: recorded.status.code(),
+            duration,
+        }
+    }
+
+    fn matches(&self, command_line: &CommandLine) -> bool {
+        self.program == command_line.program
+            && self.args == redacted_args(command_line)
+            && self.env == redacted_env(command_line)
+            && self.current_dir == command_line.current_dir
+    }
+
+    /// The recorded output of nix, an error if the mode it ran in did not capture it
+    pub fn replay_stdout(&self) -> Result<&str, JournalError> {
+        self.stdout.as_deref().ok_or_else(|| {
+            JournalError::NoStdout(
+                [self.program.as_str()]
+                    .into_iter()
+                    .chain(self.args.iter().map(String::as_str))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            )
+        })
+    }
+
+    /// The recorded exit status, nix killed by a signal is replayed as `SIGKILL`
+    pub fn exit_status(&self) -> ExitStatus {
+        match self.status {
+            Some(code) => ExitStatus::from_raw(code << 8),
+            None => ExitStatus::from_raw(libc::SIGKILL),
+        }
+    }
+}
+
+fn redacted_args(command_line: &CommandLine) -> Vec<String> {
+    command_line
+        .args
+        .iter()
+        .map(|arg| redact_arg(arg).into_owned())
+        .collect()
+}
+
+/// The variables set by `command_line`, which may hold credentials, e.g. `NIX_CONFIG`
+fn redacted_env(command_line: &CommandLine) -> BTreeMap<String, String> {
+    command_line
+        .env
+        .keys()
+        .map(|name| (name.clone(), REDACTED.to_string()))
+        .collect()
+}
+
+/// The result of an invocation to record
+#[derive(Debug)]
+pub(super) struct Recorded {
+    pub status: ExitStatus,
+    pub stdout: Option<String>,
+    pub stderr: String,
+}
+
+#[derive(Debug)]
+enum State {
+    Record(File),
+    Replay {
+        entries: Vec<JournalEntry>,
+        replayed: Vec<bool>,
+    },
+}
+
+/// A file of nix invocations, one json [JournalEntry] per line
+///
+/// When recording, every invocation run by the backend is appended to the journal.
+/// When replaying, nix is not run,
+/// instead the result of the first matching invocation not replayed yet is reproduced.
+/// Useful to debug reports of users and to write deterministic tests.
+///
+/// See [super::NixCommandLine::journal].
+#[derive(Debug, Clone)]
+pub struct Journal {
+    path: PathBuf,
+    state: Arc<Mutex<State>>,
+}
+
+impl Journal {
+    /// Record invocations to `path`, replacing its contents
+    pub fn record(path: impl AsRef<Path>) -> Result<Self, JournalError> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&path)
+            .map_err(|e| JournalError::Io(path.clone(), e))?;
+
+        Ok(Journal {
+            path,
+            state: Arc::new(Mutex::new(State::Record(file))),
+        })
+    }
+
+    /// Replay the invocations recorded in `path`
+    pub fn replay(path: impl AsRef<Path>) -> Result<Self, JournalError> {
+        let path = path.as_ref().to_path_buf();
+        let file = File::open(&path).map_err(|e| JournalError::Io(path.clone(), e))?;
+
+        let mut entries = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|e| JournalError::Io(path.clone(), e))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            entries.push(
+                serde_json::from_str(&line).map_err(|e| JournalError::Json(path.clone(), e))?,
+            );
+        }
+
+        Ok(Journal {
+            path,
+            state: Arc::new(Mutex::new(State::Replay {
+                replayed: vec![false; entries.len()],
+                entries,
+            })),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The recorded result of `command_line`, or [None] if recording
+    pub(super) fn replay_entry(
+        &self,
+        command_line: &CommandLine,
+    ) -> Option<Result<JournalEntry, JournalError>> {
+        let mut state = self.state.lock().unwrap();
+        let State::Replay {
+            ref entries,
+            ref mut replayed,
+        } = *state
+        else {
+            return None;
+        };
+
+        let entry = entries
+            .iter()
+            .zip(replayed.iter_mut())
+            .find(|(entry, replayed)| !**replayed && entry.matches(command_line))
+            .map(|(entry, replayed)| {
+                *replayed = true;
+                entry.clone()
+            })
+            .ok_or_else(|| JournalError::NotRecorded(command_line.to_string()));
+        Some(entry)
+    }
+
+    /// Append the result of `command_line` to the journal, if recording
+    pub(super) fn append(
+        &self,
+        command_line: &CommandLine,
+        recorded: Recorded,
+        duration: Duration,
+    ) -> Result<(), JournalError> {
+        let mut state = self.state.lock().unwrap();
+        let State::Record(ref mut file) = *state else {
+            return Ok(());
+        };
+
+        let entry = JournalEntry::new(command_line, recorded, duration);
+        let mut line =
+            serde_json::to_string(&entry).map_err(|e| JournalError::Json(self.path.clone(), e))?;
+        line.push('\n');
+        file.write_all(line.as_bytes())
+            .map_err(|e| JournalError::Io(self.path.clone(), e))
+    }
+}
@@ -0,0 +1,298 @@
+//! Incremental parsing of large json outputs, see [crate::RunJsonStream]
+//!
+//! Commands such as `nix path-info --json --recursive` print a single list or object
+//! that may grow to hundreds of megabytes for large closures.
+//! [JsonItems] splits such documents into their top level items while reading them,
+//! so that only a single item is buffered at a time.
+
+use std::io;
+
+use serde_json::{Map, Value};
+use thiserror::Error;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+
+#[derive(Debug, Error)]
+pub enum ParseJsonStreamError {
+    #[error("Could not read json: {0}")]
+    Io(#[from] io::Error),
+    #[error("Invalid json item: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Unexpected end of json document")]
+    UnexpectedEof,
+    #[error("Unexpected '{0}' in json document")]
+    UnexpectedCharacter(char),
+}
+
+/// An item of a json document
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonItem {
+    /// The key of an entry of a top level object,
+    /// [None] for elements of a top level list or documents that are neither
+    pub key: Option<String>,
+    pub value: Value,
+}
+
+/// The top level items of a json document, parsed as they are read
+///
+/// Yields every element of a list, every entry of an object,
+/// or any other document as a single item.
+///
+/// ```
+/// # use runix::command_line::json_stream::{JsonItem, JsonItems};
+/// # use serde_json::json;
+/// # #[tokio::main]
+/// # async fn main() {
+/// let mut items = JsonItems::new(&br#"{ "a": [1, "]"], "b": {} }"#[..]);
+/// assert_eq!(
+///     items.next_item().await.unwrap(),
+///     Some(JsonItem {
+///         key: Some("a".to_string()),
+///         value: json!([1, "]"]),
+///     })
+/// );
+/// assert_eq!(items.next_item().await.unwrap().unwrap().value, json!({}));
+/// assert_eq!(items.next_item().await.unwrap(), None);
+/// # }
+/// ```
+pub struct JsonItems<R> {
+    reader: R,
+    scanner: Scanner,
+}
+
+impl<R: AsyncBufRead + Unpin> JsonItems<R> {
+    pub fn new(reader: R) -> Self {
+        JsonItems {
+            reader,
+            scanner: Scanner::default(),
+        }
+    }
+
+    /// The next item, or [None] after the end of the document
+    pub async fn next_item(&mut self) -> Result<Option<JsonItem>, ParseJsonStreamError> {
+        loop {
+            let chunk = self.reader.fill_buf().await?;
+            if chunk.is_empty() {
+                return self.scanner.finish();
+            }
+
+            let mut consumed = 0;
+            let mut boundary = None;
+            for &byte in chunk {
+                consumed += 1;
+                boundary = self.scanner.scan(byte)?;
+                if boundary.is_some() {
+                    break;
+                }
+            }
+            self.reader.consume(consumed);
+
+            match boundary {
+                Some(Boundary::Separator) => return self.scanner.take_item().map(Some),
+                Some(Boundary::End) if !self.scanner.is_empty() => {
+                    return self.scanner.take_item().map(Some)
+                },
+                Some(Boundary::End) | None => {},
+            }
+        }
+    }
+}
+
+/// The kind of document being scanned
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Document {
+    /// Nothing but whitespace was read so far
+    #[default]
+    Unknown,
+    /// A list or object, see [Scanner::object]
+    Container,
+    /// Any other value, parsed as a whole
+    Scalar,
+    /// The closing bracket of the list or object was read
+    Done,
+}
+
+/// The end of an item
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Boundary {
+    /// A comma separating two items
+    Separator,
+    /// The end of the document
+    End,
+}
+
+/// Collects the bytes of the current item, tracking nesting and strings
+#[derive(Debug, Default)]
+struct Scanner {
+    document: Document,
+    /// Whether the container is an object rather than a list
+    object: bool,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+    buf: Vec<u8>,
+}
+
+impl Scanner {
+    fn scan(&mut self, byte: u8) -> Result<Option<Boundary>, ParseJsonStreamError> {
+        match self.document {
+            Document::Unknown => match byte {
+                b'[' | b'{' => {
+                    self.document = Document::Container;
+                    self.object = byte == b'{';
+                },
+                _ if byte.is_ascii_whitespace() => {},
+                _ => {
+                    self.document = Document::Scalar;
+                    self.buf.push(byte);
+                },
+            },
+            Document::Scalar => self.buf.push(byte),
+            Document::Done if byte.is_ascii_whitespace() => {},
+            Document::Done => return Err(ParseJsonStreamError::UnexpectedCharacter(byte as char)),
+            Document::Container if self.in_string => {
+                self.buf.push(byte);
+                match byte {
+                    _ if self.escaped => self.escaped = false,
+                    b'\\' => self.escaped = true,
+                    b'"' => self.in_string = false,
+                    _ => {},
+                }
+            },
+            Document::Container => match byte {
+                b',' if self.depth == 0 => return Ok(Some(Boundary::Separator)),
+                b']' | b'}' if self.depth == 0 => {
+                    let expected = if self.object { b'}' } else { b']' };
+                    if byte != expected {
+                        return Err(ParseJsonStreamError::UnexpectedCharacter(byte as char));
+                    }
+                    self.document = Document::Done;
+                    return Ok(Some(Boundary::End));
+                },
+                _ => {
+                    match byte {
+                        b'"' => self.in_string = true,
+                        b'[' | b'{' => self.depth += 1,
+                        b']' | b'}' => self.depth -= 1,
+                        _ => {},
+                    }
+                    self.buf.push(byte);
+                },
+            },
+        }
+        Ok(None)
+    }
+
+    /// Whether the current item consists of whitespace only
+    fn is_empty(&self) -> bool {
+        self.buf.iter().all(u8::is_ascii_whitespace)
+    }
+
+    /// Parse and reset the current item
+    fn take_item(&mut self) -> Result<JsonItem, ParseJsonStreamError> {
+        let buf = std::mem::take(&mut self.buf);
+        if buf.iter().all(u8::is_ascii_whitespace) {
+            return Err(ParseJsonStreamError::UnexpectedCharacter(','));
+        }
+
+        if !self.object {
+            return Ok(JsonItem {
+                key: None,
+                value: serde_json::from_slice(&buf)?,
+            });
+        }
+
+        // parse the entry `"key": value` as an object of its own
+        let mut entry = Vec::with_capacity(buf.len() + 2);
+        entry.push(b'{');
+        entry.extend(buf);
+        entry.push(b'}');
+        let entry: Map<String, Value> = serde_json::from_slice(&entry)?;
+        let (key, value) = entry
+            .into_iter()
+            .next()
+            .ok_or(ParseJsonStreamError::UnexpectedEof)?;
+        Ok(JsonItem {
+            key: Some(key),
+            value,
+        })
+    }
+
+    /// Handle the end of input
+    fn finish(&mut self) -> Result<Option<JsonItem>, ParseJsonStreamError> {
+        match self.document {
+            Document::Done => Ok(None),
+            Document::Scalar => {
+                self.document = Document::Done;
+                let value = serde_json::from_slice(&std::mem::take(&mut self.buf))?;
+                Ok(Some(JsonItem { key: None, value }))
+            },
+            Document::Unknown | Document::Container => Err(ParseJsonStreamError::UnexpectedEof),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tokio::io::BufReader;
+
+    use super::*;
+
+    async fn items(json: &str) -> Result<Vec<JsonItem>, ParseJsonStreamError> {
+        // read byte by byte to split tokens across reads
+        let mut items = JsonItems::new(BufReader::with_capacity(1, json.as_bytes()));
+        let mut collected = Vec::new();
+        while let Some(item) = items.next_item().await? {
+            collected.push(item);
+        }
+        Ok(collected)
+    }
+
+    fn element(value: Value) -> JsonItem {
+        JsonItem { key: None, value }
+    }
+
+    #[tokio::test]
+    async fn split_items() {
+        let list = r#" [ {"a": "x,]\"}"}, [1, [2]], "[" , null ]
+        "#;
+        assert_eq!(items(list).await.unwrap(), [
+            element(json!({ "a": "x,]\"}" })),
+            element(json!([1, [2]])),
+            element(json!("[")),
+            element(Value::Null),
+        ]);
+
+        let object = r#"{"/nix/store/a": {"narSize": 1}, "/nix/store/b": null}"#;
+        assert_eq!(items(object).await.unwrap(), [
+            JsonItem {
+                key: Some("/nix/store/a".to_string()),
+                value: json!({ "narSize": 1 }),
+            },
+            JsonItem {
+                key: Some("/nix/store/b".to_string()),
+                value: Value::Null,
+            },
+        ]);
+
+        assert_eq!(items("[]").await.unwrap(), []);
+        assert_eq!(items(" 42\n").await.unwrap(), [element(json!(42))]);
+
+        assert!(matches!(
+            items("[1, 2").await,
+            Err(ParseJsonStreamError::UnexpectedEof)
+        ));
+        assert!(matches!(
+            items("[1} ").await,
+            Err(ParseJsonStreamError::UnexpectedCharacter('}'))
+        ));
+        assert!(matches!(
+            items("[1] 2").await,
+            Err(ParseJsonStreamError::UnexpectedCharacter('2'))
+        ));
+        assert!(matches!(
+            items("[1, x]").await,
+            Err(ParseJsonStreamError::Json(_))
+        ));
+    }
+}
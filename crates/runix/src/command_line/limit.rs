@@ -0,0 +1,130 @@
+//! Limiting the number of concurrently running nix processes
+//!
+//! See [super::NixCommandLine::concurrency].
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// A limit of nix processes running at the same time
+///
+/// Clones share the same limit, e.g. all clones of a [super::NixCommandLine].
+/// Invocations exceeding the limit wait in a queue and are started
+/// in the order they were queued.
+///
+/// ```
+/// # use runix::command_line::limit::ConcurrencyLimit;
+/// # use runix::command_line::NixCommandLine;
+/// let limit = ConcurrencyLimit::new(4);
+/// let backend = NixCommandLine {
+///     concurrency: Some(limit.clone()),
+///     ..Default::default()
+/// };
+/// assert_eq!(limit.queued(), 0);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ConcurrencyLimit {
+    limit: usize,
+    semaphore: Arc<Semaphore>,
+    queued: Arc<AtomicUsize>,
+}
+
+impl ConcurrencyLimit {
+    /// Allow at most `limit` processes to run at the same time
+    ///
+    /// # Panics
+    ///
+    /// If `limit` is `0`
+    pub fn new(limit: usize) -> Self {
+        assert!(limit > 0, "concurrency limit must be at least 1");
+        ConcurrencyLimit {
+            limit,
+            semaphore: Arc::new(Semaphore::new(limit)),
+            queued: Default::default(),
+        }
+    }
+
+    /// The maximum number of concurrently running processes
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// The number of processes currently running
+    pub fn running(&self) -> usize {
+        self.limit - self.semaphore.available_permits()
+    }
+
+    /// The number of invocations waiting to be started
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+
+    /// Wait until a process may be started
+    ///
+    /// The process counts as running until the [ConcurrencyPermit] is dropped.
+    pub async fn acquire(&self) -> ConcurrencyPermit {
+        let _queued = QueuedGuard::new(&self.queued);
+        let permit = self
+            .semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("semaphore is never closed");
+        ConcurrencyPermit { _permit: permit }
+    }
+}
+
+/// Permission to run a process, see [ConcurrencyLimit::acquire]
+#[derive(Debug)]
+pub struct ConcurrencyPermit {
+    _permit: OwnedSemaphorePermit,
+}
+
+/// Counts an invocation as queued while alive,
+/// including when waiting is aborted
+struct QueuedGuard<'a>(&'a AtomicUsize);
+
+impl<'a> QueuedGuard<'a> {
+    fn new(queued: &'a AtomicUsize) -> Self {
+        queued.fetch_add(1, Ordering::SeqCst);
+        QueuedGuard(queued)
+    }
+}
+
+impl Drop for QueuedGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn queue() {
+        let limit = ConcurrencyLimit::new(1);
+        let first = limit.acquire().await;
+        assert_eq!(limit.running(), 1);
+
+        let (started, mut started_rx) = tokio::sync::mpsc::unbounded_channel();
+        let waiting = tokio::spawn({
+            let limit = limit.clone();
+            async move {
+                let _permit = limit.acquire().await;
+                started.send(()).unwrap();
+            }
+        });
+        while limit.queued() == 0 {
+            tokio::task::yield_now().await;
+        }
+        assert!(started_rx.try_recv().is_err());
+
+        drop(first);
+        waiting.await.unwrap();
+        started_rx.recv().await.unwrap();
+        assert_eq!(limit.queued(), 0);
+        assert_eq!(limit.running(), 0);
+    }
+}
@@ -4,26 +4,67 @@
 //! Note also the blanket implementation of the [Run] traits below.
 
 use core::fmt;
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
 use std::ffi::OsStr;
+use std::future::Future;
+use std::path::PathBuf;
 use std::process::{ExitStatus, Output, Stdio};
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
-use log::{debug, log};
+use log::{debug, info, log, warn};
 use serde::Deserialize;
 use serde_json::Value;
 use thiserror::Error;
-use tokio::process::Command;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::process::{Child, ChildStderr, Command};
 
-use crate::arguments::common::NixCommonArgs;
+use self::escalation::Escalation;
+use self::journal::{Journal, JournalEntry, JournalError, Recorded};
+use self::json_stream::{JsonItem, JsonItems, ParseJsonStreamError};
+use self::limit::{ConcurrencyLimit, ConcurrencyPermit};
+use self::remote::{RemoteStore, NIX_SSHOPTS};
+use self::retry::RetryPolicy;
+use self::spill::{Spill, SpilledOutput};
+use self::version::{Dialect, NixVersion, UnsupportedFeature, VersionCache};
+use crate::arguments::common::{LogFormat, LoggingArgs, NixCommonArgs};
 use crate::arguments::config::NixConfigArgs;
 use crate::arguments::eval::EvaluationArgs;
 use crate::arguments::flake::FlakeArgs;
 use crate::arguments::source::SourceArgs;
-use crate::arguments::{InstallableArg, InstallablesArgs, NixArgs};
-use crate::{NixBackend, Run, RunJson, RunTyped};
+use crate::arguments::{InstallableArg, InstallablesArgs, NixArgs, Stdin};
+use crate::build_report::{BuildReport, BuildReporter};
+use crate::command::{Build, BuildOut, ConfigShow};
+use crate::flake_ref::protocol::{redact_credentials, REDACTED};
+use crate::log_event::{LogEvent, ParseLogEventError, Verbosity};
+use crate::nix_config::NixConfigValues;
+use crate::nix_error::NixExitError;
+use crate::{
+    NixBackend,
+    OutputLine,
+    Run,
+    RunInteractive,
+    RunJson,
+    RunJsonStream,
+    RunLogged,
+    RunOutput,
+    RunStreaming,
+    RunTyped,
+    Terminal,
+};
 
+pub mod builder;
+pub mod escalation;
 pub mod flag;
+pub mod journal;
+pub mod json_stream;
+pub mod limit;
+mod pty;
+pub mod remote;
+pub mod retry;
+pub mod spill;
+pub mod version;
 
 /// Defaults for all option groups
 ///
@@ -44,6 +85,22 @@ pub struct DefaultArgs {
 pub enum NixCommandLineError {
     #[error("Error running Nix: {0}")]
     Run(std::io::Error),
+    #[error("Nix was cancelled")]
+    Cancelled,
+    #[error("Nix timed out after {0:?}")]
+    TimedOut(Duration),
+    #[error("Stdin reader was already consumed by a previous invocation")]
+    StdinConsumed,
+    #[error("Could not detect the nix version: {0}")]
+    Version(String),
+    #[error("Could not read the nix config: {0}")]
+    Config(String),
+    #[error(transparent)]
+    Unsupported(#[from] UnsupportedFeature),
+    #[error(transparent)]
+    Journal(#[from] JournalError),
+    #[error("Could not write output to a temporary file: {0}")]
+    Spill(std::io::Error),
     /// unsused
     #[deprecated]
     #[error("Nix printed {0} bytes to stderr")]
@@ -59,6 +116,46 @@ pub enum NixCommandLineError {
 pub struct NixCommandLine {
     pub nix_bin: Option<String>,
     pub defaults: DefaultArgs,
+    /// Retry invocations failing with transient errors, see [RetryPolicy]
+    pub retry: Option<RetryPolicy>,
+    /// Limit the number of nix processes running at the same time
+    pub concurrency: Option<ConcurrencyLimit>,
+    /// Run all commands against the store of a remote machine
+    ///
+    /// A store set explicitly via [NixCommonArgs::store] takes precedence.
+    pub remote_store: Option<RemoteStore>,
+    /// Detect the implementation and version of nix to adapt commands to it
+    ///
+    /// If [None], commands are invoked as supported by the latest version of Nix.
+    /// Required to use implementations other than Nix, e.g. Lix via [NixCommandLine::nix_bin].
+    pub version: Option<VersionCache>,
+    /// Run [NixCliCommand::PRIVILEGED] commands as root, unless already running as root
+    pub escalation: Option<Escalation>,
+    /// Record all invocations to a [Journal], or replay them from it instead of running nix
+    pub journal: Option<Journal>,
+    /// Run invocations failing with an error of nix once more with these logging arguments
+    ///
+    /// Typically [LoggingArgs::trace], so that the error reported includes the trace.
+    /// Like [NixCommandLine::retry], only applies to [Run] and [RunJson] (and derived traits).
+    pub trace_failures: Option<LoggingArgs>,
+}
+
+/// Replace credentials in `arg`, see [redact_credentials],
+/// and the value of an environment variable assigned by `arg`, e.g. `NIX_CONFIG=...`
+///
+/// Variables are assigned by arguments of `env(1)`, see [Escalation::wrap].
+pub(crate) fn redact_arg(arg: &str) -> Cow<'_, str> {
+    if let Some((name, _)) = arg.split_once('=') {
+        let is_variable = name
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if is_variable {
+            return Cow::Owned(format!("{name}={REDACTED}"));
+        }
+    }
+    redact_credentials(arg)
 }
 
 /// An extensioon trait for [std::process::Command]
@@ -76,7 +173,7 @@ impl CommandExt for std::process::Command {
             env = self.get_envs().collect::<HashMap<_, _>>(),
             args = self
                 .get_args()
-                .map(|arg| arg.to_string_lossy().to_string())
+                .map(|arg| redact_arg(&arg.to_string_lossy()).into_owned())
                 .collect::<Vec<_>>(),
         );
 
@@ -102,7 +199,13 @@ impl CommandExt for std::process::Command {
                 executable = shell_escape::escape(self.get_program().to_string_lossy()),
                 command_string = self
                     .get_args()
-                    .map(|arg| shell_escape::escape(arg.to_string_lossy()))
+                    .map(|arg| {
+                        shell_escape::escape(
+                            redact_arg(&arg.to_string_lossy())
+                                .into_owned()
+                                .into(),
+                        )
+                    })
                     .collect::<Vec<_>>()
                     .join(" ")
             );
@@ -118,8 +221,144 @@ impl CommandExt for std::process::Command {
 #[async_trait]
 trait CommandMode {
     type Output;
-    type Error;
-    async fn run(command: &mut Command) -> Result<Self::Output, Self::Error>;
+    type Error: From<NixCommandLineError>;
+    async fn run(command: &mut Command, nix_args: &NixArgs) -> Result<Self::Output, Self::Error>;
+
+    /// The failure reported by nix, if `error` was caused by nix exiting unsuccessfully
+    fn exit_error(error: &Self::Error) -> Option<&NixExitError>;
+
+    /// The result of an invocation to record in [NixCommandLine::journal]
+    ///
+    /// [None] if nix did not run to completion.
+    fn recorded(result: &Result<Self::Output, Self::Error>) -> Option<Recorded>;
+
+    /// Reproduce the result of an invocation recorded in [NixCommandLine::journal]
+    fn replay(entry: JournalEntry) -> Result<Self::Output, Self::Error>;
+}
+
+/// Time given to nix to exit after being interrupted, before it is killed
+const TERMINATE_GRACE_PERIOD: Duration = Duration::from_secs(2);
+
+/// Spawn `command`, in its own process group if it may be interrupted
+///
+/// Killing the process group also stops processes spawned by nix, e.g. builders.
+/// As processes outside of the foreground process group can not read from the terminal,
+/// commands that may not be interrupted remain in the process group of the host process.
+///
+/// If [NixArgs::stdin] is set, it is written to the stdin of nix in the background.
+fn spawn(command: &mut Command, nix_args: &NixArgs) -> Result<Child, NixCommandLineError> {
+    let input = match nix_args.stdin {
+        Some(ref stdin) => Some(
+            stdin
+                .take_reader()
+                .ok_or(NixCommandLineError::StdinConsumed)?,
+        ),
+        None => None,
+    };
+    if input.is_some() {
+        command.stdin(Stdio::piped());
+    }
+
+    if nix_args.timeout.is_some() || nix_args.cancel.is_some() {
+        // SAFETY: setpgid is async-signal-safe
+        unsafe {
+            command.pre_exec(|| match libc::setpgid(0, 0) {
+                0 => Ok(()),
+                _ => Err(std::io::Error::last_os_error()),
+            });
+        }
+    }
+    let mut child = command.spawn().map_err(NixCommandLineError::Run)?;
+
+    if let Some(mut input) = input {
+        let mut pipe = child.stdin.take().expect("stdin is piped");
+        tokio::spawn(async move {
+            // nix may exit without reading all of its input
+            if let Err(e) = tokio::io::copy(&mut input, &mut pipe).await {
+                debug!("Could not write stdin of nix: {e}");
+            }
+        });
+    }
+    Ok(child)
+}
+
+/// Await `future` unless the invocation times out or is cancelled first,
+/// see [NixArgs::timeout] and [NixArgs::cancel]
+async fn interruptible<T>(
+    future: impl Future<Output = Result<T, NixCommandLineError>>,
+    nix_args: &NixArgs,
+) -> Result<T, NixCommandLineError> {
+    let timeout = async {
+        match nix_args.timeout {
+            Some(timeout) => tokio::time::sleep(timeout).await,
+            None => std::future::pending().await,
+        }
+    };
+    let cancelled = async {
+        match nix_args.cancel {
+            Some(ref cancel) => cancel.cancelled().await,
+            None => std::future::pending().await,
+        }
+    };
+
+    tokio::select! {
+        result = future => result,
+        _ = timeout => Err(NixCommandLineError::TimedOut(nix_args.timeout.unwrap_or_default())),
+        _ = cancelled => Err(NixCommandLineError::Cancelled),
+    }
+}
+
+/// Terminate the process group of `child` and wait for it to exit
+///
+/// Sends `SIGTERM` first, and `SIGKILL` if nix did not exit within [TERMINATE_GRACE_PERIOD].
+async fn terminate(child: &mut Child) {
+    let Some(pid) = child.id() else {
+        // already exited
+        return;
+    };
+    let pgid = pid as libc::pid_t;
+
+    // SAFETY: killpg has no memory safety preconditions
+    unsafe { libc::killpg(pgid, libc::SIGTERM) };
+    if tokio::time::timeout(TERMINATE_GRACE_PERIOD, child.wait())
+        .await
+        .is_err()
+    {
+        // SAFETY: see above
+        unsafe { libc::killpg(pgid, libc::SIGKILL) };
+        let _ = child.wait().await;
+    }
+}
+
+/// Wait for `child` to exit, see [interruptible]
+async fn wait(child: &mut Child, nix_args: &NixArgs) -> Result<ExitStatus, NixCommandLineError> {
+    let result = interruptible(
+        async { child.wait().await.map_err(NixCommandLineError::Run) },
+        nix_args,
+    )
+    .await;
+    if result.is_err() {
+        terminate(child).await;
+    }
+    result
+}
+
+/// Log the lines of `stderr` to the logging framework and collect them
+///
+/// Retains the output of nix to report a [NixExitError] should nix fail.
+async fn log_stderr(stderr: Option<ChildStderr>) -> Result<String, NixCommandLineError> {
+    let mut captured = String::new();
+    let Some(stderr) = stderr else {
+        return Ok(captured);
+    };
+
+    let mut lines = BufReader::new(stderr).lines();
+    while let Some(line) = lines.next_line().await.map_err(NixCommandLineError::Run)? {
+        debug!("{line}");
+        captured.push_str(&line);
+        captured.push('\n');
+    }
+    Ok(captured)
 }
 
 /// Errors occuring during command exection bin [Collect] Mode
@@ -127,8 +366,8 @@ trait CommandMode {
 pub enum NixCommandLineCollectError {
     #[error(transparent)]
     CommandLine(#[from] NixCommandLineError),
-    #[error("Nix failed with: [{0}]")]
-    NixError(ExitStatus),
+    #[error("Nix failed: {0}")]
+    NixError(NixExitError),
 }
 
 /// Implementation of a command execution that collects stdout of a process
@@ -142,40 +381,109 @@ impl CommandMode for Collect {
     type Error = NixCommandLineCollectError;
     type Output = Output;
 
-    async fn run(command: &mut Command) -> Result<Self::Output, NixCommandLineCollectError> {
+    async fn run(
+        command: &mut Command,
+        nix_args: &NixArgs,
+    ) -> Result<Self::Output, NixCommandLineCollectError> {
         command.as_std().log(log::Level::Debug);
 
         let command = command
             .stdout(Stdio::piped())
-            .stderr(Stdio::inherit())
+            .stderr(Stdio::piped())
             .stdin(Stdio::inherit());
 
-        let child = command.spawn().map_err(NixCommandLineError::Run)?;
+        let mut child = spawn(command, nix_args)?;
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        let stderr = child.stderr.take();
 
-        let output = child
-            .wait_with_output()
-            .await
-            .map_err(NixCommandLineError::Run)?;
+        let result = interruptible(
+            async {
+                let mut buf = Vec::new();
+                let read_stdout = async {
+                    stdout
+                        .read_to_end(&mut buf)
+                        .await
+                        .map_err(NixCommandLineError::Run)
+                };
+                let (_, stderr) = tokio::try_join!(read_stdout, log_stderr(stderr))?;
+                let status = child.wait().await.map_err(NixCommandLineError::Run)?;
+                Ok(Output {
+                    status,
+                    stdout: buf,
+                    stderr: stderr.into_bytes(),
+                })
+            },
+            nix_args,
+        )
+        .await;
+        if result.is_err() {
+            terminate(&mut child).await;
+        }
+        let output = result?;
 
         if !output.status.success() {
-            return Err(NixCommandLineCollectError::NixError(output.status));
+            return Err(NixCommandLineCollectError::NixError(NixExitError::new(
+                output.status,
+                String::from_utf8_lossy(&output.stderr).into_owned(),
+            )));
         }
 
         Ok(output)
     }
+
+    fn exit_error(error: &Self::Error) -> Option<&NixExitError> {
+        match error {
+            NixCommandLineCollectError::NixError(exit_error) => Some(exit_error),
+            NixCommandLineCollectError::CommandLine(_) => None,
+        }
+    }
+
+    fn recorded(result: &Result<Self::Output, Self::Error>) -> Option<Recorded> {
+        match result {
+            Ok(output) => Some(Recorded {
+                status: output.status,
+                stdout: Some(String::from_utf8_lossy(&output.stdout).into_owned()),
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            }),
+            Err(e) => Self::exit_error(e).map(|exit_error| Recorded {
+                status: exit_error.status,
+                stdout: None,
+                stderr: exit_error.stderr.clone(),
+            }),
+        }
+    }
+
+    fn replay(entry: JournalEntry) -> Result<Self::Output, Self::Error> {
+        let status = entry.exit_status();
+        if !status.success() {
+            return Err(NixCommandLineCollectError::NixError(NixExitError::new(
+                status,
+                entry.stderr,
+            )));
+        }
+
+        Ok(Output {
+            status,
+            stdout: entry.stdout.unwrap_or_default().into_bytes(),
+            stderr: entry.stderr.into_bytes(),
+        })
+    }
 }
 
 /// Implementation of a command execution that connects the subprocess' stdio
 /// to the parent process stdio.
 ///
+/// Stderr is inherited so that nix can display progress and prompt on the terminal,
+/// hence the [NixExitError] of a failed invocation does not retain stderr.
+///
 /// User facing operation
 struct Passthru;
 #[async_trait]
 impl CommandMode for Passthru {
-    type Error = NixCommandLineError;
-    type Output = ExitStatus;
+    type Error = NixCommandLineRunError;
+    type Output = Output;
 
-    async fn run(command: &mut Command) -> Result<ExitStatus, Self::Error> {
+    async fn run(command: &mut Command, nix_args: &NixArgs) -> Result<Output, Self::Error> {
         command.as_std().log(log::Level::Info);
 
         let command = command
@@ -183,26 +491,459 @@ impl CommandMode for Passthru {
             .stderr(Stdio::inherit())
             .stdin(Stdio::inherit());
 
-        let status = command.status().await.map_err(NixCommandLineError::Run)?;
+        let mut child = spawn(command, nix_args)?;
+        let status = wait(&mut child, nix_args).await?;
 
-        Ok(status)
+        if !status.success() {
+            Err(NixCommandLineRunError::Exit(NixExitError::new(
+                status,
+                String::new(),
+            )))?
+        }
+
+        Ok(Output {
+            status,
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+        })
+    }
+
+    fn exit_error(error: &Self::Error) -> Option<&NixExitError> {
+        match error {
+            NixCommandLineRunError::Exit(exit_error) => Some(exit_error),
+            NixCommandLineRunError::Backend(_) => None,
+        }
+    }
+
+    fn recorded(result: &Result<Self::Output, Self::Error>) -> Option<Recorded> {
+        match result {
+            Ok(output) => Some(Recorded {
+                status: output.status,
+                stdout: None,
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            }),
+            Err(e) => Self::exit_error(e).map(|exit_error| Recorded {
+                status: exit_error.status,
+                stdout: None,
+                stderr: exit_error.stderr.clone(),
+            }),
+        }
+    }
+
+    fn replay(entry: JournalEntry) -> Result<Self::Output, Self::Error> {
+        if let Some(ref stdout) = entry.stdout {
+            print!("{stdout}");
+        }
+        eprint!("{}", entry.stderr);
+
+        let status = entry.exit_status();
+        if !status.success() {
+            return Err(NixCommandLineRunError::Exit(NixExitError::new(
+                status,
+                entry.stderr,
+            )));
+        }
+
+        Ok(Output {
+            status,
+            stdout: Vec::new(),
+            stderr: entry.stderr.into_bytes(),
+        })
     }
 }
 
+/// A fully resolved invocation of the nix CLI
+///
+/// Created by [NixCliCommand::to_command_line] without running the command,
+/// e.g. to log, audit or display the exact invocation.
+///
+/// Unlike [CommandLine::args], the [Display] implementation redacts credentials.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandLine {
+    /// The nix executable
+    pub program: String,
+    pub args: Vec<String>,
+    /// Environment variables set in addition to the inherited environment
+    pub env: BTreeMap<String, String>,
+    /// Environment variables removed from the inherited environment
+    pub env_remove: Vec<String>,
+    /// Whether the environment is not inherited
+    pub clear_env: bool,
+    /// The working directory, if not inherited
+    pub current_dir: Option<PathBuf>,
+}
+
+impl CommandLine {
+    /// A [Command] running this invocation
+    pub fn to_command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        if self.clear_env {
+            command.env_clear();
+        }
+        for name in &self.env_remove {
+            command.env_remove(name);
+        }
+        command.envs(&self.env).args(&self.args);
+
+        if let Some(ref current_dir) = self.current_dir {
+            command.current_dir(current_dir);
+        }
+        command
+    }
+}
+
+impl fmt::Display for CommandLine {
+    /// formats the invocation as shell command
+    ///
+    /// Changes to the inherited environment are expressed using `env(1)`,
+    /// the working directory is not included.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let env_command = if self.clear_env || !self.env_remove.is_empty() {
+            let mut env_command = vec!["env".to_string()];
+            if self.clear_env {
+                env_command.push("-i".to_string());
+            }
+            for name in &self.env_remove {
+                env_command.push("-u".to_string());
+                env_command.push(shell_escape::escape(name.into()).into_owned());
+            }
+            env_command
+        } else {
+            vec![]
+        };
+        let env = env_command.into_iter().chain(
+            self.env
+                .iter()
+                .map(|(k, v)| format!("{k}={}", shell_escape::escape(v.into()))),
+        );
+        let command = std::iter::once(self.program.clone())
+            .chain(self.args.iter().map(|arg| redact_arg(arg).into_owned()))
+            .map(|arg| shell_escape::escape(arg.into()).into_owned());
+
+        write!(f, "{}", env.chain(command).collect::<Vec<_>>().join(" "))
+    }
+}
+
+/// Run `command_line`, calling `on_line` for every line of its stderr and, if piped, its stdout
+///
+/// Used by [RunStreaming] and [RunLogged],
+/// stdin is inherited unless [NixArgs::stdin] is set.
+async fn run_streaming(
+    backend: &NixCommandLine,
+    command_line: &CommandLine,
+    nix_args: &NixArgs,
+    stdout: Stdio,
+    on_line: &mut (dyn FnMut(OutputLine) + Send),
+) -> Result<ExitStatus, NixCommandLineError> {
+    if let Some(entry) = backend.replay(command_line)? {
+        let stdout = entry.stdout.as_deref().unwrap_or_default().lines();
+        stdout.for_each(|line| on_line(OutputLine::Stdout(line.to_string())));
+        entry
+            .stderr
+            .lines()
+            .for_each(|line| on_line(OutputLine::Stderr(line.to_string())));
+        return Ok(entry.exit_status());
+    }
+
+    let mut command = command_line.to_command();
+    command.as_std().log(log::Level::Info);
+
+    let command = command
+        .stdout(stdout)
+        .stderr(Stdio::piped())
+        .stdin(Stdio::inherit());
+    let start = Instant::now();
+    let mut child = spawn(command, nix_args)?;
+
+    let mut stdout = child.stdout.take().map(|out| BufReader::new(out).lines());
+    let mut stderr = child.stderr.take().map(|err| BufReader::new(err).lines());
+
+    let mut recorded_stdout = String::new();
+    let mut recorded_stderr = String::new();
+    let mut on_line = |line: OutputLine| {
+        let (OutputLine::Stdout(ref text) | OutputLine::Stderr(ref text)) = line;
+        let recorded = match line {
+            OutputLine::Stdout(_) => &mut recorded_stdout,
+            OutputLine::Stderr(_) => &mut recorded_stderr,
+        };
+        recorded.push_str(text);
+        recorded.push('\n');
+        on_line(line)
+    };
+
+    let result = interruptible(
+        forward_lines(&mut stdout, &mut stderr, &mut on_line),
+        nix_args,
+    )
+    .await;
+    if let Err(e) = result {
+        terminate(&mut child).await;
+        return Err(e);
+    }
+
+    let status = wait(&mut child, nix_args).await?;
+    backend.record(
+        command_line,
+        Recorded {
+            status,
+            stdout: Some(recorded_stdout),
+            stderr: recorded_stderr,
+        },
+        start.elapsed(),
+    );
+    Ok(status)
+}
+
+/// Call `on_line` for every line of `stdout` and `stderr` until both are closed
+async fn forward_lines<O, E>(
+    stdout: &mut Option<tokio::io::Lines<BufReader<O>>>,
+    stderr: &mut Option<tokio::io::Lines<BufReader<E>>>,
+    on_line: &mut (dyn FnMut(OutputLine) + Send),
+) -> Result<(), NixCommandLineError>
+where
+    O: tokio::io::AsyncRead + Unpin,
+    E: tokio::io::AsyncRead + Unpin,
+{
+    while stdout.is_some() || stderr.is_some() {
+        tokio::select! {
+            line = async { stdout.as_mut().unwrap().next_line().await }, if stdout.is_some() => {
+                match line.map_err(NixCommandLineError::Run)? {
+                    Some(line) => on_line(OutputLine::Stdout(line)),
+                    None => *stdout = None,
+                }
+            },
+            line = async { stderr.as_mut().unwrap().next_line().await }, if stderr.is_some() => {
+                match line.map_err(NixCommandLineError::Run)? {
+                    Some(line) => on_line(OutputLine::Stderr(line)),
+                    None => *stderr = None,
+                }
+            },
+        }
+    }
+    Ok(())
+}
+
 impl NixCommandLine {
     /// Small wrapping helper function to make Run implementations simpler
+    ///
+    /// `mode_args` are added after the subcommand, see [NixCommandLine::command_line].
+    /// Retries the invocation according to [NixCommandLine::retry]
+    /// and runs it once more according to [NixCommandLine::trace_failures].
     async fn run_command<M: CommandMode, A, B: NixCliCommand<Own = A>>(
         &self,
         command: &B,
         nix_args: &NixArgs,
-        json: bool,
+        mode_args: Vec<String>,
     ) -> Result<M::Output, M::Error> {
+        let command_line = self.prepare(command, nix_args, mode_args.clone()).await?;
+        let result = self.run_retried::<M>(&command_line, nix_args).await;
+
+        let trace_args = match (&self.trace_failures, &result) {
+            (Some(logging), Err(e)) if M::exit_error(e).is_some() => logging.to_args(),
+            _ => return result,
+        };
+        if trace_args.is_empty() {
+            return result;
+        }
+
+        info!("Running failed nix again with {trace_args:?}");
+        let traced = self.command_line(command, nix_args, [mode_args, trace_args].concat());
+        match self.run_retried::<M>(&traced, nix_args).await {
+            // keep the original failure if the invocation could not be repeated,
+            // e.g. because its stdin has been consumed
+            Err(e) if M::exit_error(&e).is_some() => Err(e),
+            _ => result,
+        }
+    }
+
+    /// Run `command_line`, retrying it according to [NixCommandLine::retry]
+    async fn run_retried<M: CommandMode>(
+        &self,
+        command_line: &CommandLine,
+        nix_args: &NixArgs,
+    ) -> Result<M::Output, M::Error> {
+        let mut attempt = 1;
+        loop {
+            // every attempt is recorded, so every attempt replays an entry
+            let (result, replayed) = match self.replay(command_line)? {
+                Some(entry) => (M::replay(entry), true),
+                None => {
+                    let permit = self.acquire().await;
+                    let start = Instant::now();
+                    let result = M::run(&mut command_line.to_command(), nix_args).await;
+                    drop(permit);
+                    if let Some(recorded) = M::recorded(&result) {
+                        self.record(command_line, recorded, start.elapsed());
+                    }
+                    (result, false)
+                },
+            };
+
+            // a consumed stdin reader would fail the next attempt before nix runs
+            let replayable = nix_args.stdin.as_ref().is_none_or(Stdin::is_replayable);
+            let retry = match (&self.retry, &result) {
+                (Some(retry), Err(e)) if replayable => M::exit_error(e)
+                    .filter(|exit_error| retry.should_retry(attempt, &exit_error.error))
+                    .map(|exit_error| (retry.backoff.delay(attempt), exit_error)),
+                _ => None,
+            };
+            let Some((delay, exit_error)) = retry else {
+                return result;
+            };
+
+            warn!("Retrying nix in {delay:?} after attempt {attempt} failed: {exit_error}");
+            if !replayed {
+                tokio::time::sleep(delay).await;
+            }
+            attempt += 1;
+        }
+    }
+
+    /// The recorded result of `command_line`, if replaying a [NixCommandLine::journal]
+    fn replay(
+        &self,
+        command_line: &CommandLine,
+    ) -> Result<Option<JournalEntry>, NixCommandLineError> {
+        match self.journal {
+            Some(ref journal) => Ok(journal.replay_entry(command_line).transpose()?),
+            None => Ok(None),
+        }
+    }
+
+    /// Record the result of `command_line`, if recording a [NixCommandLine::journal]
+    fn record(&self, command_line: &CommandLine, recorded: Recorded, duration: Duration) {
+        let Some(ref journal) = self.journal else {
+            return;
+        };
+        if let Err(e) = journal.append(command_line, recorded, duration) {
+            warn!("Could not record invocation of nix: {e}");
+        }
+    }
+
+    /// Wait until nix may be started, see [NixCommandLine::concurrency]
+    async fn acquire(&self) -> Option<ConcurrencyPermit> {
+        match self.concurrency {
+            Some(ref limit) => Some(limit.acquire().await),
+            None => None,
+        }
+    }
+
+    /// The implementation and version of nix, detected using `nix --version` on first use
+    ///
+    /// [None] unless [NixCommandLine::version] is set.
+    pub async fn detect_dialect(&self) -> Result<Option<Dialect>, NixCommandLineError> {
+        let Some(ref cache) = self.version else {
+            return Ok(None);
+        };
+
+        let dialect = cache
+            .get_or_try_init(async {
+                let output = self
+                    .probe(&["--version"], NixCommandLineError::Version)
+                    .await?;
+                let dialect: Dialect = output
+                    .parse()
+                    .map_err(|e| NixCommandLineError::Version(format!("{e}")))?;
+                debug!("Detected {dialect}");
+                Ok::<_, NixCommandLineError>(dialect)
+            })
+            .await?;
+        Ok(Some(dialect))
+    }
+
+    /// The version of nix, see [NixCommandLine::detect_dialect]
+    pub async fn detect_version(&self) -> Result<Option<NixVersion>, NixCommandLineError> {
+        Ok(self.detect_dialect().await?.map(|dialect| dialect.version))
+    }
+
+    /// The dialect of nix, if already detected
+    fn dialect(&self) -> Option<&Dialect> {
+        self.version.as_ref().and_then(VersionCache::get)
+    }
+
+    /// The effective nix configuration, as reported by [ConfigShow]
+    ///
+    /// Like [NixCommandLine::detect_dialect], only applies the default environment,
+    /// e.g. to check whether experimental features are enabled before running commands.
+    pub async fn nix_config(&self) -> Result<NixConfigValues, NixCommandLineError> {
+        let dialect = self.detect_dialect().await?;
+        let args = [
+            ConfigShow::subcommand(dialect.as_ref()),
+            ConfigShow::JSON_ARGS,
+        ]
+        .concat();
+        let output = self.probe(&args, NixCommandLineError::Config).await?;
+
+        serde_json::from_str(&output).map_err(|e| NixCommandLineError::Config(format!("{e}")))
+    }
+
+    /// Run nix with `args` and the default environment, returning its stdout
+    ///
+    /// Failures of nix are reported using `error`.
+    async fn probe(
+        &self,
+        args: &[&str],
+        error: fn(String) -> NixCommandLineError,
+    ) -> Result<String, NixCommandLineError> {
+        let mut command = Command::new(self.nix_bin.as_deref().unwrap_or("nix"));
+        command.envs(&self.defaults.environment).args(args);
+        command.as_std().log(log::Level::Debug);
+
+        let output = command.output().await.map_err(NixCommandLineError::Run)?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let message = format!("nix {} failed: {}", args.join(" "), stderr.trim());
+            return Err(error(message));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// Resolve the invocation of `command` after detecting the dialect of nix
+    ///
+    /// Fails if the detected dialect does not support `command`.
+    async fn prepare<B: NixCliCommand>(
+        &self,
+        command: &B,
+        nix_args: &NixArgs,
+        mode_args: Vec<String>,
+    ) -> Result<CommandLine, NixCommandLineError> {
+        if let Some(dialect) = self.detect_dialect().await? {
+            dialect
+                .nix_version()
+                .require(NixVersion::MIN_SUPPORTED, "runix")?;
+            command.check_version(&dialect)?;
+        }
+        Ok(self.command_line(command, nix_args, mode_args))
+    }
+
+    /// Resolve the invocation of `command` including all applicable defaults
+    ///
+    /// `mode_args` are added after the subcommand, e.g. `--json` for [RunJson].
+    /// The subcommand is adapted to the dialect of nix, if already detected.
+    /// [NixCliCommand::PRIVILEGED] commands are wrapped by [NixCommandLine::escalation].
+    fn command_line<B: NixCliCommand>(
+        &self,
+        command: &B,
+        nix_args: &NixArgs,
+        mode_args: Vec<String>,
+    ) -> CommandLine {
+        let remote_store = self
+            .remote_store
+            .as_ref()
+            .filter(|_| nix_args.common.store.is_none());
+
         let args = vec![
             // apply default args always applicable
             self.defaults.config_args.to_args(),
             self.defaults.common_args.to_args(),
+            remote_store
+                .map(|remote| remote.store().to_args())
+                .unwrap_or_default(),
             nix_args.to_args(),
-            B::SUBCOMMAND.iter().map(ToString::to_string).collect(),
+            B::subcommand(self.dialect())
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
             // apply command specific defaults if applicable
             // as defined by the command impl
             B::EVAL_ARGS
@@ -211,25 +952,37 @@ impl NixCommandLine {
             B::FLAKE_ARGS
                 .map(|_| self.defaults.flake_args.to_args())
                 .unwrap_or_default(),
-            if json {
-                vec!["--json".to_string()]
-            } else {
-                vec![]
-            },
+            mode_args,
             command.args(),
             self.defaults.extra_args.clone(),
         ];
 
-        let mut command = Command::new(self.nix_bin.as_deref().unwrap_or("nix"));
-        command
-            .envs(&self.defaults.environment)
-            .args(args.into_iter().flatten());
+        let command_line = CommandLine {
+            program: self.nix_bin.as_deref().unwrap_or("nix").to_string(),
+            args: args.into_iter().flatten().collect(),
+            env: self
+                .defaults
+                .environment
+                .clone()
+                .into_iter()
+                .chain(
+                    remote_store
+                        .and_then(RemoteStore::ssh_opts)
+                        .map(|ssh_opts| (NIX_SSHOPTS.to_string(), ssh_opts)),
+                )
+                .chain(nix_args.env.clone())
+                .collect(),
+            env_remove: nix_args.env_remove.clone(),
+            clear_env: nix_args.clear_env,
+            current_dir: nix_args.current_dir.clone(),
+        };
 
-        if let Some(ref cwd) = nix_args.cwd {
-            command.current_dir(cwd);
+        match self.escalation {
+            Some(ref escalation) if B::PRIVILEGED && Escalation::is_required() => {
+                escalation.wrap(command_line)
+            },
+            _ => command_line,
         }
-
-        M::run(&mut command).await
     }
 
     // Set the global Nix config via the environment variables in flox.default_args so that
@@ -292,6 +1045,13 @@ pub trait NixCliCommand: fmt::Debug + Sized {
     const SOURCE_ARGS: Group<Self, SourceArgs> = None;
     const OWN_ARGS: Group<Self, Self::Own> = None;
 
+    /// The oldest version of Nix providing the command, see [Self::check_version]
+    const MIN_VERSION: Option<NixVersion> = None;
+
+    /// Whether the command requires root on multi-user installations,
+    /// see [NixCommandLine::escalation]
+    const PRIVILEGED: bool = false;
+
     fn args(&self) -> Vec<String> {
         let mut acc = Vec::new();
         acc.append(&mut Self::FLAKE_ARGS.map_or(Vec::new(), |f| f(self).to_args()));
@@ -302,6 +1062,57 @@ pub trait NixCliCommand: fmt::Debug + Sized {
         acc.append(&mut Self::OWN_ARGS.map_or(Vec::new(), |f| f(self).to_args()));
         acc
     }
+
+    /// The subcommand invoked on `dialect` of nix, [Self::SUBCOMMAND] by default
+    ///
+    /// Allows commands to adapt to subcommands renamed between versions and implementations.
+    fn subcommand(_dialect: Option<&Dialect>) -> &'static [&'static str] {
+        Self::SUBCOMMAND
+    }
+
+    /// Fail if `dialect` of nix does not support the command or the options in use
+    ///
+    /// By default, checks [Self::MIN_VERSION] against [Dialect::nix_version].
+    fn check_version(&self, dialect: &Dialect) -> Result<(), UnsupportedFeature> {
+        match Self::MIN_VERSION {
+            Some(required) => dialect.nix_version().require(
+                required,
+                &format!("nix {}", Self::subcommand(Some(dialect)).join(" ")),
+            ),
+            None => Ok(()),
+        }
+    }
+
+    /// The invocation [Run] would execute on `backend`, without running it
+    ///
+    /// Includes the defaults of `backend` applicable to the command.
+    ///
+    /// ```
+    /// # use runix::arguments::NixArgs;
+    /// # use runix::command::FlakeMetadata;
+    /// # use runix::command_line::{NixCliCommand, NixCommandLine};
+    /// # use runix::flake_ref::FlakeRef;
+    /// let mut cli = NixCommandLine::default();
+    /// cli.defaults
+    ///     .environment
+    ///     .insert("NIX_CONFIG".to_string(), "warn-dirty = false".to_string());
+    ///
+    /// let command = FlakeMetadata {
+    ///     flake_ref: Some("github:flox/runix".parse::<FlakeRef>().unwrap().into()),
+    ///     ..Default::default()
+    /// };
+    /// let command_line = command.to_command_line(&cli, &NixArgs::default());
+    /// assert!(command_line
+    ///     .args
+    ///     .ends_with(&["flake", "metadata", "github:flox/runix"].map(String::from)));
+    ///
+    /// let shell = command_line.to_string();
+    /// assert!(shell.starts_with("NIX_CONFIG='warn-dirty = false' nix "));
+    /// assert!(shell.ends_with(" flake metadata 'github:flox/runix'"));
+    /// ```
+    fn to_command_line(&self, backend: &NixCommandLine, nix_args: &NixArgs) -> CommandLine {
+        backend.command_line(self, nix_args, vec![])
+    }
 }
 
 /// Marker Trait for commands that can return JSON
@@ -311,7 +1122,41 @@ pub trait NixCliCommand: fmt::Debug + Sized {
 ///
 /// Commands that may output json data but doing so other than with `--json`
 /// should implment [RunJson] directly instead of this marker.
-pub trait JsonCommand {}
+pub trait JsonCommand {
+    /// Arguments making nix print json, empty for commands printing json regardless
+    const JSON_ARGS: &'static [&'static str] = &["--json"];
+
+    /// The invocation [RunJson] would execute on `backend`, see [NixCliCommand::to_command_line]
+    fn to_json_command_line(&self, backend: &NixCommandLine, nix_args: &NixArgs) -> CommandLine
+    where
+        Self: NixCliCommand,
+    {
+        backend.command_line(self, nix_args, json_args::<Self>())
+    }
+
+    /// Convert the json output of `dialect` of nix to the format of the latest Nix
+    ///
+    /// Allows [TypedCommand::Output] to deserialize output
+    /// whose format changed between versions and implementations.
+    fn normalize_json(json: Value, _dialect: Option<&Dialect>) -> Value {
+        json
+    }
+
+    /// Convert an item of the json output of `dialect` of nix, see [RunJsonStream]
+    ///
+    /// By default, entries of objects are converted to objects with a single entry.
+    fn normalize_json_item(item: JsonItem, _dialect: Option<&Dialect>) -> Value {
+        match item.key {
+            Some(key) => Value::Object([(key, item.value)].into_iter().collect()),
+            None => item.value,
+        }
+    }
+}
+
+/// The arguments making `C` print json, see [JsonCommand::JSON_ARGS]
+fn json_args<C: JsonCommand + ?Sized>() -> Vec<String> {
+    C::JSON_ARGS.iter().map(ToString::to_string).collect()
+}
 
 /// Marker Trait for commands that can be deserialized into
 /// [TypedCommand::Output]
@@ -370,14 +1215,21 @@ impl ToArgs for () {
     }
 }
 
+/// Combines two groups of arguments, e.g. for [NixCliCommand::Own]
+impl<A: ToArgs, B: ToArgs> ToArgs for (A, B) {
+    fn to_args(&self) -> Vec<String> {
+        [self.0.to_args(), self.1.to_args()].concat()
+    }
+}
+
 impl NixBackend for NixCommandLine {}
 
 #[derive(Error, Debug)]
 pub enum NixCommandLineRunError {
     #[error("An error occured in CommandLine backend: {0}")]
     Backend(#[from] NixCommandLineError),
-    #[error("Nix call unsuccessful: [{0}]")]
-    Exit(ExitStatus),
+    #[error("Nix call unsuccessful: {0}")]
+    Exit(NixExitError),
 }
 
 #[async_trait]
@@ -392,22 +1244,347 @@ where
         backend: &NixCommandLine,
         nix_args: &NixArgs,
     ) -> Result<(), NixCommandLineRunError> {
-        let exit_status = backend
-            .run_command::<Passthru, _, _>(self, nix_args, false)
+        backend
+            .run_command::<Passthru, _, _>(self, nix_args, vec![])
+            .await
+            .map(drop)
+    }
+}
+
+#[async_trait]
+impl<C> RunStreaming<NixCommandLine> for C
+where
+    C: NixCliCommand + Send + Sync,
+{
+    type StreamingError = NixCommandLineRunError;
+
+    async fn run_streaming(
+        &self,
+        backend: &NixCommandLine,
+        nix_args: &NixArgs,
+        on_line: &mut (dyn FnMut(OutputLine) + Send),
+    ) -> Result<(), NixCommandLineRunError> {
+        let command_line = backend.prepare(self, nix_args, vec![]).await?;
+
+        let mut stderr = String::new();
+        let mut on_line = |line: OutputLine| {
+            if let OutputLine::Stderr(ref line) = line {
+                stderr.push_str(line);
+                stderr.push('\n');
+            }
+            on_line(line)
+        };
+        let _permit = backend.acquire().await;
+        let exit_status = run_streaming(
+            backend,
+            &command_line,
+            nix_args,
+            Stdio::piped(),
+            &mut on_line,
+        )
+        .await?;
+
+        if !exit_status.success() {
+            Err(NixCommandLineRunError::Exit(NixExitError::new(
+                exit_status,
+                stderr,
+            )))?
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<C> RunLogged<NixCommandLine> for C
+where
+    C: NixCliCommand + Send + Sync,
+{
+    type LoggedError = NixCommandLineRunError;
+
+    /// Run the command with `--log-format internal-json`
+    ///
+    /// Stdout is passed through, unstructured lines on stderr
+    /// are reported as [Verbosity::Notice] messages.
+    /// Should nix fail, the [NixExitError] is parsed from messages and errors
+    /// rather than the raw json log.
+    async fn run_logged(
+        &self,
+        backend: &NixCommandLine,
+        nix_args: &NixArgs,
+        on_event: &mut (dyn FnMut(LogEvent) + Send),
+    ) -> Result<(), NixCommandLineRunError> {
+        let command_line = backend
+            .prepare(self, nix_args, LogFormat::internal_json().to_args())
             .await?;
 
+        let mut stderr = String::new();
+        let mut on_line = |line| {
+            let OutputLine::Stderr(line) = line else {
+                return;
+            };
+            if let Some(event) = parse_log_line(line, &mut stderr) {
+                on_event(event)
+            }
+        };
+        let _permit = backend.acquire().await;
+        let exit_status = run_streaming(
+            backend,
+            &command_line,
+            nix_args,
+            Stdio::inherit(),
+            &mut on_line,
+        )
+        .await?;
+
         if !exit_status.success() {
-            Err(NixCommandLineRunError::Exit(exit_status))?
+            Err(NixCommandLineRunError::Exit(NixExitError::new(
+                exit_status,
+                stderr,
+            )))?
         }
 
         Ok(())
     }
 }
 
+/// Parse a line printed to stderr with `--log-format internal-json`
+///
+/// Unstructured lines are returned as [Verbosity::Notice] messages.
+/// Messages and errors are appended to `stderr` to report a [NixExitError] should nix fail.
+fn parse_log_line(line: String, stderr: &mut String) -> Option<LogEvent> {
+    let event = match line.parse() {
+        Ok(event) => event,
+        Err(ParseLogEventError::NotALogEvent(msg)) => LogEvent::Message {
+            level: Verbosity::Notice,
+            msg,
+        },
+        Err(e) => {
+            debug!("Skipping log line: {e}");
+            return None;
+        },
+    };
+    if let LogEvent::Message { ref msg, .. } | LogEvent::Error { ref msg, .. } = event {
+        stderr.push_str(msg);
+        stderr.push('\n');
+    }
+    Some(event)
+}
+
+impl Build {
+    /// Build the installables and report which paths were built, substituted or present
+    ///
+    /// Runs `nix build --json --log-format internal-json`,
+    /// following the log events with a [BuildReporter].
+    pub async fn report(
+        &self,
+        backend: &NixCommandLine,
+        nix_args: &NixArgs,
+    ) -> Result<(BuildOut, BuildReport), NixCommandLineRunJsonError> {
+        let args = [json_args::<Self>(), LogFormat::internal_json().to_args()].concat();
+        let command_line = backend
+            .prepare(self, nix_args, args)
+            .await
+            .map_err(|e| NixCommandLineRunJsonError::Run(e.into()))?;
+
+        let mut stdout = String::new();
+        let mut stderr = String::new();
+        let mut reporter = BuildReporter::default();
+        let mut on_line = |line| match line {
+            OutputLine::Stdout(line) => stdout.push_str(&line),
+            OutputLine::Stderr(line) => {
+                if let Some(event) = parse_log_line(line, &mut stderr) {
+                    reporter.handle(&event)
+                }
+            },
+        };
+        let _permit = backend.acquire().await;
+        let exit_status = run_streaming(
+            backend,
+            &command_line,
+            nix_args,
+            Stdio::piped(),
+            &mut on_line,
+        )
+        .await
+        .map_err(|e| NixCommandLineRunJsonError::Run(e.into()))?;
+
+        if !exit_status.success() {
+            Err(NixCommandLineRunJsonError::Run(
+                NixCommandLineCollectError::NixError(NixExitError::new(exit_status, stderr)),
+            ))?
+        }
+
+        let out: BuildOut = serde_json::from_str(&stdout)?;
+        let report = reporter.finish(&out);
+        Ok((out, report))
+    }
+}
+
+#[async_trait]
+impl<C> RunOutput<NixCommandLine> for C
+where
+    C: NixCliCommand + Send + Sync,
+{
+    type OutputError = NixCommandLineRunError;
+    type Reader = SpilledOutput;
+
+    /// Run the command, collecting stdout and passing through stderr
+    ///
+    /// Only stderr and the exit status are recorded in a [Journal],
+    /// so successful invocations cannot be replayed.
+    async fn run_output(
+        &self,
+        backend: &NixCommandLine,
+        nix_args: &NixArgs,
+        spill_threshold: usize,
+    ) -> Result<SpilledOutput, NixCommandLineRunError> {
+        let command_line = backend.prepare(self, nix_args, vec![]).await?;
+        if let Some(entry) = backend.replay(&command_line)? {
+            let status = entry.exit_status();
+            if !status.success() {
+                return Err(NixCommandLineRunError::Exit(NixExitError::new(
+                    status,
+                    entry.stderr,
+                )));
+            }
+            let stdout = entry.replay_stdout().map_err(NixCommandLineError::from)?;
+            let mut spill = Spill::new(spill_threshold);
+            spill
+                .write(stdout.as_bytes())
+                .await
+                .map_err(NixCommandLineError::Spill)?;
+            return Ok(spill.finish().await.map_err(NixCommandLineError::Spill)?);
+        }
+
+        let mut command = command_line.to_command();
+        command.as_std().log(log::Level::Debug);
+        command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .stdin(Stdio::inherit());
+
+        let _permit = backend.acquire().await;
+        let start = Instant::now();
+        let mut child = spawn(&mut command, nix_args)?;
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        let stderr = child.stderr.take();
+
+        let result = interruptible(
+            async {
+                let collect = async {
+                    let mut spill = Spill::new(spill_threshold);
+                    let mut buf = vec![0; 64 * 1024];
+                    loop {
+                        let read = stdout
+                            .read(&mut buf)
+                            .await
+                            .map_err(NixCommandLineError::Run)?;
+                        if read == 0 {
+                            return Ok(spill);
+                        }
+                        spill
+                            .write(&buf[..read])
+                            .await
+                            .map_err(NixCommandLineError::Spill)?;
+                    }
+                };
+                let (spill, stderr) = tokio::try_join!(collect, log_stderr(stderr))?;
+                let status = child.wait().await.map_err(NixCommandLineError::Run)?;
+                Ok((status, stderr, spill))
+            },
+            nix_args,
+        )
+        .await;
+        if result.is_err() {
+            terminate(&mut child).await;
+        }
+        let (status, stderr, spill) = result?;
+        backend.record(
+            &command_line,
+            Recorded {
+                status,
+                stdout: None,
+                stderr: stderr.clone(),
+            },
+            start.elapsed(),
+        );
+
+        if !status.success() {
+            return Err(NixCommandLineRunError::Exit(NixExitError::new(
+                status, stderr,
+            )));
+        }
+        Ok(spill.finish().await.map_err(NixCommandLineError::Spill)?)
+    }
+}
+
+/// Run `command_line` connected to the stdio of the host process, see [Terminal::Inherit]
+///
+/// If [NixArgs::timeout] or [NixArgs::cancel] is set,
+/// nix runs in its own process group and can not read from the terminal, see [spawn].
+/// Use [Terminal::Pty] for interactive commands that may be interrupted.
+async fn run_inherited(
+    command_line: &CommandLine,
+    nix_args: &NixArgs,
+) -> Result<ExitStatus, NixCommandLineError> {
+    let mut command = command_line.to_command();
+    command.as_std().log(log::Level::Info);
+
+    let command = command
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .stdin(Stdio::inherit());
+    let mut child = spawn(command, nix_args)?;
+    wait(&mut child, nix_args).await
+}
+
+#[async_trait]
+impl<C> RunInteractive<NixCommandLine> for C
+where
+    C: NixCliCommand + Send + Sync,
+{
+    type InteractiveError = NixCommandLineError;
+
+    /// Run the command with the terminal of the host process
+    ///
+    /// As the output is not captured, only the exit status is recorded in a [Journal].
+    async fn run_interactive(
+        &self,
+        backend: &NixCommandLine,
+        nix_args: &NixArgs,
+        terminal: Terminal,
+    ) -> Result<ExitStatus, NixCommandLineError> {
+        let command_line = backend.prepare(self, nix_args, vec![]).await?;
+        if let Some(entry) = backend.replay(&command_line)? {
+            return Ok(entry.exit_status());
+        }
+
+        let _permit = backend.acquire().await;
+        let start = Instant::now();
+        let status = match terminal {
+            Terminal::Inherit => run_inherited(&command_line, nix_args).await?,
+            Terminal::Pty => pty::run_in_pty(&command_line, nix_args).await?,
+        };
+        backend.record(
+            &command_line,
+            Recorded {
+                status,
+                stdout: None,
+                stderr: String::new(),
+            },
+            start.elapsed(),
+        );
+        Ok(status)
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum NixCommandLineRunJsonError {
     #[error("Error decoding json: {0}")]
     Json(#[from] serde_json::Error),
+    #[error("Error decoding json: {0}")]
+    Stream(#[from] ParseJsonStreamError),
     #[error(transparent)]
     Run(NixCommandLineCollectError),
 }
@@ -425,14 +1602,151 @@ where
         nix_args: &NixArgs,
     ) -> Result<Value, Self::JsonError> {
         let output = backend
-            .run_command::<Collect, _, _>(self, nix_args, true)
+            .run_command::<Collect, _, _>(self, nix_args, json_args::<C>())
             .await
             .map_err(NixCommandLineRunJsonError::Run)?;
 
         let out_str = String::from_utf8_lossy(&output.stdout);
         debug!("JSON command output: {:?}", out_str);
 
-        Ok(serde_json::from_str(&out_str)?)
+        Ok(C::normalize_json(
+            serde_json::from_str(&out_str)?,
+            backend.dialect(),
+        ))
+    }
+}
+
+/// Copies what is read from `inner` to `copy`, if any
+struct Tee<R> {
+    inner: R,
+    copy: Option<Vec<u8>>,
+}
+
+impl<R: tokio::io::AsyncRead + Unpin> tokio::io::AsyncRead for Tee<R> {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        let filled = buf.filled().len();
+        let Tee { inner, copy } = &mut *self;
+        let poll = std::pin::Pin::new(inner).poll_read(cx, buf);
+        if let (std::task::Poll::Ready(Ok(())), Some(copy)) = (&poll, copy) {
+            copy.extend_from_slice(&buf.filled()[filled..]);
+        }
+        poll
+    }
+}
+
+/// Call `on_item` for every item read by `items`, see [RunJsonStream]
+async fn forward_items<C, R>(
+    mut items: JsonItems<R>,
+    dialect: Option<&Dialect>,
+    on_item: &mut (dyn FnMut(Value) + Send),
+) -> Result<(), ParseJsonStreamError>
+where
+    C: JsonCommand,
+    R: tokio::io::AsyncBufRead + Unpin,
+{
+    while let Some(item) = items.next_item().await? {
+        on_item(C::normalize_json_item(item, dialect));
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl<C> RunJsonStream<NixCommandLine> for C
+where
+    C: NixCliCommand + JsonCommand + Send + Sync,
+{
+    type JsonStreamError = NixCommandLineRunJsonError;
+
+    /// Run the command with `--json`, parsing its output while nix is running
+    ///
+    /// Items are passed through [JsonCommand::normalize_json_item].
+    /// Invocations are not retried, as items may have been reported already.
+    async fn run_json_stream(
+        &self,
+        backend: &NixCommandLine,
+        nix_args: &NixArgs,
+        on_item: &mut (dyn FnMut(Value) + Send),
+    ) -> Result<(), Self::JsonStreamError> {
+        let run_error = |e| NixCommandLineRunJsonError::Run(NixCommandLineCollectError::from(e));
+
+        let command_line = backend
+            .prepare(self, nix_args, json_args::<C>())
+            .await
+            .map_err(run_error)?;
+        let dialect = backend.dialect();
+
+        if let Some(entry) = backend.replay(&command_line).map_err(run_error)? {
+            let stdout = entry
+                .replay_stdout()
+                .map_err(|e| run_error(e.into()))?
+                .as_bytes();
+            let parsed = forward_items::<C, _>(JsonItems::new(stdout), dialect, on_item).await;
+            let status = entry.exit_status();
+            if !status.success() {
+                return Err(NixCommandLineRunJsonError::Run(
+                    NixCommandLineCollectError::NixError(NixExitError::new(status, entry.stderr)),
+                ));
+            }
+            return Ok(parsed?);
+        }
+
+        let mut command = command_line.to_command();
+        command.as_std().log(log::Level::Debug);
+        command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .stdin(Stdio::inherit());
+
+        let _permit = backend.acquire().await;
+        let start = Instant::now();
+        let mut child = spawn(&mut command, nix_args).map_err(run_error)?;
+        let stderr = child.stderr.take();
+        // a journal that did not replay above is recording
+        let stdout = Tee {
+            inner: child.stdout.take().expect("stdout is piped"),
+            copy: backend.journal.as_ref().map(|_| Vec::new()),
+        };
+
+        let result = interruptible(
+            async {
+                // closes stdout when parsing fails, so that nix does not block writing to it
+                let parse = async {
+                    let mut stdout = stdout;
+                    let items = JsonItems::new(BufReader::new(&mut stdout));
+                    let parsed = forward_items::<C, _>(items, dialect, on_item).await;
+                    Ok((parsed, stdout.copy))
+                };
+                let ((parsed, stdout), stderr) = tokio::try_join!(parse, log_stderr(stderr))?;
+                let status = child.wait().await.map_err(NixCommandLineError::Run)?;
+                Ok((status, stdout, stderr, parsed))
+            },
+            nix_args,
+        )
+        .await;
+        if result.is_err() {
+            terminate(&mut child).await;
+        }
+        let (status, stdout, stderr, parsed) = result.map_err(run_error)?;
+        backend.record(
+            &command_line,
+            Recorded {
+                status,
+                stdout: stdout.map(|copy| String::from_utf8_lossy(&copy).into_owned()),
+                stderr: stderr.clone(),
+            },
+            start.elapsed(),
+        );
+
+        if !status.success() {
+            return Err(NixCommandLineRunJsonError::Run(
+                NixCommandLineCollectError::NixError(NixExitError::new(status, stderr)),
+            ));
+        }
+        Ok(parsed?)
     }
 }
 
@@ -456,3 +1770,839 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use tokio_util::sync::CancellationToken;
+
+    use super::*;
+    use crate::command::{
+        FlakeCheck,
+        FlakeMetadata,
+        PathInfo,
+        RegistryList,
+        RegistryListError,
+        Repl,
+        StoreDumpPath,
+        StoreInfo,
+    };
+    use crate::nix_error::NixError;
+    use crate::registry::{RegistryError, RegistryScope};
+
+    /// A backend running a shell script instead of nix
+    fn script_backend(dir: &tempfile::TempDir, script: &str) -> NixCommandLine {
+        let path = dir.path().join("nix");
+        std::fs::write(&path, format!("#!/bin/sh\n{script}")).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        NixCommandLine {
+            nix_bin: Some(path.to_string_lossy().into_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn run_streaming() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = script_backend(&dir, "echo out; echo err >&2; printf 'last'");
+
+        let mut lines = Vec::new();
+        FlakeMetadata::default()
+            .run_streaming(&backend, &NixArgs::default(), &mut |line| lines.push(line))
+            .await
+            .unwrap();
+
+        let (stdout, stderr): (Vec<_>, Vec<_>) = lines
+            .into_iter()
+            .partition(|line| matches!(line, OutputLine::Stdout(_)));
+        assert_eq!(stdout, [
+            OutputLine::Stdout("out".to_string()),
+            OutputLine::Stdout("last".to_string())
+        ]);
+        assert_eq!(stderr, [OutputLine::Stderr("err".to_string())]);
+
+        let backend = script_backend(&dir, "echo failed >&2; exit 1");
+        let mut lines = Vec::new();
+        let result = FlakeMetadata::default()
+            .run_streaming(&backend, &NixArgs::default(), &mut |line| lines.push(line))
+            .await;
+        assert!(matches!(result, Err(NixCommandLineRunError::Exit(_))));
+        assert_eq!(lines, [OutputLine::Stderr("failed".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn environment() {
+        async fn output(backend: &NixCommandLine, nix_args: &NixArgs) -> String {
+            let mut lines = Vec::new();
+            FlakeMetadata::default()
+                .run_streaming(backend, nix_args, &mut |line| lines.push(line))
+                .await
+                .unwrap();
+            match &lines[..] {
+                [OutputLine::Stdout(line)] => line.clone(),
+                _ => panic!("unexpected output: {lines:?}"),
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let script = r#"echo "$FOO:$BAR:$RUNIX_TEST_INHERITED:$(pwd)""#;
+        let mut backend = script_backend(&dir, script);
+        backend
+            .defaults
+            .environment
+            .insert("FOO".to_string(), "default".to_string());
+        std::env::set_var("RUNIX_TEST_INHERITED", "inherited");
+        let current_dir = dir.path().canonicalize().unwrap();
+
+        let nix_args = NixArgs {
+            current_dir: Some(current_dir.clone()),
+            env: [("BAR".to_string(), "bar".to_string())].into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            output(&backend, &nix_args).await,
+            format!("default:bar:inherited:{}", current_dir.display())
+        );
+
+        let nix_args = NixArgs {
+            current_dir: Some(current_dir.clone()),
+            env: [("FOO".to_string(), "foo".to_string())].into(),
+            env_remove: vec!["RUNIX_TEST_INHERITED".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            output(&backend, &nix_args).await,
+            format!("foo:::{}", current_dir.display())
+        );
+
+        let nix_args = NixArgs {
+            clear_env: true,
+            ..Default::default()
+        };
+        assert!(output(&backend, &nix_args).await.starts_with("default:::"));
+    }
+
+    #[tokio::test]
+    async fn stdin() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = script_backend(&dir, "cat");
+
+        let nix_args = NixArgs {
+            stdin: Some(r#"{"piped":"bytes"}"#.into()),
+            ..Default::default()
+        };
+        for _ in 0..2 {
+            let output = FlakeMetadata::default()
+                .run_json(&backend, &nix_args)
+                .await
+                .unwrap();
+            assert_eq!(output, serde_json::json!({ "piped": "bytes" }));
+        }
+
+        let nix_args = NixArgs {
+            stdin: Some(Stdin::reader(&br#"{"piped":"reader"}"#[..])),
+            ..Default::default()
+        };
+        let output = FlakeMetadata::default()
+            .run_json(&backend, &nix_args)
+            .await
+            .unwrap();
+        assert_eq!(output, serde_json::json!({ "piped": "reader" }));
+
+        let result = FlakeMetadata::default().run(&backend, &nix_args).await;
+        assert!(matches!(
+            result,
+            Err(NixCommandLineRunError::Backend(
+                NixCommandLineError::StdinConsumed
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn nix_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = script_backend(
+            &dir,
+            "echo \"error: attribute 'hello' missing\" >&2; exit 1",
+        );
+        let expected = NixError::MissingAttribute {
+            attr_path: "hello".to_string(),
+            flake: None,
+        };
+
+        let result = FlakeMetadata::default()
+            .run_json(&backend, &NixArgs::default())
+            .await;
+        let Err(NixCommandLineRunJsonError::Run(NixCommandLineCollectError::NixError(error))) =
+            result
+        else {
+            panic!("unexpected result: {result:?}");
+        };
+        assert_eq!(error.error, expected);
+        assert_eq!(error.stderr, "error: attribute 'hello' missing\n");
+        assert_eq!(error.status.code(), Some(1));
+
+        // stderr of user facing invocations is left to the terminal
+        let result = FlakeMetadata::default()
+            .run(&backend, &NixArgs::default())
+            .await;
+        let Err(NixCommandLineRunError::Exit(error)) = result else {
+            panic!("unexpected result: {result:?}");
+        };
+        assert_eq!(error.stderr, "");
+        assert_eq!(error.status.code(), Some(1));
+    }
+
+    #[tokio::test]
+    async fn retry() {
+        let dir = tempfile::tempdir().unwrap();
+        let attempts = dir.path().join("attempts");
+        let script = format!(
+            r#"
+            echo attempt >> {attempts}
+            [ "$(wc -l < {attempts})" -ge 3 ] && echo '{{}}' && exit 0
+            echo "error: unable to download 'https://cache.nixos.org/nix-cache-info'" >&2
+            exit 1
+            "#,
+            attempts = attempts.display()
+        );
+        let mut backend = script_backend(&dir, &script);
+        backend.retry = Some(retry::RetryPolicy {
+            max_attempts: 3,
+            backoff: retry::Backoff {
+                initial: Duration::from_millis(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+
+        FlakeMetadata::default()
+            .run_json(&backend, &NixArgs::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&attempts).unwrap().lines().count(),
+            3
+        );
+
+        std::fs::remove_file(&attempts).unwrap();
+        backend.retry.as_mut().unwrap().max_attempts = 2;
+        let result = FlakeMetadata::default()
+            .run_json(&backend, &NixArgs::default())
+            .await;
+        assert!(matches!(
+            result,
+            Err(NixCommandLineRunJsonError::Run(
+                NixCommandLineCollectError::NixError(NixExitError {
+                    error: NixError::Network { .. },
+                    ..
+                })
+            ))
+        ));
+        assert_eq!(
+            std::fs::read_to_string(&attempts).unwrap().lines().count(),
+            2
+        );
+
+        // the input of a stdin reader can not be passed to another attempt
+        std::fs::remove_file(&attempts).unwrap();
+        let nix_args = NixArgs {
+            stdin: Some(Stdin::reader(&b"{}"[..])),
+            ..Default::default()
+        };
+        let result = FlakeMetadata::default().run_json(&backend, &nix_args).await;
+        assert!(matches!(
+            result,
+            Err(NixCommandLineRunJsonError::Run(
+                NixCommandLineCollectError::NixError(NixExitError {
+                    error: NixError::Network { .. },
+                    ..
+                })
+            ))
+        ));
+        assert_eq!(
+            std::fs::read_to_string(&attempts).unwrap().lines().count(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn trace_failures() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = r#"
+            case "$*" in
+                *--show-trace*) echo "error: undefined variable 'x'" >&2; echo "trace" >&2 ;;
+                *) echo "error: undefined variable 'x'" >&2 ;;
+            esac
+            exit 1
+            "#;
+        let mut backend = script_backend(&dir, script);
+        backend.trace_failures = Some(LoggingArgs::trace());
+
+        let result = FlakeMetadata::default()
+            .run_json(&backend, &NixArgs::default())
+            .await;
+        let Err(NixCommandLineRunJsonError::Run(NixCommandLineCollectError::NixError(error))) =
+            result
+        else {
+            panic!("unexpected result: {result:?}");
+        };
+        assert!(error.stderr.ends_with("trace\n"), "{}", error.stderr);
+
+        let nix_args = NixArgs {
+            logging: LoggingArgs::trace(),
+            ..Default::default()
+        };
+        let command_line = FlakeMetadata::default().to_command_line(&backend, &nix_args);
+        assert!(command_line.args.contains(&"--show-trace".to_string()));
+    }
+
+    #[tokio::test]
+    async fn flake_check() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = script_backend(
+            &dir,
+            r#"
+            echo "warning: unknown flake output 'lib'" >&2
+            echo "error: flake attribute 'checks.x86_64-linux.fmt' is not a derivation" >&2
+            exit 1
+            "#,
+        );
+        let report = FlakeCheck::default()
+            .check(&backend, &NixArgs::default())
+            .await
+            .unwrap();
+        assert_eq!(report.diagnostics.len(), 2);
+        assert!(!report.is_ok());
+
+        let backend = script_backend(&dir, "exit 1");
+        let result = FlakeCheck::default()
+            .check(&backend, &NixArgs::default())
+            .await;
+        assert!(matches!(result, Err(NixCommandLineRunError::Exit(_))));
+    }
+
+    #[tokio::test]
+    async fn check_remote_store() {
+        let dir = tempfile::tempdir().unwrap();
+        // report the store passed with `--store`
+        let backend = script_backend(
+            &dir,
+            r#"
+            while [ "$1" != "--store" ]; do shift; done
+            echo "{\"url\":\"$2\",\"trusted\":0}"
+            "#,
+        );
+        let info = RemoteStore::new("nix@builder")
+            .check(&backend)
+            .await
+            .unwrap();
+        assert_eq!(info.url, "ssh-ng://nix@builder");
+        assert_eq!(info.trusted, Some(false));
+        assert_eq!(backend.remote_store, None);
+    }
+
+    #[tokio::test]
+    async fn registry_list() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = script_backend(
+            &dir,
+            r#"
+            echo "user   flake:runix github:flox/runix"
+            echo "global flake:nixpkgs github:NixOS/nixpkgs/nixpkgs-unstable"
+            "#,
+        );
+        let entries = RegistryList::default()
+            .list(&backend, &NixArgs::default())
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].scope, Some(RegistryScope::User));
+
+        let backend = script_backend(&dir, "echo 'not a registry entry'");
+        let result = RegistryList::default()
+            .list(&backend, &NixArgs::default())
+            .await;
+        assert!(matches!(
+            result,
+            Err(RegistryListError::Parse(RegistryError::InvalidEntry(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn concurrency() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let script = format!(
+            "echo start >> {log}; sleep 0.1; echo stop >> {log}",
+            log = log.display()
+        );
+        let mut backend = script_backend(&dir, &script);
+        let limit = limit::ConcurrencyLimit::new(1);
+        backend.concurrency = Some(limit.clone());
+
+        let nix_args = NixArgs::default();
+        let command = FlakeMetadata::default();
+        let mut on_line = |_| {};
+        let (run, streaming, _) = tokio::join!(
+            command.run(&backend, &nix_args),
+            command.run_streaming(&backend, &nix_args, &mut on_line),
+            async {
+                while limit.queued() == 0 {
+                    tokio::task::yield_now().await;
+                }
+            }
+        );
+        run.unwrap();
+        streaming.unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&log).unwrap(),
+            "start\nstop\nstart\nstop\n"
+        );
+        assert_eq!(limit.running(), 0);
+    }
+
+    #[tokio::test]
+    async fn interrupt() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = script_backend(&dir, "sleep 10");
+        let start = std::time::Instant::now();
+
+        let nix_args = NixArgs {
+            timeout: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+        let result = FlakeMetadata::default().run(&backend, &nix_args).await;
+        assert!(matches!(
+            result,
+            Err(NixCommandLineRunError::Backend(
+                NixCommandLineError::TimedOut(_)
+            ))
+        ));
+
+        let cancel = CancellationToken::new();
+        let nix_args = NixArgs {
+            cancel: Some(cancel.clone()),
+            ..Default::default()
+        };
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            cancel.cancel();
+        });
+        let result = FlakeMetadata::default().run_json(&backend, &nix_args).await;
+        assert!(matches!(
+            result,
+            Err(NixCommandLineRunJsonError::Run(
+                NixCommandLineCollectError::CommandLine(NixCommandLineError::Cancelled)
+            ))
+        ));
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn run_logged() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = r#"
+            case "$*" in *"--log-format internal-json"*) ;; *) exit 1 ;; esac
+            echo '@nix {"action":"start","id":1,"level":3,"type":104}' >&2
+            echo 'plain' >&2
+            echo '@nix {"action":"stop","id":1}' >&2
+        "#;
+        let backend = script_backend(&dir, script);
+
+        let mut events = Vec::new();
+        FlakeMetadata::default()
+            .run_logged(&backend, &NixArgs::default(), &mut |event| {
+                events.push(event)
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(events, [
+            LogEvent::Start {
+                id: 1,
+                parent: 0,
+                level: Verbosity::Info,
+                activity: crate::log_event::ActivityType::Builds,
+                text: String::new(),
+                fields: vec![],
+            },
+            LogEvent::Message {
+                level: Verbosity::Notice,
+                msg: "plain".to_string()
+            },
+            LogEvent::Stop { id: 1 },
+        ]);
+    }
+
+    #[tokio::test]
+    async fn build_report() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = r#"
+            case "$*" in *"--json"*"--log-format internal-json"*) ;; *) exit 1 ;; esac
+            echo '@nix {"action":"start","id":1,"level":4,"type":108,"fields":["/nix/store/3dsk2kh0m6xyi5rhjqzwxd4jc4hy6r2s-hello-2.12.1","https://cache.nixos.org"]}' >&2
+            echo '[{"drvPath":"/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-hello-2.12.1.drv","outputs":{"out":"/nix/store/3dsk2kh0m6xyi5rhjqzwxd4jc4hy6r2s-hello-2.12.1"}}]'
+        "#;
+        let backend = script_backend(&dir, script);
+
+        let (out, report) = Build::default()
+            .report(&backend, &NixArgs::default())
+            .await
+            .unwrap();
+        assert_eq!(out.len(), 1);
+        assert!(report.built.is_empty());
+        assert_eq!(
+            report.substituted_from("https://cache.nixos.org").count(),
+            1
+        );
+        assert!(report.present.is_empty());
+    }
+
+    #[tokio::test]
+    async fn version() {
+        #[derive(Debug)]
+        struct Recent;
+        impl NixCliCommand for Recent {
+            type Own = ();
+
+            const MIN_VERSION: Option<NixVersion> = Some(NixVersion::new(2, 30, 0));
+            const SUBCOMMAND: &'static [&'static str] = &["recent"];
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let script = r#"
+            case "$1" in
+                --version) echo "nix (Nix) 2.18.1" ;;
+                show-config) echo '{"cores":{"value":4,"defaultValue":0}}' ;;
+                *) echo "\"$*\"" ;;
+            esac
+        "#;
+        let mut backend = script_backend(&dir, script);
+
+        let output = StoreInfo::default()
+            .run_json(&backend, &NixArgs::default())
+            .await
+            .unwrap();
+        assert!(output.as_str().unwrap().ends_with(" store info --json"));
+        assert_eq!(backend.detect_version().await.unwrap(), None);
+
+        backend.version = Some(VersionCache::default());
+        let output = StoreInfo::default()
+            .run_json(&backend, &NixArgs::default())
+            .await
+            .unwrap();
+        assert!(output.as_str().unwrap().ends_with(" store ping --json"));
+        assert_eq!(
+            backend.detect_version().await.unwrap(),
+            Some(NixVersion::new(2, 18, 1))
+        );
+        assert_eq!(
+            backend.nix_config().await.unwrap().values(),
+            BTreeMap::from([("cores".to_string(), serde_json::json!(4))])
+        );
+
+        let result = Recent.run(&backend, &NixArgs::default()).await;
+        let Err(NixCommandLineRunError::Backend(NixCommandLineError::Unsupported(unsupported))) =
+            result
+        else {
+            panic!("unexpected result: {result:?}");
+        };
+        assert_eq!(
+            unsupported.to_string(),
+            "nix recent requires nix 2.30.0 or later, found 2.18.1"
+        );
+
+        let lix: Dialect = "nix (Lix, like Nix) 2.91.1".parse().unwrap();
+        backend.version = Some(VersionCache::with_dialect(lix));
+        let output = StoreInfo::default()
+            .run_json(&backend, &NixArgs::default())
+            .await
+            .unwrap();
+        assert!(output.as_str().unwrap().ends_with(" store ping --json"));
+
+        backend.version = Some(VersionCache::with_version(NixVersion::new(2, 3, 0)));
+        let result = StoreInfo::default()
+            .run(&backend, &NixArgs::default())
+            .await;
+        assert!(matches!(
+            result,
+            Err(NixCommandLineRunError::Backend(
+                NixCommandLineError::Unsupported(_)
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn run_json_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = r#"
+            echo '{"/nix/store/a": {"narSize": 1},'
+            echo '"/nix/store/b": null}'
+        "#;
+        let backend = script_backend(&dir, script);
+
+        let mut items = Vec::new();
+        PathInfo::default()
+            .run_json_stream(&backend, &NixArgs::default(), &mut |item| items.push(item))
+            .await
+            .unwrap();
+        assert_eq!(items, [
+            serde_json::json!({ "path": "/nix/store/a", "narSize": 1 }),
+            serde_json::json!({ "path": "/nix/store/b", "valid": false }),
+        ]);
+
+        let backend = script_backend(&dir, "echo '[1, 2'; echo failed >&2; exit 1");
+        let mut items = Vec::new();
+        let result = FlakeMetadata::default()
+            .run_json_stream(&backend, &NixArgs::default(), &mut |item| items.push(item))
+            .await;
+        assert!(matches!(
+            result,
+            Err(NixCommandLineRunJsonError::Run(
+                NixCommandLineCollectError::NixError(_)
+            ))
+        ));
+        assert_eq!(items, [serde_json::json!(1)]);
+
+        let backend = script_backend(&dir, "echo '[1, 2'");
+        let result = FlakeMetadata::default()
+            .run_json_stream(&backend, &NixArgs::default(), &mut |_| {})
+            .await;
+        assert!(matches!(
+            result,
+            Err(NixCommandLineRunJsonError::Stream(
+                ParseJsonStreamError::UnexpectedEof
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn journal() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal.jsonl");
+        let script = r#"
+            case "$*" in
+                *"flake metadata"*) echo '{"url": "github:flox/runix"}'; echo fetching >&2 ;;
+                *) echo failed >&2; exit 3 ;;
+            esac
+        "#;
+        let backend = NixCommandLine {
+            journal: Some(Journal::record(&path).unwrap()),
+            ..script_backend(&dir, script)
+        };
+        let nix_args = NixArgs {
+            env: [(
+                "NIX_CONFIG".to_string(),
+                "access-tokens = github.com=secret".to_string(),
+            )]
+            .into(),
+            ..Default::default()
+        };
+
+        let metadata = FlakeMetadata::default()
+            .run_json(&backend, &nix_args)
+            .await
+            .unwrap();
+        let result = StoreInfo::default()
+            .run_json(&backend, &NixArgs::default())
+            .await;
+        assert!(matches!(
+            result,
+            Err(NixCommandLineRunJsonError::Run(
+                NixCommandLineCollectError::NixError(_)
+            ))
+        ));
+
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("secret"));
+
+        // replay without running nix
+        let backend = NixCommandLine {
+            journal: Some(Journal::replay(&path).unwrap()),
+            ..script_backend(&dir, "exit 1")
+        };
+        let replayed = FlakeMetadata::default()
+            .run_json(&backend, &nix_args)
+            .await
+            .unwrap();
+        assert_eq!(replayed, metadata);
+
+        let result = StoreInfo::default()
+            .run_json(&backend, &NixArgs::default())
+            .await;
+        let Err(NixCommandLineRunJsonError::Run(NixCommandLineCollectError::NixError(exit_error))) =
+            result
+        else {
+            panic!("unexpected result: {result:?}");
+        };
+        assert_eq!(exit_error.status.code(), Some(3));
+        assert_eq!(exit_error.stderr, "failed\n");
+
+        // every invocation is replayed once
+        let result = FlakeMetadata::default().run_json(&backend, &nix_args).await;
+        assert!(matches!(
+            result,
+            Err(NixCommandLineRunJsonError::Run(
+                NixCommandLineCollectError::CommandLine(NixCommandLineError::Journal(
+                    JournalError::NotRecorded(_)
+                ))
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn journal_json_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal.jsonl");
+        let script = r#"echo '{"/nix/store/a": {"narSize": 1}}'"#;
+        let backend = NixCommandLine {
+            journal: Some(Journal::record(&path).unwrap()),
+            ..script_backend(&dir, script)
+        };
+        let mut items = Vec::new();
+        PathInfo::default()
+            .run_json_stream(&backend, &NixArgs::default(), &mut |item| items.push(item))
+            .await
+            .unwrap();
+
+        let backend = NixCommandLine {
+            journal: Some(Journal::replay(&path).unwrap()),
+            ..script_backend(&dir, "exit 1")
+        };
+        let mut replayed = Vec::new();
+        PathInfo::default()
+            .run_json_stream(&backend, &NixArgs::default(), &mut |item| {
+                replayed.push(item)
+            })
+            .await
+            .unwrap();
+        assert_eq!(replayed, items);
+    }
+
+    #[tokio::test]
+    async fn journal_retries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal.jsonl");
+        let attempts = dir.path().join("attempts");
+        let script = format!(
+            r#"
+            echo attempt >> {attempts}
+            [ "$(wc -l < {attempts})" -ge 2 ] && echo '{{"url": "github:flox/runix"}}' && exit 0
+            echo "error: unable to download 'https://cache.nixos.org/nix-cache-info'" >&2
+            exit 1
+            "#,
+            attempts = attempts.display()
+        );
+        let retry = Some(retry::RetryPolicy {
+            backoff: retry::Backoff {
+                initial: Duration::from_millis(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+
+        let backend = NixCommandLine {
+            journal: Some(Journal::record(&path).unwrap()),
+            retry: retry.clone(),
+            ..script_backend(&dir, &script)
+        };
+        let metadata = FlakeMetadata::default()
+            .run_json(&backend, &NixArgs::default())
+            .await
+            .unwrap();
+
+        // the failed attempt is replayed and retried like the recorded one
+        let backend = NixCommandLine {
+            journal: Some(Journal::replay(&path).unwrap()),
+            retry,
+            ..script_backend(&dir, "exit 1")
+        };
+        let replayed = FlakeMetadata::default()
+            .run_json(&backend, &NixArgs::default())
+            .await
+            .unwrap();
+        assert_eq!(replayed, metadata);
+    }
+
+    #[tokio::test]
+    async fn run_interactive() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = script_backend(&dir, "exit 3");
+        let status = Repl::default()
+            .run_interactive(&backend, &NixArgs::default(), Terminal::Inherit)
+            .await
+            .unwrap();
+        assert_eq!(status.code(), Some(3));
+
+        let script = r#"
+            test -t 0 && test -t 1 && test -t 2 || exit 1
+            read line
+            test "$line" = hello || exit 2
+        "#;
+        let backend = script_backend(&dir, script);
+        let nix_args = NixArgs {
+            stdin: Some(Stdin::from("hello\n")),
+            ..Default::default()
+        };
+        let status = Repl::default()
+            .run_interactive(&backend, &nix_args, Terminal::Pty)
+            .await
+            .unwrap();
+        assert!(status.success(), "{status}");
+    }
+
+    #[tokio::test]
+    async fn run_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = script_backend(&dir, "printf 'nix-archive-1'");
+
+        let mut output = StoreDumpPath::default()
+            .run_output(&backend, &NixArgs::default(), 4)
+            .await
+            .unwrap();
+        assert!(output.is_spilled());
+        let mut nar = String::new();
+        output.read_to_string(&mut nar).await.unwrap();
+        assert_eq!(nar, "nix-archive-1");
+
+        let output = StoreDumpPath::default()
+            .run_output(&backend, &NixArgs::default(), 1024)
+            .await
+            .unwrap();
+        assert!(!output.is_spilled());
+        assert_eq!(output.len(), 13);
+
+        let backend = script_backend(&dir, "printf partial; echo failed >&2; exit 1");
+        let result = StoreDumpPath::default()
+            .run_output(&backend, &NixArgs::default(), 4)
+            .await;
+        assert!(matches!(result, Err(NixCommandLineRunError::Exit(_))));
+
+        // the output is not recorded, replaying it fails instead of returning nothing
+        let path = dir.path().join("journal.jsonl");
+        let backend = NixCommandLine {
+            journal: Some(Journal::record(&path).unwrap()),
+            ..script_backend(&dir, "printf 'nix-archive-1'")
+        };
+        StoreDumpPath::default()
+            .run_output(&backend, &NixArgs::default(), 4)
+            .await
+            .unwrap();
+        let backend = NixCommandLine {
+            journal: Some(Journal::replay(&path).unwrap()),
+            ..backend
+        };
+        let result = StoreDumpPath::default()
+            .run_output(&backend, &NixArgs::default(), 4)
+            .await;
+        assert!(matches!(
+            result,
+            Err(NixCommandLineRunError::Backend(
+                NixCommandLineError::Journal(JournalError::NoStdout(_))
+            ))
+        ));
+    }
+}
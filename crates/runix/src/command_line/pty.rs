@@ -0,0 +1,321 @@
+//! Running interactive commands in a pseudo terminal, see [crate::Terminal::Pty]
+//!
+//! The command runs in a new session with the pty as its controlling terminal.
+//! Input and output are relayed between the pty and the stdio of the host process,
+//! which is switched to raw mode while relaying if it is a terminal,
+//! so that key presses such as `Ctrl-C` reach the command unaltered.
+
+use std::fs::File;
+use std::io::{self, IsTerminal, Read, Write};
+use std::mem::MaybeUninit;
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::process::{ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::debug;
+use tokio::io::{AsyncRead, AsyncWriteExt};
+use tokio::process::Command;
+use tokio::signal::unix::{signal, SignalKind};
+
+use super::{interruptible, terminate, wait, CommandExt, CommandLine, NixCommandLineError};
+use crate::arguments::NixArgs;
+
+/// Interval at which forwarding the stdin of the host process checks whether to stop
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The character signalling the end of input to the command, `Ctrl-D`
+const EOF: u8 = 0x04;
+
+/// Run `command_line` in a new pty
+///
+/// Relays [NixArgs::stdin] if set, or the stdin of the host process,
+/// until the command exits.
+pub(super) async fn run_in_pty(
+    command_line: &CommandLine,
+    nix_args: &NixArgs,
+) -> Result<ExitStatus, NixCommandLineError> {
+    let input = match nix_args.stdin {
+        Some(ref stdin) => Some(
+            stdin
+                .take_reader()
+                .ok_or(NixCommandLineError::StdinConsumed)?,
+        ),
+        None => None,
+    };
+
+    let pty = Pty::open().map_err(NixCommandLineError::Run)?;
+    let mut child = {
+        let mut command = command_line.to_command();
+        command.as_std().log(log::Level::Info);
+        pty.attach(&mut command).map_err(NixCommandLineError::Run)?;
+        // dropping the command closes its copies of the pty
+        command.spawn().map_err(NixCommandLineError::Run)?
+    };
+
+    let result = interruptible(
+        async move { pty.relay(input).await.map_err(NixCommandLineError::Run) },
+        nix_args,
+    )
+    .await;
+    if let Err(e) = result {
+        terminate(&mut child).await;
+        return Err(e);
+    }
+
+    wait(&mut child, nix_args).await
+}
+
+/// A pseudo terminal
+struct Pty {
+    master: File,
+    slave: OwnedFd,
+}
+
+impl Pty {
+    /// Open a pty, sized like the terminal of the host process if there is one
+    fn open() -> io::Result<Self> {
+        let mut master: RawFd = -1;
+        let mut slave: RawFd = -1;
+        let size = window_size();
+        let size = size
+            .as_ref()
+            .map_or(std::ptr::null(), |size| size as *const _);
+
+        // SAFETY: all pointers are valid or null for the duration of the call
+        let result = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                size,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: openpty opened both file descriptors, owned by nothing else
+        let (master, slave) = unsafe { (File::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+        // not to be inherited by the command or other processes spawned meanwhile
+        set_cloexec(master.as_raw_fd())?;
+        set_cloexec(slave.as_raw_fd())?;
+        Ok(Pty { master, slave })
+    }
+
+    /// Connect the stdio of `command` to the pty and make it its controlling terminal
+    fn attach(&self, command: &mut Command) -> io::Result<()> {
+        command
+            .stdin(Stdio::from(self.slave.try_clone()?))
+            .stdout(Stdio::from(self.slave.try_clone()?))
+            .stderr(Stdio::from(self.slave.try_clone()?));
+
+        // SAFETY: setsid and ioctl are async-signal-safe
+        unsafe {
+            command.pre_exec(|| {
+                if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+
+    /// Relay `input` or the stdin of the host process to the pty,
+    /// and the output of the pty to the stdout of the host process,
+    /// until the command and all processes it spawned closed the pty
+    async fn relay(self, input: Option<Box<dyn AsyncRead + Send + Unpin>>) -> io::Result<()> {
+        let Pty { master, slave } = self;
+        // the pty is closed once all processes but the host process closed it
+        drop(slave);
+
+        let output = master.try_clone()?;
+        let mut output = tokio::task::spawn_blocking(move || copy_output(output));
+
+        let input_master = master.try_clone()?;
+        let _input = match input {
+            Some(reader) => Input::Reader(tokio::spawn(copy_input(reader, input_master))),
+            None => {
+                let raw_mode = RawMode::enable();
+                let stop = Arc::new(AtomicBool::new(false));
+                let stdin = io::stdin().as_fd().try_clone_to_owned()?;
+                let stopped = Arc::clone(&stop);
+                std::thread::spawn(move || {
+                    forward_stdin(File::from(stdin), input_master, &stopped)
+                });
+                Input::Stdin { stop, raw_mode }
+            },
+        };
+
+        let mut resized = signal(SignalKind::window_change())?;
+        loop {
+            tokio::select! {
+                result = &mut output => return result?,
+                _ = resized.recv() => resize(&master),
+            }
+        }
+    }
+}
+
+/// Resize the pty to the terminal of the host process
+fn resize(master: &File) {
+    let Some(size) = window_size() else {
+        return;
+    };
+    // SAFETY: size is a valid winsize
+    if unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &size) } == -1 {
+        debug!("Could not resize pty: {}", io::Error::last_os_error());
+    }
+}
+
+/// The source of input relayed to the pty, stopped on drop
+enum Input {
+    Reader(tokio::task::JoinHandle<()>),
+    Stdin {
+        stop: Arc<AtomicBool>,
+        raw_mode: Option<RawMode>,
+    },
+}
+
+impl Drop for Input {
+    fn drop(&mut self) {
+        match self {
+            Input::Reader(task) => task.abort(),
+            Input::Stdin { stop, raw_mode } => {
+                stop.store(true, Ordering::Relaxed);
+                // restore the terminal before the host process prints anything
+                raw_mode.take();
+            },
+        }
+    }
+}
+
+/// Copy the output of the pty to the stdout of the host process
+fn copy_output(mut master: File) -> io::Result<()> {
+    let mut buf = [0; 4096];
+    loop {
+        let read = match master.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(read) => read,
+            // the pty has been closed
+            Err(e) if e.raw_os_error() == Some(libc::EIO) => return Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(&buf[..read])?;
+        stdout.flush()?;
+    }
+}
+
+/// Copy `reader` to the pty, followed by [EOF]
+async fn copy_input(mut reader: Box<dyn AsyncRead + Send + Unpin>, master: File) {
+    let mut master = tokio::fs::File::from_std(master);
+    let copied = async {
+        tokio::io::copy(&mut reader, &mut master).await?;
+        master.write_all(&[EOF]).await?;
+        master.flush().await
+    };
+    // the command may exit without reading all of its input
+    if let Err(e) = copied.await {
+        debug!("Could not write input to pty: {e}");
+    }
+}
+
+/// Forward the stdin of the host process to the pty until `stop` is set
+///
+/// Polls stdin rather than blocking on it,
+/// so that input is not consumed after the command exited.
+fn forward_stdin(mut stdin: File, mut master: File, stop: &AtomicBool) {
+    let mut buf = [0; 4096];
+    while !stop.load(Ordering::Relaxed) {
+        let mut pollfd = libc::pollfd {
+            fd: stdin.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: pollfd is a single valid pollfd
+        match unsafe { libc::poll(&mut pollfd, 1, POLL_INTERVAL.as_millis() as libc::c_int) } {
+            0 => continue,
+            -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+            -1 => return,
+            _ => {},
+        }
+
+        let forwarded = match stdin.read(&mut buf) {
+            Ok(0) => master
+                .write_all(&[EOF])
+                .and(Err(io::ErrorKind::UnexpectedEof.into())),
+            Ok(read) => master.write_all(&buf[..read]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => Err(e),
+        };
+        if forwarded.is_err() {
+            return;
+        }
+    }
+}
+
+/// The size of the terminal of the host process, if attached to one
+fn window_size() -> Option<libc::winsize> {
+    let mut size = libc::winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    [libc::STDIN_FILENO, libc::STDOUT_FILENO]
+        .into_iter()
+        // SAFETY: size is a valid winsize
+        .find(|fd| unsafe { libc::ioctl(*fd, libc::TIOCGWINSZ, &mut size) } == 0)
+        .map(|_| size)
+}
+
+fn set_cloexec(fd: RawFd) -> io::Result<()> {
+    // SAFETY: fcntl has no memory safety preconditions
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Raw mode of the terminal of the host process, restored on drop
+struct RawMode(libc::termios);
+
+impl RawMode {
+    /// Switch stdin to raw mode, if it is a terminal
+    fn enable() -> Option<Self> {
+        if !io::stdin().is_terminal() {
+            return None;
+        }
+
+        let mut termios = MaybeUninit::uninit();
+        // SAFETY: termios is initialized by tcgetattr if it succeeds
+        let original = unsafe {
+            if libc::tcgetattr(libc::STDIN_FILENO, termios.as_mut_ptr()) != 0 {
+                return None;
+            }
+            termios.assume_init()
+        };
+
+        let mut raw = original;
+        // SAFETY: raw is a valid termios
+        unsafe {
+            libc::cfmakeraw(&mut raw);
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                return None;
+            }
+        }
+        Some(RawMode(original))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: self.0 is the valid termios read by tcgetattr
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.0) };
+    }
+}
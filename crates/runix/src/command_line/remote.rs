@@ -0,0 +1,132 @@
+//! Running commands against the store of a remote machine
+//!
+//! See [super::NixCommandLine::remote_store].
+
+use std::path::PathBuf;
+
+use url::form_urlencoded;
+
+use super::{NixCommandLine, NixCommandLineRunJsonError};
+use crate::arguments::common::Store;
+use crate::arguments::NixArgs;
+use crate::store_info::StoreInfo;
+use crate::{command, RunTyped};
+
+/// Environment variable read by nix for additional options passed to `ssh`
+pub const NIX_SSHOPTS: &str = "NIX_SSHOPTS";
+
+/// Protocol used to talk to a remote store over ssh
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RemoteProtocol {
+    /// `ssh-ng://`, the nix daemon protocol tunneled through ssh
+    #[default]
+    SshNg,
+    /// `ssh://`, the legacy `nix-store --serve` protocol
+    Ssh,
+}
+
+impl RemoteProtocol {
+    pub fn scheme(&self) -> &'static str {
+        match self {
+            RemoteProtocol::SshNg => "ssh-ng",
+            RemoteProtocol::Ssh => "ssh",
+        }
+    }
+}
+
+/// A nix store on a machine reachable via ssh
+///
+/// ```
+/// # use runix::arguments::NixArgs;
+/// # use runix::command::Build;
+/// # use runix::command_line::remote::RemoteStore;
+/// # use runix::command_line::{NixCliCommand, NixCommandLine};
+/// let backend = NixCommandLine {
+///     remote_store: Some(RemoteStore {
+///         ssh_key: Some("/run/secrets/builder".into()),
+///         ssh_options: vec!["-p".to_string(), "2222".to_string()],
+///         ..RemoteStore::new("nix@builder.example.com")
+///     }),
+///     ..Default::default()
+/// };
+///
+/// let command_line = Build::default().to_command_line(&backend, &NixArgs::default());
+/// assert!(command_line.args.windows(2).any(|args| args
+///     == [
+///         "--store",
+///         "ssh-ng://nix@builder.example.com?ssh-key=%2Frun%2Fsecrets%2Fbuilder"
+///     ]));
+/// assert_eq!(command_line.env["NIX_SSHOPTS"], "-p 2222");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RemoteStore {
+    /// The machine to connect to, optionally including the user,
+    /// e.g. `nix@builder.example.com`
+    pub host: String,
+    pub protocol: RemoteProtocol,
+    /// Private key used to log in
+    pub ssh_key: Option<PathBuf>,
+    /// Base64 encoded public host key of the machine
+    pub host_public_key: Option<String>,
+    /// Additional options passed to `ssh`, see [NIX_SSHOPTS]
+    pub ssh_options: Vec<String>,
+    /// Path of the nix executable on the remote machine
+    pub remote_program: Option<String>,
+}
+
+impl RemoteStore {
+    pub fn new(host: impl Into<String>) -> Self {
+        RemoteStore {
+            host: host.into(),
+            ..Default::default()
+        }
+    }
+
+    /// The store URI passed to `--store`
+    pub fn uri(&self) -> String {
+        let mut params = form_urlencoded::Serializer::new(String::new());
+        if let Some(ref ssh_key) = self.ssh_key {
+            params.append_pair("ssh-key", &ssh_key.to_string_lossy());
+        }
+        if let Some(ref host_public_key) = self.host_public_key {
+            params.append_pair("base64-ssh-public-host-key", host_public_key);
+        }
+        if let Some(ref remote_program) = self.remote_program {
+            params.append_pair("remote-program", remote_program);
+        }
+        let params = params.finish();
+
+        let uri = format!("{}://{}", self.protocol.scheme(), self.host);
+        if params.is_empty() {
+            uri
+        } else {
+            format!("{uri}?{params}")
+        }
+    }
+
+    /// The `--store` argument selecting this store
+    pub fn store(&self) -> Store {
+        Store::from(self.uri())
+    }
+
+    /// The value of [NIX_SSHOPTS], if any options are set
+    pub fn ssh_opts(&self) -> Option<String> {
+        (!self.ssh_options.is_empty()).then(|| self.ssh_options.join(" "))
+    }
+
+    /// Check that the store is reachable by running [command::StoreInfo] against it
+    ///
+    /// Uses the settings of `backend` other than its [NixCommandLine::remote_store].
+    pub async fn check(
+        &self,
+        backend: &NixCommandLine,
+    ) -> Result<StoreInfo, NixCommandLineRunJsonError> {
+        let backend = NixCommandLine {
+            remote_store: Some(self.clone()),
+            ..backend.clone()
+        };
+        command::StoreInfo::default()
+            .run_typed(&backend, &NixArgs::default())
+            .await
+    }
+}
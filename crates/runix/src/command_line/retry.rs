@@ -0,0 +1,100 @@
+//! Retrying nix invocations that failed due to transient errors
+//!
+//! See [super::NixCommandLine::retry].
+
+use std::time::Duration;
+
+use crate::nix_error::NixError;
+
+/// When and how often to retry failed invocations
+///
+/// Only invocations run with [crate::Run] and [crate::RunJson] (and derived traits)
+/// are retried, as streamed output can not be taken back.
+/// [crate::Run] leaves stderr to the terminal, so its failures are reported
+/// as [NixError::Other] and not retried by default.
+/// Invocations reading a [crate::arguments::Stdin::Reader] are not retried,
+/// as the reader is consumed by the first attempt.
+///
+/// ```
+/// # use std::time::Duration;
+/// # use runix::command_line::retry::{Backoff, RetryPolicy};
+/// # use runix::command_line::NixCommandLine;
+/// let backend = NixCommandLine {
+///     retry: Some(RetryPolicy {
+///         max_attempts: 5,
+///         backoff: Backoff {
+///             initial: Duration::from_secs(2),
+///             ..Default::default()
+///         },
+///         ..Default::default()
+///     }),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts, including the first one
+    pub max_attempts: u32,
+    /// Delay between attempts
+    pub backoff: Backoff,
+    /// Whether a failure should be retried, [NixError::is_transient] by default
+    pub retry_on: fn(&NixError) -> bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            backoff: Backoff::default(),
+            retry_on: NixError::is_transient,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Whether to retry after `attempt` (starting at `1`) failed with `error`
+    pub fn should_retry(&self, attempt: u32, error: &NixError) -> bool {
+        attempt < self.max_attempts && (self.retry_on)(error)
+    }
+}
+
+/// Exponential backoff
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Backoff {
+    /// Delay after the first attempt
+    pub initial: Duration,
+    /// Factor the delay is multiplied by after every further attempt
+    pub factor: u32,
+    /// Upper bound of the delay
+    pub max: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial: Duration::from_secs(1),
+            factor: 2,
+            max: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Backoff {
+    /// The delay after `attempt` (starting at `1`) failed
+    ///
+    /// ```
+    /// # use std::time::Duration;
+    /// # use runix::command_line::retry::Backoff;
+    /// let backoff = Backoff::default();
+    /// assert_eq!(backoff.delay(1), Duration::from_secs(1));
+    /// assert_eq!(backoff.delay(3), Duration::from_secs(4));
+    /// assert_eq!(backoff.delay(100), Duration::from_secs(30));
+    /// ```
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = self
+            .factor
+            .checked_pow(attempt.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        self.initial.saturating_mul(factor).min(self.max)
+    }
+}
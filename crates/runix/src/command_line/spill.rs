@@ -0,0 +1,167 @@
+//! Buffering large outputs in temporary files, see [crate::RunOutput]
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Cursor};
+use std::os::unix::fs::OpenOptionsExt;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncSeekExt, AsyncWriteExt, ReadBuf};
+
+/// The output of a command, held in memory or spilled to a temporary file
+///
+/// Read it using [tokio::io::AsyncReadExt].
+/// A temporary file is removed as soon as it is created,
+/// its contents are freed once the output is dropped.
+#[derive(Debug)]
+pub enum SpilledOutput {
+    Memory(Cursor<Vec<u8>>),
+    File {
+        file: tokio::fs::File,
+        /// The size of the output in bytes
+        len: u64,
+    },
+}
+
+impl SpilledOutput {
+    /// The size of the output in bytes
+    pub fn len(&self) -> u64 {
+        match self {
+            SpilledOutput::Memory(cursor) => cursor.get_ref().len() as u64,
+            SpilledOutput::File { len, .. } => *len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the output exceeded the threshold and was written to a temporary file
+    pub fn is_spilled(&self) -> bool {
+        matches!(self, SpilledOutput::File { .. })
+    }
+}
+
+impl AsyncRead for SpilledOutput {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            SpilledOutput::Memory(cursor) => Pin::new(cursor).poll_read(cx, buf),
+            SpilledOutput::File { file, .. } => Pin::new(file).poll_read(cx, buf),
+        }
+    }
+}
+
+/// Collects output in memory up to `threshold` bytes and in a temporary file beyond
+#[derive(Debug)]
+pub(super) struct Spill {
+    threshold: usize,
+    buf: Vec<u8>,
+    file: Option<(tokio::fs::File, u64)>,
+}
+
+impl Spill {
+    pub(super) fn new(threshold: usize) -> Self {
+        Spill {
+            threshold,
+            buf: Vec::new(),
+            file: None,
+        }
+    }
+
+    pub(super) async fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        if self.file.is_none() && self.buf.len() + data.len() > self.threshold {
+            let mut file = tokio::fs::File::from_std(temp_file()?);
+            file.write_all(&self.buf).await?;
+            self.file = Some((file, self.buf.len() as u64));
+            self.buf = Vec::new();
+        }
+
+        match self.file {
+            Some((ref mut file, ref mut len)) => {
+                file.write_all(data).await?;
+                *len += data.len() as u64;
+            },
+            None => self.buf.extend_from_slice(data),
+        }
+        Ok(())
+    }
+
+    /// The collected output, ready to be read from the start
+    pub(super) async fn finish(self) -> io::Result<SpilledOutput> {
+        match self.file {
+            Some((mut file, len)) => {
+                file.flush().await?;
+                file.rewind().await?;
+                Ok(SpilledOutput::File { file, len })
+            },
+            None => Ok(SpilledOutput::Memory(Cursor::new(self.buf))),
+        }
+    }
+}
+
+/// Create an anonymous file in [std::env::temp_dir]
+fn temp_file() -> io::Result<File> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let dir = std::env::temp_dir();
+    loop {
+        let path = dir.join(format!(
+            "runix-output-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path);
+        match file {
+            Ok(file) => {
+                // the file remains accessible through the open file descriptor
+                std::fs::remove_file(&path)?;
+                return Ok(file);
+            },
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+
+    async fn collect(threshold: usize, chunks: &[&[u8]]) -> SpilledOutput {
+        let mut spill = Spill::new(threshold);
+        for chunk in chunks {
+            spill.write(chunk).await.unwrap();
+        }
+        spill.finish().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn spill() {
+        let mut output = collect(8, &[b"hello", b" world"]).await;
+        assert!(output.is_spilled());
+        assert_eq!(output.len(), 11);
+        let mut read = String::new();
+        output.read_to_string(&mut read).await.unwrap();
+        assert_eq!(read, "hello world");
+
+        let mut output = collect(11, &[b"hello", b" world"]).await;
+        assert!(!output.is_spilled());
+        let mut read = String::new();
+        output.read_to_string(&mut read).await.unwrap();
+        assert_eq!(read, "hello world");
+
+        assert!(collect(0, &[]).await.is_empty());
+    }
+}
@@ -0,0 +1,253 @@
+//! Detecting the implementation and version of nix to adapt invocations to it
+//!
+//! Besides Nix itself, forks such as [Lix](https://lix.systems) provide a `nix` binary
+//! with minor differences in their CLI.
+//! A [Dialect] describes the detected binary,
+//! which commands use to pick the matching subcommands and flags.
+//!
+//! See [super::NixCommandLine::version].
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use thiserror::Error;
+use tokio::sync::OnceCell;
+
+/// A version of nix, ignoring pre-release suffixes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NixVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl NixVersion {
+    /// The oldest version supported by runix, the first release with flakes
+    pub const MIN_SUPPORTED: NixVersion = NixVersion::new(2, 4, 0);
+
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        NixVersion {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Fail if this version is older than `required` for `feature`
+    ///
+    /// For implementations other than Nix, compare [Dialect::nix_version] instead.
+    pub fn require(&self, required: NixVersion, feature: &str) -> Result<(), UnsupportedFeature> {
+        if *self < required {
+            return Err(UnsupportedFeature {
+                feature: feature.to_string(),
+                required,
+                found: *self,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for NixVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl FromStr for NixVersion {
+    type Err = ParseNixVersionError;
+
+    /// Parse a version, or the output of `nix --version`
+    ///
+    /// ```
+    /// # use runix::command_line::version::NixVersion;
+    /// let version: NixVersion = "nix (Nix) 2.18.1\n".parse().unwrap();
+    /// assert_eq!(version, NixVersion::new(2, 18, 1));
+    ///
+    /// let version: NixVersion = "2.19.0pre20231005_1b3c5ba".parse().unwrap();
+    /// assert_eq!(version, NixVersion::new(2, 19, 0));
+    ///
+    /// let version: NixVersion = "2.4".parse().unwrap();
+    /// assert_eq!(version, NixVersion::new(2, 4, 0));
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseNixVersionError(s.to_string());
+
+        let version = s.split_whitespace().last().ok_or_else(err)?;
+        let mut components = version.splitn(3, '.').map(|component| {
+            let digits = component
+                .find(|c: char| !c.is_ascii_digit())
+                .map_or(component, |end| &component[..end]);
+            digits.parse::<u32>()
+        });
+
+        let major = components.next().ok_or_else(err)?.map_err(|_| err())?;
+        let minor = components.next().ok_or_else(err)?.map_err(|_| err())?;
+        let patch = components.next().transpose().map_err(|_| err())?;
+
+        Ok(NixVersion::new(major, minor, patch.unwrap_or_default()))
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("Could not parse nix version '{0}'")]
+pub struct ParseNixVersionError(String);
+
+/// An implementation of the nix CLI
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub enum Implementation {
+    /// [Nix](https://github.com/NixOS/nix)
+    #[default]
+    Nix,
+    /// [Lix](https://lix.systems), forked from Nix 2.18
+    Lix,
+    /// [Determinate Nix](https://github.com/DeterminateSystems/nix-src), a distribution of Nix
+    Determinate,
+    /// Any other implementation, by the name it reports
+    Other(String),
+}
+
+impl Implementation {
+    fn from_name(name: &str) -> Self {
+        if name.starts_with("Lix") {
+            Implementation::Lix
+        } else if name.starts_with("Determinate Nix") {
+            Implementation::Determinate
+        } else if name == "Nix" {
+            Implementation::Nix
+        } else {
+            Implementation::Other(name.to_string())
+        }
+    }
+}
+
+impl fmt::Display for Implementation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Implementation::Nix => write!(f, "Nix"),
+            Implementation::Lix => write!(f, "Lix"),
+            Implementation::Determinate => write!(f, "Determinate Nix"),
+            Implementation::Other(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+/// The implementation and version of a nix binary
+///
+/// Commands adapt to the CLI of the binary based on [Dialect::nix_version].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Dialect {
+    pub implementation: Implementation,
+    /// The version reported by the implementation
+    pub version: NixVersion,
+}
+
+impl Dialect {
+    /// The last version of Nix merged into Lix
+    const LIX_FORK_POINT: NixVersion = NixVersion::new(2, 18, 0);
+
+    /// The dialect of Nix at `version`
+    pub fn nix(version: NixVersion) -> Self {
+        Dialect {
+            implementation: Implementation::Nix,
+            version,
+        }
+    }
+
+    /// The version of Nix with the CLI closest to this dialect
+    ///
+    /// Lix versions its releases independently (2.90 onwards)
+    /// but keeps the CLI of the Nix release it was forked from.
+    ///
+    /// ```
+    /// # use runix::command_line::version::{Dialect, NixVersion};
+    /// let lix: Dialect = "nix (Lix, like Nix) 2.91.1".parse().unwrap();
+    /// assert_eq!(lix.version, NixVersion::new(2, 91, 1));
+    /// assert_eq!(lix.nix_version(), NixVersion::new(2, 18, 0));
+    /// ```
+    pub fn nix_version(&self) -> NixVersion {
+        match self.implementation {
+            Implementation::Lix => Self::LIX_FORK_POINT,
+            _ => self.version,
+        }
+    }
+}
+
+impl fmt::Display for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.implementation, self.version)
+    }
+}
+
+impl FromStr for Dialect {
+    type Err = ParseNixVersionError;
+
+    /// Parse the output of `nix --version`
+    ///
+    /// The implementation is named in parentheses, a bare version is assumed to be Nix.
+    ///
+    /// ```
+    /// # use runix::command_line::version::{Dialect, Implementation, NixVersion};
+    /// let dialect: Dialect = "nix (Nix) 2.18.1".parse().unwrap();
+    /// assert_eq!(dialect, Dialect::nix(NixVersion::new(2, 18, 1)));
+    ///
+    /// let dialect: Dialect = "nix (Determinate Nix 3.0.0) 2.26.3".parse().unwrap();
+    /// assert_eq!(dialect.implementation, Implementation::Determinate);
+    /// assert_eq!(dialect.version, NixVersion::new(2, 26, 3));
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let implementation = match (s.find('('), s.rfind(')')) {
+            (Some(start), Some(end)) if start < end => {
+                let name = &s[start + 1..end];
+                Implementation::from_name(name.split(',').next().unwrap_or(name).trim())
+            },
+            _ => Implementation::Nix,
+        };
+
+        Ok(Dialect {
+            implementation,
+            version: s.parse()?,
+        })
+    }
+}
+
+/// A command or option that is not available in the detected version of nix
+#[derive(Debug, Error)]
+#[error("{feature} requires nix {required} or later, found {found}")]
+pub struct UnsupportedFeature {
+    pub feature: String,
+    pub required: NixVersion,
+    pub found: NixVersion,
+}
+
+/// The detected dialect of nix, shared by clones
+///
+/// Detected once on first use,
+/// unless created using [VersionCache::with_version] or [VersionCache::with_dialect].
+#[derive(Debug, Clone, Default)]
+pub struct VersionCache(Arc<OnceCell<Dialect>>);
+
+impl VersionCache {
+    /// Assume Nix at `version` instead of detecting it
+    pub fn with_version(version: NixVersion) -> Self {
+        Self::with_dialect(Dialect::nix(version))
+    }
+
+    /// Assume `dialect` instead of detecting it
+    pub fn with_dialect(dialect: Dialect) -> Self {
+        VersionCache(Arc::new(OnceCell::new_with(Some(dialect))))
+    }
+
+    /// The dialect, if already detected
+    pub fn get(&self) -> Option<&Dialect> {
+        self.0.get()
+    }
+
+    pub(super) async fn get_or_try_init<F, E>(&self, detect: F) -> Result<Dialect, E>
+    where
+        F: std::future::Future<Output = Result<Dialect, E>>,
+    {
+        self.0.get_or_try_init(|| detect).await.cloned()
+    }
+}
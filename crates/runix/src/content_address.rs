@@ -0,0 +1,333 @@
+//! Store paths of content addressed store objects, computed without building them
+//!
+//! The paths of fixed output derivations, e.g. fetchers, and of files added with
+//! `builtins.toFile` are derived from the hash of their contents,
+//! so they can be predicted from the expected hash.
+//! [ContentAddress] computes them like nix, following the
+//! [store path specification](https://nixos.org/manual/nix/stable/protocols/store-path).
+//!
+//! ```
+//! # use runix::content_address::{ContentAddress, FileIngestionMethod};
+//! # use runix::store_path::STORE_PREFIX;
+//! let ca = ContentAddress::fixed(
+//!     FileIngestionMethod::Flat,
+//!     "sha256-jZkUKv2SV28wsM18tCqNxoCZmLxdYH2Idh9RLibH2yA="
+//!         .parse()
+//!         .unwrap(),
+//! );
+//! assert_eq!(
+//!     ca.to_string(),
+//!     "fixed:sha256:086vqwk2wl8zfs47sq2xpjc9k066ilmb8z6dn0q6ymwjzlm196cd"
+//! );
+//! assert_eq!(
+//!     ca.store_path(&*STORE_PREFIX, "hello-2.12.1.tar.gz", &[], false)
+//!         .unwrap()
+//!         .to_string(),
+//!     "/nix/store/pa10z4ngm0g83kx9mssrqzz30s84vq7k-hello-2.12.1.tar.gz"
+//! );
+//! ```
+
+use std::fmt::Display;
+use std::path::Path;
+use std::str::FromStr;
+
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::hash::{nix_base32, Hash, HashAlgorithm, HashFormat, ParseHashError};
+use crate::store_path::{StorePath, StorePathError};
+
+/// The length in bytes the sha256 of a store path is compressed to for its hash part
+const HASH_PART_BYTES: usize = 20;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ParseContentAddressError {
+    #[error("Unknown content address method in '{0}'")]
+    UnknownMethod(String),
+    #[error(transparent)]
+    Hash(#[from] ParseHashError),
+}
+
+/// How the contents of a store object are hashed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileIngestionMethod {
+    /// The hash of the file itself, only for single files
+    Flat,
+    /// The hash of the NAR serialization, for any store object, written as `r:`
+    Recursive,
+}
+
+/// How the path of a content addressed store object is computed from its [Hash]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentAddressMethod {
+    /// Files added by `builtins.toFile` and `.drv` files, hashed flat with sha256
+    Text,
+    /// Outputs of fixed output derivations and sources added to the store
+    Fixed(FileIngestionMethod),
+}
+
+impl ContentAddressMethod {
+    /// Parse the `hashAlgo` of a derivation output, e.g. `r:sha256` or `text:sha256`
+    pub fn from_hash_algo(
+        hash_algo: &str,
+    ) -> Result<(ContentAddressMethod, HashAlgorithm), ParseContentAddressError> {
+        let (method, algo) = if let Some(algo) = hash_algo.strip_prefix("text:") {
+            (ContentAddressMethod::Text, algo)
+        } else if let Some(algo) = hash_algo.strip_prefix("r:") {
+            (
+                ContentAddressMethod::Fixed(FileIngestionMethod::Recursive),
+                algo,
+            )
+        } else {
+            (
+                ContentAddressMethod::Fixed(FileIngestionMethod::Flat),
+                hash_algo,
+            )
+        };
+        Ok((method, algo.parse()?))
+    }
+}
+
+/// The method and hash a store object is addressed by,
+/// as in the `CA` field of narinfos and the `ca` of `nix path-info --json`
+///
+/// Displayed and parsed as `text:<hash>`, `fixed:<hash>` or `fixed:r:<hash>`,
+/// where `<hash>` is an `<algorithm>:<digest>` hash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentAddress {
+    pub method: ContentAddressMethod,
+    pub hash: Hash,
+}
+
+impl ContentAddress {
+    pub fn fixed(method: FileIngestionMethod, hash: Hash) -> Self {
+        ContentAddress {
+            method: ContentAddressMethod::Fixed(method),
+            hash,
+        }
+    }
+
+    /// The content address of a text file with `contents`, e.g. added by `builtins.toFile`
+    pub fn text(contents: impl AsRef<[u8]>) -> Self {
+        ContentAddress {
+            method: ContentAddressMethod::Text,
+            hash: sha256(contents.as_ref()),
+        }
+    }
+
+    /// The path of the store object called `name` with this content address
+    /// in the store at `store_dir`
+    ///
+    /// Only text and recursively hashed sha256 store objects may have `references`,
+    /// and only the latter may refer to themselves.
+    /// Other references do not contribute to the path, as nix does not allow them.
+    pub fn store_path(
+        &self,
+        store_dir: impl AsRef<Path>,
+        name: &str,
+        references: &[StorePath],
+        self_reference: bool,
+    ) -> Result<StorePath, StorePathError> {
+        let store_dir = store_dir.as_ref();
+        match self.method {
+            ContentAddressMethod::Text => make_store_path(
+                store_dir,
+                &make_type("text", references, false),
+                &self.hash,
+                name,
+            ),
+            ContentAddressMethod::Fixed(FileIngestionMethod::Recursive)
+                if self.hash.algo == HashAlgorithm::Sha256 =>
+            {
+                make_store_path(
+                    store_dir,
+                    &make_type("source", references, self_reference),
+                    &self.hash,
+                    name,
+                )
+            },
+            ContentAddressMethod::Fixed(method) => {
+                let recursive = match method {
+                    FileIngestionMethod::Flat => "",
+                    FileIngestionMethod::Recursive => "r:",
+                };
+                let inner = sha256(
+                    format!(
+                        "fixed:out:{recursive}{}:",
+                        self.hash.format(HashFormat::Base16)
+                    )
+                    .as_bytes(),
+                );
+                make_store_path(store_dir, "output:out", &inner, name)
+            },
+        }
+    }
+}
+
+impl Display for ContentAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let method = match self.method {
+            ContentAddressMethod::Text => "text:",
+            ContentAddressMethod::Fixed(FileIngestionMethod::Flat) => "fixed:",
+            ContentAddressMethod::Fixed(FileIngestionMethod::Recursive) => "fixed:r:",
+        };
+        write!(f, "{method}{}", self.hash.format(HashFormat::NixBase32))
+    }
+}
+
+impl FromStr for ContentAddress {
+    type Err = ParseContentAddressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (method, hash) = if let Some(hash) = s.strip_prefix("text:") {
+            (ContentAddressMethod::Text, hash)
+        } else if let Some(hash) = s.strip_prefix("fixed:r:") {
+            (
+                ContentAddressMethod::Fixed(FileIngestionMethod::Recursive),
+                hash,
+            )
+        } else if let Some(hash) = s.strip_prefix("fixed:") {
+            (ContentAddressMethod::Fixed(FileIngestionMethod::Flat), hash)
+        } else {
+            return Err(ParseContentAddressError::UnknownMethod(s.to_string()));
+        };
+        Ok(ContentAddress {
+            method,
+            hash: hash.parse()?,
+        })
+    }
+}
+
+/// The path of a store object of `path_type` in the store at `store_dir`,
+/// identified by the sha256 `hash`
+///
+/// The hash part is the sha256 of `<type>:sha256:<base16 hash>:<store dir>:<name>`,
+/// compressed to 20 bytes.
+pub fn make_store_path(
+    store_dir: &Path,
+    path_type: &str,
+    hash: &Hash,
+    name: &str,
+) -> Result<StorePath, StorePathError> {
+    let fingerprint = format!(
+        "{path_type}:{}:{}:{name}",
+        hash.format(HashFormat::Base16),
+        store_dir.display()
+    );
+    let digest = Sha256::digest(fingerprint.as_bytes());
+
+    let mut compressed = [0u8; HASH_PART_BYTES];
+    for (i, byte) in digest.iter().enumerate() {
+        compressed[i % HASH_PART_BYTES] ^= byte;
+    }
+
+    let path = store_dir.join(format!("{}-{name}", nix_base32::encode(&compressed)));
+    let store_path = StorePath::from_path_in(store_dir, &path)?;
+    // names containing a `/` would address a file inside the store path
+    if store_path.package_path().is_some() {
+        return Err(StorePathError::InvalidName(path));
+    }
+    Ok(store_path)
+}
+
+/// The type of a store path with `references`, e.g. `text:/nix/store/...`
+fn make_type(path_type: &str, references: &[StorePath], self_reference: bool) -> String {
+    let mut references: Vec<_> = references.iter().map(ToString::to_string).collect();
+    references.sort();
+
+    let mut path_type = path_type.to_string();
+    for reference in references {
+        path_type.push(':');
+        path_type.push_str(&reference);
+    }
+    if self_reference {
+        path_type.push_str(":self");
+    }
+    path_type
+}
+
+fn sha256(data: &[u8]) -> Hash {
+    Hash::new(HashAlgorithm::Sha256, Sha256::digest(data).as_slice())
+        .expect("sha256 digests have the length of sha256 digests")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computes_store_paths() {
+        let store_dir = Path::new("/nix/store");
+
+        let text = ContentAddress::text("hello world");
+        assert_eq!(
+            text.store_path(store_dir, "hello", &[], false)
+                .unwrap()
+                .to_string(),
+            "/nix/store/ivlnvab4q9c7wbsvbfsvgaa15j9p6206-hello"
+        );
+
+        let reference: StorePath = "/nix/store/3dsk2kh0m6xyi5rhjqzwxd4jc4hy6r2s-hello-2.12.1"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            text.store_path(store_dir, "hello", &[reference], false)
+                .unwrap()
+                .to_string(),
+            "/nix/store/2290s36hmll9ayh1fjpxjb9p5jwsmbvx-hello"
+        );
+
+        let source = ContentAddress::fixed(
+            FileIngestionMethod::Recursive,
+            "sha256-MjeRjunqfGTBGU401nxIjs7PC9PZZ1FBCZp/bRB3C2M="
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(
+            source
+                .store_path(store_dir, "source", &[], false)
+                .unwrap()
+                .to_string(),
+            "/nix/store/v8ni21i7a4kh64axzfn8ry0pa507qrc2-source"
+        );
+
+        let recursive_sha1 = ContentAddress::fixed(
+            FileIngestionMethod::Recursive,
+            "sha1:0000000000000000000000000000000000000000"
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(
+            recursive_sha1
+                .store_path(store_dir, "source", &[], false)
+                .unwrap()
+                .to_string(),
+            "/nix/store/x0z4377lxx8rzjbc3y49d38l1qxrzr09-source"
+        );
+
+        assert!(text
+            .store_path(store_dir, "invalid/name", &[], false)
+            .is_err());
+    }
+
+    #[test]
+    fn parses_content_addresses() {
+        let ca: ContentAddress =
+            "fixed:r:sha256:0qqbfw86szws150m2ryrsc5wzklf91ydcd2f370n8z7ax6792drj"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            ca.method,
+            ContentAddressMethod::Fixed(FileIngestionMethod::Recursive)
+        );
+        assert_eq!(ca.to_string().parse::<ContentAddress>().unwrap(), ca);
+        assert_eq!(
+            ContentAddressMethod::from_hash_algo("text:sha256").unwrap(),
+            (ContentAddressMethod::Text, HashAlgorithm::Sha256)
+        );
+        assert!(matches!(
+            "git:sha1:0000000000000000000000000000000000000000".parse::<ContentAddress>(),
+            Err(ParseContentAddressError::UnknownMethod(_))
+        ));
+    }
+}
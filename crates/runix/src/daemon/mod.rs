@@ -0,0 +1,460 @@
+//! The [NixDaemon] backend
+//!
+//! Talks to the nix daemon over its unix socket using (a subset of) the worker protocol,
+//! rather than spawning a nix process per query.
+//! Intended for frequent, cheap store queries, e.g. checking the validity of paths.
+//!
+//! ```no_run
+//! # use runix::daemon::NixDaemon;
+//! # use runix::store_path::StorePath;
+//! # #[tokio::main]
+//! # async fn main() {
+//! let daemon = NixDaemon::connect_default().await.unwrap();
+//! let path = StorePath::from_path("/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-python3-3.10.10")
+//!     .unwrap();
+//! if daemon.is_valid_path(&path).await.unwrap() {
+//!     daemon.add_temp_root(&path).await.unwrap();
+//! }
+//! # }
+//! ```
+//!
+//! Reference:
+//! [libstore/worker-protocol.hh](https://github.com/NixOS/nix/blob/master/src/libstore/worker-protocol.hh)
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use log::debug;
+use thiserror::Error;
+use tokio::io::{AsyncWriteExt, BufStream};
+use tokio::net::UnixStream;
+use tokio::sync::Mutex;
+
+use self::wire::*;
+use crate::flake_ref::lock::NarHash;
+use crate::narinfo::Narinfo;
+use crate::store_info::StoreInfo;
+use crate::store_path::{StorePath, StorePathError};
+use crate::NixBackend;
+
+mod wire;
+
+/// The socket of the nix daemon if `NIX_DAEMON_SOCKET_PATH` is not set
+pub const DEFAULT_SOCKET: &str = "/nix/var/nix/daemon-socket/socket";
+
+const WORKER_MAGIC_1: u64 = 0x6e697863;
+const WORKER_MAGIC_2: u64 = 0x6478696f;
+
+/// The protocol version spoken by runix, `1.35`
+const PROTOCOL_VERSION: u64 = 1 << 8 | 35;
+/// The oldest protocol version supported by runix, `1.17`
+const MIN_PROTOCOL_VERSION: u64 = 1 << 8 | 17;
+
+const OP_IS_VALID_PATH: u64 = 1;
+const OP_ADD_TEMP_ROOT: u64 = 11;
+const OP_QUERY_PATH_INFO: u64 = 26;
+const OP_QUERY_VALID_PATHS: u64 = 31;
+
+const STDERR_NEXT: u64 = 0x6f6c6d67;
+const STDERR_LAST: u64 = 0x616c7473;
+const STDERR_ERROR: u64 = 0x63787470;
+const STDERR_START_ACTIVITY: u64 = 0x53545254;
+const STDERR_STOP_ACTIVITY: u64 = 0x53544f50;
+const STDERR_RESULT: u64 = 0x52534c54;
+
+fn minor(version: u64) -> u64 {
+    version & 0xff
+}
+
+#[derive(Debug, Error)]
+pub enum NixDaemonError {
+    #[error("Could not communicate with the nix daemon: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Not a nix daemon, received magic number {0:#x}")]
+    InvalidMagic(u64),
+    #[error("Unsupported protocol version {}.{}", .0 >> 8, minor(*.0))]
+    UnsupportedVersion(u64),
+    #[error("Unexpected message from the nix daemon: {0:#x}")]
+    UnexpectedMessage(u64),
+    #[error("The nix daemon reported an error: {0}")]
+    Daemon(String),
+    #[error("Invalid narHash reported by the nix daemon: '{0}'")]
+    InvalidNarHash(String),
+    #[error("Invalid store path reported by the nix daemon: {0}")]
+    InvalidStorePath(#[from] StorePathError),
+}
+
+/// A connection to the nix daemon
+///
+/// Requests are sent one at a time, concurrent calls wait for previous ones to complete.
+/// Requests must not be aborted, i.e. their futures dropped before completion,
+/// as the connection is left in the middle of an exchange.
+pub struct NixDaemon {
+    connection: Mutex<BufStream<UnixStream>>,
+    protocol_version: u64,
+    daemon_version: Option<String>,
+    trusted: Option<bool>,
+}
+
+impl fmt::Debug for NixDaemon {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NixDaemon")
+            .field("protocol_version", &self.protocol_version)
+            .field("daemon_version", &self.daemon_version)
+            .field("trusted", &self.trusted)
+            .finish_non_exhaustive()
+    }
+}
+
+impl NixBackend for NixDaemon {}
+
+impl NixDaemon {
+    /// Connect to the daemon listening on `socket`
+    pub async fn connect(socket: impl AsRef<Path>) -> Result<Self, NixDaemonError> {
+        Self::handshake(UnixStream::connect(socket).await?).await
+    }
+
+    /// Connect to the daemon at `NIX_DAEMON_SOCKET_PATH` or [DEFAULT_SOCKET]
+    pub async fn connect_default() -> Result<Self, NixDaemonError> {
+        let socket = std::env::var_os("NIX_DAEMON_SOCKET_PATH")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| DEFAULT_SOCKET.into());
+        Self::connect(socket).await
+    }
+
+    async fn handshake(stream: UnixStream) -> Result<Self, NixDaemonError> {
+        let mut stream = BufStream::new(stream);
+
+        write_u64(&mut stream, WORKER_MAGIC_1).await?;
+        stream.flush().await?;
+        match read_u64(&mut stream).await? {
+            WORKER_MAGIC_2 => {},
+            magic => return Err(NixDaemonError::InvalidMagic(magic)),
+        }
+
+        let daemon_protocol_version = read_u64(&mut stream).await?;
+        if daemon_protocol_version >> 8 != PROTOCOL_VERSION >> 8
+            || daemon_protocol_version < MIN_PROTOCOL_VERSION
+        {
+            return Err(NixDaemonError::UnsupportedVersion(daemon_protocol_version));
+        }
+        let protocol_version = daemon_protocol_version.min(PROTOCOL_VERSION);
+
+        write_u64(&mut stream, PROTOCOL_VERSION).await?;
+        // no cpu affinity
+        write_bool(&mut stream, false).await?;
+        // do not reserve space
+        write_bool(&mut stream, false).await?;
+        stream.flush().await?;
+
+        let daemon_version = if minor(protocol_version) >= 33 {
+            Some(read_string(&mut stream).await?)
+        } else {
+            None
+        };
+        let trusted = if minor(protocol_version) >= 35 {
+            match read_u64(&mut stream).await? {
+                1 => Some(true),
+                2 => Some(false),
+                _ => None,
+            }
+        } else {
+            None
+        };
+
+        process_stderr(&mut stream, protocol_version).await?;
+
+        Ok(NixDaemon {
+            connection: Mutex::new(stream),
+            protocol_version,
+            daemon_version,
+            trusted,
+        })
+    }
+
+    /// The negotiated protocol version, encoded as `major << 8 | minor`
+    pub fn protocol_version(&self) -> u64 {
+        self.protocol_version
+    }
+
+    /// The version of nix running the daemon, if reported
+    pub fn daemon_version(&self) -> Option<&str> {
+        self.daemon_version.as_deref()
+    }
+
+    /// Whether the daemon trusts this client, if reported
+    pub fn is_trusted(&self) -> Option<bool> {
+        self.trusted
+    }
+
+    /// The store served by the daemon, as reported during the handshake
+    pub fn store_info(&self) -> StoreInfo {
+        StoreInfo {
+            url: "daemon".to_string(),
+            version: self.daemon_version.clone(),
+            trusted: self.trusted,
+            max_jobs: None,
+        }
+    }
+
+    /// Whether `path` is valid, i.e. exists in the store
+    pub async fn is_valid_path(&self, path: &StorePath) -> Result<bool, NixDaemonError> {
+        let mut stream = self.connection.lock().await;
+        write_u64(&mut *stream, OP_IS_VALID_PATH).await?;
+        write_string(&mut *stream, &store_path_string(path)).await?;
+        stream.flush().await?;
+
+        process_stderr(&mut stream, self.protocol_version).await?;
+        Ok(read_bool(&mut *stream).await?)
+    }
+
+    /// The subset of `paths` that is valid
+    pub async fn query_valid_paths(
+        &self,
+        paths: &[StorePath],
+    ) -> Result<Vec<StorePath>, NixDaemonError> {
+        let paths: Vec<String> = paths.iter().map(store_path_string).collect();
+
+        let mut stream = self.connection.lock().await;
+        write_u64(&mut *stream, OP_QUERY_VALID_PATHS).await?;
+        write_strings(&mut *stream, &paths).await?;
+        if minor(self.protocol_version) >= 27 {
+            // do not substitute
+            write_bool(&mut *stream, false).await?;
+        }
+        stream.flush().await?;
+
+        process_stderr(&mut stream, self.protocol_version).await?;
+        Ok(read_strings(&mut *stream)
+            .await?
+            .iter()
+            .map(|path| path.parse())
+            .collect::<Result<_, _>>()?)
+    }
+
+    /// Metadata of `path`, [None] if the path is not valid
+    pub async fn query_path_info(
+        &self,
+        path: &StorePath,
+    ) -> Result<Option<Narinfo>, NixDaemonError> {
+        let mut stream = self.connection.lock().await;
+        write_u64(&mut *stream, OP_QUERY_PATH_INFO).await?;
+        write_string(&mut *stream, &store_path_string(path)).await?;
+        stream.flush().await?;
+
+        process_stderr(&mut stream, self.protocol_version).await?;
+        if !read_bool(&mut *stream).await? {
+            return Ok(None);
+        }
+
+        let deriver = read_string(&mut *stream).await?;
+        let nar_hash = read_string(&mut *stream).await?;
+        let references = read_strings(&mut *stream).await?;
+        let registration_time = read_u64(&mut *stream).await?;
+        let nar_size = read_u64(&mut *stream).await?;
+        let ultimate = read_bool(&mut *stream).await?;
+        let sigs = read_strings(&mut *stream).await?;
+        let ca = read_string(&mut *stream).await?;
+
+        let nar_hash = hex::decode(&nar_hash)
+            .ok()
+            .and_then(|digest| NarHash::from_digest("sha256", &digest).ok())
+            .ok_or(NixDaemonError::InvalidNarHash(nar_hash))?;
+
+        Ok(Some(Narinfo {
+            path: StorePath::new_unchecked(path.prefix(), path.basename(), None::<PathBuf>),
+            valid: true,
+            nar_hash: Some(nar_hash),
+            nar_size: Some(nar_size),
+            closure_size: None,
+            references: references
+                .iter()
+                .map(|reference| reference.parse())
+                .collect::<Result<_, _>>()?,
+            sigs,
+            deriver: match deriver.as_str() {
+                "" => None,
+                deriver => Some(deriver.parse()?),
+            },
+            registration_time: Some(registration_time as i64),
+            ultimate,
+            ca: (!ca.is_empty()).then_some(ca),
+            download_hash: None,
+            download_size: None,
+            _other: HashMap::new(),
+        }))
+    }
+
+    /// Protect `path` from garbage collection for the lifetime of the connection
+    pub async fn add_temp_root(&self, path: &StorePath) -> Result<(), NixDaemonError> {
+        let mut stream = self.connection.lock().await;
+        write_u64(&mut *stream, OP_ADD_TEMP_ROOT).await?;
+        write_string(&mut *stream, &store_path_string(path)).await?;
+        stream.flush().await?;
+
+        process_stderr(&mut stream, self.protocol_version).await?;
+        read_u64(&mut *stream).await?;
+        Ok(())
+    }
+}
+
+fn store_path_string(path: &StorePath) -> String {
+    path.out_path().to_string_lossy().into_owned()
+}
+
+/// Read log messages sent by the daemon until it reports completion or an error
+///
+/// Messages are logged, activities are ignored.
+async fn process_stderr(
+    stream: &mut BufStream<UnixStream>,
+    protocol_version: u64,
+) -> Result<(), NixDaemonError> {
+    loop {
+        match read_u64(stream).await? {
+            STDERR_LAST => return Ok(()),
+            STDERR_NEXT => {
+                let msg = read_string(stream).await?;
+                debug!("nix daemon: {}", msg.trim_end());
+            },
+            STDERR_ERROR => return Err(read_error(stream, protocol_version).await?),
+            STDERR_START_ACTIVITY => {
+                let _id = read_u64(stream).await?;
+                let _level = read_u64(stream).await?;
+                let _activity_type = read_u64(stream).await?;
+                let text = read_string(stream).await?;
+                read_fields(stream).await?;
+                let _parent = read_u64(stream).await?;
+                debug!("nix daemon: {text}");
+            },
+            STDERR_STOP_ACTIVITY => {
+                read_u64(stream).await?;
+            },
+            STDERR_RESULT => {
+                let _id = read_u64(stream).await?;
+                let _result_type = read_u64(stream).await?;
+                read_fields(stream).await?;
+            },
+            message => return Err(NixDaemonError::UnexpectedMessage(message)),
+        }
+    }
+}
+
+/// Read the fields of an activity or result
+async fn read_fields(stream: &mut BufStream<UnixStream>) -> Result<(), NixDaemonError> {
+    for _ in 0..read_u64(stream).await? {
+        match read_u64(stream).await? {
+            0 => {
+                read_u64(stream).await?;
+            },
+            1 => {
+                read_string(stream).await?;
+            },
+            field_type => return Err(NixDaemonError::UnexpectedMessage(field_type)),
+        }
+    }
+    Ok(())
+}
+
+/// Read an error reported by the daemon
+async fn read_error(
+    stream: &mut BufStream<UnixStream>,
+    protocol_version: u64,
+) -> Result<NixDaemonError, NixDaemonError> {
+    if minor(protocol_version) < 26 {
+        let msg = read_string(stream).await?;
+        let _status = read_u64(stream).await?;
+        return Ok(NixDaemonError::Daemon(msg));
+    }
+
+    let _type = read_string(stream).await?;
+    let _level = read_u64(stream).await?;
+    let _name = read_string(stream).await?;
+    let msg = read_string(stream).await?;
+    let _have_pos = read_u64(stream).await?;
+    for _ in 0..read_u64(stream).await? {
+        let _have_pos = read_u64(stream).await?;
+        let _hint = read_string(stream).await?;
+    }
+    Ok(NixDaemonError::Daemon(msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATH: &str = "/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-python3-3.10.10";
+
+    /// A daemon answering the handshake and a single request
+    async fn mock_daemon(mut stream: UnixStream) {
+        assert_eq!(read_u64(&mut stream).await.unwrap(), WORKER_MAGIC_1);
+        write_u64(&mut stream, WORKER_MAGIC_2).await.unwrap();
+        write_u64(&mut stream, PROTOCOL_VERSION).await.unwrap();
+        assert_eq!(read_u64(&mut stream).await.unwrap(), PROTOCOL_VERSION);
+        assert!(!read_bool(&mut stream).await.unwrap());
+        assert!(!read_bool(&mut stream).await.unwrap());
+        write_string(&mut stream, "2.18.1").await.unwrap();
+        write_u64(&mut stream, 1).await.unwrap();
+        write_u64(&mut stream, STDERR_LAST).await.unwrap();
+
+        while let Ok(op) = read_u64(&mut stream).await {
+            let path = read_string(&mut stream).await.unwrap();
+            write_u64(&mut stream, STDERR_NEXT).await.unwrap();
+            write_string(&mut stream, "querying\n").await.unwrap();
+            write_u64(&mut stream, STDERR_LAST).await.unwrap();
+
+            match op {
+                OP_IS_VALID_PATH => write_bool(&mut stream, path == PATH).await.unwrap(),
+                OP_QUERY_PATH_INFO => {
+                    write_bool(&mut stream, true).await.unwrap();
+                    write_string(&mut stream, "").await.unwrap();
+                    write_string(&mut stream, &"ab".repeat(32)).await.unwrap();
+                    write_strings(&mut stream, &[PATH.to_string()])
+                        .await
+                        .unwrap();
+                    write_u64(&mut stream, 1688730350).await.unwrap();
+                    write_u64(&mut stream, 226560).await.unwrap();
+                    write_bool(&mut stream, false).await.unwrap();
+                    write_strings(&mut stream, &[]).await.unwrap();
+                    write_string(&mut stream, "").await.unwrap();
+                },
+                _ => panic!("unexpected op {op}"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn query_daemon() {
+        let (client, server) = UnixStream::pair().unwrap();
+        tokio::spawn(mock_daemon(server));
+
+        let daemon = NixDaemon::handshake(client).await.unwrap();
+        assert_eq!(daemon.daemon_version(), Some("2.18.1"));
+        assert_eq!(daemon.is_trusted(), Some(true));
+        assert_eq!(daemon.store_info().version.as_deref(), Some("2.18.1"));
+
+        let path = StorePath::from_path(PATH).unwrap();
+        assert!(daemon.is_valid_path(&path).await.unwrap());
+
+        let info = daemon.query_path_info(&path).await.unwrap().unwrap();
+        assert_eq!(info.path, path);
+        assert_eq!(info.nar_hash.unwrap().to_base16(), "ab".repeat(32));
+        assert_eq!(info.nar_size, Some(226560));
+        assert_eq!(info.references, [path]);
+        assert_eq!(info.deriver, None);
+    }
+
+    #[tokio::test]
+    async fn reject_invalid_daemon() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        tokio::spawn(async move {
+            read_u64(&mut server).await.unwrap();
+            write_u64(&mut server, 42).await.unwrap();
+        });
+
+        assert!(matches!(
+            NixDaemon::handshake(client).await,
+            Err(NixDaemonError::InvalidMagic(42))
+        ));
+    }
+}
@@ -0,0 +1,97 @@
+//! Serialization of the nix daemon worker protocol
+//!
+//! Integers are sent as 64 bit little endian,
+//! strings as their length followed by their bytes, padded with zeros to 8 bytes.
+
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Upper bound of the length of strings accepted from the daemon
+const MAX_STRING_LENGTH: u64 = 64 * 1024 * 1024;
+
+fn padding(len: u64) -> usize {
+    ((8 - len % 8) % 8) as usize
+}
+
+pub(super) async fn write_u64<W: AsyncWrite + Unpin>(writer: &mut W, n: u64) -> io::Result<()> {
+    writer.write_u64_le(n).await
+}
+
+pub(super) async fn write_bool<W: AsyncWrite + Unpin>(writer: &mut W, b: bool) -> io::Result<()> {
+    write_u64(writer, b as u64).await
+}
+
+pub(super) async fn write_string<W: AsyncWrite + Unpin>(writer: &mut W, s: &str) -> io::Result<()> {
+    let len = s.len() as u64;
+    write_u64(writer, len).await?;
+    writer.write_all(s.as_bytes()).await?;
+    writer.write_all(&[0; 8][..padding(len)]).await
+}
+
+pub(super) async fn write_strings<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    strings: &[String],
+) -> io::Result<()> {
+    write_u64(writer, strings.len() as u64).await?;
+    for s in strings {
+        write_string(writer, s).await?;
+    }
+    Ok(())
+}
+
+pub(super) async fn read_u64<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<u64> {
+    reader.read_u64_le().await
+}
+
+pub(super) async fn read_bool<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<bool> {
+    Ok(read_u64(reader).await? != 0)
+}
+
+pub(super) async fn read_string<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<String> {
+    let len = read_u64(reader).await?;
+    if len > MAX_STRING_LENGTH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("string of {len} bytes exceeds the maximum length"),
+        ));
+    }
+
+    let mut buf = vec![0; len as usize + padding(len)];
+    reader.read_exact(&mut buf).await?;
+    buf.truncate(len as usize);
+    String::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub(super) async fn read_strings<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Vec<String>> {
+    let len = read_u64(reader).await?;
+    let mut strings = Vec::new();
+    for _ in 0..len {
+        strings.push(read_string(reader).await?);
+    }
+    Ok(strings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn roundtrip() {
+        let mut buf = Vec::new();
+        write_string(&mut buf, "hello").await.unwrap();
+        write_strings(&mut buf, &["12345678".to_string(), String::new()])
+            .await
+            .unwrap();
+        write_bool(&mut buf, true).await.unwrap();
+
+        assert_eq!(&buf[..16], b"\x05\0\0\0\0\0\0\0hello\0\0\0");
+        assert_eq!(buf.len(), 16 + 8 + 16 + 8 + 8);
+
+        let mut reader = &buf[..];
+        assert_eq!(read_string(&mut reader).await.unwrap(), "hello");
+        assert_eq!(read_strings(&mut reader).await.unwrap(), ["12345678", ""]);
+        assert!(read_bool(&mut reader).await.unwrap());
+        assert!(reader.is_empty());
+    }
+}
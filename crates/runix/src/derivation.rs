@@ -0,0 +1,562 @@
+//! Store derivations as shown by `nix derivation show` and added by `nix derivation add`
+//!
+//! ```
+//! # use runix::derivation::Derivation;
+//! let derivation: Derivation = serde_json::from_str(
+//!     r#"{
+//!         "name": "source",
+//!         "system": "builtin",
+//!         "builder": "builtin:fetchurl",
+//!         "args": [],
+//!         "env": { "url": "https://example.com/source.tar.gz" },
+//!         "inputDrvs": {},
+//!         "inputSrcs": [],
+//!         "outputs": {
+//!             "out": {
+//!                 "hash": "5d41402abc4b2a76b9719d911017c592",
+//!                 "hashAlgo": "md5",
+//!                 "path": "/nix/store/0qpvcd2k0cj7ahms0bff93hyw8ycjm2w-source"
+//!             }
+//!         }
+//!     }"#,
+//! )
+//! .unwrap();
+//! assert!(derivation.is_fixed_output());
+//! assert_eq!(
+//!     derivation.outputs["out"].fixed_hash().unwrap().to_string(),
+//!     "md5-XUFAKrxLKna5cZ2REBfFkg=="
+//! );
+//! assert_eq!(
+//!     derivation.outputs["out"]
+//!         .content_address()
+//!         .unwrap()
+//!         .to_string(),
+//!     "fixed:md5:4jqlbi14cxf6wpcajbphm40hax"
+//! );
+//! ```
+//!
+//! Derivations in the store can also be read directly from their `.drv` files,
+//! which are written in the ATerm format, see [Derivation::read].
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+use crate::arguments::Stdin;
+use crate::content_address::{ContentAddress, ContentAddressMethod};
+use crate::hash::Hash;
+use crate::store_path::{DrvPath, StorePath, StorePathError};
+
+/// The variable holding the structured attributes in the environment of older versions of nix
+const STRUCTURED_ATTRS_VARIABLE: &str = "__json";
+
+/// A store derivation, see [crate::command::DerivationShow]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", try_from = "SerializedDerivation")]
+pub struct Derivation {
+    /// The name of the derivation, empty if not printed by older versions of nix
+    pub name: String,
+    pub system: String,
+    /// The builder, an executable in the store or a builtin such as `builtin:fetchurl`
+    pub builder: String,
+    pub args: Vec<String>,
+    pub env: BTreeMap<String, String>,
+    pub outputs: BTreeMap<String, DerivationOutput>,
+    pub input_srcs: BTreeSet<StorePath>,
+    /// The derivations this derivation depends on and which of their outputs it uses
+    pub input_drvs: BTreeMap<DrvPath, InputDrv>,
+    /// The attributes of a derivation with `__structuredAttrs = true`
+    ///
+    /// Versions of nix that do not print them separately pass them in [Derivation::env],
+    /// from where they are parsed as well.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub structured_attrs: Option<Map<String, Value>>,
+}
+
+#[derive(Debug, Error)]
+pub enum ParseDerivationError {
+    #[error("Could not read the derivation: {0}")]
+    Read(#[from] std::io::Error),
+    #[error("Invalid derivation at byte {position}: expected {expected}")]
+    Syntax {
+        position: usize,
+        expected: &'static str,
+    },
+    #[error("Invalid store path in derivation: {0}")]
+    StorePath(#[from] StorePathError),
+    #[error("Invalid structured attributes in derivation: {0}")]
+    StructuredAttrs(#[from] serde_json::Error),
+}
+
+impl Derivation {
+    /// Whether the derivation is a fixed output derivation, e.g. fetching a source
+    pub fn is_fixed_output(&self) -> bool {
+        !self.outputs.is_empty() && self.outputs.values().all(DerivationOutput::is_fixed_output)
+    }
+
+    /// Read the store derivation at `drv_path` without invoking nix
+    pub fn read(drv_path: &DrvPath) -> Result<Self, ParseDerivationError> {
+        Self::read_file(drv_path.as_path())
+    }
+
+    /// Read a derivation in the ATerm format from `path`, see [Derivation::from_aterm]
+    pub fn read_file(path: impl AsRef<Path>) -> Result<Self, ParseDerivationError> {
+        Self::from_aterm(&std::fs::read_to_string(path)?)
+    }
+
+    /// Parse a derivation in the ATerm format used by `.drv` files
+    ///
+    /// Supports derivations with dynamic outputs written by `xp-dyn-drv`.
+    /// The name is taken from the `name` variable of the environment.
+    ///
+    /// ```
+    /// # use runix::derivation::Derivation;
+    /// let derivation = Derivation::from_aterm(
+    ///     r#"Derive([("out","/nix/store/0qpvcd2k0cj7ahms0bff93hyw8ycjm2w-hello-2.12.1","","")],[("/nix/store/0p5q1h5cy5gcm0jb3lbgksp6jblm6k6x-bash-5.2-p15.drv",["out"])],["/nix/store/v6x3cs394jgqfbi0a42pam708flxaphh-default-builder.sh"],"x86_64-linux","/nix/store/1bfwb5g1sb6pm6kbcgbxl7ml0fjjd1lb-bash-5.2-p15/bin/bash",["-e","/nix/store/v6x3cs394jgqfbi0a42pam708flxaphh-default-builder.sh"],[("name","hello-2.12.1"),("out","/nix/store/0qpvcd2k0cj7ahms0bff93hyw8ycjm2w-hello-2.12.1")])"#,
+    /// )
+    /// .unwrap();
+    ///
+    /// assert_eq!(derivation.name, "hello-2.12.1");
+    /// assert_eq!(derivation.system, "x86_64-linux");
+    /// assert_eq!(derivation.input_drvs.len(), 1);
+    /// assert!(!derivation.is_fixed_output());
+    /// ```
+    pub fn from_aterm(aterm: &str) -> Result<Self, ParseDerivationError> {
+        ATermParser::new(aterm).derivation()
+    }
+}
+
+/// The schema of derivations printed by any supported version of nix
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SerializedDerivation {
+    #[serde(default)]
+    name: String,
+    system: String,
+    builder: String,
+    args: Vec<String>,
+    env: BTreeMap<String, String>,
+    outputs: BTreeMap<String, DerivationOutput>,
+    input_srcs: BTreeSet<StorePath>,
+    input_drvs: BTreeMap<DrvPath, InputDrv>,
+    #[serde(default)]
+    structured_attrs: Option<Map<String, Value>>,
+}
+
+impl TryFrom<SerializedDerivation> for Derivation {
+    type Error = serde_json::Error;
+
+    fn try_from(derivation: SerializedDerivation) -> Result<Self, Self::Error> {
+        let structured_attrs = match derivation.structured_attrs {
+            Some(attrs) => Some(attrs),
+            None => derivation
+                .env
+                .get(STRUCTURED_ATTRS_VARIABLE)
+                .map(|json| serde_json::from_str(json))
+                .transpose()?,
+        };
+
+        Ok(Derivation {
+            name: derivation.name,
+            system: derivation.system,
+            builder: derivation.builder,
+            args: derivation.args,
+            env: derivation.env,
+            outputs: derivation.outputs,
+            input_srcs: derivation.input_srcs,
+            input_drvs: derivation.input_drvs,
+            structured_attrs,
+        })
+    }
+}
+
+/// Pass the derivation to [crate::command::DerivationAdd]
+impl TryFrom<&Derivation> for Stdin {
+    type Error = serde_json::Error;
+
+    fn try_from(derivation: &Derivation) -> Result<Self, Self::Error> {
+        Ok(Stdin::Bytes(serde_json::to_vec(derivation)?))
+    }
+}
+
+/// An output of a [Derivation]
+///
+/// Which attributes are set depends on how the output is addressed:
+/// outputs addressed by their inputs only have a [DerivationOutput::path],
+/// fixed outputs additionally have a [DerivationOutput::hash],
+/// and content addressed outputs only have a [DerivationOutput::hash_algo],
+/// as their path is not known before they are built.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DerivationOutput {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<StorePath>,
+    /// The method and algorithm used to hash the output, e.g. `r:sha256` for the hash of its NAR
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash_algo: Option<String>,
+    /// The expected hash of a fixed output in base 16
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// Whether the output may differ every time it is built
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub impure: bool,
+}
+
+impl DerivationOutput {
+    pub fn is_fixed_output(&self) -> bool {
+        self.hash.is_some()
+    }
+
+    /// The expected [Hash] of a fixed output, [None] if the output is not fixed
+    /// or its hash is invalid
+    pub fn fixed_hash(&self) -> Option<Hash> {
+        let (_, algo) = ContentAddressMethod::from_hash_algo(self.hash_algo.as_deref()?).ok()?;
+        Hash::parse_with_algo(self.hash.as_deref()?, algo).ok()
+    }
+
+    /// The content address of a fixed output, from which its path can be computed
+    /// with [ContentAddress::store_path] before it is built
+    pub fn content_address(&self) -> Option<ContentAddress> {
+        let (method, _) = ContentAddressMethod::from_hash_algo(self.hash_algo.as_deref()?).ok()?;
+        Some(ContentAddress {
+            method,
+            hash: self.fixed_hash()?,
+        })
+    }
+}
+
+/// The outputs of an input derivation used by a [Derivation]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", from = "SerializedInputDrv")]
+pub struct InputDrv {
+    pub outputs: BTreeSet<String>,
+    /// Outputs of the derivations built by outputs of the input derivation, by output
+    pub dynamic_outputs: BTreeMap<String, InputDrv>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SerializedInputDrv {
+    /// A list of outputs, as printed prior to Nix 2.18
+    Outputs(BTreeSet<String>),
+    #[serde(rename_all = "camelCase")]
+    InputDrv {
+        outputs: BTreeSet<String>,
+        #[serde(default)]
+        dynamic_outputs: BTreeMap<String, InputDrv>,
+    },
+}
+
+impl From<SerializedInputDrv> for InputDrv {
+    fn from(input_drv: SerializedInputDrv) -> Self {
+        match input_drv {
+            SerializedInputDrv::Outputs(outputs) => InputDrv {
+                outputs,
+                ..Default::default()
+            },
+            SerializedInputDrv::InputDrv {
+                outputs,
+                dynamic_outputs,
+            } => InputDrv {
+                outputs,
+                dynamic_outputs,
+            },
+        }
+    }
+}
+
+/// Recursive descent parser of the ATerm format of derivations
+struct ATermParser<'a> {
+    input: &'a str,
+    position: usize,
+}
+
+impl<'a> ATermParser<'a> {
+    fn new(input: &'a str) -> Self {
+        ATermParser { input, position: 0 }
+    }
+
+    fn derivation(mut self) -> Result<Derivation, ParseDerivationError> {
+        let dynamic = if self.eat("DrvWithVersion(") {
+            self.string()?;
+            self.expect(",")?;
+            true
+        } else {
+            self.expect("Derive(")?;
+            false
+        };
+
+        let outputs = self
+            .list(|p| {
+                p.expect("(")?;
+                let name = p.string()?;
+                p.expect(",")?;
+                let path = p.string()?;
+                p.expect(",")?;
+                let hash_algo = p.string()?;
+                p.expect(",")?;
+                let hash = p.string()?;
+                p.expect(")")?;
+
+                let impure = hash == "impure";
+                let output = DerivationOutput {
+                    path: (!path.is_empty()).then(|| path.parse()).transpose()?,
+                    hash_algo: (!hash_algo.is_empty()).then_some(hash_algo),
+                    hash: (!hash.is_empty() && !impure).then_some(hash),
+                    impure,
+                };
+                Ok((name, output))
+            })?
+            .into_iter()
+            .collect();
+        self.expect(",")?;
+
+        let input_drvs = self
+            .list(|p| {
+                p.expect("(")?;
+                let drv_path = p.string()?.parse()?;
+                p.expect(",")?;
+                let input_drv = if dynamic {
+                    p.input_drv()?
+                } else {
+                    InputDrv {
+                        outputs: p.list(Self::string)?.into_iter().collect(),
+                        ..Default::default()
+                    }
+                };
+                p.expect(")")?;
+                Ok((drv_path, input_drv))
+            })?
+            .into_iter()
+            .collect();
+        self.expect(",")?;
+
+        let input_srcs = self
+            .list(|p| Ok(p.string()?.parse()?))?
+            .into_iter()
+            .collect();
+        self.expect(",")?;
+        let system = self.string()?;
+        self.expect(",")?;
+        let builder = self.string()?;
+        self.expect(",")?;
+        let args = self.list(Self::string)?;
+        self.expect(",")?;
+        let env: BTreeMap<String, String> = self
+            .list(|p| {
+                p.expect("(")?;
+                let name = p.string()?;
+                p.expect(",")?;
+                let value = p.string()?;
+                p.expect(")")?;
+                Ok((name, value))
+            })?
+            .into_iter()
+            .collect();
+        self.expect(")")?;
+        if self.position != self.input.trim_end().len() {
+            return Err(self.error("end of derivation"));
+        }
+
+        let structured_attrs = env
+            .get(STRUCTURED_ATTRS_VARIABLE)
+            .map(|json| serde_json::from_str(json))
+            .transpose()?;
+
+        Ok(Derivation {
+            name: env.get("name").cloned().unwrap_or_default(),
+            system,
+            builder,
+            args,
+            env,
+            outputs,
+            input_srcs,
+            input_drvs,
+            structured_attrs,
+        })
+    }
+
+    /// The outputs of an input derivation and of the derivations it builds,
+    /// e.g. `(["out"],[("out",(["lib"],[]))])`
+    fn input_drv(&mut self) -> Result<InputDrv, ParseDerivationError> {
+        self.expect("(")?;
+        let outputs = self.list(Self::string)?.into_iter().collect();
+        self.expect(",")?;
+        let dynamic_outputs = self
+            .list(|p| {
+                p.expect("(")?;
+                let output = p.string()?;
+                p.expect(",")?;
+                let input_drv = p.input_drv()?;
+                p.expect(")")?;
+                Ok((output, input_drv))
+            })?
+            .into_iter()
+            .collect();
+        self.expect(")")?;
+        Ok(InputDrv {
+            outputs,
+            dynamic_outputs,
+        })
+    }
+
+    /// A list of items parsed by `item`, e.g. `["a","b"]`
+    fn list<T>(
+        &mut self,
+        mut item: impl FnMut(&mut Self) -> Result<T, ParseDerivationError>,
+    ) -> Result<Vec<T>, ParseDerivationError> {
+        self.expect("[")?;
+        let mut items = Vec::new();
+        if self.eat("]") {
+            return Ok(items);
+        }
+        loop {
+            items.push(item(self)?);
+            if self.eat("]") {
+                return Ok(items);
+            }
+            self.expect(",")?;
+        }
+    }
+
+    /// A quoted string with `\\`, `\"`, `\n`, `\r` and `\t` escaped
+    fn string(&mut self) -> Result<String, ParseDerivationError> {
+        self.expect("\"")?;
+        let mut string = String::new();
+        let mut chars = self.input[self.position..].char_indices();
+        while let Some((offset, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.position += offset + 1;
+                    return Ok(string);
+                },
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => string.push('\n'),
+                    Some((_, 'r')) => string.push('\r'),
+                    Some((_, 't')) => string.push('\t'),
+                    Some((_, c)) => string.push(c),
+                    None => break,
+                },
+                c => string.push(c),
+            }
+        }
+        self.position = self.input.len();
+        Err(self.error("closing quote"))
+    }
+
+    /// Consume `token` if the remaining input starts with it
+    fn eat(&mut self, token: &str) -> bool {
+        let found = self.input[self.position..].starts_with(token);
+        if found {
+            self.position += token.len();
+        }
+        found
+    }
+
+    fn expect(&mut self, token: &'static str) -> Result<(), ParseDerivationError> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.error(token))
+        }
+    }
+
+    fn error(&self, expected: &'static str) -> ParseDerivationError {
+        ParseDerivationError::Syntax {
+            position: self.position,
+            expected,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_derivation() {
+        let derivations: BTreeMap<DrvPath, Derivation> = serde_json::from_str(
+            r#"
+{
+  "/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-hello-2.12.1.drv": {
+    "args": ["-e", "/nix/store/v6x3cs394jgqfbi0a42pam708flxaphh-default-builder.sh"],
+    "builder": "/nix/store/1bfwb5g1sb6pm6kbcgbxl7ml0fjjd1lb-bash-5.2-p15/bin/bash",
+    "env": {
+      "__json": "{\"pname\":\"hello\",\"version\":\"2.12.1\"}",
+      "out": "/nix/store/0qpvcd2k0cj7ahms0bff93hyw8ycjm2w-hello-2.12.1"
+    },
+    "inputDrvs": {
+      "/nix/store/0p5q1h5cy5gcm0jb3lbgksp6jblm6k6x-bash-5.2-p15.drv": ["out"],
+      "/nix/store/6x3j52kn1wdl9h5iq8y6l2ywmbzjwsxq-stdenv-linux.drv": {
+        "dynamicOutputs": {},
+        "outputs": ["out"]
+      }
+    },
+    "inputSrcs": ["/nix/store/v6x3cs394jgqfbi0a42pam708flxaphh-default-builder.sh"],
+    "name": "hello-2.12.1",
+    "outputs": {
+      "out": { "path": "/nix/store/0qpvcd2k0cj7ahms0bff93hyw8ycjm2w-hello-2.12.1" },
+      "dev": { "hashAlgo": "r:sha256" }
+    },
+    "system": "x86_64-linux"
+  }
+}
+            "#,
+        )
+        .expect("should parse");
+
+        let (drv_path, derivation) = derivations.into_iter().next().unwrap();
+        assert_eq!(
+            drv_path.basename(),
+            "7rjqb838snvvxcmpvck1smfxhkwzqal5-hello-2.12.1.drv"
+        );
+        assert!(!derivation.is_fixed_output());
+        assert_eq!(derivation.outputs["dev"].path, None);
+        assert!(derivation
+            .input_drvs
+            .values()
+            .all(|input| input.outputs == BTreeSet::from(["out".to_string()])));
+        assert_eq!(
+            derivation.structured_attrs.as_ref().unwrap()["pname"],
+            "hello"
+        );
+
+        let json = serde_json::to_string(&derivation).unwrap();
+        let added: Derivation = serde_json::from_str(&json).unwrap();
+        assert_eq!(added, derivation);
+    }
+
+    #[test]
+    fn parses_aterm() {
+        let aterm = r#"DrvWithVersion("xp-dyn-drv",[("dev","","r:sha256",""),("out","","r:sha256","impure")],[("/nix/store/6x3j52kn1wdl9h5iq8y6l2ywmbzjwsxq-stdenv-linux.drv",(["out"],[("out",(["lib"],[]))]))],[],"x86_64-linux","/bin/sh",["-c","echo \"hi\"\n"],[("__json","{\"pname\":\"hello\"}"),("name","hello")])"#;
+        let derivation = Derivation::from_aterm(aterm).unwrap();
+
+        assert_eq!(derivation.name, "hello");
+        assert_eq!(derivation.args[1], "echo \"hi\"\n");
+        assert_eq!(derivation.outputs["dev"], DerivationOutput {
+            hash_algo: Some("r:sha256".to_string()),
+            ..Default::default()
+        });
+        assert!(derivation.outputs["out"].impure);
+        let stdenv = derivation.input_drvs.values().next().unwrap();
+        assert_eq!(
+            stdenv.dynamic_outputs["out"].outputs,
+            BTreeSet::from(["lib".to_string()])
+        );
+        assert_eq!(
+            derivation.structured_attrs.as_ref().unwrap()["pname"],
+            "hello"
+        );
+
+        let fixed = r#"Derive([("out","/nix/store/0qpvcd2k0cj7ahms0bff93hyw8ycjm2w-source","md5","5d41402abc4b2a76b9719d911017c592")],[],[],"builtin","builtin:fetchurl",[],[("name","source")])"#;
+        assert!(Derivation::from_aterm(fixed).unwrap().is_fixed_output());
+
+        assert!(matches!(
+            Derivation::from_aterm(&fixed[..fixed.len() - 1]),
+            Err(ParseDerivationError::Syntax { expected: ")", .. })
+        ));
+    }
+}
@@ -0,0 +1,349 @@
+//! Development environments as printed by `nix print-dev-env --json`
+//!
+//! Rather than starting an interactive shell like `nix develop`,
+//! a [DevEnv] is applied to an environment to run arbitrary programs in it:
+//!
+//! ```no_run
+//! # use runix::command::PrintDevEnv;
+//! # use runix::command_line::NixCommandLine;
+//! # use runix::arguments::NixArgs;
+//! # use runix::RunTyped;
+//! # #[tokio::main]
+//! # async fn main() {
+//! let dev_env = PrintDevEnv::default()
+//!     .run_typed(&NixCommandLine::default(), &NixArgs::default())
+//!     .await
+//!     .unwrap();
+//!
+//! let status = dev_env
+//!     .command("cargo")
+//!     .arg("build")
+//!     .status()
+//!     .await
+//!     .unwrap();
+//! # }
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsStr;
+use std::fmt::Write;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use shell_escape::escape;
+use tokio::process::Command;
+
+/// Variables of the builder that `nix develop` does not apply to the shell
+const IGNORED_VARIABLES: &[&str] = &[
+    "BASHOPTS",
+    "HOME",
+    "NIX_BUILD_TOP",
+    "NIX_ENFORCE_PURITY",
+    "NIX_LOG_FD",
+    "NIX_REMOTE",
+    "PPID",
+    "SHELLOPTS",
+    "SSL_CERT_FILE",
+    "NIX_SSL_CERT_FILE",
+    "TEMP",
+    "TEMPDIR",
+    "TERM",
+    "TMP",
+    "TMPDIR",
+    "TZ",
+    "UID",
+];
+
+/// Search paths that `nix develop` prepends to those of the outer environment
+const PATH_VARIABLES: &[&str] = &["PATH", "XDG_DATA_DIRS"];
+
+/// A shell variable of a [DevEnv]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+pub enum DevEnvVariable {
+    /// An environment variable
+    Exported(String),
+    /// A shell variable that is not exported
+    Var(String),
+    Array(Vec<String>),
+    Associative(BTreeMap<String, String>),
+    /// A variable of a type not known to runix
+    #[serde(other)]
+    Unknown,
+}
+
+impl DevEnvVariable {
+    /// The value of a scalar variable, exported or not
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            DevEnvVariable::Exported(value) | DevEnvVariable::Var(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// A bash statement declaring the variable as `name`, [None] for unknown variables
+    ///
+    /// ```
+    /// # use std::collections::BTreeMap;
+    /// # use runix::dev_env::DevEnvVariable;
+    /// let array = DevEnvVariable::Array(vec!["a b".to_string(), "c".to_string()]);
+    /// assert_eq!(array.to_bash("xs").unwrap(), "declare -a xs=('a b' c)");
+    ///
+    /// let map = DevEnvVariable::Associative(BTreeMap::from([("k".to_string(), "v".to_string())]));
+    /// assert_eq!(map.to_bash("m").unwrap(), "declare -A m=([k]=v)");
+    /// ```
+    pub fn to_bash(&self, name: &str) -> Option<String> {
+        let statement = match self {
+            DevEnvVariable::Exported(value) => format!("export {name}={}", escape(value.into())),
+            DevEnvVariable::Var(value) => format!("{name}={}", escape(value.into())),
+            DevEnvVariable::Array(values) => {
+                let values: Vec<_> = values.iter().map(|value| escape(value.into())).collect();
+                format!("declare -a {name}=({})", values.join(" "))
+            },
+            DevEnvVariable::Associative(values) => {
+                let values: Vec<_> = values
+                    .iter()
+                    .map(|(key, value)| {
+                        format!("[{}]={}", escape(key.into()), escape(value.into()))
+                    })
+                    .collect();
+                format!("declare -A {name}=({})", values.join(" "))
+            },
+            DevEnvVariable::Unknown => return None,
+        };
+        Some(statement)
+    }
+}
+
+/// The attributes of a derivation using `__structuredAttrs`
+///
+/// Builders of such derivations read their attributes from files rather than variables.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StructuredAttrs {
+    /// The contents of `.attrs.json`
+    #[serde(rename = ".attrs.json")]
+    pub json: String,
+    /// The contents of `.attrs.sh`, declaring the attributes as bash variables
+    #[serde(rename = ".attrs.sh")]
+    pub sh: String,
+}
+
+impl StructuredAttrs {
+    /// The parsed contents of `.attrs.json`
+    pub fn attrs(&self) -> Result<Value, serde_json::Error> {
+        serde_json::from_str(&self.json)
+    }
+}
+
+/// The environment of a derivation's builder, see [crate::command::PrintDevEnv]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DevEnv {
+    pub variables: BTreeMap<String, DevEnvVariable>,
+    /// Shell functions defined by the builder, e.g. `buildPhase`, by name
+    #[serde(default)]
+    pub bash_functions: BTreeMap<String, String>,
+    /// The attributes of derivations using `__structuredAttrs`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub structured_attrs: Option<StructuredAttrs>,
+}
+
+impl DevEnv {
+    /// The exported variables, except those `nix develop` ignores
+    pub fn variables(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.variables
+            .iter()
+            .filter(|(name, _)| !IGNORED_VARIABLES.contains(&name.as_str()))
+            .filter_map(|(name, variable)| match variable {
+                DevEnvVariable::Exported(value) => Some((name.as_str(), value.as_str())),
+                _ => None,
+            })
+    }
+
+    /// Apply the variables to `base` like `nix develop` does
+    ///
+    /// Variables override those of `base`,
+    /// except search paths such as `PATH` which are prepended to them.
+    ///
+    /// ```
+    /// # use runix::dev_env::DevEnv;
+    /// let dev_env: DevEnv = serde_json::from_str(
+    ///     r#"{
+    ///         "variables": {
+    ///             "PATH": { "type": "exported", "value": "/nix/store/...-cargo/bin" },
+    ///             "HOME": { "type": "exported", "value": "/homeless-shelter" },
+    ///             "out": { "type": "exported", "value": "/nix/store/...-outputs/out" },
+    ///             "phases": { "type": "var", "value": "buildPhase" }
+    ///         },
+    ///         "bashFunctions": { "buildPhase": "cargo build" }
+    ///     }"#,
+    /// )
+    /// .unwrap();
+    ///
+    /// let env = dev_env.apply([
+    ///     ("PATH".to_string(), "/usr/bin".to_string()),
+    ///     ("HOME".to_string(), "/home/user".to_string()),
+    /// ]);
+    /// assert_eq!(env["PATH"], "/nix/store/...-cargo/bin:/usr/bin");
+    /// assert_eq!(env["HOME"], "/home/user");
+    /// assert_eq!(env["out"], "/nix/store/...-outputs/out");
+    /// assert!(!env.contains_key("phases"));
+    /// ```
+    pub fn apply(
+        &self,
+        base: impl IntoIterator<Item = (String, String)>,
+    ) -> HashMap<String, String> {
+        let mut env: HashMap<String, String> = base.into_iter().collect();
+        for (name, value) in self.variables() {
+            let value = match env.get(name) {
+                Some(outer) if PATH_VARIABLES.contains(&name) && !outer.is_empty() => {
+                    format!("{value}:{outer}")
+                },
+                _ => value.to_string(),
+            };
+            env.insert(name.to_string(), value);
+        }
+        env
+    }
+
+    /// A bash script setting up the environment in a running shell, similar to `nix print-dev-env`
+    ///
+    /// Declares all variables, except those `nix develop` ignores, and functions.
+    /// Search paths such as `PATH` are prepended to those of the shell, see [DevEnv::apply].
+    /// [DevEnv::structured_attrs] are not written to files.
+    ///
+    /// ```
+    /// # use runix::dev_env::DevEnv;
+    /// let dev_env: DevEnv = serde_json::from_str(
+    ///     r#"{
+    ///         "variables": {
+    ///             "PATH": { "type": "exported", "value": "/nix/store/...-cargo/bin" },
+    ///             "phases": { "type": "var", "value": "buildPhase checkPhase" }
+    ///         },
+    ///         "bashFunctions": { "buildPhase": "    cargo build\n" }
+    ///     }"#,
+    /// )
+    /// .unwrap();
+    ///
+    /// assert_eq!(
+    ///     dev_env.to_bash(),
+    ///     "\
+    /// export PATH=/nix/store/...-cargo/bin${PATH:+:$PATH}
+    /// phases='buildPhase checkPhase'
+    /// buildPhase ()
+    /// {
+    ///     cargo build
+    /// }
+    /// "
+    /// );
+    /// ```
+    pub fn to_bash(&self) -> String {
+        let mut script = String::new();
+        for (name, variable) in &self.variables {
+            if IGNORED_VARIABLES.contains(&name.as_str()) {
+                continue;
+            }
+            let Some(statement) = variable.to_bash(name) else {
+                continue;
+            };
+            script.push_str(&statement);
+            if PATH_VARIABLES.contains(&name.as_str()) {
+                let _ = write!(script, "${{{name}:+:${name}}}");
+            }
+            script.push('\n');
+        }
+        for (name, body) in &self.bash_functions {
+            let _ = writeln!(script, "{name} ()\n{{\n{body}}}");
+        }
+        script
+    }
+
+    /// Apply the variables to the environment of the host process, see [DevEnv::apply]
+    pub fn environment(&self) -> HashMap<String, String> {
+        self.apply(std::env::vars())
+    }
+
+    /// A command running `program` in the environment, see [DevEnv::environment]
+    ///
+    /// The program is run directly rather than through a shell,
+    /// so [DevEnv::bash_functions] and non exported variables are not available to it.
+    pub fn command(&self, program: impl AsRef<OsStr>) -> Command {
+        let mut command = Command::new(program);
+        command.env_clear().envs(self.environment());
+        command
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_dev_env() {
+        let dev_env: DevEnv = serde_json::from_str(
+            r#"
+{
+  "bashFunctions": {
+    "genericBuild": "    export GZIP_NO_TIMESTAMPS=1;\n    ...\n"
+  },
+  "variables": {
+    "BASHOPTS": { "type": "var", "value": "checkwinsize:cmdhist" },
+    "NIX_BUILD_TOP": { "type": "exported", "value": "/build" },
+    "XDG_DATA_DIRS": { "type": "exported", "value": "/nix/store/...-pkg-config/share" },
+    "buildInputs": { "type": "exported", "value": "" },
+    "outputs": { "type": "var", "value": "out" },
+    "pkgsHostTarget": { "type": "array", "value": ["/nix/store/...-rustc"] },
+    "envHostTargetHooks": { "type": "associative", "value": { "a": "b" } },
+    "FUNCNAME": { "type": "unknown" }
+  }
+}
+            "#,
+        )
+        .expect("should parse");
+
+        assert_eq!(
+            dev_env.variables["pkgsHostTarget"],
+            DevEnvVariable::Array(vec!["/nix/store/...-rustc".to_string()])
+        );
+        assert_eq!(dev_env.variables["FUNCNAME"], DevEnvVariable::Unknown);
+        assert!(dev_env.bash_functions.contains_key("genericBuild"));
+        assert_eq!(dev_env.variables().collect::<Vec<_>>(), [
+            ("XDG_DATA_DIRS", "/nix/store/...-pkg-config/share"),
+            ("buildInputs", ""),
+        ]);
+
+        let env = dev_env.apply([("XDG_DATA_DIRS".to_string(), String::new())]);
+        assert_eq!(env["XDG_DATA_DIRS"], "/nix/store/...-pkg-config/share");
+        assert_eq!(dev_env.structured_attrs, None);
+    }
+
+    #[test]
+    fn parses_structured_attrs() {
+        let dev_env: DevEnv = serde_json::from_str(
+            r#"
+{
+  "bashFunctions": {},
+  "structuredAttrs": {
+    ".attrs.json": "{\"outputs\":{\"out\":\"/nix/store/...-hello\"}}",
+    ".attrs.sh": "declare -A outputs=(['out']='/nix/store/...-hello' )\n"
+  },
+  "variables": {
+    "NIX_ATTRS_JSON_FILE": { "type": "exported", "value": "/build/.attrs.json" }
+  }
+}
+            "#,
+        )
+        .expect("should parse");
+
+        let structured_attrs = dev_env.structured_attrs.as_ref().unwrap();
+        assert_eq!(
+            structured_attrs.attrs().unwrap()["outputs"]["out"],
+            "/nix/store/...-hello"
+        );
+        assert!(structured_attrs.sh.starts_with("declare -A outputs"));
+        assert_eq!(
+            dev_env.variables["NIX_ATTRS_JSON_FILE"].as_str(),
+            Some("/build/.attrs.json")
+        );
+    }
+}
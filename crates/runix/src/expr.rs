@@ -0,0 +1,534 @@
+//! Nix expressions, built from a typed syntax tree rather than by string concatenation
+//!
+//! [Expr] renders valid nix code, quoting strings and attribute names as needed,
+//! e.g. to pass to `--expr` and `--apply` or to generate nix files.
+//! [Expr::pretty] renders on multiple lines, [Display] on a single line.
+//!
+//! ```
+//! # use runix::arguments::Apply;
+//! # use runix::expr::{Expr, Param};
+//! let expr = Expr::ident("pkgs")
+//!     .select("hello")
+//!     .select("overrideAttrs")
+//!     .call(Expr::lambda(
+//!         Param::ident("old"),
+//!         Expr::attrs([("name", Expr::from("hello-${patched}"))]),
+//!     ));
+//! assert_eq!(
+//!     expr.to_string(),
+//!     r#"pkgs.hello.overrideAttrs (old: { name = "hello-\${patched}"; })"#
+//! );
+//!
+//! let apply = Expr::lambda(
+//!     Param::pattern(["version", "src"], true),
+//!     Expr::list([Expr::ident("version"), Expr::Int(1)]),
+//! );
+//! assert_eq!(apply.to_string(), "{ version, src, ... }: [ version 1 ]");
+//!
+//! let apply: Apply = apply.into();
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Write};
+use std::path::PathBuf;
+
+/// Keywords, which must be quoted when used as attribute names
+const KEYWORDS: &[&str] = &[
+    "assert", "else", "if", "in", "inherit", "let", "or", "rec", "then", "with",
+];
+
+/// The indentation of [Expr::pretty]
+const INDENT: &str = "  ";
+
+/// A nix expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    /// A path, relative paths are relative to the file containing the expression
+    Path(PathBuf),
+    /// A lookup in the nix search path, e.g. `<nixpkgs>`
+    SearchPath(String),
+    /// A variable
+    Ident(String),
+    List(Vec<Expr>),
+    Attrs(BTreeMap<String, Expr>),
+    /// `rec { ... }`
+    RecAttrs(BTreeMap<String, Expr>),
+    /// An attribute path selected from an expression, e.g. `pkgs.hello`
+    Select(Box<Expr>, Vec<String>),
+    /// A function applied to an argument
+    Call(Box<Expr>, Box<Expr>),
+    Lambda(Param, Box<Expr>),
+    Let(BTreeMap<String, Expr>, Box<Expr>),
+    With(Box<Expr>, Box<Expr>),
+    /// Nix code inserted verbatim, wrapped in parentheses where required
+    Raw(String),
+}
+
+/// The parameter of a [Expr::Lambda]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Param {
+    /// `x: ...`
+    Ident(String),
+    /// `{ a, b ? default, ... } @ bind: ...`
+    Pattern {
+        fields: Vec<(String, Option<Expr>)>,
+        ellipsis: bool,
+        bind: Option<String>,
+    },
+}
+
+impl Param {
+    pub fn ident(name: impl Into<String>) -> Self {
+        Param::Ident(name.into())
+    }
+
+    /// A pattern of `fields` without defaults, allowing other attributes if `ellipsis`
+    pub fn pattern<S: Into<String>>(fields: impl IntoIterator<Item = S>, ellipsis: bool) -> Self {
+        Param::Pattern {
+            fields: fields
+                .into_iter()
+                .map(|field| (field.into(), None))
+                .collect(),
+            ellipsis,
+            bind: None,
+        }
+    }
+}
+
+impl Expr {
+    pub fn ident(name: impl Into<String>) -> Self {
+        Expr::Ident(name.into())
+    }
+
+    pub fn list(items: impl IntoIterator<Item = Expr>) -> Self {
+        Expr::List(items.into_iter().collect())
+    }
+
+    pub fn attrs<K: Into<String>>(attrs: impl IntoIterator<Item = (K, Expr)>) -> Self {
+        Expr::Attrs(attrs.into_iter().map(|(k, v)| (k.into(), v)).collect())
+    }
+
+    pub fn lambda(param: Param, body: Expr) -> Self {
+        Expr::Lambda(param, Box::new(body))
+    }
+
+    /// Select the attribute `name`, extending the attribute path of a selection
+    pub fn select(self, name: impl Into<String>) -> Self {
+        match self {
+            Expr::Select(expr, mut path) => {
+                path.push(name.into());
+                Expr::Select(expr, path)
+            },
+            expr => Expr::Select(Box::new(expr), vec![name.into()]),
+        }
+    }
+
+    /// Apply the function `self` to `arg`
+    pub fn call(self, arg: Expr) -> Self {
+        Expr::Call(Box::new(self), Box::new(arg))
+    }
+
+    /// The expression on multiple lines, with nested lists and attribute sets indented
+    pub fn pretty(&self) -> String {
+        let mut out = String::new();
+        self.render(&mut out, Some(0))
+            .expect("writing to a string does not fail");
+        out
+    }
+
+    /// Write the expression to `out`, on multiple lines at `indent` if [Some]
+    fn render(&self, out: &mut impl Write, indent: Option<usize>) -> std::fmt::Result {
+        match self {
+            Expr::Null => write!(out, "null"),
+            Expr::Bool(b) => write!(out, "{b}"),
+            // the literal of the smallest integer would overflow before being negated
+            Expr::Int(i64::MIN) => write!(out, "(-{} - 1)", i64::MAX),
+            Expr::Int(i) => write!(out, "{i}"),
+            Expr::Float(f) => write!(out, "{}", float(*f)),
+            Expr::String(s) => write!(out, "{}", quote(s)),
+            Expr::Path(path) => {
+                // drop repeated and trailing separators, which path literals may not contain
+                let path: PathBuf = path.components().collect();
+                let path = match path.to_string_lossy() {
+                    path if path.is_empty() => ".".into(),
+                    path if path == "/" => "/.".into(),
+                    path => path,
+                };
+                let literal = path
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | '+' | '/'))
+                    && !path.ends_with('/');
+                match (literal, path.starts_with('/')) {
+                    (true, true) => write!(out, "{path}"),
+                    (true, false) if path.starts_with("./") || path.starts_with("../") => {
+                        write!(out, "{path}")
+                    },
+                    (true, false) => write!(out, "./{path}"),
+                    // paths that are not valid path literals are concatenated to a root
+                    (false, true) => write!(out, "(/. + {})", quote(&path)),
+                    (false, false) => write!(out, "(./. + {})", quote(&format!("/{path}"))),
+                }
+            },
+            Expr::SearchPath(name) => write!(out, "<{name}>"),
+            Expr::Ident(name) => write!(out, "{name}"),
+            Expr::List(items) => {
+                if items.is_empty() {
+                    return write!(out, "[ ]");
+                }
+                write!(out, "[")?;
+                for item in items {
+                    newline(out, indent.map(|i| i + 1))?;
+                    item.render_atomic(out, indent.map(|i| i + 1))?;
+                }
+                newline(out, indent)?;
+                write!(out, "]")
+            },
+            Expr::Attrs(attrs) => render_bindings(out, attrs, indent),
+            Expr::RecAttrs(attrs) => {
+                write!(out, "rec ")?;
+                render_bindings(out, attrs, indent)
+            },
+            Expr::Select(expr, path) => {
+                expr.render_atomic(out, indent)?;
+                for name in path {
+                    write!(out, ".{}", attr_name(name))?;
+                }
+                Ok(())
+            },
+            Expr::Call(function, arg) => {
+                match **function {
+                    Expr::Call(..) => function.render(out, indent)?,
+                    _ => function.render_atomic(out, indent)?,
+                }
+                write!(out, " ")?;
+                arg.render_atomic(out, indent)
+            },
+            Expr::Lambda(param, body) => {
+                match param {
+                    Param::Ident(name) => write!(out, "{name}")?,
+                    Param::Pattern {
+                        fields,
+                        ellipsis,
+                        bind,
+                    } => {
+                        let mut fields: Vec<String> = fields
+                            .iter()
+                            .map(|(name, default)| match default {
+                                Some(default) => format!("{name} ? {default}"),
+                                None => name.clone(),
+                            })
+                            .collect();
+                        if *ellipsis {
+                            fields.push("...".to_string());
+                        }
+                        if fields.is_empty() {
+                            write!(out, "{{ }}")?;
+                        } else {
+                            write!(out, "{{ {} }}", fields.join(", "))?;
+                        }
+                        if let Some(bind) = bind {
+                            write!(out, " @ {bind}")?;
+                        }
+                    },
+                }
+                write!(out, ": ")?;
+                body.render(out, indent)
+            },
+            Expr::Let(bindings, body) => {
+                write!(out, "let")?;
+                for (name, value) in bindings {
+                    newline(out, indent.map(|i| i + 1))?;
+                    render_binding(out, name, value, indent.map(|i| i + 1))?;
+                }
+                newline(out, indent)?;
+                write!(out, "in")?;
+                newline(out, indent)?;
+                body.render(out, indent)
+            },
+            Expr::With(scope, body) => {
+                write!(out, "with ")?;
+                scope.render(out, indent)?;
+                write!(out, "; ")?;
+                body.render(out, indent)
+            },
+            Expr::Raw(code) => write!(out, "{code}"),
+        }
+    }
+
+    /// Render the expression, in parentheses unless it binds tighter than function application
+    fn render_atomic(&self, out: &mut impl Write, indent: Option<usize>) -> std::fmt::Result {
+        let atomic = match self {
+            Expr::Int(i) => *i >= 0 || *i == i64::MIN,
+            // infinity and NaN are rendered in parentheses
+            Expr::Float(f) => f.is_sign_positive() || !f.is_finite(),
+            Expr::Call(..) | Expr::Lambda(..) | Expr::Let(..) | Expr::With(..) | Expr::Raw(_) => {
+                false
+            },
+            _ => true,
+        };
+        if atomic {
+            return self.render(out, indent);
+        }
+        write!(out, "(")?;
+        self.render(out, indent)?;
+        write!(out, ")")
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.render(f, None)
+    }
+}
+
+impl From<Expr> for String {
+    fn from(expr: Expr) -> Self {
+        expr.to_string()
+    }
+}
+
+impl From<&str> for Expr {
+    fn from(s: &str) -> Self {
+        Expr::String(s.to_string())
+    }
+}
+
+impl From<String> for Expr {
+    fn from(s: String) -> Self {
+        Expr::String(s)
+    }
+}
+
+impl From<bool> for Expr {
+    fn from(b: bool) -> Self {
+        Expr::Bool(b)
+    }
+}
+
+impl From<i64> for Expr {
+    fn from(i: i64) -> Self {
+        Expr::Int(i)
+    }
+}
+
+impl From<PathBuf> for Expr {
+    fn from(path: PathBuf) -> Self {
+        Expr::Path(path)
+    }
+}
+
+impl<T: Into<Expr>> From<Vec<T>> for Expr {
+    fn from(items: Vec<T>) -> Self {
+        Expr::list(items.into_iter().map(Into::into))
+    }
+}
+
+impl<T: Into<Expr>> From<Option<T>> for Expr {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Expr::Null, Into::into)
+    }
+}
+
+/// Write a line break and indentation if rendering on multiple lines, otherwise a space
+fn newline(out: &mut impl Write, indent: Option<usize>) -> std::fmt::Result {
+    match indent {
+        Some(indent) => write!(out, "\n{}", INDENT.repeat(indent)),
+        None => write!(out, " "),
+    }
+}
+
+fn render_bindings(
+    out: &mut impl Write,
+    attrs: &BTreeMap<String, Expr>,
+    indent: Option<usize>,
+) -> std::fmt::Result {
+    if attrs.is_empty() {
+        return write!(out, "{{ }}");
+    }
+    write!(out, "{{")?;
+    for (name, value) in attrs {
+        newline(out, indent.map(|i| i + 1))?;
+        render_binding(out, name, value, indent.map(|i| i + 1))?;
+    }
+    newline(out, indent)?;
+    write!(out, "}}")
+}
+
+fn render_binding(
+    out: &mut impl Write,
+    name: &str,
+    value: &Expr,
+    indent: Option<usize>,
+) -> std::fmt::Result {
+    write!(out, "{} = ", attr_name(name))?;
+    value.render(out, indent)?;
+    write!(out, ";")
+}
+
+/// Whether `name` can be used as variable, i.e. is an identifier and not a keyword
+pub fn is_identifier(name: &str) -> bool {
+    name.chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '\'' | '-'))
+        && !KEYWORDS.contains(&name)
+}
+
+/// `name` as attribute name, quoted unless it is a valid identifier
+fn attr_name(name: &str) -> String {
+    if is_identifier(name) {
+        name.to_string()
+    } else {
+        quote(name)
+    }
+}
+
+/// `f` as float literal, which nix requires to have a fractional part, e.g. `1.0e16`
+///
+/// Nix has no literals for infinity and NaN, they are rendered as overflowing expressions.
+fn float(f: f64) -> String {
+    if f.is_nan() {
+        return "(1.0e308 * 10.0 - 1.0e308 * 10.0)".to_string();
+    }
+    if f.is_infinite() {
+        let sign = if f.is_sign_negative() { "-" } else { "" };
+        return format!("({sign}1.0e308 * 10.0)");
+    }
+
+    let literal = format!("{f:?}");
+    match literal.split_once('e') {
+        Some((mantissa, exponent)) if !mantissa.contains('.') => {
+            format!("{mantissa}.0e{exponent}")
+        },
+        _ => literal,
+    }
+}
+
+/// `s` as double quoted string literal
+fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            '$' if chars.peek() == Some(&'{') => quoted.push_str("\\$"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_strings_and_names() {
+        let expr = Expr::attrs([
+            ("with spaces", Expr::from("a \"quoted\" ${x}\n$y \\")),
+            ("in", Expr::Null),
+            ("x86_64-linux", Expr::Float(-1.0)),
+        ]);
+        assert_eq!(
+            expr.to_string(),
+            r#"{ "in" = null; "with spaces" = "a \"quoted\" \${x}\n$y \\"; x86_64-linux = -1.0; }"#
+        );
+    }
+
+    #[test]
+    fn renders_floats() {
+        let floats: Vec<_> = [1.0, 1e16, -1e16, 1.5e-7, f64::INFINITY, f64::NAN]
+            .into_iter()
+            .map(|f| Expr::list([Expr::Float(f)]).to_string())
+            .collect();
+        assert_eq!(floats, [
+            "[ 1.0 ]",
+            "[ 1.0e16 ]",
+            "[ (-1.0e16) ]",
+            "[ 1.5e-7 ]",
+            "[ (1.0e308 * 10.0) ]",
+            "[ (1.0e308 * 10.0 - 1.0e308 * 10.0) ]"
+        ]);
+    }
+
+    #[test]
+    fn renders_paths() {
+        let paths: Vec<_> = [
+            "/nix/store",
+            "flake.nix",
+            "../a b",
+            "/a b",
+            "",
+            "/",
+            "a//b/",
+        ]
+        .into_iter()
+        .map(|path| Expr::Path(path.into()).to_string())
+        .collect();
+        assert_eq!(paths, [
+            "/nix/store",
+            "./flake.nix",
+            r#"(./. + "/../a b")"#,
+            r#"(/. + "/a b")"#,
+            "./.",
+            "/.",
+            "./a/b"
+        ]);
+    }
+
+    #[test]
+    fn parenthesizes_arguments() {
+        let expr = Expr::ident("import")
+            .call(Expr::SearchPath("nixpkgs".to_string()))
+            .call(Expr::attrs([("system", Expr::from("x86_64-linux"))]))
+            .select("lib")
+            .select("id")
+            .call(Expr::Int(-1))
+            .call(Expr::ident("f").call(Expr::list([Expr::ident("f").call(Expr::Null)])))
+            .call(Expr::Int(i64::MIN));
+        assert_eq!(
+            expr.to_string(),
+            r#"(import <nixpkgs> { system = "x86_64-linux"; }).lib.id (-1) (f [ (f null) ]) (-9223372036854775807 - 1)"#
+        );
+    }
+
+    #[test]
+    fn renders_pretty() {
+        let expr = Expr::Let(
+            BTreeMap::from([("x".to_string(), Expr::list([Expr::Int(1), Expr::Int(2)]))]),
+            Box::new(Expr::attrs([
+                ("empty", Expr::list([])),
+                ("nested", Expr::attrs([("x", Expr::ident("x"))])),
+            ])),
+        );
+        assert_eq!(
+            expr.pretty(),
+            "\
+let
+  x = [
+    1
+    2
+  ];
+in
+{
+  empty = [ ];
+  nested = {
+    x = x;
+  };
+}"
+        );
+    }
+}
@@ -0,0 +1,332 @@
+//! The [NixFfi] backend, available with the `ffi` feature
+//!
+//! Evaluates expressions and queries the store in-process using the Nix C API
+//! (`libnixutilc`, `libnixstorec` and `libnixexprc`),
+//! avoiding the cost of spawning nix and (de)serializing json.
+//! The libraries need to be available to the linker, e.g. in a nix shell providing `nix.dev`.
+//!
+//! [crate::command_line::NixCommandLine] remains the default backend,
+//! [NixFfi] implements a subset of operations for performance sensitive consumers.
+//!
+//! ```no_run
+//! # use std::path::Path;
+//! # use runix::ffi::NixFfi;
+//! let nix = NixFfi::open(None).unwrap();
+//! let value = nix.eval("{ a = 1 + 1; }", Path::new(".")).unwrap();
+//! assert_eq!(value, serde_json::json!({ "a": 2 }));
+//! ```
+
+use std::collections::BTreeMap;
+use std::ffi::{c_char, c_uint, c_void, CStr, CString, NulError};
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+use once_cell::sync::OnceCell;
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::store_path::StorePath;
+use crate::NixBackend;
+
+mod sys;
+
+#[derive(Debug, Clone, Error)]
+pub enum NixFfiError {
+    #[error("Nix failed ({code}): {message}")]
+    Nix { code: i32, message: String },
+    #[error("Argument contains a nul byte: {0}")]
+    Nul(#[from] NulError),
+    #[error("Values of type {0} can not be represented as json")]
+    Unrepresentable(i32),
+}
+
+/// A store and evaluator of the nix library
+///
+/// Not thread safe, use one instance per thread instead.
+pub struct NixFfi {
+    store: *mut sys::Store,
+    state: *mut sys::EvalState,
+}
+
+impl NixBackend for NixFfi {}
+
+impl NixFfi {
+    /// Open the store at `uri`, or the default store, and create an evaluator using it
+    pub fn open(uri: Option<&str>) -> Result<Self, NixFfiError> {
+        init()?;
+
+        let ctx = Context::new();
+        let uri = uri.map(CString::new).transpose()?;
+
+        // SAFETY: uri is either null or a valid nul terminated string
+        let store = unsafe {
+            sys::nix_store_open(
+                ctx.0,
+                uri.as_ref().map_or(ptr::null(), |uri| uri.as_ptr()),
+                ptr::null_mut(),
+            )
+        };
+        ctx.check_ptr(store)?;
+
+        let mut lookup_path = [ptr::null()];
+        // SAFETY: store is valid, lookup_path is a null terminated array
+        let state = unsafe { sys::nix_state_create(ctx.0, lookup_path.as_mut_ptr(), store) };
+        if let Err(e) = ctx.check_ptr(state) {
+            // SAFETY: store was opened above and is not used anymore
+            unsafe { sys::nix_store_free(store) };
+            return Err(e);
+        }
+
+        Ok(NixFfi { store, state })
+    }
+
+    /// The version of nix used by the store
+    pub fn store_version(&self) -> Result<String, NixFfiError> {
+        let ctx = Context::new();
+        let mut version = String::new();
+        // SAFETY: version outlives the call
+        ctx.check(unsafe {
+            sys::nix_store_get_version(
+                ctx.0,
+                self.store,
+                collect_string,
+                &mut version as *mut String as *mut c_void,
+            )
+        })?;
+        Ok(version)
+    }
+
+    /// Whether `path` is valid, i.e. exists in the store
+    pub fn is_valid_path(&self, path: &StorePath) -> Result<bool, NixFfiError> {
+        let ctx = Context::new();
+        let path = self.parse_path(&ctx, path)?;
+        // SAFETY: store and path are valid
+        let valid = unsafe { sys::nix_store_is_valid_path(ctx.0, self.store, path.0) };
+        ctx.check(unsafe { sys::nix_err_code(ctx.0) })?;
+        Ok(valid)
+    }
+
+    /// Build or substitute `path`, returning the paths of its outputs by name
+    pub fn realise(&self, path: &StorePath) -> Result<BTreeMap<String, PathBuf>, NixFfiError> {
+        unsafe extern "C" fn collect_output(
+            user_data: *mut c_void,
+            name: *const c_char,
+            out: *const c_char,
+        ) {
+            let outputs = &mut *(user_data as *mut BTreeMap<String, PathBuf>);
+            outputs.insert(
+                CStr::from_ptr(name).to_string_lossy().into_owned(),
+                PathBuf::from(CStr::from_ptr(out).to_string_lossy().into_owned()),
+            );
+        }
+
+        let ctx = Context::new();
+        let path = self.parse_path(&ctx, path)?;
+        let mut outputs = BTreeMap::new();
+        // SAFETY: outputs outlives the call
+        ctx.check(unsafe {
+            sys::nix_store_realise(
+                ctx.0,
+                self.store,
+                path.0,
+                &mut outputs as *mut BTreeMap<String, PathBuf> as *mut c_void,
+                Some(collect_output),
+            )
+        })?;
+        Ok(outputs)
+    }
+
+    /// Evaluate `expr` and convert the result to json
+    ///
+    /// Relative paths in `expr` are resolved against `base_path`.
+    /// Fails for values without json representation, i.e. functions.
+    pub fn eval(&self, expr: &str, base_path: &Path) -> Result<Value, NixFfiError> {
+        let ctx = Context::new();
+        let expr = CString::new(expr)?;
+        let base_path = CString::new(base_path.to_string_lossy().into_owned())?;
+
+        // SAFETY: state is valid
+        let value = ValueRef(ctx.check_ptr(unsafe { sys::nix_alloc_value(ctx.0, self.state) })?);
+        // SAFETY: expr and base_path are valid nul terminated strings
+        ctx.check(unsafe {
+            sys::nix_expr_eval_from_string(
+                ctx.0,
+                self.state,
+                expr.as_ptr(),
+                base_path.as_ptr(),
+                value.0,
+            )
+        })?;
+        self.to_json(&ctx, &value)
+    }
+
+    fn parse_path(&self, ctx: &Context, path: &StorePath) -> Result<PathRef, NixFfiError> {
+        let path = CString::new(path.out_path().to_string_lossy().into_owned())?;
+        // SAFETY: path is a valid nul terminated string
+        let path = unsafe { sys::nix_store_parse_path(ctx.0, self.store, path.as_ptr()) };
+        Ok(PathRef(ctx.check_ptr(path)?))
+    }
+
+    fn to_json(&self, ctx: &Context, value: &ValueRef) -> Result<Value, NixFfiError> {
+        // SAFETY: all values are obtained from the evaluator of self
+        // and are kept alive by ValueRef
+        unsafe {
+            ctx.check(sys::nix_value_force(ctx.0, self.state, value.0))?;
+
+            let json = match sys::nix_get_type(ctx.0, value.0) {
+                sys::NIX_TYPE_NULL => Value::Null,
+                sys::NIX_TYPE_BOOL => Value::Bool(sys::nix_get_bool(ctx.0, value.0)),
+                sys::NIX_TYPE_INT => Value::from(sys::nix_get_int(ctx.0, value.0)),
+                sys::NIX_TYPE_FLOAT => Value::from(sys::nix_get_float(ctx.0, value.0)),
+                sys::NIX_TYPE_STRING => {
+                    let mut string = String::new();
+                    ctx.check(sys::nix_get_string(
+                        ctx.0,
+                        value.0,
+                        collect_string,
+                        &mut string as *mut String as *mut c_void,
+                    ))?;
+                    Value::String(string)
+                },
+                sys::NIX_TYPE_PATH => {
+                    let path =
+                        ctx.check_ptr(sys::nix_get_path_string(ctx.0, value.0).cast_mut())?;
+                    Value::String(CStr::from_ptr(path).to_string_lossy().into_owned())
+                },
+                sys::NIX_TYPE_LIST => {
+                    let mut list = Vec::new();
+                    for i in 0..sys::nix_get_list_size(ctx.0, value.0) {
+                        let item = sys::nix_get_list_byidx(ctx.0, value.0, self.state, i);
+                        let item = ValueRef(ctx.check_ptr(item)?);
+                        list.push(self.to_json(ctx, &item)?);
+                    }
+                    Value::Array(list)
+                },
+                sys::NIX_TYPE_ATTRS => {
+                    let mut attrs = serde_json::Map::new();
+                    for i in 0..sys::nix_get_attrs_size(ctx.0, value.0) {
+                        let mut name = ptr::null();
+                        let attr =
+                            sys::nix_get_attr_byidx(ctx.0, value.0, self.state, i, &mut name);
+                        let attr = ValueRef(ctx.check_ptr(attr)?);
+                        attrs.insert(
+                            CStr::from_ptr(name).to_string_lossy().into_owned(),
+                            self.to_json(ctx, &attr)?,
+                        );
+                    }
+                    Value::Object(attrs)
+                },
+                value_type => {
+                    ctx.check(sys::nix_err_code(ctx.0))?;
+                    return Err(NixFfiError::Unrepresentable(value_type));
+                },
+            };
+            ctx.check(sys::nix_err_code(ctx.0))?;
+            Ok(json)
+        }
+    }
+}
+
+impl Drop for NixFfi {
+    fn drop(&mut self) {
+        // SAFETY: both were created in NixFfi::open and are not used after this
+        unsafe {
+            sys::nix_state_free(self.state);
+            sys::nix_store_free(self.store);
+        }
+    }
+}
+
+/// Initialize the nix libraries once per process
+fn init() -> Result<(), NixFfiError> {
+    static INIT: OnceCell<Result<(), NixFfiError>> = OnceCell::new();
+
+    INIT.get_or_init(|| {
+        let ctx = Context::new();
+        // SAFETY: ctx is valid
+        unsafe {
+            ctx.check(sys::nix_libutil_init(ctx.0))?;
+            ctx.check(sys::nix_libstore_init(ctx.0))?;
+            ctx.check(sys::nix_libexpr_init(ctx.0))?;
+        }
+        Ok(())
+    })
+    .clone()
+}
+
+/// A `nix_c_context`, receiving errors of API calls
+struct Context(*mut sys::NixCContext);
+
+impl Context {
+    fn new() -> Self {
+        // SAFETY: no preconditions
+        Context(unsafe { sys::nix_c_context_create() })
+    }
+
+    /// Turn the error code returned by a call into an error with its message
+    fn check(&self, code: sys::NixErr) -> Result<(), NixFfiError> {
+        if code == sys::NIX_OK {
+            return Ok(());
+        }
+
+        let mut len: c_uint = 0;
+        // SAFETY: the message is copied before the next call using self
+        let message = unsafe {
+            let message = sys::nix_err_msg(ptr::null_mut(), self.0, &mut len);
+            if message.is_null() {
+                String::new()
+            } else {
+                let bytes = std::slice::from_raw_parts(message as *const u8, len as usize);
+                String::from_utf8_lossy(bytes).into_owned()
+            }
+        };
+        Err(NixFfiError::Nix { code, message })
+    }
+
+    /// Check calls returning null on failure
+    fn check_ptr<T>(&self, ptr: *mut T) -> Result<*mut T, NixFfiError> {
+        // SAFETY: self is valid
+        self.check(unsafe { sys::nix_err_code(self.0) })?;
+        if ptr.is_null() {
+            return Err(NixFfiError::Nix {
+                code: -1,
+                message: "nix returned null".to_string(),
+            });
+        }
+        Ok(ptr)
+    }
+}
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        // SAFETY: created in Context::new
+        unsafe { sys::nix_c_context_free(self.0) }
+    }
+}
+
+/// A store path parsed by the store, freed on drop
+struct PathRef(*mut sys::StorePath);
+
+impl Drop for PathRef {
+    fn drop(&mut self) {
+        // SAFETY: created by nix_store_parse_path
+        unsafe { sys::nix_store_path_free(self.0) }
+    }
+}
+
+/// A reference to a value of the evaluator, released on drop
+struct ValueRef(*mut sys::NixValue);
+
+impl Drop for ValueRef {
+    fn drop(&mut self) {
+        // SAFETY: the value was allocated by or obtained from the evaluator
+        unsafe { sys::nix_gc_decref(ptr::null_mut(), self.0 as *const c_void) };
+    }
+}
+
+/// [sys::GetStringCallback] appending to the [String] passed as `user_data`
+unsafe extern "C" fn collect_string(start: *const c_char, n: c_uint, user_data: *mut c_void) {
+    let string = &mut *(user_data as *mut String);
+    let bytes = std::slice::from_raw_parts(start as *const u8, n as usize);
+    string.push_str(&String::from_utf8_lossy(bytes));
+}
@@ -0,0 +1,156 @@
+//! Raw bindings of the subset of the Nix C API used by [super::NixFfi]
+//!
+//! Reference:
+//! [nix_api_util.h](https://github.com/NixOS/nix/blob/master/src/libutil-c/nix_api_util.h),
+//! [nix_api_store.h](https://github.com/NixOS/nix/blob/master/src/libstore-c/nix_api_store.h),
+//! [nix_api_expr.h](https://github.com/NixOS/nix/blob/master/src/libexpr-c/nix_api_expr.h),
+//! [nix_api_value.h](https://github.com/NixOS/nix/blob/master/src/libexpr-c/nix_api_value.h)
+
+use std::ffi::{c_char, c_uint, c_void};
+
+/// Error code returned by most functions, [NIX_OK] on success
+pub type NixErr = i32;
+pub const NIX_OK: NixErr = 0;
+
+/// Type of a value, as returned by [nix_get_type]
+pub type ValueType = i32;
+pub const NIX_TYPE_INT: ValueType = 1;
+pub const NIX_TYPE_FLOAT: ValueType = 2;
+pub const NIX_TYPE_BOOL: ValueType = 3;
+pub const NIX_TYPE_STRING: ValueType = 4;
+pub const NIX_TYPE_PATH: ValueType = 5;
+pub const NIX_TYPE_NULL: ValueType = 6;
+pub const NIX_TYPE_ATTRS: ValueType = 7;
+pub const NIX_TYPE_LIST: ValueType = 8;
+
+#[repr(C)]
+pub struct NixCContext {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct Store {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct StorePath {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct EvalState {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct NixValue {
+    _private: [u8; 0],
+}
+
+/// Receives strings returned by the API, valid only for the duration of the call
+pub type GetStringCallback =
+    unsafe extern "C" fn(start: *const c_char, n: c_uint, user_data: *mut c_void);
+
+/// Receives the outputs of a realised path
+pub type RealiseCallback =
+    unsafe extern "C" fn(user_data: *mut c_void, outname: *const c_char, out: *const c_char);
+
+#[link(name = "nixutilc")]
+extern "C" {
+    pub fn nix_c_context_create() -> *mut NixCContext;
+    pub fn nix_c_context_free(context: *mut NixCContext);
+    pub fn nix_libutil_init(context: *mut NixCContext) -> NixErr;
+    pub fn nix_err_code(context: *const NixCContext) -> NixErr;
+    pub fn nix_err_msg(
+        context: *mut NixCContext,
+        read_context: *const NixCContext,
+        n: *mut c_uint,
+    ) -> *const c_char;
+}
+
+#[link(name = "nixstorec")]
+extern "C" {
+    pub fn nix_libstore_init(context: *mut NixCContext) -> NixErr;
+    pub fn nix_store_open(
+        context: *mut NixCContext,
+        uri: *const c_char,
+        params: *mut *mut *const c_char,
+    ) -> *mut Store;
+    pub fn nix_store_free(store: *mut Store);
+    pub fn nix_store_get_version(
+        context: *mut NixCContext,
+        store: *mut Store,
+        callback: GetStringCallback,
+        user_data: *mut c_void,
+    ) -> NixErr;
+    pub fn nix_store_parse_path(
+        context: *mut NixCContext,
+        store: *mut Store,
+        path: *const c_char,
+    ) -> *mut StorePath;
+    pub fn nix_store_path_free(path: *mut StorePath);
+    pub fn nix_store_is_valid_path(
+        context: *mut NixCContext,
+        store: *mut Store,
+        path: *mut StorePath,
+    ) -> bool;
+    pub fn nix_store_realise(
+        context: *mut NixCContext,
+        store: *mut Store,
+        path: *mut StorePath,
+        user_data: *mut c_void,
+        callback: Option<RealiseCallback>,
+    ) -> NixErr;
+}
+
+#[link(name = "nixexprc")]
+extern "C" {
+    pub fn nix_libexpr_init(context: *mut NixCContext) -> NixErr;
+    pub fn nix_state_create(
+        context: *mut NixCContext,
+        lookup_path: *mut *const c_char,
+        store: *mut Store,
+    ) -> *mut EvalState;
+    pub fn nix_state_free(state: *mut EvalState);
+    pub fn nix_alloc_value(context: *mut NixCContext, state: *mut EvalState) -> *mut NixValue;
+    pub fn nix_gc_decref(context: *mut NixCContext, object: *const c_void) -> NixErr;
+    pub fn nix_expr_eval_from_string(
+        context: *mut NixCContext,
+        state: *mut EvalState,
+        expr: *const c_char,
+        path: *const c_char,
+        value: *mut NixValue,
+    ) -> NixErr;
+    pub fn nix_value_force(
+        context: *mut NixCContext,
+        state: *mut EvalState,
+        value: *mut NixValue,
+    ) -> NixErr;
+    pub fn nix_get_type(context: *mut NixCContext, value: *const NixValue) -> ValueType;
+    pub fn nix_get_bool(context: *mut NixCContext, value: *const NixValue) -> bool;
+    pub fn nix_get_int(context: *mut NixCContext, value: *const NixValue) -> i64;
+    pub fn nix_get_float(context: *mut NixCContext, value: *const NixValue) -> f64;
+    pub fn nix_get_string(
+        context: *mut NixCContext,
+        value: *const NixValue,
+        callback: GetStringCallback,
+        user_data: *mut c_void,
+    ) -> NixErr;
+    pub fn nix_get_path_string(context: *mut NixCContext, value: *const NixValue) -> *const c_char;
+    pub fn nix_get_list_size(context: *mut NixCContext, value: *const NixValue) -> c_uint;
+    pub fn nix_get_list_byidx(
+        context: *mut NixCContext,
+        value: *const NixValue,
+        state: *mut EvalState,
+        ix: c_uint,
+    ) -> *mut NixValue;
+    pub fn nix_get_attrs_size(context: *mut NixCContext, value: *const NixValue) -> c_uint;
+    pub fn nix_get_attr_byidx(
+        context: *mut NixCContext,
+        value: *const NixValue,
+        state: *mut EvalState,
+        i: c_uint,
+        name: *mut *const c_char,
+    ) -> *mut NixValue;
+}
@@ -0,0 +1,249 @@
+//! Diagnostics of `nix flake check`, parsed from its stderr
+//!
+//! Like other errors (see [crate::nix_error]), nix reports failed checks only as text.
+//! [FlakeCheckReport::from_stderr] splits it into [Diagnostic]s
+//! and recognizes the flake output they refer to, e.g. to annotate it in CI.
+//! See [crate::command::FlakeCheck::check] to run the checks and collect the report.
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::nix_error::ANSI_ESCAPE;
+
+/// Frames of the trace naming the output being checked,
+/// e.g. `while checking the derivation 'packages.x86_64-linux.default'`
+static CHECKING: Lazy<Regex> = Lazy::new(|| Regex::new(r"while checking [^']*'([^']+)'").unwrap());
+
+/// Messages naming a flake output,
+/// e.g. `flake attribute 'packages.x86_64-linux.default' is not a derivation`
+static OUTPUT: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?:flake attribute|flake output attribute|flake output|app|derivation) '([^']+)'")
+        .unwrap()
+});
+
+static SYSTEM: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"^[a-z0-9_]+-(?:linux|darwin|freebsd|netbsd|openbsd|cygwin|windows|none|wasi|redox|genode)$",
+    )
+    .unwrap()
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// An error or warning printed by `nix flake check`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// The flake output the diagnostic refers to, e.g. `packages.x86_64-linux.default`
+    pub attr_path: Option<String>,
+    /// The system of [Diagnostic::attr_path], if it refers to an output of a single system
+    pub system: Option<String>,
+    pub message: String,
+    /// The context of the error, outermost first, e.g. `while checking flake output 'packages'`
+    ///
+    /// Nix prints more frames if run with `--show-trace`,
+    /// see [crate::arguments::common::LoggingArgs].
+    pub trace: Vec<String>,
+}
+
+impl Diagnostic {
+    fn new(severity: Severity, message: &str) -> Self {
+        Diagnostic {
+            severity,
+            attr_path: None,
+            system: None,
+            message: message.trim().to_string(),
+            trace: Vec::new(),
+        }
+    }
+
+    /// Determine the output the diagnostic refers to
+    ///
+    /// Nix names outputs in the trace and the message,
+    /// the most specific, i.e. longest, attribute path is used.
+    fn locate(mut self) -> Self {
+        let candidates = self
+            .trace
+            .iter()
+            .filter_map(|frame| CHECKING.captures(frame))
+            .chain(OUTPUT.captures(&self.message))
+            .map(|captures| captures[1].to_string());
+
+        self.attr_path =
+            candidates.fold(None, |longest: Option<String>, attr_path| match longest {
+                Some(longest) if longest.split('.').count() > attr_path.split('.').count() => {
+                    Some(longest)
+                },
+                _ => Some(attr_path),
+            });
+        self.system = self.attr_path.as_ref().and_then(|attr_path| {
+            attr_path
+                .split('.')
+                .find(|component| SYSTEM.is_match(component))
+                .map(ToString::to_string)
+        });
+        self
+    }
+}
+
+/// The diagnostics of a run of `nix flake check`
+///
+/// ```
+/// # use runix::flake_check::{FlakeCheckReport, Severity};
+/// let report = FlakeCheckReport::from_stderr(
+///     "\
+/// warning: unknown flake output 'lib'
+/// error:
+///        … while checking flake output 'packages'
+///
+///        … while checking the derivation 'packages.x86_64-linux.default'
+///
+///        error: flake attribute 'packages.x86_64-linux.default' is not a derivation",
+/// );
+///
+/// assert!(!report.is_ok());
+/// let error = report.errors().next().unwrap();
+/// assert_eq!(
+///     error.attr_path.as_deref(),
+///     Some("packages.x86_64-linux.default")
+/// );
+/// assert_eq!(error.system.as_deref(), Some("x86_64-linux"));
+/// assert_eq!(error.trace.len(), 2);
+///
+/// let warning = report.warnings().next().unwrap();
+/// assert_eq!(warning.attr_path.as_deref(), Some("lib"));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FlakeCheckReport {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl FlakeCheckReport {
+    /// Collect the errors and warnings printed to `stderr`
+    ///
+    /// Each diagnostic starts with an unindented `error:` or `warning:` line,
+    /// followed by indented lines with its trace and the innermost error.
+    pub fn from_stderr(stderr: &str) -> Self {
+        let stderr = ANSI_ESCAPE.replace_all(stderr, "");
+        let mut diagnostics = Vec::new();
+        let mut current: Option<Diagnostic> = None;
+
+        for line in stderr.lines() {
+            if let Some(message) = line.strip_prefix("error:") {
+                diagnostics.extend(current.replace(Diagnostic::new(Severity::Error, message)));
+                continue;
+            }
+            if let Some(message) = line.strip_prefix("warning:") {
+                diagnostics.extend(current.replace(Diagnostic::new(Severity::Warning, message)));
+                continue;
+            }
+            // other unindented lines, e.g. progress messages, end the diagnostic
+            if !line.is_empty() && !line.starts_with(char::is_whitespace) {
+                diagnostics.extend(current.take());
+                continue;
+            }
+
+            let Some(ref mut diagnostic) = current else {
+                continue;
+            };
+            let line = line.trim();
+            if let Some(frame) = line.strip_prefix('…') {
+                diagnostic.trace.push(frame.trim().to_string());
+            } else if let Some(message) = line.strip_prefix("error:") {
+                diagnostic.message = message.trim().to_string();
+            }
+        }
+        diagnostics.extend(current);
+
+        FlakeCheckReport {
+            diagnostics: diagnostics.into_iter().map(Diagnostic::locate).collect(),
+        }
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.severity == Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.severity == Severity::Warning)
+    }
+
+    /// Whether no check failed
+    pub fn is_ok(&self) -> bool {
+        self.errors().next().is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_diagnostics() {
+        let stderr = "\
+\x1b[35;1mwarning:\x1b[0m Git tree '/home/user/project' is dirty
+evaluating flake...
+checking flake output 'apps'...
+warning: app 'apps.aarch64-darwin.default' lacks attribute 'meta'
+warning: flake output attribute 'defaultPackage' is deprecated; use 'packages.<system>.default' instead
+\x1b[31;1merror:\x1b[0m
+       … while checking flake output 'checks'
+
+         at /nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-source/flake.nix:12:5:
+
+           11|
+           12|     checks.x86_64-linux.fmt = import ./fmt.nix;
+             |     ^
+           13|   };
+
+       … while checking the derivation 'checks.x86_64-linux.fmt'
+
+       error: undefined variable 'pkgs'
+
+       at /nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-source/fmt.nix:1:1:
+error: flake attribute 'nixosConfigurations.host.config.system.build.toplevel' is not a derivation";
+
+        let report = FlakeCheckReport::from_stderr(stderr);
+        assert_eq!(report.warnings().count(), 3);
+        assert_eq!(report.diagnostics[0].attr_path, None);
+        assert_eq!(
+            report.diagnostics[1].attr_path.as_deref(),
+            Some("apps.aarch64-darwin.default")
+        );
+        assert_eq!(
+            report.diagnostics[2].attr_path.as_deref(),
+            Some("defaultPackage")
+        );
+
+        let errors: Vec<_> = report.errors().collect();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0], &Diagnostic {
+            severity: Severity::Error,
+            attr_path: Some("checks.x86_64-linux.fmt".to_string()),
+            system: Some("x86_64-linux".to_string()),
+            message: "undefined variable 'pkgs'".to_string(),
+            trace: vec![
+                "while checking flake output 'checks'".to_string(),
+                "while checking the derivation 'checks.x86_64-linux.fmt'".to_string(),
+            ],
+        });
+        assert_eq!(
+            errors[1].attr_path.as_deref(),
+            Some("nixosConfigurations.host.config.system.build.toplevel")
+        );
+        assert_eq!(errors[1].system, None);
+        assert!(!report.is_ok());
+
+        assert!(FlakeCheckReport::from_stderr("evaluating flake...").is_ok());
+    }
+}
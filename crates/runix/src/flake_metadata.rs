@@ -3,10 +3,10 @@ use std::path::PathBuf;
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DisplayFromStr};
 
-use crate::flake_ref::lock::{Rev, RevCount};
+use crate::flake_ref::lock::{DirtyRev, LockFile, Rev, RevCount};
 use crate::flake_ref::{self};
 
-pub type FlakeLock = serde_json::Value;
+pub type FlakeLock = LockFile;
 
 /// Flake Metadata as it is exposed through `nix flake metadata`
 #[serde_as]
@@ -16,9 +16,14 @@ pub struct FlakeMetadata {
     pub description: Option<String>,
     pub last_modified: flake_ref::Timestamp,
 
+    /// The lock file of the flake, including inputs locked by this invocation
     pub locks: FlakeLock,
 
+    /// The flake ref as passed to nix
     pub original: flake_ref::FlakeRef,
+    /// The flake ref after resolving it in the flake registries
+    pub resolved: flake_ref::FlakeRef,
+    /// The flake ref pinned to the fetched revision
     pub locked: flake_ref::FlakeRef,
 
     #[serde_as(as = "DisplayFromStr")]
@@ -28,8 +33,68 @@ pub struct FlakeMetadata {
     #[serde_as(as = "DisplayFromStr")]
     pub url: flake_ref::FlakeRef,
 
+    /// The store path of the flake's source
     pub path: PathBuf,
 
+    /// The revision of a git flake, [None] if its work tree has uncommitted changes
     pub revision: Option<Rev>,
+    /// The revision of a git flake whose work tree has uncommitted changes
+    pub dirty_revision: Option<DirtyRev>,
     pub rev_count: Option<RevCount>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_flake_metadata() {
+        let metadata: FlakeMetadata = serde_json::from_str(
+            r#"
+{
+  "description": "A flake",
+  "dirtyRevision": "2e0e3cc4c2b0b3e8f1fd6d3a21fa4a5e6b2d7b1c-dirty",
+  "lastModified": 1688392541,
+  "locked": {
+    "dirtyRev": "2e0e3cc4c2b0b3e8f1fd6d3a21fa4a5e6b2d7b1c-dirty",
+    "dirtyShortRev": "2e0e3cc-dirty",
+    "lastModified": 1688392541,
+    "type": "git",
+    "url": "file:///home/user/project"
+  },
+  "locks": {
+    "nodes": {
+      "nixpkgs": {
+        "locked": {
+          "lastModified": 1688392541,
+          "narHash": "sha256-lHrKvEkCPTUO+7tPfjIcb7Trk6k31rz18vkyqmkeJfY=",
+          "owner": "NixOS",
+          "repo": "nixpkgs",
+          "rev": "ea4c80b39be4c09702b0cb3b42eab59e2ba4f24b",
+          "type": "github"
+        },
+        "original": { "id": "nixpkgs", "type": "indirect" }
+      },
+      "root": { "inputs": { "nixpkgs": "nixpkgs" } }
+    },
+    "root": "root",
+    "version": 7
+  },
+  "original": { "type": "git", "url": "file:///home/user/project" },
+  "originalUrl": "git+file:///home/user/project",
+  "path": "/nix/store/083m43hjhry94cvfmqdv7kjpvsl3zzvi-source",
+  "resolved": { "type": "git", "url": "file:///home/user/project" },
+  "resolvedUrl": "git+file:///home/user/project",
+  "url": "git+file:///home/user/project"
+}
+            "#,
+        )
+        .expect("should parse");
+
+        assert_eq!(metadata.revision, None);
+        assert_eq!(metadata.dirty_revision.unwrap().short(), "2e0e3cc");
+        assert_eq!(metadata.resolved, metadata.original);
+        assert_eq!(metadata.rev_count, None);
+        assert!(metadata.locks.resolve_path("nixpkgs").is_ok());
+    }
+}
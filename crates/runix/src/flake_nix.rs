@@ -0,0 +1,336 @@
+//! Generate `flake.nix` files from a typed description
+//!
+//! [FlakeNix] renders the `description`, the `inputs`, declared like [DeclaredInput]s,
+//! and the `outputs` of a flake as formatted nix code,
+//! e.g. to scaffold new flakes or to adapt a flake created by [crate::command::FlakeInit].
+//!
+//! ```
+//! # use runix::expr::Expr;
+//! # use runix::flake_nix::{FlakeNix, FlakeOutputs};
+//! # use runix::flake_ref::inputs::DeclaredInput;
+//! let flake = FlakeNix {
+//!     description: Some("A flake providing hello".to_string()),
+//!     inputs: [(
+//!         "nixpkgs".to_string(),
+//!         DeclaredInput::from(
+//!             "github:NixOS/nixpkgs/nixos-23.05"
+//!                 .parse::<runix::flake_ref::FlakeRef>()
+//!                 .unwrap(),
+//!         ),
+//!     )]
+//!     .into(),
+//!     outputs: FlakeOutputs::per_system([(
+//!         "packages",
+//!         Expr::attrs([("default", Expr::ident("pkgs").select("hello"))]),
+//!     )]),
+//! };
+//!
+//! assert_eq!(
+//!     flake.to_string(),
+//!     r#"{
+//!   description = "A flake providing hello";
+//!   inputs = {
+//!     nixpkgs = {
+//!       url = "github:NixOS/nixpkgs/nixos-23.05";
+//!     };
+//!   };
+//!   outputs = { self, nixpkgs, ... } @ inputs: let
+//!     forAllSystems = f: nixpkgs.lib.genAttrs [
+//!       "aarch64-darwin"
+//!       "aarch64-linux"
+//!       "x86_64-darwin"
+//!       "x86_64-linux"
+//!     ] (system: f {
+//!       pkgs = nixpkgs.legacyPackages.${system};
+//!       system = system;
+//!     });
+//!   in
+//!   {
+//!     packages = forAllSystems ({ pkgs, system, ... }: {
+//!       default = pkgs.hello;
+//!     });
+//!   };
+//! }
+//! "#
+//! );
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::expr::{is_identifier, Expr, Param};
+use crate::flake_ref::inputs::{DeclaredInput, DeclaredInputs};
+
+/// The systems [FlakeOutputs::per_system] defines outputs for
+pub const DEFAULT_SYSTEMS: &[&str] = &[
+    "aarch64-darwin",
+    "aarch64-linux",
+    "x86_64-darwin",
+    "x86_64-linux",
+];
+
+/// The names the `outputs` function binds besides the inputs
+const OUTPUTS_BINDINGS: &[&str] = &["self", "inputs"];
+
+#[derive(Debug, Error)]
+pub enum FlakeNixError {
+    #[error("Could not write '{0}': {1}")]
+    Write(PathBuf, #[source] std::io::Error),
+}
+
+/// The contents of a `flake.nix`
+///
+/// Displayed as formatted nix code.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FlakeNix {
+    pub description: Option<String>,
+    pub inputs: DeclaredInputs,
+    pub outputs: FlakeOutputs,
+}
+
+/// The `outputs` of a [FlakeNix]
+///
+/// Outputs are the body of the `outputs` function, which has `self`,
+/// the inputs and the attribute set of all inputs, `inputs`, in scope.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FlakeOutputs {
+    /// Outputs given by an expression, e.g. `{ packages.x86_64-linux.default = ...; }`
+    Expr(Expr),
+    /// Outputs defined for each of `systems`,
+    /// with the packages of the input `nixpkgs` as `pkgs` and `system` in scope
+    ///
+    /// Each output is an attribute set by system, e.g. `packages.<system>`.
+    PerSystem {
+        /// The name of the input providing `lib.genAttrs` and `legacyPackages`
+        nixpkgs: String,
+        systems: Vec<String>,
+        outputs: BTreeMap<String, Expr>,
+    },
+}
+
+impl Default for FlakeOutputs {
+    fn default() -> Self {
+        FlakeOutputs::Expr(Expr::Attrs(BTreeMap::new()))
+    }
+}
+
+impl FlakeOutputs {
+    /// `outputs` for the [DEFAULT_SYSTEMS] using the input `nixpkgs`
+    pub fn per_system<K: Into<String>>(outputs: impl IntoIterator<Item = (K, Expr)>) -> Self {
+        FlakeOutputs::PerSystem {
+            nixpkgs: "nixpkgs".to_string(),
+            systems: DEFAULT_SYSTEMS.iter().map(ToString::to_string).collect(),
+            outputs: outputs.into_iter().map(|(k, v)| (k.into(), v)).collect(),
+        }
+    }
+
+    /// The body of the `outputs` function
+    fn to_expr(&self) -> Expr {
+        let (nixpkgs, systems, outputs) = match self {
+            FlakeOutputs::Expr(expr) => return expr.clone(),
+            FlakeOutputs::PerSystem {
+                nixpkgs,
+                systems,
+                outputs,
+            } => (nixpkgs, systems, outputs),
+        };
+
+        let nixpkgs = input_expr(nixpkgs);
+        let for_all_systems = Expr::lambda(
+            Param::ident("f"),
+            nixpkgs
+                .clone()
+                .select("lib")
+                .select("genAttrs")
+                .call(Expr::from(systems.clone()))
+                .call(Expr::lambda(
+                    Param::ident("system"),
+                    Expr::ident("f").call(Expr::attrs([
+                        (
+                            "pkgs",
+                            Expr::Raw(format!("{nixpkgs}.legacyPackages.${{system}}")),
+                        ),
+                        ("system", Expr::ident("system")),
+                    ])),
+                )),
+        );
+
+        let outputs = outputs.iter().map(|(name, output)| {
+            (
+                name.clone(),
+                Expr::ident("forAllSystems").call(Expr::lambda(
+                    Param::pattern(["pkgs", "system"], true),
+                    output.clone(),
+                )),
+            )
+        });
+
+        Expr::Let(
+            [("forAllSystems".to_string(), for_all_systems)].into(),
+            Box::new(Expr::Attrs(outputs.collect())),
+        )
+    }
+}
+
+impl FlakeNix {
+    /// The flake as nix expression
+    pub fn to_expr(&self) -> Expr {
+        let mut attrs = BTreeMap::new();
+        if let Some(description) = &self.description {
+            attrs.insert("description".to_string(), Expr::from(description.as_str()));
+        }
+        if !self.inputs.is_empty() {
+            attrs.insert("inputs".to_string(), inputs_expr(&self.inputs));
+        }
+
+        // inputs that are not valid variable names or would shadow `self` and `inputs`
+        // are only available from `inputs`
+        let params = ["self"]
+            .into_iter()
+            .chain(
+                self.inputs
+                    .keys()
+                    .map(String::as_str)
+                    .filter(|name| is_variable(name)),
+            )
+            .map(|name| (name.to_string(), None))
+            .collect();
+        attrs.insert(
+            "outputs".to_string(),
+            Expr::lambda(
+                Param::Pattern {
+                    fields: params,
+                    ellipsis: true,
+                    bind: Some("inputs".to_string()),
+                },
+                self.outputs.to_expr(),
+            ),
+        );
+
+        Expr::Attrs(attrs)
+    }
+
+    /// Write the flake to `flake.nix` in `dir`, returning the path of the file
+    ///
+    /// An existing `flake.nix` is replaced atomically.
+    pub fn write(&self, dir: impl AsRef<Path>) -> Result<PathBuf, FlakeNixError> {
+        let path = dir.as_ref().join("flake.nix");
+        let tmp_path = path.with_file_name(format!(".flake.nix.{}.tmp", std::process::id()));
+
+        fs::write(&tmp_path, self.to_string())
+            .and_then(|_| fs::rename(&tmp_path, &path))
+            .map_err(|e| {
+                let _ = fs::remove_file(&tmp_path);
+                FlakeNixError::Write(path.clone(), e)
+            })?;
+        Ok(path)
+    }
+}
+
+impl Display for FlakeNix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}", self.to_expr().pretty())
+    }
+}
+
+/// The input `name` in the scope of the `outputs` function
+fn input_expr(name: &str) -> Expr {
+    if is_variable(name) {
+        Expr::ident(name)
+    } else {
+        Expr::ident("inputs").select(name)
+    }
+}
+
+/// Whether the input `name` is bound to a variable of the same name in the `outputs` function
+fn is_variable(name: &str) -> bool {
+    is_identifier(name) && !OUTPUTS_BINDINGS.contains(&name)
+}
+
+/// `inputs` as attribute set of input declarations
+fn inputs_expr(inputs: &DeclaredInputs) -> Expr {
+    Expr::Attrs(
+        inputs
+            .iter()
+            .map(|(name, input)| (name.clone(), input_decl(input)))
+            .collect(),
+    )
+}
+
+fn input_decl(input: &DeclaredInput) -> Expr {
+    let mut attrs = BTreeMap::new();
+    if let Some(flake_ref) = &input.flake_ref {
+        attrs.insert("url".to_string(), Expr::from(flake_ref.to_string()));
+    }
+    if let Some(follows) = &input.follows {
+        attrs.insert("follows".to_string(), Expr::from(follows.as_str()));
+    }
+    if !input.flake {
+        attrs.insert("flake".to_string(), Expr::Bool(false));
+    }
+    if !input.inputs.is_empty() {
+        attrs.insert("inputs".to_string(), inputs_expr(&input.inputs));
+    }
+    Expr::Attrs(attrs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_inputs() {
+        let inputs: DeclaredInputs = serde_json::from_value(serde_json::json!({
+            "nixpkgs": { "url": "github:NixOS/nixpkgs/nixos-23.05" },
+            "home-manager": {
+                "url": "github:nix-community/home-manager",
+                "inputs": { "nixpkgs": { "follows": "nixpkgs" } }
+            },
+            "nixpkgs.src": { "url": "github:NixOS/nixpkgs", "flake": false },
+        }))
+        .unwrap();
+
+        let flake = FlakeNix {
+            description: None,
+            inputs: inputs.clone(),
+            outputs: FlakeOutputs::Expr(Expr::attrs([(
+                "src",
+                Expr::ident("inputs").select("nixpkgs.src"),
+            )])),
+        };
+        assert_eq!(
+            flake.to_expr().to_string(),
+            "{ inputs = { home-manager = { inputs = { nixpkgs = { follows = \"nixpkgs\"; }; }; \
+             url = \"github:nix-community/home-manager\"; }; \
+             nixpkgs = { url = \"github:NixOS/nixpkgs/nixos-23.05\"; }; \
+             \"nixpkgs.src\" = { flake = false; url = \"github:NixOS/nixpkgs\"; }; }; \
+             outputs = { self, home-manager, nixpkgs, ... } @ inputs: \
+             { src = inputs.\"nixpkgs.src\"; }; }"
+        );
+
+        let shadowing = FlakeNix {
+            inputs: [
+                ("inputs".to_string(), DeclaredInput::default()),
+                ("self".to_string(), DeclaredInput::default()),
+            ]
+            .into(),
+            outputs: FlakeOutputs::PerSystem {
+                nixpkgs: "inputs".to_string(),
+                systems: vec!["x86_64-linux".to_string()],
+                outputs: BTreeMap::new(),
+            },
+            ..Default::default()
+        };
+        let rendered = shadowing.to_expr().to_string();
+        assert!(rendered.contains("outputs = { self, ... } @ inputs: "));
+        assert!(rendered.contains("inputs.inputs.lib.genAttrs"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = flake.write(dir.path()).unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), flake.to_string());
+    }
+}
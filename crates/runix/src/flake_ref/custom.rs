@@ -0,0 +1,332 @@
+//! Flake ref schemes defined outside of runix
+//!
+//! Downstream crates can add flake ref types with their own scheme
+//! by implementing [FlakeRefSource] and [FlakeRefAttributes] for them
+//! and registering the type with [register_scheme].
+//! Registered schemes participate in parsing [FlakeRef]s from strings and json,
+//! parsed refs of such types are represented as [FlakeRef::Custom].
+//!
+//! Builtin schemes always take precedence over registered ones.
+
+use std::any::{Any, TypeId};
+use std::error::Error;
+use std::fmt::{Debug, Display};
+use std::sync::RwLock;
+
+use once_cell::sync::Lazy;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use url::Url;
+
+#[cfg(doc)]
+use super::FlakeRef;
+use super::{FlakeRefAttributes, FlakeRefSource};
+
+/// Error returned by [FlakeRefSource::from_url] of registered schemes
+pub type CustomParseError = Box<dyn Error + Send + Sync>;
+
+/// Object safe interface of flake ref types of registered schemes
+///
+/// Implemented for all types that can be passed to [register_scheme].
+pub trait CustomFlakeRef: Display + Debug + Send + Sync + 'static {
+    fn as_any(&self) -> &dyn Any;
+
+    fn as_attributes(&self) -> &dyn FlakeRefAttributes;
+
+    fn clone_boxed(&self) -> Box<dyn CustomFlakeRef>;
+
+    fn eq_dyn(&self, other: &dyn CustomFlakeRef) -> bool;
+
+    fn to_json(&self) -> Result<Value, serde_json::Error>;
+}
+
+impl<T> CustomFlakeRef for T
+where
+    T: FlakeRefSource + FlakeRefAttributes + Serialize + Clone + PartialEq + Debug,
+    T: Send + Sync + 'static,
+{
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_attributes(&self) -> &dyn FlakeRefAttributes {
+        self
+    }
+
+    fn clone_boxed(&self) -> Box<dyn CustomFlakeRef> {
+        Box::new(self.clone())
+    }
+
+    fn eq_dyn(&self, other: &dyn CustomFlakeRef) -> bool {
+        other.as_any().downcast_ref::<T>() == Some(self)
+    }
+
+    fn to_json(&self) -> Result<Value, serde_json::Error> {
+        serde_json::to_value(self)
+    }
+}
+
+/// A flake ref of a registered scheme
+#[derive(Debug)]
+pub struct CustomRef(Box<dyn CustomFlakeRef>);
+
+impl CustomRef {
+    pub fn new(flake_ref: impl CustomFlakeRef) -> Self {
+        CustomRef(Box::new(flake_ref))
+    }
+
+    /// Access the concrete flake ref, if it is of type `T`
+    pub fn downcast_ref<T: CustomFlakeRef>(&self) -> Option<&T> {
+        self.0.as_any().downcast_ref()
+    }
+
+    pub(super) fn as_attributes(&self) -> &dyn FlakeRefAttributes {
+        self.0.as_attributes()
+    }
+}
+
+impl Clone for CustomRef {
+    fn clone(&self) -> Self {
+        CustomRef(self.0.clone_boxed())
+    }
+}
+
+impl PartialEq for CustomRef {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.eq_dyn(&*other.0)
+    }
+}
+
+impl Eq for CustomRef {}
+
+impl Display for CustomRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Serialize for CustomRef {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.0
+            .to_json()
+            .map_err(serde::ser::Error::custom)?
+            .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for CustomRef {
+    /// Deserialize with the first registered scheme that accepts the value
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let value = Value::deserialize(deserializer)?;
+        let schemes = SCHEMES.read().unwrap_or_else(|e| e.into_inner());
+        schemes
+            .iter()
+            .find_map(|scheme| (scheme.from_json)(&value))
+            .ok_or_else(|| serde::de::Error::custom("no registered flake ref scheme matches"))
+    }
+}
+
+struct Scheme {
+    type_id: TypeId,
+    parses: fn(&Url) -> bool,
+    from_url: fn(Url) -> Result<CustomRef, CustomParseError>,
+    from_json: fn(&Value) -> Option<CustomRef>,
+}
+
+static SCHEMES: Lazy<RwLock<Vec<Scheme>>> = Lazy::new(Default::default);
+
+/// Register a flake ref type with a custom scheme
+///
+/// Once registered, urls accepted by [FlakeRefSource::parses] of `T`
+/// are parsed into [FlakeRef::Custom] by [FlakeRef::parse_url] and [FlakeRef::from_str].
+/// Json objects are deserialized as `T` if they fail to deserialize as any builtin type,
+/// `T` should therefore verify the `type` attribute when deserializing.
+/// Registering the same type more than once has no effect.
+///
+/// ```
+/// # use std::borrow::Cow;
+/// # use std::fmt::Display;
+/// # use std::str::FromStr;
+/// # use serde::{Deserialize, Serialize};
+/// # use url::Url;
+/// use runix::flake_ref::custom::{register_scheme, CustomParseError};
+/// use runix::flake_ref::{FlakeRef, FlakeRefAttributes, FlakeRefSource};
+///
+/// #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// struct ArtifactRef {
+///     name: String,
+/// }
+///
+/// impl FlakeRefAttributes for ArtifactRef {}
+///
+/// impl FlakeRefSource for ArtifactRef {
+///     type ParseErr = CustomParseError;
+///
+///     fn scheme() -> Cow<'static, str> {
+///         "artifact".into()
+///     }
+///
+///     fn from_url(url: Url) -> Result<Self, Self::ParseErr> {
+///         Ok(ArtifactRef {
+///             name: url.path().to_string(),
+///         })
+///     }
+/// }
+/// # impl Display for ArtifactRef {
+/// #     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+/// #         write!(f, "artifact:{}", self.name)
+/// #     }
+/// # }
+/// # impl FromStr for ArtifactRef {
+/// #     type Err = CustomParseError;
+/// #     fn from_str(s: &str) -> Result<Self, Self::Err> {
+/// #         Self::from_url(Url::parse(s)?)
+/// #     }
+/// # }
+///
+/// register_scheme::<ArtifactRef>();
+///
+/// let flake_ref: FlakeRef = "artifact:my-flake".parse().unwrap();
+/// let FlakeRef::Custom(custom) = flake_ref else {
+///     panic!("expected a custom flake ref")
+/// };
+/// assert_eq!(
+///     custom.downcast_ref::<ArtifactRef>().unwrap().name,
+///     "my-flake"
+/// );
+/// ```
+pub fn register_scheme<T>()
+where
+    T: CustomFlakeRef + FlakeRefSource + DeserializeOwned,
+    T::ParseErr: Into<CustomParseError>,
+{
+    let mut schemes = SCHEMES.write().unwrap_or_else(|e| e.into_inner());
+    if schemes
+        .iter()
+        .any(|scheme| scheme.type_id == TypeId::of::<T>())
+    {
+        return;
+    }
+
+    schemes.push(Scheme {
+        type_id: TypeId::of::<T>(),
+        parses: T::parses,
+        from_url: |url| T::from_url(url).map(CustomRef::new).map_err(Into::into),
+        from_json: |value| {
+            serde_json::from_value::<T>(value.clone())
+                .ok()
+                .map(CustomRef::new)
+        },
+    });
+}
+
+/// Parse `url` with the first registered scheme that [FlakeRefSource::parses] it
+///
+/// [None] if no registered scheme accepts the url.
+pub(super) fn parse_url(url: &Url) -> Option<Result<CustomRef, CustomParseError>> {
+    let schemes = SCHEMES.read().unwrap_or_else(|e| e.into_inner());
+    let scheme = schemes.iter().find(|scheme| (scheme.parses)(url))?;
+    Some((scheme.from_url)(url.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::flake_ref::lock::Rev;
+    use crate::flake_ref::FlakeRef;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(tag = "type", rename = "store")]
+    struct StoreRef {
+        name: String,
+        rev: Option<Rev>,
+    }
+
+    impl FlakeRefAttributes for StoreRef {
+        fn rev(&self) -> Option<Rev> {
+            self.rev.clone()
+        }
+    }
+
+    impl FlakeRefSource for StoreRef {
+        type ParseErr = CustomParseError;
+
+        fn scheme() -> Cow<'static, str> {
+            "store".into()
+        }
+
+        fn from_url(url: Url) -> Result<Self, Self::ParseErr> {
+            let rev = url
+                .query_pairs()
+                .find(|(name, _)| name == "rev")
+                .map(|(_, rev)| rev.parse())
+                .transpose()?;
+            Ok(StoreRef {
+                name: url.path().to_string(),
+                rev,
+            })
+        }
+    }
+
+    impl Display for StoreRef {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "store:{}", self.name)?;
+            if let Some(ref rev) = self.rev {
+                write!(f, "?rev={}", **rev)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl FromStr for StoreRef {
+        type Err = CustomParseError;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            Self::from_url(Url::parse(s)?)
+        }
+    }
+
+    #[test]
+    fn custom_scheme() {
+        register_scheme::<StoreRef>();
+        register_scheme::<StoreRef>();
+
+        let s = "store:my-flake?rev=50500a744e3c2af9d89123ae17b71406b428c3ab";
+        let flake_ref: FlakeRef = s.parse().unwrap();
+        assert_eq!(flake_ref.to_string(), s);
+        assert_eq!(
+            flake_ref.rev().as_deref().map(String::as_str),
+            Some("50500a744e3c2af9d89123ae17b71406b428c3ab")
+        );
+
+        let FlakeRef::Custom(ref custom) = flake_ref else {
+            panic!("expected a custom flake ref")
+        };
+        assert_eq!(custom.downcast_ref::<StoreRef>().unwrap().name, "my-flake");
+
+        let json = serde_json::to_value(&flake_ref).unwrap();
+        assert_eq!(json["type"], "store");
+        assert_eq!(serde_json::from_value::<FlakeRef>(json).unwrap(), flake_ref);
+
+        // builtin schemes take precedence
+        assert!(matches!(
+            "github:flox/runix".parse::<FlakeRef>().unwrap(),
+            FlakeRef::Github(_)
+        ));
+
+        assert!(matches!(
+            FlakeRef::parse_url("store:my-flake?rev=invalid"),
+            Err(crate::flake_ref::ParseFlakeRefError::InvalidComponent { .. })
+        ));
+    }
+}
@@ -1,6 +1,7 @@
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fmt::Display;
+use std::path::PathBuf;
 use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
@@ -11,8 +12,17 @@ use url::Url;
 use self::application::{Application, ApplicationProtocol};
 use super::lock::NarHash;
 use super::protocol::{self, Protocol, WrappedUrl, WrappedUrlParseError};
-use super::{Attrs, FlakeRefSource};
+use super::{
+    decode_query,
+    encode_query,
+    Attrs,
+    BoolReprs,
+    FlakeRefAttributes,
+    FlakeRefSource,
+    QueryError,
+};
 use crate::url_parser::{
+    extract_dir_attr,
     extract_name_attr,
     extract_nar_hash_attr,
     extract_unpack_attr,
@@ -49,6 +59,8 @@ pub struct FileAttributes {
     pub unpack: Option<bool>,
 
     pub name: Option<String>,
+
+    pub dir: Option<PathBuf>,
 }
 
 impl TryFrom<Attrs> for FileAttributes {
@@ -58,10 +70,12 @@ impl TryFrom<Attrs> for FileAttributes {
         let nar_hash = extract_nar_hash_attr(&attrs)?;
         let unpack = extract_unpack_attr(&attrs)?;
         let name = extract_name_attr(&attrs)?;
+        let dir = extract_dir_attr(&attrs)?;
         Ok(FileAttributes {
             nar_hash,
             unpack,
             name,
+            dir,
         })
     }
 }
@@ -186,6 +200,18 @@ impl FileProtocol for protocol::File {}
 impl FileProtocol for protocol::HTTP {}
 impl FileProtocol for protocol::HTTPS {}
 
+impl<Protocol: FileProtocol, App: ApplicationProtocol> FlakeRefAttributes
+    for FileBasedRef<Protocol, App>
+{
+    fn dir(&self) -> Option<PathBuf> {
+        self.attributes.dir.clone()
+    }
+
+    fn nar_hash(&self) -> Option<NarHash> {
+        self.attributes.nar_hash.clone()
+    }
+}
+
 impl<Protocol: FileProtocol, App: ApplicationProtocol> FlakeRefSource
     for FileBasedRef<Protocol, App>
 {
@@ -256,15 +282,19 @@ impl<Protocol: FileProtocol, App: ApplicationProtocol> FlakeRefSource
             ));
         };
 
-        let mut pairs = url
-            .query_pairs()
-            .map(|(k, v)| (k, v.to_string()))
+        let mut pairs = decode_query(url.query().unwrap_or_default())
+            .into_iter()
             .collect::<HashMap<_, _>>();
 
         let attributes = FileAttributes {
-            nar_hash: pairs.remove("narHash"),
-            unpack: pairs.remove("unpack").map(|v| v == "1"),
+            nar_hash: pairs
+                .remove("narHash")
+                .map(|v| v.parse())
+                .transpose()
+                .map_err(|e| QueryError::param("narHash", e))?,
+            unpack: pairs.remove("unpack").map(|v| BoolReprs::String(v).into()),
             name: pairs.remove("name"),
+            dir: pairs.remove("dir").map(PathBuf::from),
         };
 
         Ok(FileBasedRef {
@@ -283,6 +313,51 @@ impl<Protocol: FileProtocol, App: ApplicationProtocol> FileBasedRef<Protocol, Ap
             _type: Default::default(),
         }
     }
+
+    /// Set the `narHash` attribute
+    pub fn with_nar_hash(mut self, nar_hash: impl Into<NarHash>) -> Self {
+        self.attributes.nar_hash = Some(nar_hash.into());
+        self
+    }
+
+    /// Set whether the file should be unpacked
+    pub fn with_unpack(mut self, unpack: bool) -> Self {
+        self.attributes.unpack = Some(unpack);
+        self
+    }
+
+    /// Set the name of the resulting store path
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.attributes.name = Some(name.into());
+        self
+    }
+
+    /// Set the `dir` attribute pointing to a flake in a subdirectory
+    pub fn with_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.attributes.dir = Some(dir.into());
+        self
+    }
+}
+
+impl<Protocol: FileProtocol, App: ApplicationProtocol> From<FileUrl<Protocol>>
+    for FileBasedRef<Protocol, App>
+{
+    /// A file or tarball flake ref pointing to `url`
+    ///
+    /// ```
+    /// # use runix::flake_ref::file::{FileUrl, TarballRef};
+    /// # use runix::flake_ref::protocol::HTTPS;
+    /// let url: FileUrl<HTTPS> = "https://example.com/source.tar.gz".parse().unwrap();
+    /// let flake_ref = TarballRef::from(url).with_name("source");
+    ///
+    /// assert_eq!(
+    ///     flake_ref.to_string(),
+    ///     "https://example.com/source.tar.gz?name=source"
+    /// );
+    /// ```
+    fn from(url: FileUrl<Protocol>) -> Self {
+        Self::new(url, Default::default())
+    }
 }
 
 impl<Protocol: FileProtocol, App: ApplicationProtocol> Display for FileBasedRef<Protocol, App> {
@@ -293,25 +368,27 @@ impl<Protocol: FileProtocol, App: ApplicationProtocol> Display for FileBasedRef<
                 application = self._type,
                 url = self.url
             ))
-            .unwrap()
+            .map_err(|_| std::fmt::Error)?
         } else {
             self.url.clone()
         };
 
-        let mut query = url.query_pairs_mut();
+        let mut pairs = Vec::new();
         if let Some(ref nar_hash) = self.attributes.nar_hash {
-            query.append_pair("narHash", nar_hash);
+            pairs.push(("narHash", nar_hash.to_string()));
         }
         if let Some(unpack) = self.attributes.unpack {
-            query.append_pair("unpack", &(unpack as u8).to_string());
+            pairs.push(("unpack", (unpack as u8).to_string()));
         }
         if let Some(ref name) = self.attributes.name {
-            query.append_pair("name", name);
+            pairs.push(("name", name.clone()));
+        }
+        if let Some(ref dir) = self.attributes.dir {
+            pairs.push(("dir", dir.to_string_lossy().into_owned()));
         }
-        let url = query.finish();
 
-        if matches!(url.query(), Some("")) {
-            url.set_query(None)
+        if !pairs.is_empty() {
+            url.set_query(Some(&encode_query(pairs)));
         }
 
         write!(f, "{url}")
@@ -337,8 +414,8 @@ pub enum ParseFileError {
     InvalidScheme(String, String),
     #[error("No repo specified")]
     NoRepo,
-    #[error("Couldn't parse query: {0}")]
-    Query(#[from] serde_urlencoded::de::Error),
+    #[error(transparent)]
+    Query(#[from] QueryError),
 }
 
 #[cfg(test)]
@@ -366,6 +443,7 @@ mod tests {
                 nar_hash: None,
                 unpack: Some(true),
                 name: None,
+                dir: None,
             },
         };
 
@@ -376,6 +454,19 @@ mod tests {
         );
     }
 
+    #[test]
+    fn build_file_refs() {
+        let url: FileUrl<File> = "file:///somewhere/there".parse().unwrap();
+        assert_eq!(
+            FileFileRef::from(url.clone()).with_unpack(true),
+            FileFileRef::from_str("file:///somewhere/there?unpack=1").unwrap()
+        );
+        assert_eq!(
+            FileTarballRef::from(url).with_name("there"),
+            FileTarballRef::from_str("tarball+file:///somewhere/there?name=there").unwrap()
+        );
+    }
+
     #[test]
     fn file_file_roundtrips() {
         roundtrip::<FileFileRef>("file:///somewhere/there");
@@ -433,6 +524,76 @@ mod tests {
 
     #[test]
     fn test_parse_nar_hash() {
-        roundtrip::<FileFileRef>("file:///somewhere/there?narHash=sha256-MjeRjunqfGTBGU401nxIjs7PC9PZZ1FBCZp%2FbRB3C2M%3D")
+        roundtrip::<FileFileRef>(
+            "file:///somewhere/there?narHash=sha256-MjeRjunqfGTBGU401nxIjs7PC9PZZ1FBCZp/bRB3C2M%3D",
+        );
+        roundtrip_to::<FileFileRef>(
+            "file:///somewhere/there?narHash=sha256-MjeRjunqfGTBGU401nxIjs7PC9PZZ1FBCZp%2FbRB3C2M%3D",
+            "file:///somewhere/there?narHash=sha256-MjeRjunqfGTBGU401nxIjs7PC9PZZ1FBCZp/bRB3C2M%3D",
+        );
+
+        let parsed = FileFileRef::from_str(
+            "file:///somewhere/there?narHash=sha256-MjeRjunqfGTBGU401nxIjs7PC9PZZ1FBCZp/bRB3C2M%3D",
+        )
+        .unwrap();
+        assert_eq!(
+            parsed.attributes.nar_hash,
+            Some(
+                "sha256-MjeRjunqfGTBGU401nxIjs7PC9PZZ1FBCZp/bRB3C2M="
+                    .parse()
+                    .unwrap()
+            )
+        );
+        assert_eq!(
+            serde_json::to_value(&parsed).unwrap()["narHash"],
+            "sha256-MjeRjunqfGTBGU401nxIjs7PC9PZZ1FBCZp/bRB3C2M="
+        );
+    }
+
+    #[test]
+    fn tarball_nar_hash() {
+        roundtrip::<HttpsTarballRef>(
+            "https://example.com/source.tar.zst?narHash=sha256-MjeRjunqfGTBGU401nxIjs7PC9PZZ1FBCZp/bRB3C2M%3D",
+        );
+
+        // nix' `<algorithm>:<digest>` form is normalized to SRI
+        let tarball = HttpsTarballRef::from_str(
+            "https://example.com/source.tar.gz?narHash=sha256:1b3mzrpfdbzqv2xv1wm7kjmynkm6zvsz6g5a0zbp0lw7v5j5kz5d",
+        )
+        .unwrap();
+        assert!(tarball.to_string().contains("narHash=sha256-"));
+
+        for invalid in [
+            "sha256-MjeRjunqfGTBGU401nxIjs7PC9PZZ1FBCZp",
+            "sha256:1b3mzrpfdbzqv2xv1wm7kjmynkm6zvsz6g5a0zbp0lw7v5j5kz5",
+            "blake3-MjeRjunqfGTBGU401nxIjs7PC9PZZ1FBCZp/bRB3C2M%3D",
+        ] {
+            assert!(matches!(
+                HttpsTarballRef::from_str(&format!(
+                    "https://example.com/source.tar.gz?narHash={invalid}"
+                )),
+                Err(ParseFileError::Query(QueryError { param: Some(ref param), .. })) if param == "narHash"
+            ));
+        }
+    }
+
+    #[test]
+    fn tarball_extensions() {
+        for ext in [
+            "zip", "tar", "tgz", "tar.gz", "tar.xz", "tar.bz2", "tar.zst",
+        ] {
+            let url = format!("https://example.com/source.{ext}");
+            assert!(
+                HttpsTarballRef::parses(&Url::parse(&url).unwrap()),
+                "{url} should be parsed as tarball"
+            );
+            assert!(
+                !HttpsFileRef::parses(&Url::parse(&url).unwrap()),
+                "{url} should not be parsed as file"
+            );
+        }
+        assert!(HttpsFileRef::parses(
+            &Url::parse("https://example.com/source.txt").unwrap()
+        ));
     }
 }
@@ -1,7 +1,7 @@
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fmt::{Debug, Display};
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
@@ -11,7 +11,19 @@ use url::Url;
 
 use super::lock::{LastModified, NarHash, Rev, RevCount};
 use super::protocol::{self, Protocol, WrappedUrl, WrappedUrlParseError};
-use super::{Attrs, FlakeRefSource, Timestamp, TimestampDeserialize};
+use super::{
+    decode_component,
+    decode_query,
+    encode_path,
+    encode_query,
+    Attrs,
+    BoolReprs,
+    FlakeRefAttributes,
+    FlakeRefSource,
+    QueryError,
+    Timestamp,
+    TimestampDeserialize,
+};
 use crate::url_parser::{
     extract_all_refs_attr,
     extract_dir_attr,
@@ -40,15 +52,20 @@ pub struct GitRef<Protocol: GitProtocol> {
 #[skip_serializing_none]
 #[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone, Default)]
 pub struct GitAttributes {
+    #[serde(default, deserialize_with = "BoolReprs::deserialize_option")]
     pub shallow: Option<bool>,
+    #[serde(default, deserialize_with = "BoolReprs::deserialize_option")]
     pub submodules: Option<bool>,
-    #[serde(rename = "allRefs")]
+    #[serde(
+        rename = "allRefs",
+        default,
+        deserialize_with = "BoolReprs::deserialize_option"
+    )]
     pub all_refs: Option<bool>,
 
     #[serde(rename = "revCount")]
     pub rev_count: Option<RevCount>,
 
-    #[serde(flatten)]
     pub rev: Option<Rev>,
 
     #[serde(rename = "ref")]
@@ -100,6 +117,105 @@ impl<Protocol: GitProtocol> GitRef<Protocol> {
     pub fn new(url: GitUrl<Protocol>, attributes: GitAttributes) -> Self {
         Self { url, attributes }
     }
+
+    /// Set the branch or tag to fetch
+    pub fn with_ref(mut self, reference: impl Into<String>) -> Self {
+        self.attributes.reference = Some(reference.into());
+        self
+    }
+
+    /// Set the commit to fetch
+    pub fn with_rev(mut self, rev: Rev) -> Self {
+        self.attributes.rev = Some(rev);
+        self
+    }
+
+    /// Set the `dir` attribute pointing to a flake in a subdirectory
+    pub fn with_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.attributes.dir = Some(dir.into());
+        self
+    }
+
+    /// Set whether to fetch a shallow clone
+    pub fn with_shallow(mut self, shallow: bool) -> Self {
+        self.attributes.shallow = Some(shallow);
+        self
+    }
+
+    /// Set whether to fetch submodules
+    pub fn with_submodules(mut self, submodules: bool) -> Self {
+        self.attributes.submodules = Some(submodules);
+        self
+    }
+
+    /// Set whether to fetch all refs of the repository
+    pub fn with_all_refs(mut self, all_refs: bool) -> Self {
+        self.attributes.all_refs = Some(all_refs);
+        self
+    }
+
+    /// Set the `revCount` attribute
+    pub fn with_rev_count(mut self, rev_count: impl Into<RevCount>) -> Self {
+        self.attributes.rev_count = Some(rev_count.into());
+        self
+    }
+
+    /// Set the `narHash` attribute
+    pub fn with_nar_hash(mut self, nar_hash: impl Into<NarHash>) -> Self {
+        self.attributes.nar_hash = Some(nar_hash.into());
+        self
+    }
+
+    /// Set the `lastModified` attribute
+    pub fn with_last_modified(mut self, last_modified: impl Into<LastModified>) -> Self {
+        self.attributes.last_modified = Some(last_modified.into());
+        self
+    }
+}
+
+impl<Protocol: GitProtocol> From<GitUrl<Protocol>> for GitRef<Protocol> {
+    /// A git flake ref pointing to the default branch of the repository at `url`
+    ///
+    /// ```
+    /// # use runix::flake_ref::git::{GitRef, GitUrl};
+    /// # use runix::flake_ref::protocol::HTTPS;
+    /// let url: GitUrl<HTTPS> = "https://github.com/flox/runix".parse().unwrap();
+    /// let flake_ref = GitRef::from(url).with_ref("main").with_shallow(true);
+    ///
+    /// assert_eq!(
+    ///     flake_ref.to_string(),
+    ///     "git+https://github.com/flox/runix?ref=main&shallow=1"
+    /// );
+    /// ```
+    fn from(url: GitUrl<Protocol>) -> Self {
+        Self::new(url, Default::default())
+    }
+}
+
+impl<Protocol: GitProtocol> FlakeRefAttributes for GitRef<Protocol> {
+    fn rev(&self) -> Option<Rev> {
+        self.attributes.rev.clone()
+    }
+
+    fn ref_(&self) -> Option<String> {
+        self.attributes.reference.clone()
+    }
+
+    fn dir(&self) -> Option<PathBuf> {
+        self.attributes.dir.clone()
+    }
+
+    fn nar_hash(&self) -> Option<NarHash> {
+        self.attributes.nar_hash.clone()
+    }
+
+    fn last_modified(&self) -> Option<LastModified> {
+        self.attributes.last_modified.clone()
+    }
+
+    fn rev_count(&self) -> Option<RevCount> {
+        self.attributes.rev_count.clone()
+    }
 }
 
 impl<Protocol: GitProtocol> FlakeRefSource for GitRef<Protocol> {
@@ -117,34 +233,38 @@ impl<Protocol: GitProtocol> FlakeRefSource for GitRef<Protocol> {
             ));
         }
 
-        let mut pairs = url
-            .query_pairs()
-            .map(|(k, v)| (k, v.to_string()))
+        let mut pairs = decode_query(url.query().unwrap_or_default())
+            .into_iter()
             .collect::<HashMap<_, _>>();
 
         let attributes = GitAttributes {
-            shallow: pairs.remove("shallow").map(|v| v == "1"),
-            submodules: pairs.remove("submodules").map(|v| v == "1"),
-            all_refs: pairs.remove("allRefs").map(|v| v == "1"),
+            shallow: pairs.remove("shallow").map(|v| BoolReprs::String(v).into()),
+            submodules: pairs
+                .remove("submodules")
+                .map(|v| BoolReprs::String(v).into()),
+            all_refs: pairs.remove("allRefs").map(|v| BoolReprs::String(v).into()),
             rev_count: pairs
                 .remove("revCount")
-                .map(|v| v.parse::<u64>())
-                .map_or(Ok(None), |v| v.map(Some))
-                .map_err(|e| ParseGitError::Query(e.to_string()))?
-                .map(RevCount),
+                .map(|v| v.parse::<RevCount>())
+                .transpose()
+                .map_err(|e| QueryError::param("revCount", e))?,
             rev: pairs
                 .remove("rev")
                 .map(|v| Rev::from_str(&v))
                 .map_or(Ok(None), |v| v.map(Some))
-                .map_err(|e| ParseGitError::Query(e.to_string()))?,
+                .map_err(|e| QueryError::param("rev", e))?,
             reference: pairs.remove("ref"),
             dir: pairs.remove("dir").map(PathBuf::from),
             last_modified: pairs
                 .remove("lastModified")
                 .map(|v| Timestamp::try_from(TimestampDeserialize::TsString(v)))
                 .map_or(Ok(None), |v| v.map(Some))
-                .map_err(|e| ParseGitError::Query(e.to_string()))?,
-            nar_hash: pairs.remove("narHash"),
+                .map_err(|e| QueryError::param("lastModified", e))?,
+            nar_hash: pairs
+                .remove("narHash")
+                .map(|v| v.parse())
+                .transpose()
+                .map_err(|e| QueryError::param("narHash", e))?,
         };
 
         // Special Urls (File, Http, Https, Ftp) are by spec required to be absolute
@@ -156,14 +276,14 @@ impl<Protocol: GitProtocol> FlakeRefSource for GitRef<Protocol> {
         let url = {
             let mut inner_url = Url::parse(url.as_str().trim_start_matches("git+"))?;
 
-            let mut path = Path::new(url.path()).to_path_buf();
+            let mut path = PathBuf::from(decode_component(url.path()));
             if path.is_relative() {
                 path = path
                     .canonicalize()
                     .map_err(|e| ParseGitError::Canonicalize(path, e))?;
             }
 
-            inner_url.set_path(&format!("{}", path.to_string_lossy()));
+            inner_url.set_path(&encode_path(&path.to_string_lossy()));
 
             GitUrl::<Protocol>::try_from(inner_url)?
         };
@@ -174,42 +294,41 @@ impl<Protocol: GitProtocol> FlakeRefSource for GitRef<Protocol> {
 
 impl<Protocol: GitProtocol> Display for GitRef<Protocol> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut url = Url::parse(&format!("git+{url}", url = self.url)).unwrap();
+        let mut url =
+            Url::parse(&format!("git+{url}", url = self.url)).map_err(|_| std::fmt::Error)?;
 
-        let mut pairs = url.query_pairs_mut();
+        let mut pairs = Vec::new();
 
         if let Some(v) = self.attributes.all_refs {
-            pairs.append_pair("allRefs", &(v as u8).to_string());
+            pairs.push(("allRefs", (v as u8).to_string()));
         }
         if let Some(ref v) = self.attributes.dir {
-            pairs.append_pair("dir", &v.to_string_lossy());
+            pairs.push(("dir", v.to_string_lossy().into_owned()));
         }
         if let Some(ref v) = self.attributes.last_modified {
-            pairs.append_pair("lastModified", &v.0.timestamp().to_string());
+            pairs.push(("lastModified", v.epoch_seconds().to_string()));
         }
         if let Some(ref v) = self.attributes.reference {
-            pairs.append_pair("ref", v);
+            pairs.push(("ref", v.clone()));
         }
         if let Some(ref v) = self.attributes.rev {
-            pairs.append_pair("rev", v);
+            pairs.push(("rev", v.to_string()));
         }
         if let Some(ref v) = self.attributes.rev_count {
-            pairs.append_pair("revCount", &v.0.to_string());
+            pairs.push(("revCount", v.to_string()));
         }
         if let Some(v) = self.attributes.shallow {
-            pairs.append_pair("shallow", &(v as u8).to_string());
+            pairs.push(("shallow", (v as u8).to_string()));
         }
         if let Some(v) = self.attributes.submodules {
-            pairs.append_pair("submodules", &(v as u8).to_string());
+            pairs.push(("submodules", (v as u8).to_string()));
         }
         if let Some(ref nar_hash) = self.attributes.nar_hash {
-            pairs.append_pair("narHash", nar_hash);
+            pairs.push(("narHash", nar_hash.to_string()));
         }
 
-        let url = pairs.finish();
-
-        if matches!(url.query(), Some("")) {
-            url.set_query(None)
+        if !pairs.is_empty() {
+            url.set_query(Some(&encode_query(pairs)));
         }
 
         write!(f, "{url}")
@@ -220,11 +339,57 @@ impl<Protocol: GitProtocol> FromStr for GitRef<Protocol> {
     type Err = ParseGitError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let url = Url::parse(s)?;
+        let url = Url::parse(&normalize_scp_like(s))?;
         Self::from_url(url)
     }
 }
 
+/// Rewrite scp-like ssh urls into proper urls
+///
+/// Git accepts `git@github.com:owner/repo.git` as a shorthand for
+/// `ssh://git@github.com/owner/repo.git`.
+/// This is also the form offered by GitHub's clone dialog,
+/// so users commonly paste `git+ssh://git@github.com:owner/repo.git`,
+/// which is not a valid url as `owner` is parsed as the port.
+///
+/// If the authority of a `git+ssh://` url contains a non-numeric "port",
+/// the `:` is replaced with a `/`, other strings are returned unchanged.
+/// The replacement preserves the length and offsets of the input.
+///
+/// ```
+/// # use runix::flake_ref::git::normalize_scp_like;
+/// assert_eq!(
+///     normalize_scp_like("git+ssh://git@github.com:flox/runix.git?ref=main"),
+///     "git+ssh://git@github.com/flox/runix.git?ref=main"
+/// );
+/// assert_eq!(
+///     normalize_scp_like("git+ssh://git@example.com:2222/flox/runix"),
+///     "git+ssh://git@example.com:2222/flox/runix"
+/// );
+/// ```
+pub fn normalize_scp_like(s: &str) -> Cow<'_, str> {
+    let scheme = format!("{}://", GitRef::<protocol::SSH>::scheme());
+    let Some(rest) = s.strip_prefix(&scheme) else {
+        return Cow::Borrowed(s);
+    };
+
+    let authority_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let authority = &rest[..authority_end];
+    let host_start = authority.rfind('@').map_or(0, |at| at + 1);
+
+    match authority[host_start..].find(':') {
+        Some(colon)
+            if !authority[host_start + colon + 1..]
+                .chars()
+                .all(|c| c.is_ascii_digit()) =>
+        {
+            let colon = scheme.len() + host_start + colon;
+            Cow::Owned(format!("{}/{}", &s[..colon], &s[colon + 1..]))
+        },
+        _ => Cow::Borrowed(s),
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum ParseGitError {
     #[error(transparent)]
@@ -235,8 +400,8 @@ pub enum ParseGitError {
     InvalidScheme(String, String),
     #[error("No repo specified")]
     NoRepo,
-    #[error("Couldn't parse query: {0}")]
-    Query(String),
+    #[error(transparent)]
+    Query(#[from] QueryError),
     #[error("Could not resolve relative path '{0:?}': {1}")]
     Canonicalize(PathBuf, std::io::Error),
 }
@@ -250,7 +415,7 @@ mod tests {
     use super::*;
     use crate::flake_ref::FlakeRef;
 
-    static FLAKE_REF: &'_ str = "git+file:///somewhere/on/the/drive?dir=abc&lastModified=1666570118&ref=feature%2Fxyz&shallow=0&submodules=0";
+    static FLAKE_REF: &'_ str = "git+file:///somewhere/on/the/drive?dir=abc&lastModified=1666570118&ref=feature/xyz&shallow=0&submodules=0";
 
     #[test]
     fn parses_git_path_flakeref() {
@@ -273,13 +438,51 @@ mod tests {
         assert_eq!(expected.to_string(), FLAKE_REF);
     }
 
+    #[test]
+    fn build_git_ref() {
+        let url: GitUrl<protocol::File> = "file:///somewhere/on/the/drive".parse().unwrap();
+        let built = GitRef::from(url)
+            .with_dir("abc")
+            .with_last_modified(Utc.timestamp_opt(1666570118, 0).unwrap())
+            .with_ref("feature/xyz")
+            .with_shallow(false)
+            .with_submodules(false);
+
+        assert_eq!(built, GitRef::from_str(FLAKE_REF).unwrap());
+    }
+
+    #[test]
+    fn parses_scp_like() {
+        let expected: GitRef<protocol::SSH> =
+            GitRef::from_str("git+ssh://git@github.com/flox/runix.git?ref=main").unwrap();
+
+        assert_eq!(
+            GitRef::from_str("git+ssh://git@github.com:flox/runix.git?ref=main").unwrap(),
+            expected
+        );
+        assert_eq!(
+            "git+ssh://git@github.com:flox/runix.git?ref=main"
+                .parse::<FlakeRef>()
+                .unwrap(),
+            FlakeRef::GitSsh(expected)
+        );
+        assert_eq!(
+            normalize_scp_like("git+https://github.com:flox/runix"),
+            "git+https://github.com:flox/runix"
+        );
+    }
+
     #[test]
     fn parses_nar_hash() {
         let url = "git+file:///somewhere/on/the/drive?narHash=sha256-Gzcv5BkK4SIQVbxqMLxIBbJJcC0k6nGjgfve0X5lSzw%3D".to_string();
         let attrs: GitRef<protocol::File> = GitRef {
             url: "file:///somewhere/on/the/drive".parse().unwrap(),
             attributes: GitAttributes {
-                nar_hash: Some("sha256-Gzcv5BkK4SIQVbxqMLxIBbJJcC0k6nGjgfve0X5lSzw=".to_string()),
+                nar_hash: Some(
+                    "sha256-Gzcv5BkK4SIQVbxqMLxIBbJJcC0k6nGjgfve0X5lSzw="
+                        .parse()
+                        .unwrap(),
+                ),
                 ..Default::default()
             },
         };
@@ -351,10 +554,45 @@ mod tests {
           "shallow": true,
           "submodules": false,
         });
-        serde_json::from_value::<GitRef<protocol::SSH>>(expected.clone()).expect("should parse");
+        let flakeref = serde_json::from_value::<GitRef<protocol::SSH>>(expected.clone())
+            .expect("should parse");
+        assert_eq!(
+            flakeref.attributes.rev,
+            Some("0630fc9307852b30ea4c5915b6b74fa9db51d641".parse().unwrap())
+        );
         serde_json::from_value::<FlakeRef>(expected).expect("should parse");
     }
 
+    #[test]
+    fn bools_from_json() {
+        let flakeref = serde_json::from_value::<GitRef<protocol::File>>(json!({
+            "type": "git",
+            "url": "file:///somewhere/on/the/drive",
+            "shallow": 1,
+            "submodules": "0",
+            "allRefs": true,
+        }))
+        .expect("should parse");
+
+        assert_eq!(flakeref.attributes.shallow, Some(true));
+        assert_eq!(flakeref.attributes.submodules, Some(false));
+        assert_eq!(flakeref.attributes.all_refs, Some(true));
+        assert_eq!(
+            serde_json::to_value(&flakeref).unwrap(),
+            json!({
+                "type": "git",
+                "url": "file:///somewhere/on/the/drive",
+                "shallow": true,
+                "submodules": false,
+                "allRefs": true,
+            })
+        );
+        assert_eq!(
+            flakeref.to_string(),
+            "git+file:///somewhere/on/the/drive?allRefs=1&shallow=1&submodules=0"
+        );
+    }
+
     /// assert that relative file urls are resolved to git urls correctly
     #[test]
     fn relative_git_urls() {
@@ -4,13 +4,23 @@ use std::path::PathBuf;
 use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use serde_with::skip_serializing_none;
 use thiserror::Error;
 use url::Url;
 
 use self::service::GitService;
 use super::lock::{LastModified, NarHash, Rev, RevOrRef};
-use super::{Attrs, FlakeRefSource};
+use super::{
+    decode_component,
+    deserialize_query,
+    encode_path,
+    form_to_query,
+    Attrs,
+    FlakeRefAttributes,
+    FlakeRefSource,
+    QueryError,
+};
 use crate::url_parser::{
     extract_dir_attr,
     extract_host_attr,
@@ -75,6 +85,25 @@ impl TryFrom<Attrs> for GitServiceAttributes {
     }
 }
 
+impl<Service: service::GitServiceHost> TryFrom<Attrs> for GitServiceRef<Service> {
+    type Error = UrlParseError;
+
+    fn try_from(attrs: Attrs) -> Result<Self, Self::Error> {
+        let owner = match attrs.get("owner") {
+            Some(Value::String(owner)) => owner.clone(),
+            Some(v) => return Err(UrlParseError::AttributeType("owner", "String", v.clone())),
+            None => return Err(UrlParseError::MissingAttribute("owner")),
+        };
+        let repo = match attrs.get("repo") {
+            Some(Value::String(repo)) => repo.clone(),
+            Some(v) => return Err(UrlParseError::AttributeType("repo", "String", v.clone())),
+            None => return Err(UrlParseError::MissingAttribute("repo")),
+        };
+        let attributes = GitServiceAttributes::try_from(attrs)?;
+        Ok(GitServiceRef::new(owner, repo, attributes))
+    }
+}
+
 pub mod service {
     use std::borrow::Cow;
 
@@ -110,8 +139,12 @@ pub mod service {
         }
     }
 
-    pub(crate) trait GitServiceHost: Default + Eq {
+    /// A git hosting service with a dedicated flake ref scheme, e.g. `github:`
+    pub trait GitServiceHost: Default + Eq {
         fn scheme() -> Cow<'static, str>;
+
+        /// The host used by nix if no `?host=` parameter is given
+        fn default_host() -> Cow<'static, str>;
     }
 
     #[derive(Default, Debug, PartialEq, Eq, Clone)]
@@ -120,6 +153,10 @@ pub mod service {
         fn scheme() -> Cow<'static, str> {
             "github".into()
         }
+
+        fn default_host() -> Cow<'static, str> {
+            "github.com".into()
+        }
     }
 
     #[derive(Default, Debug, PartialEq, Eq, Clone)]
@@ -128,6 +165,25 @@ pub mod service {
         fn scheme() -> Cow<'static, str> {
             "gitlab".into()
         }
+
+        fn default_host() -> Cow<'static, str> {
+            "gitlab.com".into()
+        }
+    }
+
+    /// [sr.ht](https://sr.ht) hosted repositories
+    ///
+    /// Owners on sourcehut are prefixed with `~`, e.g. `sourcehut:~owner/repo`
+    #[derive(Default, Debug, PartialEq, Eq, Clone)]
+    pub struct Sourcehut;
+    impl GitServiceHost for Sourcehut {
+        fn scheme() -> Cow<'static, str> {
+            "sourcehut".into()
+        }
+
+        fn default_host() -> Cow<'static, str> {
+            "git.sr.ht".into()
+        }
     }
 }
 
@@ -142,6 +198,164 @@ impl<Service: Default> GitServiceRef<Service> {
     }
 }
 
+impl GitServiceRef<service::Github> {
+    /// Create a `github:<owner>/<repo>` flake ref
+    ///
+    /// ```
+    /// # use runix::flake_ref::git_service::GitServiceRef;
+    /// let flake_ref = GitServiceRef::github("flox", "runix")
+    ///     .with_ref("main")
+    ///     .with_dir("crates/runix");
+    ///
+    /// assert_eq!(
+    ///     flake_ref.to_string(),
+    ///     "github:flox/runix/main?dir=crates/runix"
+    /// );
+    /// ```
+    pub fn github(owner: impl Into<String>, repo: impl Into<String>) -> Self {
+        Self::new(owner.into(), repo.into(), Default::default())
+    }
+}
+
+impl GitServiceRef<service::Gitlab> {
+    /// Create a `gitlab:<owner>/<repo>` flake ref
+    pub fn gitlab(owner: impl Into<String>, repo: impl Into<String>) -> Self {
+        Self::new(owner.into(), repo.into(), Default::default())
+    }
+}
+
+impl GitServiceRef<service::Sourcehut> {
+    /// Create a `sourcehut:<owner>/<repo>` flake ref
+    ///
+    /// Note that sourcehut owners are prefixed with `~`
+    pub fn sourcehut(owner: impl Into<String>, repo: impl Into<String>) -> Self {
+        Self::new(owner.into(), repo.into(), Default::default())
+    }
+}
+
+impl<Service> GitServiceRef<Service> {
+    /// Set the `host` attribute, e.g. for GitHub Enterprise
+    pub fn with_host(mut self, host: impl Into<String>) -> Self {
+        self.attributes.host = Some(host.into());
+        self
+    }
+
+    /// Set the `dir` attribute pointing to a flake in a subdirectory
+    pub fn with_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.attributes.dir = Some(dir.into());
+        self
+    }
+
+    /// Set the branch or tag to fetch
+    ///
+    /// Git service refs only point to either a `ref` or a `rev`,
+    /// so this unsets a previously set `rev`
+    pub fn with_ref(mut self, reference: impl Into<String>) -> Self {
+        self.attributes.rev = None;
+        self.attributes.reference = Some(reference.into());
+        self
+    }
+
+    /// Set the commit to fetch
+    ///
+    /// Git service refs only point to either a `ref` or a `rev`,
+    /// so this unsets a previously set `ref`
+    pub fn with_rev(mut self, rev: Rev) -> Self {
+        self.attributes.reference = None;
+        self.attributes.rev = Some(rev);
+        self
+    }
+
+    /// Set either the commit or the branch or tag to fetch
+    pub fn with_rev_or_ref(self, rev_or_ref: RevOrRef) -> Self {
+        match rev_or_ref {
+            RevOrRef::Rev { rev } => self.with_rev(rev),
+            RevOrRef::Ref { reference } => self.with_ref(reference),
+        }
+    }
+
+    /// Set the `narHash` attribute
+    pub fn with_nar_hash(mut self, nar_hash: impl Into<NarHash>) -> Self {
+        self.attributes.nar_hash = Some(nar_hash.into());
+        self
+    }
+
+    /// Set the `lastModified` attribute
+    pub fn with_last_modified(mut self, last_modified: impl Into<LastModified>) -> Self {
+        self.attributes.last_modified = Some(last_modified.into());
+        self
+    }
+
+    /// The commit or branch/tag this flake ref points to
+    ///
+    /// Set either as the third path segment or with a `?rev=`/`?ref=` parameter.
+    /// Path segments of 40 hexadecimal characters are interpreted as commits.
+    ///
+    /// ```
+    /// # use runix::flake_ref::git_service::GitServiceRef;
+    /// # use runix::flake_ref::git_service::service::Github;
+    /// # use runix::flake_ref::lock::RevOrRef;
+    /// let flake_ref: GitServiceRef<Github> = "github:flox/runix/main".parse().unwrap();
+    /// assert_eq!(
+    ///     flake_ref.rev_or_ref(),
+    ///     Some(RevOrRef::Ref {
+    ///         reference: "main".to_string()
+    ///     })
+    /// );
+    ///
+    /// let flake_ref: GitServiceRef<Github> =
+    ///     "github:flox/runix/50500a744e3c2af9d89123ae17b71406b428c3ab"
+    ///         .parse()
+    ///         .unwrap();
+    /// assert!(matches!(flake_ref.rev_or_ref(), Some(RevOrRef::Rev { .. })));
+    /// ```
+    pub fn rev_or_ref(&self) -> Option<RevOrRef> {
+        match (&self.attributes.rev, &self.attributes.reference) {
+            (Some(rev), _) => Some(RevOrRef::Rev { rev: rev.clone() }),
+            (None, Some(reference)) => Some(RevOrRef::Ref {
+                reference: reference.clone(),
+            }),
+            (None, None) => None,
+        }
+    }
+}
+
+impl<Service: service::GitServiceHost> GitServiceRef<Service> {
+    /// The host serving the repository
+    ///
+    /// Either the host set with the `?host=` parameter,
+    /// e.g. for GitHub Enterprise or self-hosted GitLab instances,
+    /// or the default host of the service (e.g. `github.com`).
+    pub fn host(&self) -> Cow<'_, str> {
+        match self.attributes.host {
+            Some(ref host) => host.into(),
+            None => Service::default_host(),
+        }
+    }
+}
+
+impl<Service> FlakeRefAttributes for GitServiceRef<Service> {
+    fn rev(&self) -> Option<Rev> {
+        self.attributes.rev.clone()
+    }
+
+    fn ref_(&self) -> Option<String> {
+        self.attributes.reference.clone()
+    }
+
+    fn dir(&self) -> Option<PathBuf> {
+        self.attributes.dir.clone()
+    }
+
+    fn nar_hash(&self) -> Option<NarHash> {
+        self.attributes.nar_hash.clone()
+    }
+
+    fn last_modified(&self) -> Option<LastModified> {
+        self.attributes.last_modified.clone()
+    }
+}
+
 impl<Service: service::GitServiceHost> FlakeRefSource for GitServiceRef<Service> {
     type ParseErr = ParseGitServiceError;
 
@@ -160,13 +374,18 @@ impl<Service: service::GitServiceHost> FlakeRefSource for GitServiceRef<Service>
             .path()
             .split_once('/')
             .ok_or(ParseGitServiceError::NoRepo)?;
+        // the third path segment is a ref or a rev, refs may contain further slashes
         let (repo, rev_or_ref) = match rest.split_once('/') {
-            Some((repo, rev_or_ref)) => (repo, Some(rev_or_ref.to_owned().into())),
+            Some((repo, "")) => (repo, None),
+            Some((repo, rev_or_ref)) => (repo, Some(RevOrRef::from(decode_component(rev_or_ref)))),
             None => (rest, None),
         };
+        if owner.is_empty() || repo.is_empty() {
+            Err(ParseGitServiceError::NoRepo)?;
+        }
 
         let mut attributes: GitServiceAttributes =
-            serde_urlencoded::from_str(url.query().unwrap_or_default())?;
+            deserialize_query(url.query().unwrap_or_default())?;
 
         if attributes.rev.is_some() && attributes.reference.is_some() {
             Err(ParseGitServiceError::TwoRevs)?;
@@ -183,8 +402,8 @@ impl<Service: service::GitServiceHost> FlakeRefSource for GitServiceRef<Service>
         }
 
         Ok(GitServiceRef {
-            owner: owner.to_string(),
-            repo: repo.to_string(),
+            owner: decode_component(owner),
+            repo: decode_component(repo),
             attributes,
             _type: Default::default(),
         })
@@ -207,8 +426,8 @@ impl<Service: service::GitServiceHost> Display for GitServiceRef<Service> {
             f,
             "{schema}:{owner}/{repo}",
             schema = Self::scheme(),
-            owner = self.owner,
-            repo = self.repo
+            owner = encode_path(&self.owner),
+            repo = encode_path(&self.repo)
         )?;
 
         if let Some(part) = attributes
@@ -217,10 +436,10 @@ impl<Service: service::GitServiceHost> Display for GitServiceRef<Service> {
             .map(|rev| rev.to_string())
             .or_else(|| attributes.reference.take())
         {
-            write!(f, "/{part}")?;
+            write!(f, "/{part}", part = encode_path(&part))?;
         };
 
-        let query = serde_urlencoded::to_string(attributes).unwrap_or_default();
+        let query = form_to_query(&serde_urlencoded::to_string(attributes).unwrap_or_default());
         if !query.is_empty() {
             write!(f, "?{query}",)?;
         }
@@ -235,8 +454,8 @@ pub enum ParseGitServiceError {
     Url(#[from] url::ParseError),
     #[error("Url contains multiple commit hashes")]
     TwoRevs,
-    #[error("Couldn't parse query: {0}")]
-    Query(#[from] serde_urlencoded::de::Error),
+    #[error(transparent)]
+    Query(#[from] QueryError),
     #[error("Invalid scheme (expected: '{0}:', found '{1}:'")]
     InvalidScheme(String, String),
     #[error("No repo specified")]
@@ -268,6 +487,69 @@ mod tests {
         );
     }
 
+    #[test]
+    fn build_git_service_refs() {
+        let rev = Rev::from_str("50500a744e3c2af9d89123ae17b71406b428c3ab").unwrap();
+
+        assert_eq!(
+            GitServiceRef::github("flox", "runix").with_ref("main"),
+            GitServiceRef::<service::Github>::from_str("github:flox/runix/main").unwrap()
+        );
+        assert_eq!(
+            GitServiceRef::gitlab("flox", "runix")
+                .with_ref("main")
+                .with_rev(rev.clone())
+                .with_host("gitlab.mycorp.com"),
+            GitServiceRef::<service::Gitlab>::from_str(
+                "gitlab:flox/runix/50500a744e3c2af9d89123ae17b71406b428c3ab?host=gitlab.mycorp.com"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            GitServiceRef::sourcehut("~flox", "runix").with_dir("crates/runix"),
+            GitServiceRef::<service::Sourcehut>::from_str("sourcehut:~flox/runix?dir=crates/runix")
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_host() {
+        roundtrip::<GitServiceRef<service::Github>>("github:org/repo?host=github.mycorp.com");
+        roundtrip::<GitServiceRef<service::Gitlab>>(
+            "gitlab:org/repo/feature-branch?host=gitlab.mycorp.com",
+        );
+
+        let parsed =
+            GitServiceRef::<service::Github>::from_str("github:org/repo?host=github.mycorp.com")
+                .unwrap();
+        assert_eq!(parsed.attributes.host.as_deref(), Some("github.mycorp.com"));
+        assert_eq!(parsed.host(), "github.mycorp.com");
+
+        let parsed = GitServiceRef::<service::Github>::from_str("github:org/repo").unwrap();
+        assert_eq!(parsed.host(), "github.com");
+        let parsed = GitServiceRef::<service::Gitlab>::from_str("gitlab:org/repo").unwrap();
+        assert_eq!(parsed.host(), "gitlab.com");
+        let parsed = GitServiceRef::<service::Sourcehut>::from_str("sourcehut:~org/repo").unwrap();
+        assert_eq!(parsed.host(), "git.sr.ht");
+    }
+
+    #[test]
+    fn parse_sourcehut_simple() {
+        roundtrip::<GitServiceRef<service::Sourcehut>>("sourcehut:~owner/repo");
+        roundtrip::<GitServiceRef<service::Sourcehut>>("sourcehut:~owner/repo/feature-branch");
+        roundtrip_to::<GitServiceRef<service::Sourcehut>>(
+            "sourcehut:~owner/repo?rev=50500a744e3c2af9d89123ae17b71406b428c3ab",
+            "sourcehut:~owner/repo/50500a744e3c2af9d89123ae17b71406b428c3ab",
+        );
+
+        let parsed =
+            GitServiceRef::<service::Sourcehut>::from_str("sourcehut:~owner/repo").unwrap();
+        assert_eq!(parsed.owner, "~owner");
+        assert_eq!(parsed.repo, "repo");
+
+        GitServiceRef::<service::Sourcehut>::from_str("github:owner/repo").unwrap_err();
+    }
+
     #[test]
     fn parse_invalid_simple() {
         GitServiceRef::<service::Github>::from_str("github:owner/repo/feature?ref=another-feature")
@@ -287,7 +569,43 @@ mod tests {
         assert!(matches!(
             GitServiceRef::<service::Github>::from_str("github:owner"),
             Err(ParseGitServiceError::NoRepo)
-        ))
+        ));
+        assert!(matches!(
+            GitServiceRef::<service::Github>::from_str("github:owner/"),
+            Err(ParseGitServiceError::NoRepo)
+        ));
+    }
+
+    #[test]
+    fn parse_rev_or_ref_segment() {
+        let rev = Rev::from_str("50500a744e3c2af9d89123ae17b71406b428c3ab").unwrap();
+
+        let parsed = GitServiceRef::<service::Github>::from_str(
+            "github:owner/repo/50500a744e3c2af9d89123ae17b71406b428c3ab",
+        )
+        .unwrap();
+        assert_eq!(parsed.rev_or_ref(), Some(RevOrRef::Rev { rev }));
+
+        // only exactly 40 hex characters are interpreted as a rev
+        let parsed = GitServiceRef::<service::Github>::from_str(
+            "github:owner/repo/release-50500a744e3c2af9d89123ae17b71406b428c3ab",
+        )
+        .unwrap();
+        assert_eq!(
+            parsed.rev_or_ref(),
+            Some(RevOrRef::Ref {
+                reference: "release-50500a744e3c2af9d89123ae17b71406b428c3ab".to_string()
+            })
+        );
+
+        let parsed = GitServiceRef::<service::Github>::from_str("github:owner/repo/").unwrap();
+        assert_eq!(parsed.rev_or_ref(), None);
+        assert_eq!(parsed.to_string(), "github:owner/repo");
+
+        roundtrip_to::<GitServiceRef<service::Github>>(
+            "github:owner/repo/feature%2Fxyz",
+            "github:owner/repo/feature/xyz",
+        );
     }
 
     #[test]
@@ -321,7 +639,11 @@ mod tests {
                 dir: None,
                 reference: Some("unstable".into()),
                 rev: Some(Rev::from_str("0630fc9307852b30ea4c5915b6b74fa9db51d641").unwrap()),
-                nar_hash: Some("sha256-Gzcv5BkK4SIQVbxqMLxIBbJJcC0k6nGjgfve0X5lSzw=".into()),
+                nar_hash: Some(
+                    "sha256-Gzcv5BkK4SIQVbxqMLxIBbJJcC0k6nGjgfve0X5lSzw="
+                        .parse()
+                        .unwrap(),
+                ),
                 last_modified: Some(Utc.timestamp_opt(1688730350, 0).unwrap().into()),
             },
             _type: GitService::default(),
@@ -1,6 +1,7 @@
 use std::borrow::Cow;
 use std::collections::BTreeMap;
 use std::fmt::Display;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
@@ -8,7 +9,20 @@ use serde_json::Value;
 use thiserror::Error;
 use url::Url;
 
-use super::{Attrs, FlakeRef, FlakeRefSource};
+use super::lock::{LastModified, NarHash, Rev, RevCount};
+use super::{
+    decode_component,
+    deserialize_query,
+    encode_path,
+    encode_query,
+    Attrs,
+    FlakeRef,
+    FlakeRefAttributes,
+    FlakeRefSource,
+    QueryError,
+    Timestamp,
+    TimestampDeserialize,
+};
 use crate::url_parser::{resolve_flake_ref, UrlParseError, PARSER_UTIL_BIN_PATH};
 
 /// <https://cs.github.com/NixOS/nix/blob/f225f4307662fe9a57543d0c86c28aa9fddaf0d2/src/libfetchers/path.cc#L46>
@@ -72,6 +86,39 @@ impl IndirectRef {
         }
     }
 
+    /// Create a `flake:<id>` flake ref
+    ///
+    /// ```
+    /// # use runix::flake_ref::indirect::IndirectRef;
+    /// let flake_ref = IndirectRef::from_id("nixpkgs").with_ref("nixos-23.05");
+    ///
+    /// assert_eq!(flake_ref.to_string(), "flake:nixpkgs/nixos-23.05");
+    /// ```
+    pub fn from_id(id: impl Into<String>) -> Self {
+        Self::new(id.into(), Default::default())
+    }
+
+    /// Set the `ref` attribute, overriding the ref of the registry entry
+    pub fn with_ref(mut self, reference: impl Into<String>) -> Self {
+        self.attributes.insert("ref".to_string(), reference.into());
+        self
+    }
+
+    /// Set the `rev` attribute, overriding the rev of the registry entry
+    pub fn with_rev(mut self, rev: Rev) -> Self {
+        self.attributes.insert("rev".to_string(), rev.to_string());
+        self
+    }
+
+    /// Set the `dir` attribute pointing to a flake in a subdirectory
+    pub fn with_dir(mut self, dir: impl AsRef<Path>) -> Self {
+        self.attributes.insert(
+            "dir".to_string(),
+            dir.as_ref().to_string_lossy().into_owned(),
+        );
+        self
+    }
+
     /// Resolves an indirect flake reference to a concrete reference
     ///
     /// Note that this method calls `parser-util`, which relies on the `NIX_USER_CONF_FILES`
@@ -84,6 +131,35 @@ impl IndirectRef {
     }
 }
 
+/// Indirect refs store their attributes untyped,
+/// attributes that fail to parse are reported as [None]
+impl FlakeRefAttributes for IndirectRef {
+    fn rev(&self) -> Option<Rev> {
+        self.attributes.get("rev")?.parse().ok()
+    }
+
+    fn ref_(&self) -> Option<String> {
+        self.attributes.get("ref").cloned()
+    }
+
+    fn dir(&self) -> Option<PathBuf> {
+        self.attributes.get("dir").map(PathBuf::from)
+    }
+
+    fn nar_hash(&self) -> Option<NarHash> {
+        self.attributes.get("narHash")?.parse().ok()
+    }
+
+    fn last_modified(&self) -> Option<LastModified> {
+        let last_modified = self.attributes.get("lastModified")?;
+        Timestamp::try_from(TimestampDeserialize::TsString(last_modified.clone())).ok()
+    }
+
+    fn rev_count(&self) -> Option<RevCount> {
+        self.attributes.get("revCount")?.parse().map(RevCount).ok()
+    }
+}
+
 impl FlakeRefSource for IndirectRef {
     type ParseErr = ParseIndirectError;
 
@@ -91,27 +167,76 @@ impl FlakeRefSource for IndirectRef {
         "flake".into()
     }
 
+    /// Parse `flake:<id>(/<ref>)?(/<rev>)?` urls
+    ///
+    /// A single segment following the id is interpreted as a rev
+    /// if it is a valid commit hash, otherwise as a ref.
     fn from_url(url: Url) -> Result<Self, Self::ParseErr> {
-        let id = url.path().to_string();
-        let attributes = serde_urlencoded::from_str(url.query().unwrap_or_default())?;
-        let _type = Tag::Indirect;
+        let mut segments = url.path().split('/').map(decode_component);
+        let id = segments.next().unwrap_or_default();
+        let mut attributes: BTreeMap<String, String> =
+            deserialize_query(url.query().unwrap_or_default())?;
+
+        let (reference, rev) = match (segments.next(), segments.next(), segments.next()) {
+            (None, ..) => (None, None),
+            (Some(rev_or_ref), None, _) => match Rev::from_str(&rev_or_ref) {
+                Ok(_) => (None, Some(rev_or_ref)),
+                Err(_) => (Some(rev_or_ref), None),
+            },
+            (Some(reference), Some(rev), None) if Rev::from_str(&rev).is_ok() => {
+                (Some(reference), Some(rev))
+            },
+            _ => return Err(ParseIndirectError::InvalidPath(url.path().to_string())),
+        };
+
+        for (name, value) in [("ref", reference), ("rev", rev)] {
+            let Some(value) = value else { continue };
+            if attributes.contains_key(name) {
+                return Err(ParseIndirectError::ConflictingAttribute(name));
+            }
+            attributes.insert(name.to_string(), value);
+        }
+
         Ok(IndirectRef {
             id,
             attributes,
-            _type,
+            _type: Tag::Indirect,
         })
     }
 }
 
 impl Display for IndirectRef {
+    /// Writes `ref` and `rev` as path segments like nix does,
+    /// unless they could not be parsed back from the path
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut attributes = self.attributes.clone();
+
         write!(f, "{prefix}:{id}", prefix = Self::scheme(), id = self.id)?;
-        if !self.attributes.is_empty() {
-            write!(
-                f,
-                "?{attributes}",
-                attributes = serde_urlencoded::to_string(&self.attributes).unwrap_or_default()
-            )?
+
+        let reference = attributes
+            .get("ref")
+            .filter(|reference| {
+                !reference.is_empty()
+                    && !reference.contains('/')
+                    && Rev::from_str(reference).is_err()
+            })
+            .cloned();
+        let rev = attributes
+            .get("rev")
+            .filter(|rev| Rev::from_str(rev).is_ok())
+            .cloned();
+
+        if let Some(reference) = reference {
+            attributes.remove("ref");
+            write!(f, "/{}", encode_path(&reference))?;
+        }
+        if let Some(rev) = rev {
+            attributes.remove("rev");
+            write!(f, "/{rev}")?;
+        }
+
+        if !attributes.is_empty() {
+            write!(f, "?{attributes}", attributes = encode_query(&attributes))?
         }
 
         Ok(())
@@ -140,8 +265,12 @@ pub enum ParseIndirectError {
     Url(#[from] url::ParseError),
     #[error("Invalid scheme (expected: '{0}:', found '{1}:'")]
     InvalidScheme(String, String),
-    #[error("Couldn't parse query: {0}")]
-    Query(#[from] serde_urlencoded::de::Error),
+    #[error(transparent)]
+    Query(#[from] QueryError),
+    #[error("Invalid path '{0}' (expected: '<id>(/<ref>)?(/<rev>)?')")]
+    InvalidPath(String),
+    #[error("'{0}' is set both as a path segment and as a query parameter")]
+    ConflictingAttribute(&'static str),
 }
 
 #[cfg(test)]
@@ -151,6 +280,7 @@ mod tests {
     use temp_env::with_var;
 
     use super::*;
+    use crate::flake_ref::tests::{roundtrip, roundtrip_to};
     use crate::flake_ref::FlakeRef;
     use crate::registry::Registry;
     use crate::url_parser::PARSER_UTIL_BIN_PATH;
@@ -169,6 +299,16 @@ mod tests {
         assert_eq!(expect.to_string(), original);
     }
 
+    #[test]
+    fn build_indirect_ref() {
+        assert_eq!(
+            IndirectRef::from_id("nixpkgs")
+                .with_ref("nixos-23.05")
+                .with_dir("lib"),
+            IndirectRef::from_str("flake:nixpkgs?ref=nixos-23.05&dir=lib").unwrap()
+        );
+    }
+
     #[test]
     fn parses_registry_flakeref() {
         let original = "nixpkgs".to_string();
@@ -239,6 +379,53 @@ mod tests {
         )
     }
 
+    #[test]
+    fn parses_ref_and_rev_segments() {
+        let rev = "50500a744e3c2af9d89123ae17b71406b428c3ab";
+        let rev_typed = Rev::from_str(rev).unwrap();
+
+        let parsed = IndirectRef::from_str("flake:nixpkgs/nixos-23.05").unwrap();
+        assert_eq!(
+            parsed,
+            IndirectRef::from_id("nixpkgs").with_ref("nixos-23.05")
+        );
+
+        let parsed = IndirectRef::from_str(&format!("flake:nixpkgs/{rev}")).unwrap();
+        assert_eq!(
+            parsed,
+            IndirectRef::from_id("nixpkgs").with_rev(rev_typed.clone())
+        );
+
+        let parsed =
+            IndirectRef::from_str(&format!("flake:nixpkgs/nixos-23.05/{rev}?dir=lib")).unwrap();
+        assert_eq!(
+            parsed,
+            IndirectRef::from_id("nixpkgs")
+                .with_ref("nixos-23.05")
+                .with_rev(rev_typed)
+                .with_dir("lib")
+        );
+        assert_eq!(
+            parsed.to_string(),
+            format!("flake:nixpkgs/nixos-23.05/{rev}?dir=lib")
+        );
+
+        roundtrip_to::<IndirectRef>(
+            &format!("flake:nixpkgs?rev={rev}&ref=nixos-23.05"),
+            &format!("flake:nixpkgs/nixos-23.05/{rev}"),
+        );
+        roundtrip::<IndirectRef>("flake:nixpkgs?ref=feature/xyz");
+
+        assert!(matches!(
+            IndirectRef::from_str("flake:nixpkgs/nixos-23.05/not-a-rev"),
+            Err(ParseIndirectError::InvalidPath(_))
+        ));
+        assert!(matches!(
+            IndirectRef::from_str("flake:nixpkgs/nixos-23.05?ref=nixos-unstable"),
+            Err(ParseIndirectError::ConflictingAttribute("ref"))
+        ));
+    }
+
     #[test]
     fn does_not_parse_other() {
         IndirectRef::from_str("github:nixpkgs").unwrap_err();
@@ -0,0 +1,426 @@
+//! Inputs declared in a `flake.nix` and their comparison with a [LockFile]
+//!
+//! Declared inputs are the `inputs` attribute of a `flake.nix`,
+//! e.g. as obtained with `nix eval --json --expr '(import ./flake.nix).inputs'`
+//! or [DeclaredInput::eval].
+//! [LockFile::stale_inputs] reports lock entries that do not match these declarations,
+//! i.e. the lock file needs to be updated by `nix flake lock`.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+use thiserror::Error;
+
+use super::indirect::IndirectRef;
+use super::lock::{InputRef, LockFile};
+use super::FlakeRef;
+use crate::arguments::source::SourceArgs;
+use crate::arguments::NixArgs;
+use crate::command::Eval;
+use crate::{NixBackend, RunJson};
+
+/// Inputs of a flake by name
+pub type DeclaredInputs = BTreeMap<String, DeclaredInput>;
+
+/// An input as declared in the `inputs` attribute of a `flake.nix`
+///
+/// ```
+/// # use runix::flake_ref::inputs::DeclaredInputs;
+/// let inputs: DeclaredInputs = serde_json::from_value(serde_json::json!({
+///     "nixpkgs": { "url": "github:NixOS/nixpkgs/nixos-23.05" },
+///     "home-manager": {
+///         "type": "github",
+///         "owner": "nix-community",
+///         "repo": "home-manager",
+///         "inputs": { "nixpkgs": { "follows": "nixpkgs" } }
+///     },
+/// }))
+/// .unwrap();
+///
+/// assert_eq!(
+///     inputs["home-manager"].inputs["nixpkgs"].follows.as_deref(),
+///     Some("nixpkgs")
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeclaredInput {
+    /// The flake ref given as `url` or as attributes
+    ///
+    /// [None] for inputs that `follow` another input or are looked up in the registry by name.
+    pub flake_ref: Option<FlakeRef>,
+    /// The input path this input follows, e.g. `nixpkgs`
+    pub follows: Option<String>,
+    /// Whether the input is a flake, i.e. not declared with `flake = false`
+    pub flake: bool,
+    /// Overrides of the input's own inputs
+    pub inputs: DeclaredInputs,
+}
+
+impl Default for DeclaredInput {
+    fn default() -> Self {
+        DeclaredInput {
+            flake_ref: None,
+            follows: None,
+            flake: true,
+            inputs: DeclaredInputs::new(),
+        }
+    }
+}
+
+impl From<FlakeRef> for DeclaredInput {
+    fn from(flake_ref: FlakeRef) -> Self {
+        DeclaredInput {
+            flake_ref: Some(flake_ref),
+            ..Default::default()
+        }
+    }
+}
+
+impl DeclaredInput {
+    /// Evaluate the inputs declared in the `flake.nix` in `flake_dir`
+    ///
+    /// Runs `nix eval --json --expr '(import <flake_dir>/flake.nix).inputs or {}'`.
+    pub async fn eval<B>(
+        flake_dir: impl AsRef<Path>,
+        backend: &B,
+        nix_args: &NixArgs,
+    ) -> Result<DeclaredInputs, EvalInputsError<<Eval as RunJson<B>>::JsonError>>
+    where
+        B: NixBackend + Sync,
+        Eval: RunJson<B>,
+    {
+        let flake_nix = flake_dir.as_ref().join("flake.nix");
+        let escaped = flake_nix
+            .to_string_lossy()
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace("${", "\\${");
+
+        let value = Eval {
+            source: SourceArgs {
+                expr: Some(format!(r#"(import "{escaped}").inputs or {{}}"#).into()),
+            },
+            ..Default::default()
+        }
+        .run_json(backend, nix_args)
+        .await
+        .map_err(EvalInputsError::Eval)?;
+
+        serde_json::from_value(value).map_err(EvalInputsError::Parse)
+    }
+
+    /// The flake ref nix locks the input `name` from
+    ///
+    /// Inputs without explicit flake ref refer to the flake of the same name in the registry.
+    fn original(&self, name: &str) -> FlakeRef {
+        self.flake_ref
+            .clone()
+            .unwrap_or_else(|| FlakeRef::Indirect(IndirectRef::from_id(name)))
+    }
+}
+
+impl<'de> Deserialize<'de> for DeclaredInput {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        use serde::de::Error;
+
+        let mut attrs = serde_json::Map::<String, Value>::deserialize(deserializer)?;
+        let mut input = DeclaredInput::default();
+
+        if let Some(url) = attrs.remove("url") {
+            let url = String::deserialize(url).map_err(D::Error::custom)?;
+            input.flake_ref = Some(url.parse().map_err(D::Error::custom)?);
+        }
+        if let Some(follows) = attrs.remove("follows") {
+            input.follows = Some(String::deserialize(follows).map_err(D::Error::custom)?);
+        }
+        if let Some(flake) = attrs.remove("flake") {
+            input.flake = bool::deserialize(flake).map_err(D::Error::custom)?;
+        }
+        if let Some(inputs) = attrs.remove("inputs") {
+            input.inputs = DeclaredInputs::deserialize(inputs).map_err(D::Error::custom)?;
+        }
+        // flake refs given as attributes, e.g. `{ type = "github"; owner = ...; }`
+        if attrs.contains_key("type") {
+            input.flake_ref = Some(FlakeRef::from_attrs(attrs).map_err(D::Error::custom)?);
+        }
+
+        Ok(input)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum EvalInputsError<E: std::error::Error + 'static> {
+    #[error("Could not evaluate flake inputs: {0}")]
+    Eval(#[source] E),
+    #[error("Could not parse flake inputs: {0}")]
+    Parse(#[source] serde_json::Error),
+}
+
+/// A lock file entry that does not match the declared inputs, see [LockFile::stale_inputs]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StaleInput {
+    /// The input is declared but not locked
+    Missing { input: String },
+    /// The input is locked but no longer declared
+    Orphaned { input: String },
+    /// The input was locked from a different flake ref than declared
+    OriginalChanged {
+        input: String,
+        declared: Box<FlakeRef>,
+        locked: Option<Box<FlakeRef>>,
+    },
+    /// The input follows a different input than declared
+    FollowsChanged {
+        input: String,
+        declared: Option<String>,
+        locked: Option<String>,
+    },
+}
+
+impl StaleInput {
+    /// The path of the stale input, e.g. `home-manager/nixpkgs`
+    pub fn input(&self) -> &str {
+        match self {
+            StaleInput::Missing { input }
+            | StaleInput::Orphaned { input }
+            | StaleInput::OriginalChanged { input, .. }
+            | StaleInput::FollowsChanged { input, .. } => input,
+        }
+    }
+}
+
+impl LockFile {
+    /// Compare the lock file with the inputs declared in the locking flake's `flake.nix`
+    ///
+    /// Reports inputs that are declared but not locked, locked but not declared,
+    /// locked from a different `original` flake ref than declared,
+    /// or that `follow` another input than declared.
+    /// Overrides of transitive inputs, e.g. `inputs.a.inputs.nixpkgs.follows`, are checked as well.
+    /// An empty report means the lock file is up to date with the declarations.
+    ///
+    /// ```
+    /// # use runix::flake_ref::inputs::{DeclaredInputs, StaleInput};
+    /// # use runix::flake_ref::lock::LockFile;
+    /// let lock: LockFile = r#"{
+    ///   "nodes": {
+    ///     "nixpkgs": {
+    ///       "locked": {
+    ///         "owner": "NixOS",
+    ///         "repo": "nixpkgs",
+    ///         "rev": "ea4c80b39be4c09702b0cb3b42eab59e2ba4f24b",
+    ///         "type": "github"
+    ///       },
+    ///       "original": { "id": "nixpkgs", "type": "indirect" }
+    ///     },
+    ///     "root": { "inputs": { "nixpkgs": "nixpkgs" } }
+    ///   },
+    ///   "root": "root",
+    ///   "version": 7
+    /// }"#
+    /// .parse()
+    /// .unwrap();
+    ///
+    /// let declared: DeclaredInputs = serde_json::from_value(serde_json::json!({
+    ///     "nixpkgs": {},
+    /// }))
+    /// .unwrap();
+    /// assert!(lock.stale_inputs(&declared).is_empty());
+    ///
+    /// let declared: DeclaredInputs = serde_json::from_value(serde_json::json!({
+    ///     "nixpkgs": { "url": "github:NixOS/nixpkgs/nixos-23.05" },
+    /// }))
+    /// .unwrap();
+    /// assert!(matches!(
+    ///     &lock.stale_inputs(&declared)[..],
+    ///     [StaleInput::OriginalChanged { .. }]
+    /// ));
+    /// ```
+    pub fn stale_inputs(&self, declared: &DeclaredInputs) -> Vec<StaleInput> {
+        let mut stale = Vec::new();
+        self.collect_stale_inputs(&self.root, "", declared, &mut stale);
+
+        if let Some(root) = self.root_node() {
+            stale.extend(
+                root.inputs
+                    .keys()
+                    .filter(|name| !declared.contains_key(*name))
+                    .map(|name| StaleInput::Orphaned {
+                        input: name.clone(),
+                    }),
+            );
+        }
+
+        stale.sort_by(|a, b| a.input().cmp(b.input()));
+        stale
+    }
+
+    fn collect_stale_inputs(
+        &self,
+        key: &str,
+        prefix: &str,
+        declared: &DeclaredInputs,
+        stale: &mut Vec<StaleInput>,
+    ) {
+        let is_root = key == self.root;
+        let Some(node) = self.nodes.get(key) else {
+            return;
+        };
+
+        for (name, input) in declared {
+            let path = format!("{prefix}{name}");
+            match node.inputs.get(name) {
+                None => stale.push(StaleInput::Missing { input: path }),
+                Some(InputRef::Follows(follows)) => {
+                    let locked = Some(follows.join("/"));
+                    if input.follows != locked {
+                        stale.push(StaleInput::FollowsChanged {
+                            input: path,
+                            declared: input.follows.clone(),
+                            locked,
+                        });
+                    }
+                },
+                Some(InputRef::Node(_)) if input.follows.is_some() => {
+                    stale.push(StaleInput::FollowsChanged {
+                        input: path,
+                        declared: input.follows.clone(),
+                        locked: None,
+                    });
+                },
+                Some(InputRef::Node(input_key)) => {
+                    let locked = self.nodes.get(input_key).and_then(|n| n.original.as_ref());
+                    // transitive inputs without explicit flake ref are not overridden
+                    if is_root || input.flake_ref.is_some() {
+                        let original = input.original(name);
+                        if !locked.is_some_and(|locked| locked.equivalent(&original)) {
+                            stale.push(StaleInput::OriginalChanged {
+                                input: path.clone(),
+                                declared: Box::new(original),
+                                locked: locked.cloned().map(Box::new),
+                            });
+                        }
+                    }
+                    self.collect_stale_inputs(input_key, &format!("{path}/"), &input.inputs, stale);
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const LOCK: &str = r#"{
+      "nodes": {
+        "home-manager": {
+          "inputs": { "nixpkgs": ["nixpkgs"] },
+          "locked": {
+            "owner": "nix-community",
+            "repo": "home-manager",
+            "rev": "903e06d734bcae48efb79b9afd51b406d2744179",
+            "type": "github"
+          },
+          "original": {
+            "owner": "nix-community",
+            "repo": "home-manager",
+            "type": "github"
+          }
+        },
+        "nixpkgs": {
+          "locked": {
+            "owner": "NixOS",
+            "repo": "nixpkgs",
+            "rev": "ea4c80b39be4c09702b0cb3b42eab59e2ba4f24b",
+            "type": "github"
+          },
+          "original": {
+            "owner": "NixOS",
+            "ref": "nixos-23.05",
+            "repo": "nixpkgs",
+            "type": "github"
+          }
+        },
+        "root": {
+          "inputs": { "home-manager": "home-manager", "nixpkgs": "nixpkgs" }
+        }
+      },
+      "root": "root",
+      "version": 7
+    }"#;
+
+    fn parse_declared(value: Value) -> DeclaredInputs {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn up_to_date() {
+        let lock: LockFile = LOCK.parse().unwrap();
+        let declared = parse_declared(json!({
+            "nixpkgs": { "url": "github:NixOS/nixpkgs/nixos-23.05" },
+            "home-manager": {
+                "type": "github",
+                "owner": "nix-community",
+                "repo": "home-manager",
+                "inputs": { "nixpkgs": { "follows": "nixpkgs" } }
+            },
+        }));
+        assert_eq!(lock.stale_inputs(&declared), []);
+    }
+
+    #[test]
+    fn stale() {
+        let lock: LockFile = LOCK.parse().unwrap();
+        let declared = parse_declared(json!({
+            "nixpkgs": { "url": "github:NixOS/nixpkgs/nixos-unstable" },
+            "home-manager": {
+                "url": "github:nix-community/home-manager",
+                "inputs": { "nixpkgs": { "follows": "" } }
+            },
+            "flake-utils": { "url": "github:numtide/flake-utils" },
+        }));
+
+        let stale = lock.stale_inputs(&declared);
+        assert_eq!(stale.iter().map(StaleInput::input).collect::<Vec<_>>(), [
+            "flake-utils",
+            "home-manager/nixpkgs",
+            "nixpkgs"
+        ]);
+        assert!(matches!(stale[0], StaleInput::Missing { .. }));
+        assert_eq!(stale[1], StaleInput::FollowsChanged {
+            input: "home-manager/nixpkgs".to_string(),
+            declared: Some("".to_string()),
+            locked: Some("nixpkgs".to_string()),
+        });
+        let StaleInput::OriginalChanged {
+            declared, locked, ..
+        } = &stale[2]
+        else {
+            panic!("expected a changed original")
+        };
+        assert_eq!(declared.to_string(), "github:NixOS/nixpkgs/nixos-unstable");
+        assert_eq!(
+            locked.as_ref().unwrap().to_string(),
+            "github:NixOS/nixpkgs/nixos-23.05"
+        );
+
+        let declared = parse_declared(json!({ "nixpkgs": { "follows": "home-manager/nixpkgs" } }));
+        let stale = lock.stale_inputs(&declared);
+        assert_eq!(stale.iter().map(StaleInput::input).collect::<Vec<_>>(), [
+            "home-manager",
+            "nixpkgs"
+        ]);
+        assert!(matches!(stale[0], StaleInput::Orphaned { .. }));
+        assert!(matches!(stale[1], StaleInput::FollowsChanged {
+            locked: None,
+            ..
+        }));
+    }
+}
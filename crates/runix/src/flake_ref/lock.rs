@@ -1,18 +1,157 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::fs;
+use std::path::Path;
 use std::str::FromStr;
 
 use derive_more::Deref;
 use once_cell::sync::Lazy;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use serde_with::DeserializeFromStr;
+use serde_json::Value;
+use serde_with::{DeserializeFromStr, SerializeDisplay};
 use thiserror::Error;
 
-use super::Timestamp;
+use super::{FlakeRef, FlakeRefAttributes, Timestamp};
+use crate::arguments::flake::OverrideInput;
+use crate::arguments::NixArgs;
+use crate::hash::{Hash, HashAlgorithm};
+use crate::{command, NixBackend, RunTyped};
 
-static HASH_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new("[a-f0-9]{40}").unwrap());
+static HASH_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new("^[a-fA-F0-9]{40}$").unwrap());
 
-/// todo: parse/validate narHash?
-pub type NarHash = String;
+static SRI_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new("^(md5|sha1|sha256|sha512)-([A-Za-z0-9+/]+={0,2})$").unwrap());
+
+/// A hash of the contents of a flake's source tree or store path in [SRI](https://www.w3.org/TR/SRI/) format
+///
+/// Besides SRI hashes, parsing accepts the `<algorithm>:<digest>` form used by older nix versions,
+/// with the digest encoded in base16, nix' base32 or base64.
+/// Hashes are always normalized to SRI format,
+/// other encodings of the digest are available through [NarHash::to_base16] and [NarHash::to_nix_base32].
+/// Malformed hashes are rejected when parsed, see [validate_nar_hash].
+///
+/// ```
+/// # use runix::flake_ref::lock::NarHash;
+/// let nar_hash: NarHash = "sha256-MjeRjunqfGTBGU401nxIjs7PC9PZZ1FBCZp/bRB3C2M="
+///     .parse()
+///     .unwrap();
+/// assert_eq!(nar_hash.algorithm(), "sha256");
+///
+/// let base32: NarHash = format!("sha256:{}", nar_hash.to_nix_base32())
+///     .parse()
+///     .unwrap();
+/// assert_eq!(base32, nar_hash);
+///
+/// "sha256-MjeRjunqfGTBGU401nxIjs7PC9PZZ1FBCZp"
+///     .parse::<NarHash>()
+///     .unwrap_err();
+/// ```
+#[derive(DeserializeFromStr, SerializeDisplay, Clone, Debug, PartialEq, Eq, Hash, Deref)]
+pub struct NarHash(String);
+
+impl NarHash {
+    /// Create a hash from the raw `digest` produced by `algorithm`
+    ///
+    /// Fails if `algorithm` is unsupported or `digest` has the wrong length for it.
+    pub fn from_digest(algorithm: &str, digest: &[u8]) -> Result<Self, InvalidNarHash> {
+        let sri = format!("{algorithm}-{}", base64::encode(digest));
+        algorithm
+            .parse()
+            .and_then(|algorithm| Hash::new(algorithm, digest))
+            .map(NarHash::from)
+            .map_err(|_| InvalidNarHash(sri))
+    }
+
+    /// The hash with its parsed algorithm and digest
+    pub fn hash(&self) -> Hash {
+        self.0.parse().expect("validated when parsed")
+    }
+
+    /// The hash algorithm, e.g. `sha256`
+    pub fn algorithm(&self) -> &str {
+        self.0
+            .split_once('-')
+            .map_or(&self.0, |(algorithm, _)| algorithm)
+    }
+
+    /// The raw digest
+    pub fn digest(&self) -> Vec<u8> {
+        base64::decode(self.to_base64()).expect("validated when parsed")
+    }
+
+    /// The digest in base64, i.e. the SRI hash without algorithm
+    pub fn to_base64(&self) -> &str {
+        self.0.split_once('-').map_or("", |(_, digest)| digest)
+    }
+
+    /// The digest in base16 (lowercase hex)
+    pub fn to_base16(&self) -> String {
+        hex::encode(self.digest())
+    }
+
+    /// The digest in nix' base32 encoding, as used in store paths and by `nix-hash --to-base32`
+    pub fn to_nix_base32(&self) -> String {
+        self.hash().to_nix_base32()
+    }
+}
+
+impl From<Hash> for NarHash {
+    fn from(hash: Hash) -> Self {
+        NarHash(hash.to_string())
+    }
+}
+
+impl FromStr for NarHash {
+    type Err = InvalidNarHash;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !s.contains(':') {
+            validate_nar_hash(s)?;
+            return Ok(NarHash(s.to_string()));
+        }
+        s.parse::<Hash>()
+            .map(NarHash::from)
+            .map_err(|_| InvalidNarHash(s.to_string()))
+    }
+}
+
+impl Display for NarHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Validate that `hash` is a hash in [SRI](https://www.w3.org/TR/SRI/) format,
+/// e.g. `sha256-MjeRjunqfGTBGU401nxIjs7PC9PZZ1FBCZp/bRB3C2M=`
+///
+/// Checks the hash algorithm as well as the length of the base64 encoded digest.
+///
+/// ```
+/// # use runix::flake_ref::lock::validate_nar_hash;
+/// validate_nar_hash("sha256-MjeRjunqfGTBGU401nxIjs7PC9PZZ1FBCZp/bRB3C2M=").unwrap();
+/// validate_nar_hash("sha256-MjeRjunqfGTBGU401nxIjs7PC9PZZ1FBCZp").unwrap_err();
+/// validate_nar_hash("sha256:1b3mzrpfdbzqv2xv1wm7kjmynkm6zvsz6g5a0zbp0lw7v5j5kz5d").unwrap_err();
+/// ```
+pub fn validate_nar_hash(hash: &str) -> Result<(), InvalidNarHash> {
+    let invalid = || InvalidNarHash(hash.to_string());
+
+    let captures = SRI_REGEX.captures(hash).ok_or_else(invalid)?;
+    let digest_bytes = captures[1]
+        .parse::<HashAlgorithm>()
+        .expect("matched by SRI_REGEX")
+        .digest_size();
+
+    // length of the padded base64 encoding
+    if captures[2].len() != usize::div_ceil(digest_bytes, 3) * 4 {
+        return Err(invalid());
+    }
+    Ok(())
+}
+
+#[derive(Error, Debug)]
+#[error("Invalid narHash '{0}', expected an SRI hash such as 'sha256-<base64>'")]
+pub struct InvalidNarHash(String);
 pub type LastModified = Timestamp;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
@@ -35,25 +174,101 @@ impl From<String> for RevOrRef {
     }
 }
 
-#[derive(DeserializeFromStr, Serialize, Clone, Debug, PartialEq, Eq, Deref)]
+/// A full git commit hash, i.e. 40 hex characters
+///
+/// Abbreviated hashes are not valid revisions, as nix requires full hashes to fetch commits.
+/// Use [Rev::short] to abbreviate a revision like nix' `shortRev` attribute
+/// and [Rev::matches_short] to compare a revision to an abbreviated hash.
+///
+/// ```
+/// # use runix::flake_ref::lock::Rev;
+/// let rev: Rev = "1e684b371cf05300bc2b432f958f285855bac8fb".parse().unwrap();
+/// assert_eq!(rev.short(), "1e684b3");
+/// assert!(rev.matches_short("1e684b37"));
+///
+/// "1e684b3".parse::<Rev>().unwrap_err();
+/// ```
+#[derive(DeserializeFromStr, Serialize, Clone, Debug, PartialEq, Eq, Hash, Deref)]
 pub struct Rev(String);
+
+/// Length of abbreviated revisions, see [Rev::short]
+pub const SHORT_REV_LENGTH: usize = 7;
+
+impl Rev {
+    /// The revision abbreviated to [SHORT_REV_LENGTH] characters, as in nix' `shortRev`
+    pub fn short(&self) -> &str {
+        &self.0[..SHORT_REV_LENGTH]
+    }
+
+    /// Whether `short` is an abbreviation of this revision
+    ///
+    /// Like git, abbreviations need at least 4 characters and are compared case insensitively.
+    pub fn matches_short(&self, short: &str) -> bool {
+        short.len() >= 4
+            && short.len() <= self.0.len()
+            && self.0[..short.len()].eq_ignore_ascii_case(short)
+    }
+}
+
 impl FromStr for Rev {
     type Err = InvalidRev;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         if !HASH_REGEX.is_match(s) {
-            Err(InvalidRev)
+            Err(InvalidRev(s.to_string()))
         } else {
             Ok(Rev(s.to_string()))
         }
     }
 }
 
+impl Display for Rev {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 #[derive(Error, Debug)]
-#[error("Invalid revision hash")]
-pub struct InvalidRev;
+#[error("Invalid revision hash '{0}', expected 40 hex characters")]
+pub struct InvalidRev(String);
+
+/// The revision of a git work tree with uncommitted changes
+///
+/// Nix denotes it as the revision of `HEAD` followed by `-dirty`.
+///
+/// ```
+/// # use runix::flake_ref::lock::DirtyRev;
+/// let dirty: DirtyRev = "1e684b371cf05300bc2b432f958f285855bac8fb-dirty"
+///     .parse()
+///     .unwrap();
+/// assert_eq!(dirty.short(), "1e684b3");
+///
+/// "1e684b371cf05300bc2b432f958f285855bac8fb"
+///     .parse::<DirtyRev>()
+///     .unwrap_err();
+/// ```
+#[derive(DeserializeFromStr, SerializeDisplay, Clone, Debug, PartialEq, Eq, Hash, Deref)]
+pub struct DirtyRev(Rev);
+
+impl FromStr for DirtyRev {
+    type Err = InvalidRev;
 
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rev = s
+            .strip_suffix("-dirty")
+            .ok_or_else(|| InvalidRev(s.to_string()))?;
+        Ok(DirtyRev(rev.parse()?))
+    }
+}
+
+impl Display for DirtyRev {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-dirty", self.0)
+    }
+}
+
+/// The number of commits in the history of a revision
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, Clone)]
 #[serde(try_from = "StringOrInt")]
 pub struct RevCount(pub u64);
 
@@ -63,6 +278,26 @@ impl From<u64> for RevCount {
     }
 }
 
+impl From<RevCount> for u64 {
+    fn from(value: RevCount) -> Self {
+        value.0
+    }
+}
+
+impl FromStr for RevCount {
+    type Err = <u64 as FromStr>::Err;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(RevCount(s.parse()?))
+    }
+}
+
+impl Display for RevCount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(untagged)]
 enum StringOrInt {
@@ -75,8 +310,1439 @@ impl TryFrom<StringOrInt> for RevCount {
 
     fn try_from(value: StringOrInt) -> Result<Self, Self::Error> {
         match value {
-            StringOrInt::String(s) => Ok(RevCount(s.parse()?)),
+            StringOrInt::String(s) => s.parse(),
             StringOrInt::Int(i) => Ok(RevCount(i)),
         }
     }
 }
+
+/// Lock file versions understood by [LockFile], matching nix
+pub const SUPPORTED_LOCK_VERSIONS: std::ops::RangeInclusive<u32> = 5..=LOCK_VERSION;
+
+/// The lock file version written by current versions of nix
+///
+/// Lock files of older [supported versions](SUPPORTED_LOCK_VERSIONS)
+/// are upgraded to this version when deserialized.
+pub const LOCK_VERSION: u32 = 7;
+
+/// The contents of a `flake.lock` file
+///
+/// Lock files form a graph of [LockedNode]s keyed by name,
+/// starting at the [LockFile::root] node, i.e. the locking flake itself.
+/// Edges are declared by the `inputs` of each node, see [InputRef].
+///
+/// Deserializing a lock file upgrades older versions to [LOCK_VERSION],
+/// e.g. the `info` attributes of version 5 are merged into the `locked` attributes.
+/// Lock files of unknown versions are rejected.
+///
+/// ```
+/// # use runix::flake_ref::lock::LockFile;
+/// let lock: LockFile = r#"{
+///   "nodes": {
+///     "nixpkgs": {
+///       "locked": {
+///         "lastModified": 1688392541,
+///         "narHash": "sha256-lHrKvEkCPTUO+7tPfjIcb7Trk6k31rz18vkyqmkeJfY=",
+///         "owner": "NixOS",
+///         "repo": "nixpkgs",
+///         "rev": "ea4c80b39be4c09702b0cb3b42eab59e2ba4f24b",
+///         "type": "github"
+///       },
+///       "original": { "id": "nixpkgs", "type": "indirect" }
+///     },
+///     "root": { "inputs": { "nixpkgs": "nixpkgs" } }
+///   },
+///   "root": "root",
+///   "version": 7
+/// }"#
+/// .parse()
+/// .unwrap();
+///
+/// let (key, nixpkgs) = lock.resolve_path("nixpkgs").unwrap();
+/// assert_eq!(key, "nixpkgs");
+/// assert_eq!(
+///     nixpkgs.locked.as_ref().unwrap().to_string(),
+///     "github:NixOS/nixpkgs/ea4c80b39be4c09702b0cb3b42eab59e2ba4f24b?lastModified=1688392541&narHash=sha256-lHrKvEkCPTUO%2B7tPfjIcb7Trk6k31rz18vkyqmkeJfY%3D"
+/// );
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(try_from = "Value")]
+pub struct LockFile {
+    pub nodes: BTreeMap<String, LockedNode>,
+    /// Key of the root node in [LockFile::nodes]
+    pub root: String,
+    pub version: u32,
+}
+
+/// The schema of lock files of the current [LOCK_VERSION]
+#[derive(Deserialize)]
+struct CurrentLockFile {
+    nodes: BTreeMap<String, LockedNode>,
+    root: String,
+}
+
+impl TryFrom<Value> for LockFile {
+    type Error = LockFileError;
+
+    /// Deserialize a lock file of any supported version, upgrading it to [LOCK_VERSION]
+    fn try_from(mut value: Value) -> Result<Self, Self::Error> {
+        let version = value
+            .get("version")
+            .and_then(Value::as_u64)
+            .ok_or(LockFileError::MissingVersion)?;
+        let version = u32::try_from(version).unwrap_or(u32::MAX);
+
+        if version > LOCK_VERSION {
+            return Err(LockFileError::FutureVersion(version));
+        }
+        if !SUPPORTED_LOCK_VERSIONS.contains(&version) {
+            return Err(LockFileError::UnsupportedVersion(version));
+        }
+
+        // merge `info` into `locked` like nix, <https://github.com/NixOS/nix/blob/2.17.0/src/libexpr/flake/lockfile.cc>
+        if version < 7 {
+            let nodes = value.get_mut("nodes").and_then(Value::as_object_mut);
+            for node in nodes.into_iter().flat_map(|nodes| nodes.values_mut()) {
+                let Some(node) = node.as_object_mut() else {
+                    continue;
+                };
+                if let Some(Value::Object(info)) = node.remove("info") {
+                    if let Some(Value::Object(locked)) = node.get_mut("locked") {
+                        locked.extend(info);
+                    }
+                }
+            }
+        }
+
+        let CurrentLockFile { nodes, root } = serde_json::from_value(value)?;
+        Ok(LockFile {
+            nodes,
+            root,
+            version: LOCK_VERSION,
+        })
+    }
+}
+
+/// A node of a [LockFile], i.e. the locking flake or one of its (transitive) inputs
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct LockedNode {
+    /// Inputs of this node by input name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub inputs: BTreeMap<String, InputRef>,
+    /// The locked flake ref, absent for the root node
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locked: Option<FlakeRef>,
+    /// The flake ref as originally specified, absent for the root node
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original: Option<FlakeRef>,
+    /// Whether the input is a flake, i.e. not declared with `flake = false`
+    #[serde(default = "default_true", skip_serializing_if = "is_true")]
+    pub flake: bool,
+}
+
+impl LockedNode {
+    /// A node for an input locked to `locked`, originally specified as `original`
+    pub fn new(locked: FlakeRef, original: FlakeRef) -> Self {
+        LockedNode {
+            inputs: BTreeMap::new(),
+            locked: Some(locked),
+            original: Some(original),
+            flake: true,
+        }
+    }
+
+    /// Create a node from the `locked` and `original` attribute sets found in lock files
+    ///
+    /// See [FlakeRef::from_attrs] for how attribute sets are converted into flake refs.
+    pub fn from_attrs(
+        locked: serde_json::Map<String, Value>,
+        original: serde_json::Map<String, Value>,
+    ) -> Result<Self, serde_json::Error> {
+        Ok(LockedNode::new(
+            FlakeRef::from_attrs(locked)?,
+            FlakeRef::from_attrs(original)?,
+        ))
+    }
+
+    /// The `locked` attribute set as written to lock files, see [FlakeRef::to_attrs]
+    pub fn locked_attrs(&self) -> Option<serde_json::Map<String, Value>> {
+        self.locked.as_ref().map(FlakeRef::to_attrs)
+    }
+
+    /// The `original` attribute set as written to lock files, see [FlakeRef::to_attrs]
+    pub fn original_attrs(&self) -> Option<serde_json::Map<String, Value>> {
+        self.original.as_ref().map(FlakeRef::to_attrs)
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn is_true(b: &bool) -> bool {
+    *b
+}
+
+/// Reference from an input of a [LockedNode] to another node
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum InputRef {
+    /// Key of the input's node in [LockFile::nodes]
+    Node(String),
+    /// An input declared with `follows`,
+    /// as path of input names starting at the root node, e.g. `["nixpkgs"]`
+    Follows(Vec<String>),
+}
+
+impl LockFile {
+    /// Read and [validate](LockFile::validate) the lock file at `path`
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, LockFileError> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    /// The root node, i.e. the locking flake
+    pub fn root_node(&self) -> Option<&LockedNode> {
+        self.nodes.get(&self.root)
+    }
+
+    /// The key of the node an input points to, following `follows` references
+    ///
+    /// `follows` paths are resolved starting at the root node, like nix does.
+    /// Fails if an input along the way does not exist or if `follows` form a cycle.
+    pub fn resolve(&self, input: &InputRef) -> Result<&str, ResolveError> {
+        self.resolve_with_stack(input, &mut Vec::new())
+    }
+
+    /// Resolve `input` while tracking the `follows` paths currently being resolved
+    fn resolve_with_stack<'a>(
+        &'a self,
+        input: &InputRef,
+        stack: &mut Vec<String>,
+    ) -> Result<&'a str, ResolveError> {
+        let names = match input {
+            InputRef::Node(key) => {
+                return self
+                    .nodes
+                    .get_key_value(key)
+                    .map(|(key, _)| key.as_str())
+                    .ok_or_else(|| ResolveError::MissingNode(key.clone()))
+            },
+            InputRef::Follows(names) => names,
+        };
+
+        let path = names.join("/");
+        if let Some(start) = stack.iter().position(|p| *p == path) {
+            let mut cycle = stack.split_off(start);
+            cycle.push(path);
+            return Err(ResolveError::Cycle(cycle));
+        }
+        stack.push(path);
+
+        let mut key = self.root.as_str();
+        for (i, name) in names.iter().enumerate() {
+            let node = self
+                .nodes
+                .get(key)
+                .ok_or_else(|| ResolveError::MissingNode(key.to_string()))?;
+            let input = node
+                .inputs
+                .get(name)
+                .ok_or_else(|| ResolveError::MissingInput(names[..=i].join("/")))?;
+            key = self.resolve_with_stack(input, stack)?;
+        }
+
+        stack.pop();
+        Ok(key)
+    }
+
+    /// Resolve an input path starting at the root node, e.g. `home-manager/nixpkgs`
+    ///
+    /// Returns the key and node the input points to after following all `follows` references.
+    /// The empty path resolves to the root node.
+    pub fn resolve_path(&self, path: &str) -> Result<(&str, &LockedNode), ResolveError> {
+        let names = path
+            .split('/')
+            .filter(|name| !name.is_empty())
+            .map(ToString::to_string)
+            .collect();
+        let key = self.resolve(&InputRef::Follows(names))?;
+        Ok((key, &self.nodes[key]))
+    }
+
+    /// The locked flake ref of the input at `path`, see [LockFile::resolve_path]
+    pub fn locked_ref(&self, path: &str) -> Result<&FlakeRef, ResolveError> {
+        let (key, node) = self.resolve_path(path)?;
+        node.locked
+            .as_ref()
+            .ok_or_else(|| ResolveError::NotLocked(key.to_string()))
+    }
+
+    /// The original flake ref of the input at `path`, see [LockFile::resolve_path]
+    pub fn original_ref(&self, path: &str) -> Result<&FlakeRef, ResolveError> {
+        let (key, node) = self.resolve_path(path)?;
+        node.original
+            .as_ref()
+            .ok_or_else(|| ResolveError::NotLocked(key.to_string()))
+    }
+
+    /// `--override-input` arguments pinning every input to its locked flake ref
+    ///
+    /// Passing these to a nix command evaluates the flake with the inputs of this lock file,
+    /// regardless of the flake's own `flake.lock`.
+    ///
+    /// ```
+    /// # use runix::arguments::flake::FlakeArgs;
+    /// # use runix::flake_ref::lock::LockFile;
+    /// # let lock: LockFile = r#"{
+    /// #   "nodes": {
+    /// #     "nixpkgs": {
+    /// #       "locked": {
+    /// #         "owner": "NixOS",
+    /// #         "repo": "nixpkgs",
+    /// #         "rev": "ea4c80b39be4c09702b0cb3b42eab59e2ba4f24b",
+    /// #         "type": "github"
+    /// #       }
+    /// #     },
+    /// #     "root": { "inputs": { "nixpkgs": "nixpkgs" } }
+    /// #   },
+    /// #   "root": "root",
+    /// #   "version": 7
+    /// # }"#
+    /// # .parse()
+    /// # .unwrap();
+    /// let flake_args = FlakeArgs {
+    ///     override_inputs: lock.override_inputs(),
+    ///     ..Default::default()
+    /// };
+    /// assert_eq!(flake_args.override_inputs[0].from, "nixpkgs");
+    /// assert_eq!(
+    ///     flake_args.override_inputs[0].to.to_string(),
+    ///     "github:NixOS/nixpkgs/ea4c80b39be4c09702b0cb3b42eab59e2ba4f24b"
+    /// );
+    /// ```
+    pub fn override_inputs(&self) -> Vec<OverrideInput> {
+        self.locked_inputs()
+            .into_iter()
+            .map(|(path, flake_ref)| OverrideInput::new(path, flake_ref.clone()))
+            .collect()
+    }
+
+    /// The resolved inputs of the node `key` as pairs of input name and node key
+    pub fn inputs<'a>(
+        &'a self,
+        key: &str,
+    ) -> impl Iterator<Item = (&'a str, Result<&'a str, ResolveError>)> {
+        self.nodes
+            .get(key)
+            .into_iter()
+            .flat_map(|node| node.inputs.iter())
+            .map(|(name, input)| (name.as_str(), self.resolve(input)))
+    }
+
+    /// Lock the input at `path` to `locked`, leaving all other inputs untouched
+    ///
+    /// If the input's node is shared with other inputs,
+    /// the node is copied under a new key and the input's parent is pointed to the copy.
+    /// Nodes no longer reachable from the root are removed afterwards, see [LockFile::prune].
+    /// Returns the previously locked flake ref.
+    ///
+    /// Inputs that `follow` another input can not be updated themselves,
+    /// update the input they follow instead.
+    pub fn update_input(
+        &mut self,
+        path: &str,
+        locked: FlakeRef,
+    ) -> Result<Option<FlakeRef>, UpdateInputError> {
+        let names: Vec<&str> = path.split('/').filter(|name| !name.is_empty()).collect();
+        let Some((name, parent_path)) = names.split_last() else {
+            return Err(UpdateInputError::Root);
+        };
+
+        let (parent_key, parent) = self.resolve_path(&parent_path.join("/"))?;
+        let key = match parent.inputs.get(*name) {
+            Some(InputRef::Node(key)) => key.clone(),
+            Some(InputRef::Follows(_)) => return Err(UpdateInputError::Follows(names.join("/"))),
+            None => return Err(ResolveError::MissingInput(names.join("/")).into()),
+        };
+        let parent_key = parent_key.to_string();
+
+        let shared = self.nodes.iter().any(|(node_key, node)| {
+            node.inputs.iter().any(|(input_name, input)| {
+                matches!(input, InputRef::Node(target) if *target == key)
+                    && (*node_key != parent_key || input_name != name)
+            })
+        });
+
+        let key = if shared {
+            let copy_key = (2..)
+                .map(|i| format!("{key}_{i}"))
+                .find(|candidate| !self.nodes.contains_key(candidate))
+                .expect("unbounded range");
+            let copy = self.nodes[&key].clone();
+            self.nodes.insert(copy_key.clone(), copy);
+            self.nodes
+                .get_mut(&parent_key)
+                .expect("resolved above")
+                .inputs
+                .insert(name.to_string(), InputRef::Node(copy_key.clone()));
+            copy_key
+        } else {
+            key
+        };
+
+        let node = self.nodes.get_mut(&key).expect("resolved above");
+        let previous = node.locked.replace(locked);
+        self.prune();
+        Ok(previous)
+    }
+
+    /// Remove all nodes that are not reachable from the root node
+    pub fn prune(&mut self) {
+        let mut reachable = BTreeSet::from([self.root.clone()]);
+        let mut queue = vec![self.root.clone()];
+        while let Some(key) = queue.pop() {
+            for (_, target) in self.inputs(&key) {
+                if let Ok(target) = target {
+                    if reachable.insert(target.to_string()) {
+                        queue.push(target.to_string());
+                    }
+                }
+            }
+        }
+        self.nodes.retain(|key, _| reachable.contains(key));
+    }
+
+    /// Serialize the lock file exactly like nix does
+    ///
+    /// Nix writes lock files with sorted keys, two space indentation and a trailing newline.
+    /// Lock files written by nix are thus reproduced byte for byte,
+    /// unless they contain attributes not modelled by [FlakeRef].
+    pub fn to_nix_json(&self) -> Result<String, LockFileError> {
+        fn sort_keys(value: Value) -> Value {
+            match value {
+                Value::Object(map) => {
+                    let mut entries: Vec<_> = map.into_iter().collect();
+                    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+                    Value::Object(
+                        entries
+                            .into_iter()
+                            .map(|(k, v)| (k, sort_keys(v)))
+                            .collect(),
+                    )
+                },
+                Value::Array(values) => Value::Array(values.into_iter().map(sort_keys).collect()),
+                value => value,
+            }
+        }
+
+        let value = serde_json::to_value(self).map_err(LockFileError::Serialize)?;
+        let mut json =
+            serde_json::to_string_pretty(&sort_keys(value)).map_err(LockFileError::Serialize)?;
+        json.push('\n');
+        Ok(json)
+    }
+
+    /// Write the lock file to `path` in the format of [LockFile::to_nix_json]
+    ///
+    /// The file is replaced atomically,
+    /// so readers never observe a partially written lock file.
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), LockFileError> {
+        let path = path.as_ref();
+        let json = self.to_nix_json()?;
+
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let tmp_path = path.with_file_name(format!(".{file_name}.{}.tmp", std::process::id()));
+
+        fs::write(&tmp_path, json)
+            .and_then(|_| fs::rename(&tmp_path, path))
+            .map_err(|e| {
+                let _ = fs::remove_file(&tmp_path);
+                LockFileError::Write(e)
+            })
+    }
+
+    /// The locked flake refs of all (transitive) inputs by input path, e.g. `home-manager/nixpkgs`
+    ///
+    /// Inputs that `follow` other inputs are omitted, as they are listed under their target.
+    pub fn locked_inputs(&self) -> BTreeMap<String, &FlakeRef> {
+        let mut locked = BTreeMap::new();
+        let mut queue = vec![(String::new(), self.root.as_str(), 0)];
+
+        while let Some((prefix, key, depth)) = queue.pop() {
+            // guards against cycles in malformed lock files
+            if depth > self.nodes.len() {
+                continue;
+            }
+            let Some(node) = self.nodes.get(key) else {
+                continue;
+            };
+            for (name, input) in &node.inputs {
+                let InputRef::Node(input_key) = input else {
+                    continue;
+                };
+                let path = format!("{prefix}{name}");
+                if let Some(flake_ref) = self.nodes.get(input_key).and_then(|n| n.locked.as_ref()) {
+                    locked.insert(path.clone(), flake_ref);
+                }
+                queue.push((format!("{path}/"), input_key, depth + 1));
+            }
+        }
+        locked
+    }
+
+    /// Compare the locked inputs of two lock files, see [LockFile::locked_inputs]
+    ///
+    /// ```
+    /// # use runix::flake_ref::lock::{InputChange, LockFile};
+    /// let old: LockFile = r#"{
+    ///   "nodes": {
+    ///     "nixpkgs": {
+    ///       "locked": {
+    ///         "lastModified": 1688392541,
+    ///         "owner": "NixOS",
+    ///         "repo": "nixpkgs",
+    ///         "rev": "ea4c80b39be4c09702b0cb3b42eab59e2ba4f24b",
+    ///         "type": "github"
+    ///       }
+    ///     },
+    ///     "root": { "inputs": { "nixpkgs": "nixpkgs" } }
+    ///   },
+    ///   "root": "root",
+    ///   "version": 7
+    /// }"#
+    /// .parse()
+    /// .unwrap();
+    /// let new = old
+    ///     .to_nix_json()
+    ///     .unwrap()
+    ///     .replace("1688392541", "1688997341")
+    ///     .replace("ea4c80b", "0a8b8d3");
+    /// let new: LockFile = new.parse().unwrap();
+    ///
+    /// let diff = LockFile::diff(&old, &new);
+    /// let [InputChange::Updated { input, old, new }] = &diff.changes[..] else {
+    ///     panic!("expected a single update")
+    /// };
+    /// assert_eq!(input, "nixpkgs");
+    /// assert_eq!(old.rev.as_ref().unwrap().short(), "ea4c80b");
+    /// assert_eq!(new.rev.as_ref().unwrap().short(), "0a8b8d3");
+    /// assert_eq!(diff.changes[0].last_modified_delta().unwrap().num_days(), 7);
+    /// ```
+    pub fn diff(old: &LockFile, new: &LockFile) -> LockDiff {
+        let old_inputs = old.locked_inputs();
+        let mut new_inputs = new.locked_inputs();
+
+        let mut changes = Vec::new();
+        for (input, old_ref) in old_inputs {
+            let old = Box::new(LockedInput::from(old_ref));
+            match new_inputs.remove(&input) {
+                None => changes.push(InputChange::Removed { input, old }),
+                Some(new_ref) if new_ref != old_ref => changes.push(InputChange::Updated {
+                    input,
+                    old,
+                    new: Box::new(new_ref.into()),
+                }),
+                Some(_) => {},
+            }
+        }
+        for (input, new_ref) in new_inputs {
+            changes.push(InputChange::Added {
+                input,
+                new: Box::new(new_ref.into()),
+            });
+        }
+        changes.sort_by(|a, b| a.input().cmp(b.input()));
+
+        LockDiff { changes }
+    }
+
+    /// Fetch all locked inputs and compare their `narHash` with the one recorded in the lock file
+    ///
+    /// Sources are fetched with `nix flake prefetch`, which reuses sources already in the store.
+    /// The recorded `narHash` is removed from the flake ref before fetching,
+    /// as nix would otherwise refuse to fetch mismatching sources itself.
+    /// Inputs are checked one by one, failures to fetch an input are part of the report.
+    pub async fn verify_nar_hashes<B, E>(&self, backend: &B, nix_args: &NixArgs) -> NarHashReport<E>
+    where
+        B: NixBackend + Sync,
+        command::FlakePrefetch: RunTyped<B, Output = command::FlakePrefetchOut, TypedError = E>,
+    {
+        let mut report = NarHashReport {
+            verified: Vec::new(),
+            mismatches: Vec::new(),
+            unverified: Vec::new(),
+            failed: Vec::new(),
+        };
+
+        for (input, locked) in self.locked_inputs() {
+            let Some(expected) = locked.nar_hash() else {
+                report.unverified.push(input);
+                continue;
+            };
+            let mut attrs = locked.to_attrs();
+            attrs.remove("narHash");
+            let Ok(unpinned) = FlakeRef::from_attrs(attrs) else {
+                report.unverified.push(input);
+                continue;
+            };
+
+            let prefetch = command::FlakePrefetch {
+                flake_ref: Some(unpinned.into()),
+                ..Default::default()
+            };
+            match prefetch.run_typed(backend, nix_args).await {
+                Ok(out) if out.hash == expected => report.verified.push(input),
+                Ok(out) => report.mismatches.push(NarHashMismatch {
+                    input,
+                    expected,
+                    actual: out.hash,
+                }),
+                Err(e) => report.failed.push((input, e)),
+            }
+        }
+        report
+    }
+
+    /// Check that the version is supported and that the node graph is well formed
+    pub fn validate(&self) -> Result<(), LockFileError> {
+        if !SUPPORTED_LOCK_VERSIONS.contains(&self.version) {
+            return Err(LockFileError::UnsupportedVersion(self.version));
+        }
+        if self.root_node().is_none() {
+            return Err(LockFileError::MissingNode(self.root.clone()));
+        }
+        for (key, node) in &self.nodes {
+            for (name, input) in &node.inputs {
+                if let Err(source) = self.resolve(input) {
+                    return Err(LockFileError::UnresolvedInput {
+                        node: key.clone(),
+                        input: name.clone(),
+                        source,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for LockFile {
+    type Err = LockFileError;
+
+    /// Parse and [validate](LockFile::validate) the contents of a lock file
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lock = LockFile::try_from(serde_json::from_str::<Value>(s)?)?;
+        lock.validate()?;
+        Ok(lock)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum LockFileError {
+    #[error("Could not read lock file: {0}")]
+    Read(#[from] std::io::Error),
+    #[error("Could not parse lock file: {0}")]
+    Parse(#[from] serde_json::Error),
+    #[error("Could not serialize lock file: {0}")]
+    Serialize(#[source] serde_json::Error),
+    #[error("Could not write lock file: {0}")]
+    Write(#[source] std::io::Error),
+    #[error("Lock file has no version")]
+    MissingVersion,
+    #[error(
+        "Unsupported lock file version {0}, supported versions are {} to {}",
+        SUPPORTED_LOCK_VERSIONS.start(),
+        SUPPORTED_LOCK_VERSIONS.end()
+    )]
+    UnsupportedVersion(u32),
+    #[error(
+        "Lock file version {0} is newer than the latest supported version {LOCK_VERSION}, \
+         it was likely written by a newer version of nix"
+    )]
+    FutureVersion(u32),
+    #[error("Lock file has no node '{0}'")]
+    MissingNode(String),
+    #[error("Input '{input}' of lock file node '{node}' does not resolve to a node")]
+    UnresolvedInput {
+        node: String,
+        input: String,
+        #[source]
+        source: ResolveError,
+    },
+}
+
+/// The result of [LockFile::verify_nar_hashes], inputs are identified by their input path
+#[derive(Debug)]
+pub struct NarHashReport<E> {
+    /// Inputs whose source matches the recorded `narHash`
+    pub verified: Vec<String>,
+    /// Inputs whose source does not match the recorded `narHash`
+    pub mismatches: Vec<NarHashMismatch>,
+    /// Inputs without recorded `narHash`
+    pub unverified: Vec<String>,
+    /// Inputs that could not be fetched
+    pub failed: Vec<(String, E)>,
+}
+
+impl<E> NarHashReport<E> {
+    /// Whether all inputs with a recorded `narHash` were fetched and matched
+    pub fn is_ok(&self) -> bool {
+        self.mismatches.is_empty() && self.failed.is_empty()
+    }
+}
+
+/// A locked input whose source does not match its recorded `narHash`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NarHashMismatch {
+    pub input: String,
+    /// The `narHash` recorded in the lock file
+    pub expected: NarHash,
+    /// The `narHash` of the fetched source
+    pub actual: NarHash,
+}
+
+/// An input of a [LockFile] could not be updated, see [LockFile::update_input]
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum UpdateInputError {
+    #[error(transparent)]
+    Resolve(#[from] ResolveError),
+    #[error("Input '{0}' follows another input, update the followed input instead")]
+    Follows(String),
+    #[error("The root node can not be updated")]
+    Root,
+}
+
+/// An input of a [LockFile] that does not resolve to a node
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ResolveError {
+    #[error("Lock file has no node '{0}'")]
+    MissingNode(String),
+    #[error("Lock file has no input '{0}'")]
+    MissingInput(String),
+    #[error("Inputs follow each other in a cycle: {}", .0.join(" -> "))]
+    Cycle(Vec<String>),
+    #[error("Lock file node '{0}' is not locked")]
+    NotLocked(String),
+}
+
+/// Changes between the inputs of two lock files, see [LockFile::diff]
+///
+/// [Display]s a summary of the changes like `nix flake update`.
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct LockDiff {
+    /// Changes ordered by input path
+    pub changes: Vec<InputChange>,
+}
+
+impl LockDiff {
+    /// Whether the lock files lock the same inputs
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+impl Display for LockDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for change in &self.changes {
+            match change {
+                InputChange::Added { input, new } => {
+                    writeln!(f, "• Added input '{input}':")?;
+                    writeln!(f, "    {new}")?;
+                },
+                InputChange::Removed { input, .. } => {
+                    writeln!(f, "• Removed input '{input}'")?;
+                },
+                InputChange::Updated { input, old, new } => {
+                    writeln!(f, "• Updated input '{input}':")?;
+                    writeln!(f, "    {old}")?;
+                    writeln!(f, "  → {new}")?;
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A change of a single input between two lock files
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InputChange {
+    Added {
+        input: String,
+        new: Box<LockedInput>,
+    },
+    Removed {
+        input: String,
+        old: Box<LockedInput>,
+    },
+    Updated {
+        input: String,
+        old: Box<LockedInput>,
+        new: Box<LockedInput>,
+    },
+}
+
+impl InputChange {
+    /// The path of the changed input, e.g. `home-manager/nixpkgs`
+    pub fn input(&self) -> &str {
+        match self {
+            InputChange::Added { input, .. }
+            | InputChange::Removed { input, .. }
+            | InputChange::Updated { input, .. } => input,
+        }
+    }
+
+    /// The input as locked in the old lock file
+    pub fn before(&self) -> Option<&LockedInput> {
+        match self {
+            InputChange::Added { .. } => None,
+            InputChange::Removed { old, .. } | InputChange::Updated { old, .. } => Some(old),
+        }
+    }
+
+    /// The input as locked in the new lock file
+    pub fn after(&self) -> Option<&LockedInput> {
+        match self {
+            InputChange::Removed { .. } => None,
+            InputChange::Added { new, .. } | InputChange::Updated { new, .. } => Some(new),
+        }
+    }
+
+    /// Time between the `lastModified` dates of an updated input
+    ///
+    /// Negative if the input was downgraded to an older source.
+    pub fn last_modified_delta(&self) -> Option<chrono::Duration> {
+        let old = self.before()?.last_modified.as_ref()?;
+        let new = self.after()?.last_modified.as_ref()?;
+        Some(new.signed_duration_since(old))
+    }
+}
+
+/// The locked flake ref of an input along with its locking attributes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LockedInput {
+    pub flake_ref: FlakeRef,
+    pub rev: Option<Rev>,
+    pub last_modified: Option<LastModified>,
+    pub nar_hash: Option<NarHash>,
+}
+
+impl From<&FlakeRef> for LockedInput {
+    fn from(flake_ref: &FlakeRef) -> Self {
+        LockedInput {
+            flake_ref: flake_ref.clone(),
+            rev: flake_ref.rev(),
+            last_modified: flake_ref.last_modified(),
+            nar_hash: flake_ref.nar_hash(),
+        }
+    }
+}
+
+impl Display for LockedInput {
+    /// Format like nix, e.g. `'github:NixOS/nixpkgs/<rev>' (2023-07-03)`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "'{}'", self.flake_ref.redacted())?;
+        if let Some(ref last_modified) = self.last_modified {
+            write!(f, " ({})", last_modified.0.format("%Y-%m-%d"))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Run;
+
+    /// sha256 of the empty string
+    const SRI: &str = "sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=";
+    const BASE16: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+    const NIX_BASE32: &str = "0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73";
+
+    #[test]
+    fn nar_hash_conversions() {
+        let nar_hash: NarHash = SRI.parse().unwrap();
+        assert_eq!(nar_hash.to_base16(), BASE16);
+        assert_eq!(nar_hash.to_nix_base32(), NIX_BASE32);
+        assert_eq!(nar_hash.digest().len(), 32);
+
+        for alternative in [
+            format!("sha256:{BASE16}"),
+            format!("sha256:{NIX_BASE32}"),
+            format!("sha256:{}", nar_hash.to_base64()),
+        ] {
+            assert_eq!(alternative.parse::<NarHash>().unwrap(), nar_hash);
+        }
+
+        assert_eq!(
+            NarHash::from_digest("sha256", &nar_hash.digest()).unwrap(),
+            nar_hash
+        );
+        NarHash::from_digest("sha256", &[0; 20]).unwrap_err();
+        NarHash::from_digest("sha3", &[0; 32]).unwrap_err();
+    }
+
+    const LOCK: &str = r#"{
+  "nodes": {
+    "flake-utils": {
+      "inputs": {
+        "systems": "systems"
+      },
+      "locked": {
+        "lastModified": 1687709756,
+        "narHash": "sha256-Y5wKlQSkgEK2weWdOu4J3riRd+kV/VCgHsqLNTTWQ/0=",
+        "owner": "numtide",
+        "repo": "flake-utils",
+        "rev": "dbabf0ca0c0c4bce6ea5eaf65af5cb694d2082c7",
+        "type": "github"
+      },
+      "original": {
+        "owner": "numtide",
+        "repo": "flake-utils",
+        "type": "github"
+      }
+    },
+    "home-manager": {
+      "inputs": {
+        "nixpkgs": [
+          "nixpkgs"
+        ]
+      },
+      "locked": {
+        "lastModified": 1688220547,
+        "narHash": "sha256-2NrqVOpSmG9Wl+rXUa6aqyo2ZSqLHnp2nq1ZaVsYqGY=",
+        "ref": "master",
+        "rev": "903e06d734bcae48efb79b9afd51b406d2744179",
+        "revCount": 2901,
+        "type": "git",
+        "url": "https://github.com/nix-community/home-manager"
+      },
+      "original": {
+        "type": "git",
+        "url": "https://github.com/nix-community/home-manager"
+      }
+    },
+    "nixpkgs": {
+      "locked": {
+        "lastModified": 1688392541,
+        "narHash": "sha256-lHrKvEkCPTUO+7tPfjIcb7Trk6k31rz18vkyqmkeJfY=",
+        "owner": "NixOS",
+        "repo": "nixpkgs",
+        "rev": "ea4c80b39be4c09702b0cb3b42eab59e2ba4f24b",
+        "type": "github"
+      },
+      "original": {
+        "id": "nixpkgs",
+        "type": "indirect"
+      }
+    },
+    "root": {
+      "inputs": {
+        "flake-utils": "flake-utils",
+        "home-manager": "home-manager",
+        "nixpkgs": "nixpkgs"
+      }
+    },
+    "systems": {
+      "flake": false,
+      "locked": {
+        "lastModified": 1681028828,
+        "narHash": "sha256-Vy1rq5AaRuLzOxct8nz4T6wlgyUR7zLU309k9mBC768=",
+        "owner": "nix-systems",
+        "repo": "default",
+        "rev": "da67096a3b9bf56a91d16901293e51ba5b49a27e",
+        "type": "github"
+      },
+      "original": {
+        "owner": "nix-systems",
+        "repo": "default",
+        "type": "github"
+      }
+    }
+  },
+  "root": "root",
+  "version": 7
+}
+"#;
+
+    #[test]
+    fn lock_file() {
+        let lock: LockFile = LOCK.parse().unwrap();
+        assert_eq!(lock.nodes.len(), 5);
+
+        let (key, systems) = lock.resolve_path("flake-utils/systems").unwrap();
+        assert_eq!(key, "systems");
+        assert!(!systems.flake);
+
+        // follows are resolved from the root node
+        let (key, _) = lock.resolve_path("home-manager/nixpkgs").unwrap();
+        assert_eq!(key, "nixpkgs");
+        assert_eq!(lock.inputs("home-manager").collect::<Vec<_>>(), [(
+            "nixpkgs",
+            Ok("nixpkgs")
+        )]);
+
+        let home_manager = lock.nodes["home-manager"].locked.as_ref().unwrap();
+        assert_eq!(home_manager.rev_count(), Some(RevCount(2901)));
+        assert!(matches!(
+            lock.nodes["nixpkgs"].original,
+            Some(FlakeRef::Indirect(_))
+        ));
+
+        assert_eq!(lock.resolve_path("").unwrap().0, "root");
+        assert_eq!(
+            lock.resolve_path("nixpkgs/nixpkgs"),
+            Err(ResolveError::MissingInput("nixpkgs/nixpkgs".to_string()))
+        );
+    }
+
+    #[test]
+    fn locked_refs() {
+        let lock: LockFile = LOCK.parse().unwrap();
+        let json: Value = serde_json::from_str(LOCK).unwrap();
+
+        for (key, node) in &lock.nodes {
+            assert_eq!(
+                node.locked_attrs().map(Value::Object).as_ref(),
+                json["nodes"][key].get("locked")
+            );
+            assert_eq!(
+                node.original_attrs().map(Value::Object).as_ref(),
+                json["nodes"][key].get("original")
+            );
+        }
+
+        let node = LockedNode::from_attrs(
+            json["nodes"]["home-manager"]["locked"]
+                .as_object()
+                .unwrap()
+                .clone(),
+            json["nodes"]["home-manager"]["original"]
+                .as_object()
+                .unwrap()
+                .clone(),
+        )
+        .unwrap();
+        assert!(matches!(node.locked, Some(FlakeRef::GitHttps(_))));
+        assert_eq!(node.locked.as_ref(), lock.locked_ref("home-manager").ok());
+        assert_eq!(
+            lock.original_ref("home-manager/nixpkgs")
+                .unwrap()
+                .to_string(),
+            "flake:nixpkgs"
+        );
+        assert_eq!(
+            lock.locked_ref(""),
+            Err(ResolveError::NotLocked("root".to_string()))
+        );
+
+        assert_eq!(
+            lock.override_inputs()
+                .iter()
+                .map(|input| input.from.as_str())
+                .collect::<Vec<_>>(),
+            [
+                "flake-utils",
+                "flake-utils/systems",
+                "home-manager",
+                "nixpkgs"
+            ]
+        );
+    }
+
+    #[test]
+    fn update_input() {
+        let old: LockFile = LOCK.parse().unwrap();
+        let nixpkgs: FlakeRef = "github:NixOS/nixpkgs/0a8b8d3c0a8b8d3c0a8b8d3c0a8b8d3c0a8b8d3c"
+            .parse()
+            .unwrap();
+
+        let mut lock = old.clone();
+        let previous = lock.update_input("nixpkgs", nixpkgs.clone()).unwrap();
+        assert_eq!(previous.as_ref(), old.locked_ref("nixpkgs").ok());
+        // followers see the update
+        assert_eq!(lock.locked_ref("home-manager/nixpkgs"), Ok(&nixpkgs));
+        assert_eq!(lock.nodes.len(), old.nodes.len());
+        assert_eq!(
+            LockFile::diff(&old, &lock)
+                .changes
+                .iter()
+                .map(InputChange::input)
+                .collect::<Vec<_>>(),
+            ["nixpkgs"]
+        );
+
+        assert_eq!(
+            lock.update_input("home-manager/nixpkgs", nixpkgs.clone()),
+            Err(UpdateInputError::Follows(
+                "home-manager/nixpkgs".to_string()
+            ))
+        );
+        assert_eq!(
+            lock.update_input("", nixpkgs.clone()),
+            Err(UpdateInputError::Root)
+        );
+        assert_eq!(
+            lock.update_input("flake-utils/nixpkgs", nixpkgs.clone()),
+            Err(UpdateInputError::Resolve(ResolveError::MissingInput(
+                "flake-utils/nixpkgs".to_string()
+            )))
+        );
+    }
+
+    #[test]
+    fn update_shared_input() {
+        let mut lock: LockFile = LOCK.parse().unwrap();
+        lock.nodes
+            .get_mut("root")
+            .unwrap()
+            .inputs
+            .insert("systems".to_string(), InputRef::Node("systems".to_string()));
+        let old = lock.clone();
+
+        let systems: FlakeRef = "github:nix-systems/x86_64-linux".parse().unwrap();
+        lock.update_input("flake-utils/systems", systems.clone())
+            .unwrap();
+
+        assert_eq!(
+            lock.resolve_path("flake-utils/systems").unwrap().0,
+            "systems_2"
+        );
+        assert_eq!(lock.locked_ref("flake-utils/systems"), Ok(&systems));
+        assert_eq!(lock.locked_ref("systems"), old.locked_ref("systems"));
+        assert!(!lock.nodes["systems_2"].flake);
+
+        // nodes no input refers to anymore are pruned
+        lock.update_input(
+            "flake-utils/systems",
+            old.locked_ref("systems").unwrap().clone(),
+        )
+        .unwrap();
+        lock.nodes.get_mut("root").unwrap().inputs.remove("systems");
+        lock.prune();
+        assert!(!lock.nodes.contains_key("systems"));
+        assert!(lock.nodes.contains_key("systems_2"));
+        lock.validate().unwrap();
+    }
+
+    /// Backend "fetching" sources by looking up their narHash
+    struct PrefetchBackend(BTreeMap<String, NarHash>);
+    impl NixBackend for PrefetchBackend {}
+
+    #[async_trait::async_trait]
+    impl Run<PrefetchBackend> for command::FlakePrefetch {
+        type Error = std::io::Error;
+
+        async fn run(&self, _: &PrefetchBackend, _: &NixArgs) -> Result<(), Self::Error> {
+            unimplemented!()
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl RunTyped<PrefetchBackend> for command::FlakePrefetch {
+        type Output = command::FlakePrefetchOut;
+        type TypedError = std::io::Error;
+
+        async fn run_typed(
+            &self,
+            backend: &PrefetchBackend,
+            _: &NixArgs,
+        ) -> Result<Self::Output, Self::TypedError> {
+            let flake_ref = self.flake_ref.as_ref().unwrap().to_string();
+            assert!(!flake_ref.contains("narHash"));
+            let hash = backend.0.get(&flake_ref).cloned().ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::NotFound, flake_ref.clone())
+            })?;
+            Ok(command::FlakePrefetchOut {
+                hash,
+                store_path: "/nix/store/00000000000000000000000000000000-source".into(),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn verify_nar_hashes() {
+        let lock: LockFile = LOCK.parse().unwrap();
+        let unpinned = |path: &str| {
+            let mut attrs = lock.locked_ref(path).unwrap().to_attrs();
+            attrs.remove("narHash");
+            FlakeRef::from_attrs(attrs).unwrap().to_string()
+        };
+        let nar_hash = |path: &str| lock.locked_ref(path).unwrap().nar_hash().unwrap();
+        let other: NarHash = "sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU="
+            .parse()
+            .unwrap();
+
+        let backend = PrefetchBackend(BTreeMap::from([
+            (unpinned("nixpkgs"), nar_hash("nixpkgs")),
+            (unpinned("home-manager"), nar_hash("home-manager")),
+            (unpinned("flake-utils"), other.clone()),
+        ]));
+
+        let report = lock.verify_nar_hashes(&backend, &NixArgs::default()).await;
+        assert!(!report.is_ok());
+        assert_eq!(report.verified, ["home-manager", "nixpkgs"]);
+        assert_eq!(report.mismatches, [NarHashMismatch {
+            input: "flake-utils".to_string(),
+            expected: nar_hash("flake-utils"),
+            actual: other,
+        }]);
+        assert_eq!(report.unverified, Vec::<String>::new());
+        assert_eq!(
+            report
+                .failed
+                .iter()
+                .map(|(input, _)| input.as_str())
+                .collect::<Vec<_>>(),
+            ["flake-utils/systems"]
+        );
+    }
+
+    #[test]
+    fn follows_cycle() {
+        let mut lock: LockFile = LOCK.parse().unwrap();
+        let follows = |path: &str| InputRef::Follows(path.split('/').map(String::from).collect());
+        let root = lock.nodes.get_mut("root").unwrap();
+        root.inputs.insert("a".to_string(), follows("b"));
+        root.inputs
+            .insert("b".to_string(), follows("home-manager/nixpkgs"));
+        lock.nodes
+            .get_mut("home-manager")
+            .unwrap()
+            .inputs
+            .insert("nixpkgs".to_string(), follows("a"));
+
+        assert_eq!(
+            lock.resolve_path("a").unwrap_err(),
+            ResolveError::Cycle(
+                ["a", "b", "home-manager/nixpkgs", "a"]
+                    .map(String::from)
+                    .to_vec()
+            )
+        );
+        assert!(matches!(
+            lock.validate(),
+            Err(LockFileError::UnresolvedInput {
+                source: ResolveError::Cycle(_),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn write_lock_file() {
+        let lock: LockFile = LOCK.parse().unwrap();
+        assert_eq!(lock.to_nix_json().unwrap(), LOCK);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("flake.lock");
+        lock.write(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), LOCK);
+        assert_eq!(LockFile::read(&path).unwrap(), lock);
+    }
+
+    #[test]
+    fn diff_lock_files() {
+        let old: LockFile = LOCK.parse().unwrap();
+        assert!(LockFile::diff(&old, &old).is_empty());
+
+        let mut new = old.clone();
+        // remove flake-utils and its transitive input
+        new.nodes.remove("flake-utils");
+        new.nodes.remove("systems");
+        new.nodes
+            .get_mut("root")
+            .unwrap()
+            .inputs
+            .remove("flake-utils");
+        // update nixpkgs
+        new.nodes.get_mut("nixpkgs").unwrap().locked = Some(
+            "github:NixOS/nixpkgs/0a8b8d3c0a8b8d3c0a8b8d3c0a8b8d3c0a8b8d3c?lastModified=1688300000"
+                .parse()
+                .unwrap(),
+        );
+        // add an input
+        new.nodes.insert("flake-compat".to_string(), LockedNode {
+            inputs: Default::default(),
+            locked: Some("github:edolstra/flake-compat".parse().unwrap()),
+            original: None,
+            flake: false,
+        });
+        new.nodes.get_mut("root").unwrap().inputs.insert(
+            "flake-compat".to_string(),
+            InputRef::Node("flake-compat".to_string()),
+        );
+        new.validate().unwrap();
+
+        let diff = LockFile::diff(&old, &new);
+        assert_eq!(
+            diff.changes
+                .iter()
+                .map(|change| match change {
+                    InputChange::Added { input, .. } => format!("+{input}"),
+                    InputChange::Removed { input, .. } => format!("-{input}"),
+                    InputChange::Updated { input, .. } => format!("~{input}"),
+                })
+                .collect::<Vec<_>>(),
+            [
+                "+flake-compat",
+                "-flake-utils",
+                "-flake-utils/systems",
+                "~nixpkgs"
+            ]
+        );
+
+        let update = &diff.changes[3];
+        assert_eq!(
+            update
+                .before()
+                .unwrap()
+                .nar_hash
+                .as_deref()
+                .map(String::as_str),
+            Some("sha256-lHrKvEkCPTUO+7tPfjIcb7Trk6k31rz18vkyqmkeJfY=")
+        );
+        assert_eq!(update.after().unwrap().nar_hash, None);
+        assert!(update.last_modified_delta().unwrap() < chrono::Duration::zero());
+        assert_eq!(diff.changes[0].last_modified_delta(), None);
+
+        assert_eq!(
+            diff.to_string(),
+            "• Added input 'flake-compat':\n    'github:edolstra/flake-compat'\n\
+             • Removed input 'flake-utils'\n\
+             • Removed input 'flake-utils/systems'\n\
+             • Updated input 'nixpkgs':\n    \
+             'github:NixOS/nixpkgs/ea4c80b39be4c09702b0cb3b42eab59e2ba4f24b?lastModified=1688392541&narHash=sha256-lHrKvEkCPTUO%2B7tPfjIcb7Trk6k31rz18vkyqmkeJfY%3D' (2023-07-03)\n  \
+             → 'github:NixOS/nixpkgs/0a8b8d3c0a8b8d3c0a8b8d3c0a8b8d3c0a8b8d3c?lastModified=1688300000' (2023-07-02)\n"
+        );
+    }
+
+    #[test]
+    fn lock_file_versions() {
+        let v5 = r#"{
+          "nodes": {
+            "nixpkgs": {
+              "info": {
+                "lastModified": 1688392541,
+                "narHash": "sha256-lHrKvEkCPTUO+7tPfjIcb7Trk6k31rz18vkyqmkeJfY="
+              },
+              "locked": {
+                "owner": "NixOS",
+                "repo": "nixpkgs",
+                "rev": "ea4c80b39be4c09702b0cb3b42eab59e2ba4f24b",
+                "type": "github"
+              },
+              "original": { "id": "nixpkgs", "type": "indirect" }
+            },
+            "root": { "inputs": { "nixpkgs": "nixpkgs" } }
+          },
+          "root": "root",
+          "version": 5
+        }"#;
+        let lock: LockFile = v5.parse().unwrap();
+        assert_eq!(lock.version, LOCK_VERSION);
+        assert_eq!(
+            lock.locked_ref("nixpkgs"),
+            LOCK.parse::<LockFile>().unwrap().locked_ref("nixpkgs")
+        );
+
+        let v6 = v5.replace(r#""version": 5"#, r#""version": 6"#);
+        assert_eq!(v6.parse::<LockFile>().unwrap(), lock);
+
+        let v8 = v5.replace(r#""version": 5"#, r#""version": 8"#);
+        assert!(matches!(
+            v8.parse::<LockFile>(),
+            Err(LockFileError::FutureVersion(8))
+        ));
+        let err = serde_json::from_str::<LockFile>(&v8).unwrap_err();
+        assert!(err.to_string().contains("newer version of nix"));
+
+        let v4 = v5.replace(r#""version": 5"#, r#""version": 4"#);
+        assert!(matches!(
+            v4.parse::<LockFile>(),
+            Err(LockFileError::UnsupportedVersion(4))
+        ));
+
+        let unversioned = v5.replace(r#""version": 5"#, r#""vers": 5"#);
+        assert!(matches!(
+            unversioned.parse::<LockFile>(),
+            Err(LockFileError::MissingVersion)
+        ));
+    }
+
+    #[test]
+    fn invalid_lock_file() {
+        let mut lock: LockFile = LOCK.parse().unwrap();
+        lock.version = 4;
+        assert!(matches!(
+            lock.validate(),
+            Err(LockFileError::UnsupportedVersion(4))
+        ));
+
+        let mut lock: LockFile = LOCK.parse().unwrap();
+        lock.nodes.get_mut("nixpkgs").unwrap().inputs.insert(
+            "self".to_string(),
+            InputRef::Follows(vec!["nixpkgs".to_string(), "self".to_string()]),
+        );
+        assert!(matches!(
+            lock.validate(),
+            Err(LockFileError::UnresolvedInput { .. })
+        ));
+
+        let mut lock: LockFile = LOCK.parse().unwrap();
+        lock.nodes.remove("root");
+        assert!(matches!(
+            lock.validate(),
+            Err(LockFileError::MissingNode(_))
+        ));
+    }
+
+    #[test]
+    fn rev() {
+        let rev: Rev = "1E684B371cf05300bc2b432f958f285855bac8fb".parse().unwrap();
+        assert_eq!(rev.short(), "1E684B3");
+        assert!(rev.matches_short("1e68"));
+        assert!(!rev.matches_short("1e6"));
+        assert!(!rev.matches_short("1e684c"));
+        assert!(!rev.matches_short(&format!("{rev}0")));
+
+        for invalid in [
+            "",
+            "1e684b3",
+            "1e684b371cf05300bc2b432f958f285855bac8fb0",
+            "ge684b371cf05300bc2b432f958f285855bac8fb",
+        ] {
+            invalid.parse::<Rev>().unwrap_err();
+        }
+
+        assert_eq!(
+            serde_json::from_str::<RevCount>(r#""42""#).unwrap(),
+            RevCount(42)
+        );
+        assert_eq!(
+            serde_json::from_str::<RevCount>("42").unwrap(),
+            RevCount(42)
+        );
+        serde_json::from_str::<RevCount>(r#""-1""#).unwrap_err();
+        assert_eq!(RevCount(42).to_string(), "42");
+    }
+
+    #[test]
+    fn rejects_malformed_nar_hash() {
+        for malformed in [
+            "",
+            "sha256",
+            "sha256-",
+            "sha256:",
+            "sha3-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=",
+            "sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU",
+            "sha512-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=",
+            "sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b85z",
+            // 'e' is not part of nix' base32 alphabet
+            "sha256:emdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73",
+            // overflowing the digest
+            "sha256:zmdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73",
+        ] {
+            malformed.parse::<NarHash>().unwrap_err();
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use std::borrow::Cow;
 use std::fmt::Display;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
@@ -9,8 +9,18 @@ use thiserror::Error;
 use url::Url;
 
 use super::lock::{LastModified, NarHash, Rev, RevCount};
-use super::{Attrs, FlakeRefSource};
+use super::{
+    decode_component,
+    deserialize_query,
+    encode_path,
+    form_to_query,
+    Attrs,
+    FlakeRefAttributes,
+    FlakeRefSource,
+    QueryError,
+};
 use crate::url_parser::{
+    extract_dir_attr,
     extract_last_modified_attr,
     extract_nar_hash_attr,
     extract_path_attr,
@@ -21,7 +31,7 @@ use crate::url_parser::{
 
 /// <https://cs.github.com/NixOS/nix/blob/f225f4307662fe9a57543d0c86c28aa9fddaf0d2/src/libfetchers/path.cc#L46>
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
-#[serde(tag = "path")]
+#[serde(tag = "type", rename = "path")]
 pub struct PathRef {
     pub path: PathBuf,
     #[serde(flatten)]
@@ -41,12 +51,81 @@ pub struct PathAttributes {
     pub last_modified: Option<LastModified>,
 
     pub rev: Option<Rev>,
+
+    pub dir: Option<PathBuf>,
 }
 
 impl PathRef {
     pub fn new(path: PathBuf, attributes: PathAttributes) -> Self {
         Self { path, attributes }
     }
+
+    /// Create a `path:` flake ref pointing to `path`
+    ///
+    /// ```
+    /// # use runix::flake_ref::path::PathRef;
+    /// let flake_ref = PathRef::from_path("/nix/store/083m43hjhry94cvfmqdv7kjpvsl3zzvi-source");
+    ///
+    /// assert_eq!(
+    ///     flake_ref.to_string(),
+    ///     "path:/nix/store/083m43hjhry94cvfmqdv7kjpvsl3zzvi-source"
+    /// );
+    /// ```
+    pub fn from_path(path: impl Into<PathBuf>) -> Self {
+        Self::new(path.into(), Default::default())
+    }
+
+    /// Set the `dir` attribute pointing to a flake in a subdirectory
+    pub fn with_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.attributes.dir = Some(dir.into());
+        self
+    }
+
+    /// Set the `rev` attribute
+    pub fn with_rev(mut self, rev: Rev) -> Self {
+        self.attributes.rev = Some(rev);
+        self
+    }
+
+    /// Set the `revCount` attribute
+    pub fn with_rev_count(mut self, rev_count: impl Into<RevCount>) -> Self {
+        self.attributes.rev_count = Some(rev_count.into());
+        self
+    }
+
+    /// Set the `narHash` attribute
+    pub fn with_nar_hash(mut self, nar_hash: impl Into<NarHash>) -> Self {
+        self.attributes.nar_hash = Some(nar_hash.into());
+        self
+    }
+
+    /// Set the `lastModified` attribute
+    pub fn with_last_modified(mut self, last_modified: impl Into<LastModified>) -> Self {
+        self.attributes.last_modified = Some(last_modified.into());
+        self
+    }
+}
+
+impl FlakeRefAttributes for PathRef {
+    fn rev(&self) -> Option<Rev> {
+        self.attributes.rev.clone()
+    }
+
+    fn dir(&self) -> Option<PathBuf> {
+        self.attributes.dir.clone()
+    }
+
+    fn nar_hash(&self) -> Option<NarHash> {
+        self.attributes.nar_hash.clone()
+    }
+
+    fn last_modified(&self) -> Option<LastModified> {
+        self.attributes.last_modified.clone()
+    }
+
+    fn rev_count(&self) -> Option<RevCount> {
+        self.attributes.rev_count.clone()
+    }
 }
 
 impl FlakeRefSource for PathRef {
@@ -63,9 +142,8 @@ impl FlakeRefSource for PathRef {
                 url.scheme().to_string(),
             ));
         }
-        let path = Path::new(url.path()).to_path_buf();
-        let attributes: PathAttributes =
-            serde_urlencoded::from_str(url.query().unwrap_or_default())?;
+        let path = PathBuf::from(decode_component(url.path()));
+        let attributes: PathAttributes = deserialize_query(url.query().unwrap_or_default())?;
 
         Ok(PathRef { path, attributes })
     }
@@ -85,14 +163,15 @@ impl Display for PathRef {
         let mut url: Url = format!(
             "{scheme}:{path}",
             scheme = Self::scheme(),
-            path = self.path.to_string_lossy(),
+            path = encode_path(&self.path.to_string_lossy()),
         )
         .parse()
-        .unwrap();
+        .map_err(|_| std::fmt::Error)?;
 
         url.set_query(
             serde_urlencoded::to_string(&self.attributes)
                 .ok()
+                .map(|query| form_to_query(&query))
                 .as_deref(),
         );
 
@@ -117,6 +196,7 @@ impl TryFrom<Attrs> for PathRef {
         let nar_hash = extract_nar_hash_attr(&attrs)?;
         let last_modified = extract_last_modified_attr(&attrs)?;
         let rev = extract_rev_attr(&attrs)?;
+        let dir = extract_dir_attr(&attrs)?;
         Ok(PathRef {
             path,
             attributes: PathAttributes {
@@ -124,6 +204,7 @@ impl TryFrom<Attrs> for PathRef {
                 nar_hash,
                 last_modified,
                 rev,
+                dir,
             },
         })
     }
@@ -135,8 +216,8 @@ pub enum ParsePathRefError {
     Url(#[from] url::ParseError),
     #[error("Invalid scheme (expected: '{0}:', found '{1}:'")]
     InvalidScheme(String, String),
-    #[error("Couldn't parse query: {0}")]
-    Query(#[from] serde_urlencoded::de::Error),
+    #[error(transparent)]
+    Query(#[from] QueryError),
 }
 
 #[cfg(test)]
@@ -174,13 +255,45 @@ mod tests {
             path: "/nix/store/083m43hjhry94cvfmqdv7kjpvsl3zzvi-source".into(),
             attributes: PathAttributes {
                 rev_count: None,
-                nar_hash: Some("sha256-MTXmIYowHM1wyIYyqPdBLia5SjGnxETv0YkIbDsbkx4=".into()),
+                nar_hash: Some(
+                    "sha256-MTXmIYowHM1wyIYyqPdBLia5SjGnxETv0YkIbDsbkx4="
+                        .parse()
+                        .unwrap(),
+                ),
                 last_modified: Some(Utc.timestamp_opt(1666570118, 0).unwrap().into()),
-                rev: Some("1e684b371cf05300bc2b432f958f285855bac8fb".parse().unwrap())
+                rev: Some("1e684b371cf05300bc2b432f958f285855bac8fb".parse().unwrap()),
+                dir: None,
             }
         })
     }
 
+    #[test]
+    fn build_path_ref() {
+        let built = PathRef::from_path("/nix/store/083m43hjhry94cvfmqdv7kjpvsl3zzvi-source")
+            .with_nar_hash(
+                "sha256-MTXmIYowHM1wyIYyqPdBLia5SjGnxETv0YkIbDsbkx4="
+                    .parse::<NarHash>()
+                    .unwrap(),
+            )
+            .with_last_modified(Utc.timestamp_opt(1666570118, 0).unwrap())
+            .with_rev("1e684b371cf05300bc2b432f958f285855bac8fb".parse().unwrap());
+
+        assert_eq!(built, PathRef {
+            path: "/nix/store/083m43hjhry94cvfmqdv7kjpvsl3zzvi-source".into(),
+            attributes: PathAttributes {
+                rev_count: None,
+                nar_hash: Some(
+                    "sha256-MTXmIYowHM1wyIYyqPdBLia5SjGnxETv0YkIbDsbkx4="
+                        .parse()
+                        .unwrap(),
+                ),
+                last_modified: Some(Utc.timestamp_opt(1666570118, 0).unwrap().into()),
+                rev: Some("1e684b371cf05300bc2b432f958f285855bac8fb".parse().unwrap()),
+                dir: None,
+            },
+        });
+    }
+
     /// Ensure that a path flake ref serializes without information loss
     #[test]
     fn path_to_from_url() {
@@ -188,9 +301,14 @@ mod tests {
             path: "/nix/store/083m43hjhry94cvfmqdv7kjpvsl3zzvi-source".into(),
             attributes: PathAttributes {
                 rev_count: None,
-                nar_hash: Some("sha256-MTXmIYowHM1wyIYyqPdBLia5SjGnxETv0YkIbDsbkx4=".into()),
+                nar_hash: Some(
+                    "sha256-MTXmIYowHM1wyIYyqPdBLia5SjGnxETv0YkIbDsbkx4="
+                        .parse()
+                        .unwrap(),
+                ),
                 last_modified: Some(Utc.timestamp_opt(1666570118, 0).unwrap().into()),
                 rev: Some("1e684b371cf05300bc2b432f958f285855bac8fb".parse().unwrap()),
+                dir: None,
             },
         };
 
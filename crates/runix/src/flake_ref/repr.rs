@@ -0,0 +1,187 @@
+//! Selectable serde representations of [FlakeRef]s
+//!
+//! [FlakeRef] itself (de)serializes as an attribute set,
+//! the form nix uses in lock files, e.g. `{ "type": "github", "owner": "flox", "repo": "runix" }`.
+//! Nix commands and `flake.nix` inputs however often use the url form, e.g. `github:flox/runix`.
+//!
+//! The adapters in this module select the representation of a field using [serde_with]:
+//!
+//! ```
+//! # use runix::flake_ref::repr::{AsAttrs, AsUrl};
+//! # use runix::flake_ref::FlakeRef;
+//! # use serde::{Deserialize, Serialize};
+//! # use serde_with::serde_as;
+//! #[serde_as]
+//! #[derive(Serialize, Deserialize)]
+//! struct Input {
+//!     #[serde_as(as = "AsUrl")]
+//!     url: FlakeRef,
+//!     #[serde_as(as = "AsAttrs")]
+//!     locked: FlakeRef,
+//! }
+//!
+//! let input: Input = serde_json::from_value(serde_json::json!({
+//!     "url": "github:flox/runix",
+//!     "locked": { "type": "github", "owner": "flox", "repo": "runix" },
+//! }))
+//! .unwrap();
+//! assert_eq!(input.url, input.locked);
+//! ```
+//!
+//! while [UrlFlakeRef] and [AttrsFlakeRef] wrap a [FlakeRef] to select its representation
+//! when (de)serializing it directly.
+
+use derive_more::{Deref, DerefMut, From};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_with::{serde_as, DeserializeAs, SerializeAs};
+
+use super::FlakeRef;
+
+/// (De)serialize a [FlakeRef] as url string, e.g. `"github:flox/runix"`
+///
+/// Note that deserializing uses [FlakeRef::from_str](std::str::FromStr::from_str),
+/// which may call `parser-util` for flake refs that are not urls.
+pub struct AsUrl;
+
+impl SerializeAs<FlakeRef> for AsUrl {
+    fn serialize_as<S>(source: &FlakeRef, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(source)
+    }
+}
+
+impl<'de> DeserializeAs<'de, FlakeRef> for AsUrl {
+    fn deserialize_as<D>(deserializer: D) -> Result<FlakeRef, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let url = String::deserialize(deserializer)?;
+        url.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// (De)serialize a [FlakeRef] as attribute set, the default representation
+pub struct AsAttrs;
+
+impl SerializeAs<FlakeRef> for AsAttrs {
+    fn serialize_as<S>(source: &FlakeRef, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        source.serialize(serializer)
+    }
+}
+
+impl<'de> DeserializeAs<'de, FlakeRef> for AsAttrs {
+    fn deserialize_as<D>(deserializer: D) -> Result<FlakeRef, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        FlakeRef::deserialize(deserializer)
+    }
+}
+
+/// Deserialize a [FlakeRef] from either representation, serialize it as url string
+///
+/// Useful to read user provided configuration,
+/// which like `flake.nix` inputs may contain either form.
+pub struct AsUrlOrAttrs;
+
+impl SerializeAs<FlakeRef> for AsUrlOrAttrs {
+    fn serialize_as<S>(source: &FlakeRef, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        AsUrl::serialize_as(source, serializer)
+    }
+}
+
+impl<'de> DeserializeAs<'de, FlakeRef> for AsUrlOrAttrs {
+    fn deserialize_as<D>(deserializer: D) -> Result<FlakeRef, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[serde_as]
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum UrlOrAttrs {
+            Url(#[serde_as(as = "AsUrl")] FlakeRef),
+            Attrs(FlakeRef),
+        }
+
+        match UrlOrAttrs::deserialize(deserializer)? {
+            UrlOrAttrs::Url(flake_ref) | UrlOrAttrs::Attrs(flake_ref) => Ok(flake_ref),
+        }
+    }
+}
+
+/// A [FlakeRef] that (de)serializes as url string, see [AsUrl]
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, From, Deref, DerefMut)]
+#[serde(transparent)]
+pub struct UrlFlakeRef(#[serde_as(as = "AsUrl")] pub FlakeRef);
+
+/// A [FlakeRef] that (de)serializes as attribute set, see [AsAttrs]
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, From, Deref, DerefMut)]
+#[serde(transparent)]
+pub struct AttrsFlakeRef(#[serde_as(as = "AsAttrs")] pub FlakeRef);
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn representations() {
+        let flake_ref: FlakeRef = "github:flox/runix?dir=crates".parse().unwrap();
+        let url = json!("github:flox/runix?dir=crates");
+        let attrs = json!({
+            "type": "github",
+            "owner": "flox",
+            "repo": "runix",
+            "dir": "crates",
+        });
+
+        assert_eq!(
+            serde_json::to_value(UrlFlakeRef(flake_ref.clone())).unwrap(),
+            url
+        );
+        assert_eq!(
+            serde_json::from_value::<UrlFlakeRef>(url.clone())
+                .unwrap()
+                .0,
+            flake_ref
+        );
+        assert_eq!(
+            serde_json::to_value(AttrsFlakeRef(flake_ref.clone())).unwrap(),
+            attrs
+        );
+        assert_eq!(
+            serde_json::from_value::<AttrsFlakeRef>(attrs.clone())
+                .unwrap()
+                .0,
+            flake_ref
+        );
+
+        serde_json::from_value::<UrlFlakeRef>(attrs.clone()).unwrap_err();
+        serde_json::from_value::<AttrsFlakeRef>(url.clone()).unwrap_err();
+
+        #[serde_as]
+        #[derive(Debug, Serialize, Deserialize)]
+        struct Either(#[serde_as(as = "AsUrlOrAttrs")] FlakeRef);
+
+        assert_eq!(
+            serde_json::from_value::<Either>(url.clone()).unwrap().0,
+            flake_ref
+        );
+        assert_eq!(
+            serde_json::from_value::<Either>(attrs).unwrap().0,
+            flake_ref
+        );
+        assert_eq!(serde_json::to_value(Either(flake_ref)).unwrap(), url);
+    }
+}
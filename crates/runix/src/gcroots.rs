@@ -0,0 +1,289 @@
+//! Garbage collector roots, the symlinks in `/nix/var/nix/gcroots` keeping store paths alive
+//!
+//! Applications protect build results with indirect roots, as created by
+//! `nix build --out-link` and `nix-store --add-root`:
+//! a symlink to the store path anywhere on the filesystem,
+//! registered by a symlink to it in `gcroots/auto`.
+//! Removing the outer symlink makes the root stale, stale roots are removed by [GcRoots::remove_stale].
+//!
+//! ```no_run
+//! # use runix::gcroots::GcRoots;
+//! # use runix::store_path::StorePath;
+//! let path =
+//!     StorePath::from_path("/nix/store/3dsk2kh0m6xyi5rhjqzwxd4jc4hy6r2s-hello-2.12.1").unwrap();
+//!
+//! let roots = GcRoots::default();
+//! roots.add_indirect("/home/user/hello", &path).unwrap();
+//! assert!(roots
+//!     .list()
+//!     .unwrap()
+//!     .iter()
+//!     .any(|root| root.target.as_ref() == Some(&path)));
+//! ```
+
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use sha1::{Digest, Sha1};
+use thiserror::Error;
+
+use crate::hash::nix_base32;
+use crate::store_path::{StorePath, STORE_PREFIX};
+
+/// The default location of the garbage collector roots
+pub const GCROOTS_DIR: &str = "/nix/var/nix/gcroots";
+
+/// The directory of the roots registering indirect roots, relative to [GCROOTS_DIR]
+const AUTO: &str = "auto";
+
+#[derive(Debug, Error)]
+pub enum GcRootError {
+    #[error("Could not create root '{0}': {1}")]
+    Create(PathBuf, #[source] io::Error),
+    #[error("Could not read roots in '{0}': {1}")]
+    Read(PathBuf, #[source] io::Error),
+    #[error("Could not remove root '{0}': {1}")]
+    Remove(PathBuf, #[source] io::Error),
+    #[error("Cannot create root '{0}': the file exists and is not a symlink")]
+    NotASymlink(PathBuf),
+}
+
+/// A root found in the garbage collector roots
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GcRoot {
+    /// The symlink in the gcroots directory
+    pub link: PathBuf,
+    /// The symlink outside the gcroots directory an indirect root links to,
+    /// [None] for direct roots
+    pub indirect: Option<PathBuf>,
+    /// The store path kept alive, [None] if the root is stale
+    pub target: Option<StorePath>,
+}
+
+impl GcRoot {
+    /// Whether the root no longer keeps a store path alive,
+    /// e.g. because the outer symlink of an indirect root was removed
+    pub fn is_stale(&self) -> bool {
+        self.target.is_none()
+    }
+}
+
+/// The garbage collector roots of a store
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GcRoots {
+    /// The gcroots directory, [GCROOTS_DIR] by default
+    pub dir: PathBuf,
+    /// The store the roots link to, [STORE_PREFIX] by default
+    pub store_dir: PathBuf,
+}
+
+impl Default for GcRoots {
+    fn default() -> Self {
+        GcRoots::new(GCROOTS_DIR, &*STORE_PREFIX)
+    }
+}
+
+impl GcRoots {
+    pub fn new(dir: impl Into<PathBuf>, store_dir: impl Into<PathBuf>) -> Self {
+        GcRoots {
+            dir: dir.into(),
+            store_dir: store_dir.into(),
+        }
+    }
+
+    /// The root registering the indirect root at `link`
+    ///
+    /// Named like nix names them, by the SHA-1 of the path of the link,
+    /// so that roots created by nix and by [GcRoots::add_indirect] replace each other.
+    pub fn auto_root(&self, link: &Path) -> PathBuf {
+        let digest = Sha1::digest(link.as_os_str().as_bytes());
+        self.dir.join(AUTO).join(nix_base32::encode(&digest))
+    }
+
+    /// Create a symlink at `link` to `path` and register it as indirect root
+    ///
+    /// An existing symlink at `link` is replaced, relative links are relative to
+    /// the current directory.
+    pub fn add_indirect(
+        &self,
+        link: impl AsRef<Path>,
+        path: &StorePath,
+    ) -> Result<GcRoot, GcRootError> {
+        let link = std::env::current_dir()
+            .map_err(|e| GcRootError::Create(link.as_ref().to_path_buf(), e))?
+            .join(link);
+
+        replace_symlink(&path.as_path(), &link)?;
+
+        let root = self.auto_root(&link);
+        if let Some(auto) = root.parent() {
+            std::fs::create_dir_all(auto).map_err(|e| GcRootError::Create(root.clone(), e))?;
+        }
+        replace_symlink(&link, &root)?;
+
+        Ok(GcRoot {
+            link: root,
+            indirect: Some(link),
+            target: Some(path.clone()),
+        })
+    }
+
+    /// All roots in the gcroots directory, including stale ones
+    ///
+    /// Follows the links like the garbage collector does:
+    /// symlinks into the store are direct roots, symlinks to symlinks into the store
+    /// are indirect roots and symlinks to missing files are stale indirect roots.
+    /// Other symlinks, e.g. to the profiles directory, are ignored.
+    pub fn list(&self) -> Result<Vec<GcRoot>, GcRootError> {
+        let mut roots = Vec::new();
+        self.find_roots(&self.dir, &mut roots)?;
+        roots.sort_by(|a, b| a.link.cmp(&b.link));
+        Ok(roots)
+    }
+
+    /// Remove the stale indirect roots, returning the removed roots
+    ///
+    /// Only roots in `gcroots/auto` are removed, as nix does during garbage collection.
+    pub fn remove_stale(&self) -> Result<Vec<GcRoot>, GcRootError> {
+        let auto = self.dir.join(AUTO);
+        let stale: Vec<_> = self
+            .list()?
+            .into_iter()
+            .filter(|root| root.is_stale() && root.link.starts_with(&auto))
+            .collect();
+
+        for root in &stale {
+            std::fs::remove_file(&root.link)
+                .map_err(|e| GcRootError::Remove(root.link.clone(), e))?;
+        }
+        Ok(stale)
+    }
+
+    fn find_roots(&self, dir: &Path, roots: &mut Vec<GcRoot>) -> Result<(), GcRootError> {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(GcRootError::Read(dir.to_path_buf(), e)),
+        };
+
+        for entry in entries {
+            let entry = entry.map_err(|e| GcRootError::Read(dir.to_path_buf(), e))?;
+            let file_type = entry
+                .file_type()
+                .map_err(|e| GcRootError::Read(entry.path(), e))?;
+            let link = entry.path();
+
+            if file_type.is_dir() {
+                self.find_roots(&link, roots)?;
+                continue;
+            }
+            if !file_type.is_symlink() {
+                continue;
+            }
+
+            let target = read_link(&link)?;
+            if let Ok(path) = StorePath::from_path_in(&self.store_dir, &target) {
+                roots.push(GcRoot {
+                    link,
+                    indirect: None,
+                    target: Some(path),
+                });
+                continue;
+            }
+
+            // the outer symlink of an indirect root was removed
+            let Ok(metadata) = std::fs::symlink_metadata(&target) else {
+                roots.push(GcRoot {
+                    link,
+                    indirect: Some(target),
+                    target: None,
+                });
+                continue;
+            };
+            // symlinks to other files, e.g. the profiles directory, are not roots
+            if !metadata.is_symlink() {
+                continue;
+            }
+            if let Ok(path) = StorePath::from_path_in(&self.store_dir, read_link(&target)?) {
+                roots.push(GcRoot {
+                    link,
+                    indirect: Some(target),
+                    target: Some(path),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The absolute target of the symlink at `link`
+fn read_link(link: &Path) -> Result<PathBuf, GcRootError> {
+    let target = std::fs::read_link(link).map_err(|e| GcRootError::Read(link.to_path_buf(), e))?;
+    Ok(link.parent().unwrap_or(Path::new("/")).join(target))
+}
+
+/// Create a symlink at `link` to `target`, replacing an existing symlink atomically
+fn replace_symlink(target: &Path, link: &Path) -> Result<(), GcRootError> {
+    match std::fs::symlink_metadata(link) {
+        Ok(metadata) if !metadata.is_symlink() => {
+            return Err(GcRootError::NotASymlink(link.to_path_buf()))
+        },
+        _ => {},
+    }
+
+    let file_name = link.file_name().unwrap_or_default().to_string_lossy();
+    let tmp_link = link.with_file_name(format!(".{file_name}.{}.tmp", std::process::id()));
+    std::os::unix::fs::symlink(target, &tmp_link)
+        .and_then(|_| std::fs::rename(&tmp_link, link))
+        .map_err(|e| {
+            let _ = std::fs::remove_file(&tmp_link);
+            GcRootError::Create(link.to_path_buf(), e)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manages_indirect_roots() {
+        let dir = tempfile::tempdir().unwrap();
+        let store_dir = dir.path().join("store");
+        let path = StorePath::from_path_in(
+            &store_dir,
+            store_dir.join("3dsk2kh0m6xyi5rhjqzwxd4jc4hy6r2s-hello-2.12.1"),
+        )
+        .unwrap();
+        std::fs::create_dir_all(path.as_path()).unwrap();
+
+        let roots = GcRoots::new(dir.path().join("gcroots"), &store_dir);
+        std::fs::create_dir_all(dir.path().join("profiles")).unwrap();
+        std::fs::create_dir_all(&roots.dir).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("profiles"), roots.dir.join("profiles"))
+            .unwrap();
+        std::os::unix::fs::symlink(path.as_path(), roots.dir.join("direct")).unwrap();
+
+        let result = dir.path().join("result");
+        let root = roots.add_indirect(&result, &path).unwrap();
+        assert_eq!(std::fs::read_link(&result).unwrap(), path.as_path());
+        assert_eq!(roots.add_indirect(&result, &path).unwrap(), root);
+
+        let listed = roots.list().unwrap();
+        assert_eq!(listed.len(), 2);
+        assert!(listed.contains(&root));
+        assert!(roots.remove_stale().unwrap().is_empty());
+
+        std::fs::remove_file(&result).unwrap();
+        let stale = roots.remove_stale().unwrap();
+        assert_eq!(stale.len(), 1);
+        assert!(stale[0].is_stale());
+        assert_eq!(roots.list().unwrap()[0].indirect, None);
+
+        std::fs::write(&result, "").unwrap();
+        assert!(matches!(
+            roots.add_indirect(&result, &path),
+            Err(GcRootError::NotASymlink(_))
+        ));
+    }
+}
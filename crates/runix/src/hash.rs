@@ -0,0 +1,301 @@
+//! Cryptographic hashes as used by nix, in any of the encodings nix reads and writes
+//!
+//! Nix writes hashes in [SRI](https://www.w3.org/TR/SRI/) format, e.g. in flake lock files,
+//! and as `<algorithm>:<digest>` with the digest in base16, nix' base32 or base64,
+//! e.g. in narinfos, the store database and derivations.
+//! [Hash] parses all of them and converts between them, like `nix hash convert`.
+//!
+//! ```
+//! # use runix::hash::{Hash, HashAlgorithm, HashFormat};
+//! let hash: Hash = "sha256-MjeRjunqfGTBGU401nxIjs7PC9PZZ1FBCZp/bRB3C2M="
+//!     .parse()
+//!     .unwrap();
+//! assert_eq!(hash.algo, HashAlgorithm::Sha256);
+//!
+//! let base32 = hash.format(HashFormat::NixBase32);
+//! assert_eq!(
+//!     base32,
+//!     "sha256:0qqbfw86szws150m2ryrsc5wzklf91ydcd2f370n8z7ax6792drj"
+//! );
+//! assert_eq!(base32.parse::<Hash>().unwrap(), hash);
+//!
+//! let bare = Hash::parse_with_algo(&hash.to_base16(), HashAlgorithm::Sha256).unwrap();
+//! assert_eq!(
+//!     bare.to_string(),
+//!     "sha256-MjeRjunqfGTBGU401nxIjs7PC9PZZ1FBCZp/bRB3C2M="
+//! );
+//! ```
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use serde_with::{DeserializeFromStr, SerializeDisplay};
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ParseHashError {
+    #[error("Unknown hash algorithm '{0}'")]
+    UnknownAlgorithm(String),
+    #[error("Hash '{0}' is not of the form '<algorithm>-<base64>' or '<algorithm>:<digest>'")]
+    MissingAlgorithm(String),
+    #[error("Invalid {0} digest '{1}'")]
+    InvalidDigest(HashAlgorithm, String),
+}
+
+/// A hash algorithm supported by nix
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, DeserializeFromStr, SerializeDisplay,
+)]
+pub enum HashAlgorithm {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl HashAlgorithm {
+    /// The size of the digests produced by the algorithm in bytes
+    pub fn digest_size(&self) -> usize {
+        match self {
+            HashAlgorithm::Md5 => 16,
+            HashAlgorithm::Sha1 => 20,
+            HashAlgorithm::Sha256 => 32,
+            HashAlgorithm::Sha512 => 64,
+        }
+    }
+}
+
+impl Display for HashAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HashAlgorithm::Md5 => write!(f, "md5"),
+            HashAlgorithm::Sha1 => write!(f, "sha1"),
+            HashAlgorithm::Sha256 => write!(f, "sha256"),
+            HashAlgorithm::Sha512 => write!(f, "sha512"),
+        }
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = ParseHashError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "md5" => Ok(HashAlgorithm::Md5),
+            "sha1" => Ok(HashAlgorithm::Sha1),
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            "sha512" => Ok(HashAlgorithm::Sha512),
+            _ => Err(ParseHashError::UnknownAlgorithm(s.to_string())),
+        }
+    }
+}
+
+/// An encoding of hashes, see [Hash::format]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashFormat {
+    /// `<algorithm>:<lowercase hex>`
+    Base16,
+    /// `<algorithm>:<nix base32>`, as used in store paths
+    NixBase32,
+    /// `<algorithm>:<base64>`
+    Base64,
+    /// `<algorithm>-<base64>`
+    Sri,
+}
+
+/// A digest and the algorithm that produced it
+///
+/// Hashes compare by algorithm, then digest, regardless of the format they were parsed from.
+/// They are displayed and serialized in SRI format.
+#[derive(
+    Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, DeserializeFromStr, SerializeDisplay,
+)]
+pub struct Hash {
+    pub algo: HashAlgorithm,
+    pub digest: Vec<u8>,
+}
+
+impl Hash {
+    /// Create a hash from the raw `digest` produced by `algo`
+    ///
+    /// Fails if `digest` has the wrong length for `algo`.
+    pub fn new(algo: HashAlgorithm, digest: impl Into<Vec<u8>>) -> Result<Self, ParseHashError> {
+        let digest = digest.into();
+        if digest.len() != algo.digest_size() {
+            return Err(ParseHashError::InvalidDigest(algo, hex::encode(digest)));
+        }
+        Ok(Hash { algo, digest })
+    }
+
+    /// Parse a digest without algorithm in base16, nix' base32 or base64,
+    /// e.g. the hash of a fixed output derivation
+    ///
+    /// Hashes with an algorithm are accepted as well, if it is `algo`.
+    pub fn parse_with_algo(s: &str, algo: HashAlgorithm) -> Result<Self, ParseHashError> {
+        if let Ok(hash) = s.parse::<Hash>() {
+            if hash.algo != algo {
+                return Err(ParseHashError::InvalidDigest(algo, s.to_string()));
+            }
+            return Ok(hash);
+        }
+
+        let size = algo.digest_size();
+        let digest = if s.len() == size * 2 {
+            hex::decode(s).ok()
+        } else if s.len() == nix_base32::encoded_len(size) {
+            nix_base32::decode(s, size)
+        } else {
+            base64::decode(s).ok()
+        }
+        .ok_or_else(|| ParseHashError::InvalidDigest(algo, s.to_string()))?;
+
+        Hash::new(algo, digest).map_err(|_| ParseHashError::InvalidDigest(algo, s.to_string()))
+    }
+
+    /// The hash in `format`
+    pub fn format(&self, format: HashFormat) -> String {
+        match format {
+            HashFormat::Base16 => format!("{}:{}", self.algo, self.to_base16()),
+            HashFormat::NixBase32 => format!("{}:{}", self.algo, self.to_nix_base32()),
+            HashFormat::Base64 => format!("{}:{}", self.algo, self.to_base64()),
+            HashFormat::Sri => format!("{}-{}", self.algo, self.to_base64()),
+        }
+    }
+
+    /// The digest in base16 (lowercase hex)
+    pub fn to_base16(&self) -> String {
+        hex::encode(&self.digest)
+    }
+
+    /// The digest in nix' base32 encoding, as used in store paths and by `nix-hash --to-base32`
+    pub fn to_nix_base32(&self) -> String {
+        nix_base32::encode(&self.digest)
+    }
+
+    /// The digest in base64
+    pub fn to_base64(&self) -> String {
+        base64::encode(&self.digest)
+    }
+}
+
+impl FromStr for Hash {
+    type Err = ParseHashError;
+
+    /// Parse an SRI hash or a hash of the form `<algorithm>:<digest>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((algo, digest)) = s.split_once(':') {
+            return Hash::parse_with_algo(digest, algo.parse()?);
+        }
+
+        let (algo, digest) = s
+            .split_once('-')
+            .ok_or_else(|| ParseHashError::MissingAlgorithm(s.to_string()))?;
+        let algo: HashAlgorithm = algo.parse()?;
+        // the padded base64 encoding
+        if digest.len() != algo.digest_size().div_ceil(3) * 4 {
+            return Err(ParseHashError::InvalidDigest(algo, digest.to_string()));
+        }
+        let digest = base64::decode(digest)
+            .map_err(|_| ParseHashError::InvalidDigest(algo, digest.to_string()))?;
+        Hash::new(algo, digest)
+    }
+}
+
+impl Display for Hash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.format(HashFormat::Sri))
+    }
+}
+
+/// Nix' base32 encoding
+///
+/// Uses a custom alphabet and processes the digest starting at its last byte
+/// <https://github.com/NixOS/nix/blob/2.17.0/src/libutil/hash.cc>
+pub(crate) mod nix_base32 {
+    const ALPHABET: &[u8; 32] = b"0123456789abcdfghijklmnpqrsvwxyz";
+
+    pub(crate) fn encoded_len(size: usize) -> usize {
+        (size * 8 - 1) / 5 + 1
+    }
+
+    pub(crate) fn encode(digest: &[u8]) -> String {
+        (0..encoded_len(digest.len()))
+            .rev()
+            .map(|n| {
+                let b = n * 5;
+                let (i, j) = (b / 8, b % 8);
+                let high = digest
+                    .get(i + 1)
+                    .map_or(0, |byte| (*byte as u16) << (8 - j));
+                let c = ((digest[i] as u16 >> j) | high) & 0x1f;
+                ALPHABET[c as usize] as char
+            })
+            .collect()
+    }
+
+    pub(crate) fn decode(s: &str, size: usize) -> Option<Vec<u8>> {
+        let mut digest = vec![0u8; size];
+        for (n, c) in s.bytes().rev().enumerate() {
+            let value = ALPHABET.iter().position(|a| *a == c)? as u16;
+            let b = n * 5;
+            let (i, j) = (b / 8, b % 8);
+            digest[i] |= (value << j) as u8;
+            let carry = (value >> (8 - j)) as u8;
+            match digest.get_mut(i + 1) {
+                Some(next) => *next |= carry,
+                None if carry != 0 => return None,
+                None => {},
+            }
+        }
+        Some(digest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SRI: &str = "sha256-MjeRjunqfGTBGU401nxIjs7PC9PZZ1FBCZp/bRB3C2M=";
+
+    #[test]
+    fn converts_formats() {
+        let hash: Hash = SRI.parse().unwrap();
+        for format in [
+            HashFormat::Base16,
+            HashFormat::NixBase32,
+            HashFormat::Base64,
+            HashFormat::Sri,
+        ] {
+            assert_eq!(hash.format(format).parse::<Hash>().unwrap(), hash);
+        }
+        assert_eq!(serde_json::to_string(&hash).unwrap(), format!("\"{SRI}\""));
+
+        let md5 =
+            Hash::parse_with_algo("5d41402abc4b2a76b9719d911017c592", HashAlgorithm::Md5).unwrap();
+        assert!(md5 < hash);
+    }
+
+    #[test]
+    fn rejects_invalid_hashes() {
+        assert_eq!(
+            "sha3-AAAA".parse::<Hash>(),
+            Err(ParseHashError::UnknownAlgorithm("sha3".to_string()))
+        );
+        assert!(matches!(
+            "MjeRjunqfGTBGU401nxIjs7PC9PZZ1FBCZp".parse::<Hash>(),
+            Err(ParseHashError::MissingAlgorithm(_))
+        ));
+        assert!(matches!(
+            "sha256-MjeRjunqfGTBGU401nxIjs7PC9PZZ1FBCZp".parse::<Hash>(),
+            Err(ParseHashError::InvalidDigest(HashAlgorithm::Sha256, _))
+        ));
+        assert!(matches!(
+            Hash::parse_with_algo(SRI, HashAlgorithm::Sha512),
+            Err(ParseHashError::InvalidDigest(HashAlgorithm::Sha512, _))
+        ));
+        // the unused high bits of the last base32 character must be zero
+        assert!(format!("sha256:{}", "z".repeat(52))
+            .parse::<Hash>()
+            .is_err());
+    }
+}
@@ -1,16 +1,20 @@
 //! A much simplified installable representation
 
 use std::fmt::Display;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
-use derive_more::{AsRef, Display, From, IntoIterator};
+use derive_more::{AsRef, From, IntoIterator};
 use once_cell::sync::Lazy;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::command_line::ToArgs;
 use crate::flake_ref::{FlakeRef, ParseFlakeRefError};
-use crate::store_path::StorePath;
-use crate::url_parser::{InstallableOutputs, UrlParseError};
+use crate::store_path::{StorePath, StorePathError, STORE_PREFIX};
+pub use crate::url_parser::InstallableOutputs;
+use crate::url_parser::UrlParseError;
 
 /// regex listing valid characters for attributes
 ///
@@ -24,11 +28,131 @@ use crate::url_parser::{InstallableOutputs, UrlParseError};
 static VALID_ATTRIBUTE: Lazy<Regex> =
     Lazy::new(|| Regex::new("^([a-zA-Z0-9-._~!$&'()*+,;=:%@?/ ]*)$").unwrap());
 
-#[derive(Clone, Debug, Display, Eq, From, PartialEq)]
+/// (De)serializes as passed to nix on the command line,
+/// except for [Installable::Expr] and [Installable::File]
+/// which (de)serialize as objects of their fields.
+#[derive(Clone, Debug, Eq, From, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "SerializedInstallable", into = "SerializedInstallable")]
 pub enum Installable {
     FlakeAttribute(FlakeAttribute),
     StorePath(StorePath),
-    // TODO Nix file and Nix expression
+    DerivationOutputs(DerivationOutputs),
+    /// An attribute of a nix expression, passed as `--expr <expr> <attr_path>`
+    ///
+    /// Nix accepts only one `--expr` per command,
+    /// commands taking multiple installables should only combine
+    /// expression installables of the same `expr`.
+    #[from(ignore)]
+    Expr {
+        expr: String,
+        attr_path: AttrPath,
+    },
+    /// An attribute of a nix file, passed as `--file <file> <attr_path>`
+    ///
+    /// The same restrictions as for [Installable::Expr] apply.
+    #[from(ignore)]
+    File {
+        file: PathBuf,
+        attr_path: AttrPath,
+    },
+}
+
+impl Installable {
+    /// The outputs selected by the installable
+    ///
+    /// [None] for plain store paths, which have no outputs to select from
+    pub fn outputs(&self) -> Option<&InstallableOutputs> {
+        match self {
+            Installable::FlakeAttribute(flake_attribute) => Some(&flake_attribute.outputs),
+            Installable::StorePath(_) | Installable::Expr { .. } | Installable::File { .. } => None,
+            Installable::DerivationOutputs(derivation) => Some(&derivation.outputs),
+        }
+    }
+
+    /// Select `outputs` of the installable
+    ///
+    /// Store paths of derivations turn into [DerivationOutputs],
+    /// while selecting the default outputs of a derivation refers to the derivation itself.
+    /// Fails for store paths other than derivations and for expressions.
+    ///
+    /// ```
+    /// # use runix::installable::{Installable, InstallableOutputs};
+    /// let drv: Installable = "/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-hello-2.12.1.drv"
+    ///     .parse()
+    ///     .unwrap();
+    /// let outputs = drv.with_outputs("^out,man".parse().unwrap()).unwrap();
+    /// assert_eq!(
+    ///     outputs.to_string(),
+    ///     "/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-hello-2.12.1.drv^out,man"
+    /// );
+    /// ```
+    pub fn with_outputs(self, outputs: InstallableOutputs) -> Result<Self, ParseInstallableError> {
+        let drv_path = match self {
+            Installable::FlakeAttribute(flake_attribute) => {
+                return Ok(flake_attribute.with_outputs(outputs).into())
+            },
+            Installable::StorePath(store_path) if outputs == InstallableOutputs::Default => {
+                return Ok(store_path.into())
+            },
+            Installable::StorePath(store_path) => store_path,
+            Installable::DerivationOutputs(derivation) => derivation.drv_path,
+            source @ (Installable::Expr { .. } | Installable::File { .. }) => {
+                return Err(ParseInstallableError::UnsupportedOutputs(
+                    source.to_string(),
+                ))
+            },
+        };
+
+        if outputs == InstallableOutputs::Default {
+            return Ok(drv_path.into());
+        }
+        Ok(DerivationOutputs::new(drv_path, outputs)?.into())
+    }
+
+    /// Parse an attrpath of the nix file at `file`, as in `nix --file <file> <attr_path>`
+    ///
+    /// ```
+    /// # use runix::installable::Installable;
+    /// let installable = Installable::from_file_attr("./default.nix", "packages.hello").unwrap();
+    /// assert_eq!(
+    ///     installable.to_string(),
+    ///     "--file ./default.nix packages.hello"
+    /// );
+    /// ```
+    pub fn from_file_attr(
+        file: impl Into<PathBuf>,
+        attr_path: &str,
+    ) -> Result<Self, ParseInstallableError> {
+        Ok(Installable::File {
+            file: file.into(),
+            attr_path: attr_path.parse()?,
+        })
+    }
+
+    /// Parse an attrpath of the nix expression `expr`, as in `nix --expr <expr> <attr_path>`
+    pub fn from_expr_attr(
+        expr: impl Into<String>,
+        attr_path: &str,
+    ) -> Result<Self, ParseInstallableError> {
+        Ok(Installable::Expr {
+            expr: expr.into(),
+            attr_path: attr_path.parse()?,
+        })
+    }
+
+    /// An installable for `store_path`, selecting `outputs` if given
+    fn from_store_path(
+        store_path: StorePath,
+        outputs: Option<&str>,
+    ) -> Result<Self, ParseInstallableError> {
+        match outputs {
+            Some(outputs) => Ok(Installable::DerivationOutputs(DerivationOutputs::new(
+                store_path,
+                parse_outputs(outputs)?,
+            )?)),
+            None => Ok(Installable::StorePath(store_path)),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -38,6 +162,56 @@ pub struct FlakeAttribute {
     pub outputs: InstallableOutputs,
 }
 
+impl FlakeAttribute {
+    /// Select `outputs` of the flake attribute
+    pub fn with_outputs(mut self, outputs: InstallableOutputs) -> Self {
+        self.outputs = outputs;
+        self
+    }
+}
+
+/// Outputs of a derivation in the store, e.g. `/nix/store/<hash>-hello.drv^out`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DerivationOutputs {
+    drv_path: StorePath,
+    outputs: InstallableOutputs,
+}
+
+impl DerivationOutputs {
+    /// Select `outputs` of the derivation at `drv_path`
+    ///
+    /// Fails if `drv_path` is not the path of a derivation
+    /// or if no outputs are selected explicitly, as `drv_path` on its own refers to the derivation.
+    pub fn new(
+        drv_path: StorePath,
+        outputs: InstallableOutputs,
+    ) -> Result<Self, ParseInstallableError> {
+        if !drv_path.is_derivation() {
+            return Err(ParseInstallableError::NotADerivation(drv_path.to_string()));
+        }
+        if outputs == InstallableOutputs::Default {
+            return Err(ParseInstallableError::InvalidOutputs(String::new()));
+        }
+        Ok(DerivationOutputs { drv_path, outputs })
+    }
+
+    /// The store path of the derivation
+    pub fn drv_path(&self) -> &StorePath {
+        &self.drv_path
+    }
+
+    /// The selected outputs of the derivation
+    pub fn outputs(&self) -> &InstallableOutputs {
+        &self.outputs
+    }
+}
+
+impl Display for DerivationOutputs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", self.drv_path, self.outputs)
+    }
+}
+
 /// The attrpath component of an installable
 ///
 /// This implementation wraps a [Vec<String>] for components.
@@ -54,7 +228,26 @@ pub struct FlakeAttribute {
 /// AttrPath::try_from(["abc", "xyz"]).expect("Parses from array");
 /// AttrPath::try_from(&*vec!["abc", "xyz"]).expect("Parses from vec");
 /// ```
-#[derive(Debug, Clone, PartialEq, Eq, Default, IntoIterator)]
+///
+/// Components containing dots are quoted when printed,
+/// such that the printed attrpath parses back into the same components:
+///
+/// ```
+/// # use runix::installable::AttrPath;
+/// let mut attr_path: AttrPath = "legacyPackages.\"x86_64-linux\".hello".parse().unwrap();
+/// assert_eq!(attr_path.to_string(), "legacyPackages.x86_64-linux.hello");
+///
+/// attr_path.pop();
+/// attr_path.push_attr("python3.10").unwrap();
+/// assert_eq!(
+///     attr_path.to_string(),
+///     "legacyPackages.x86_64-linux.\"python3.10\""
+/// );
+/// ```
+///
+/// (De)serializes as list of components, like nix reports attrpaths in json.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, IntoIterator, Serialize, Deserialize)]
+#[serde(try_from = "Vec<String>", into = "Vec<String>")]
 pub struct AttrPath(Vec<Attribute>);
 
 impl AttrPath {
@@ -67,6 +260,43 @@ impl AttrPath {
         Ok(self)
     }
 
+    /// Add an already validated component to the end of the attrpath
+    pub fn push(&mut self, attr: Attribute) -> &mut Self {
+        self.0.push(attr);
+        self
+    }
+
+    /// Remove and return the last component of the attrpath
+    pub fn pop(&mut self) -> Option<Attribute> {
+        self.0.pop()
+    }
+
+    /// Create a new attrpath by appending all components of `other` to this one
+    pub fn join(&self, other: &AttrPath) -> AttrPath {
+        self.iter().chain(other.iter()).collect()
+    }
+
+    /// The attrpath without its last component, [None] if the attrpath is empty
+    pub fn parent(&self) -> Option<AttrPath> {
+        let (_, parent) = self.0.split_last()?;
+        Some(AttrPath(parent.to_vec()))
+    }
+
+    /// The last component of the attrpath
+    pub fn last(&self) -> Option<&Attribute> {
+        self.0.last()
+    }
+
+    /// Whether the first components of this attrpath are those of `prefix`
+    pub fn starts_with(&self, prefix: &AttrPath) -> bool {
+        self.0.starts_with(&prefix.0)
+    }
+
+    /// the number of components of the attrpath
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
     /// get an iterator over all components of the attrpath
     pub fn iter(&self) -> impl Iterator<Item = &Attribute> {
         self.0.iter()
@@ -119,14 +349,20 @@ impl FromStr for AttrPath {
         let mut cur = String::new();
 
         let mut start_quote = None;
+        // whether the current component was quoted, to retain a trailing `""`
+        let mut quoted = false;
 
-        for (n, c) in s.chars().enumerate() {
+        for (n, c) in s.char_indices() {
             match c {
                 '.' if start_quote.is_none() => {
                     attributes.push_attr(&std::mem::take(&mut cur))?;
+                    quoted = false;
                 },
                 '"' if start_quote.is_some() => start_quote = None,
-                '"' if start_quote.is_none() => start_quote = Some(n),
+                '"' if start_quote.is_none() => {
+                    start_quote = Some(n);
+                    quoted = true;
+                },
                 other => cur.push(other),
             }
         }
@@ -135,7 +371,7 @@ impl FromStr for AttrPath {
             return Err(ParseInstallableError::UnclosedQuote(s[start..].to_string()));
         }
 
-        if !cur.is_empty() {
+        if !cur.is_empty() || quoted {
             attributes.push_attr(&cur)?;
         }
         Ok(attributes)
@@ -184,12 +420,38 @@ impl<A: AsRef<str>, const N: usize> TryFrom<[A; N]> for AttrPath {
     }
 }
 
+impl TryFrom<Vec<String>> for AttrPath {
+    type Error = ParseInstallableError;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        value.as_slice().try_into()
+    }
+}
+
+impl From<AttrPath> for Vec<String> {
+    fn from(attr_path: AttrPath) -> Self {
+        attr_path.into_iter().map(|attr| attr.0).collect()
+    }
+}
+
 /// A validated attribute
 ///
 /// Component of an attrpath
-#[derive(Debug, Clone, PartialEq, Eq, AsRef)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, AsRef)]
 pub struct Attribute(String);
 
+impl Attribute {
+    /// The unquoted attribute name
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether the attribute has to be quoted within an attrpath
+    fn needs_quotes(&self) -> bool {
+        self.0.is_empty() || self.0.contains('.')
+    }
+}
+
 impl FromStr for Attribute {
     type Err = ParseInstallableError;
 
@@ -203,7 +465,7 @@ impl FromStr for Attribute {
 
 impl Display for Attribute {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if self.0.contains('.') {
+        if self.needs_quotes() {
             write!(f, "\"{}\"", self.0)
         } else {
             write!(f, "{}", self.0)
@@ -229,38 +491,250 @@ impl FromStr for FlakeAttribute {
     /// 4. then resolve the attrpath from the fragment
     ///    <https://github.com/NixOS/nix/blob/33aca20616adb872dfab1b3852fe58b948783cd2/src/libexpr/attr-path.cc#L9-L32>
     ///
-    /// In this implementation we split of the "fragment" part,
+    /// In this implementation we split of the output selection and the "fragment" part,
     /// before parsing the left hand side as a flakeref,
     /// in order to separate the parsing of the components.
+    /// Like nix, the output selection follows the last `^`,
+    /// also if there is no fragment, e.g. `.^out`.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.split_once('#') {
-            Some((flakeref, attr_path)) => Ok(match attr_path.split_once('^') {
-                Some((attr_path, outputs)) => {
-                    let outputs = if outputs == "*" {
-                        InstallableOutputs::All
-                    } else {
-                        InstallableOutputs::Selected(
-                            outputs.split(',').map(ToString::to_string).collect(),
-                        )
-                    };
-                    FlakeAttribute {
-                        flakeref: flakeref.parse()?,
-                        attr_path: attr_path.parse()?,
-                        outputs,
-                    }
-                },
-                None => FlakeAttribute {
-                    flakeref: flakeref.parse()?,
-                    attr_path: attr_path.parse()?,
-                    outputs: InstallableOutputs::Default,
-                },
-            }),
-
-            None => Ok(FlakeAttribute {
+        let (s, outputs) = split_outputs(s)?;
+        let Some((flakeref, attr_path)) = s.split_once('#') else {
+            return Ok(FlakeAttribute {
                 flakeref: s.parse()?,
                 attr_path: AttrPath::default(),
-                outputs: InstallableOutputs::Default,
-            }),
+                outputs,
+            });
+        };
+
+        Ok(FlakeAttribute {
+            flakeref: flakeref.parse()?,
+            attr_path: attr_path.parse()?,
+            outputs,
+        })
+    }
+}
+
+/// Split the output selection following the last `^` that is not within a quoted attribute
+/// off an installable
+///
+/// A `^` followed by a `#` or `/` is part of the flakeref or attrpath,
+/// as is any `^` within quotes.
+fn split_outputs(s: &str) -> Result<(&str, InstallableOutputs), ParseInstallableError> {
+    let mut quoted = false;
+    let mut caret = None;
+    for (n, c) in s.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '^' if !quoted => caret = Some(n),
+            _ => {},
+        }
+    }
+
+    match caret {
+        Some(n) if !s[n + 1..].contains(['#', '/']) => Ok((&s[..n], parse_outputs(&s[n + 1..])?)),
+        _ => Ok((s, InstallableOutputs::Default)),
+    }
+}
+
+/// Parse the output selection following the `^` of an installable
+fn parse_outputs(outputs: &str) -> Result<InstallableOutputs, ParseInstallableError> {
+    if outputs == "*" {
+        return Ok(InstallableOutputs::All);
+    }
+
+    let selected = outputs
+        .split(',')
+        .map(|output| {
+            if output.is_empty() || output.contains(['*', '"']) {
+                Err(ParseInstallableError::InvalidOutputs(outputs.to_string()))
+            } else {
+                Ok(output.to_string())
+            }
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(InstallableOutputs::Selected(selected))
+}
+
+impl FromStr for InstallableOutputs {
+    type Err = ParseInstallableError;
+
+    /// Parse an output selection in caret syntax
+    ///
+    /// `^*` selects all outputs, `^out,dev` the listed outputs
+    /// and the empty string the default outputs.
+    ///
+    /// ```
+    /// # use runix::installable::InstallableOutputs;
+    /// assert_eq!(
+    ///     "".parse::<InstallableOutputs>().unwrap(),
+    ///     InstallableOutputs::Default
+    /// );
+    /// assert_eq!(
+    ///     "^*".parse::<InstallableOutputs>().unwrap(),
+    ///     InstallableOutputs::All
+    /// );
+    /// assert_eq!(
+    ///     "^out,dev".parse::<InstallableOutputs>().unwrap(),
+    ///     InstallableOutputs::Selected(vec!["out".to_string(), "dev".to_string()])
+    /// );
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Ok(InstallableOutputs::Default);
+        }
+        let outputs = s
+            .strip_prefix('^')
+            .ok_or_else(|| ParseInstallableError::InvalidOutputs(s.to_string()))?;
+        parse_outputs(outputs)
+    }
+}
+
+impl Display for InstallableOutputs {
+    /// formats the output selection in caret syntax, nothing for the default outputs
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_url_suffix())
+    }
+}
+
+impl FromStr for Installable {
+    type Err = ParseInstallableError;
+
+    /// Parse an installable as passed to nix on the command line
+    ///
+    /// Like nix, paths into the nix store are parsed as [StorePath]s,
+    /// or [DerivationOutputs] if they select outputs of a derivation.
+    /// This includes paths resolving into the store, e.g. `./result` links of `nix build`.
+    /// Anything else is parsed as a [FlakeAttribute],
+    /// i.e. a flakeref, optionally followed by `#<attrpath>` and an output selection.
+    ///
+    /// Attrpaths of nix files or expressions (`nix --file`/`nix --expr`)
+    /// can not be distinguished from flakerefs,
+    /// use [Installable::from_file_attr] and [Installable::from_expr_attr] to parse them.
+    ///
+    /// ```
+    /// # use runix::installable::Installable;
+    /// let installable: Installable = "github:flox/runix#packages.\"x86_64-linux\".runix^out,dev"
+    ///     .parse()
+    ///     .unwrap();
+    ///
+    /// let Installable::FlakeAttribute(flake_attribute) = installable else {
+    ///     panic!("expected a flake attribute")
+    /// };
+    /// assert_eq!(flake_attribute.attr_path.as_slice().len(), 3);
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !s.contains('#') {
+            let (path, outputs) = match s.rsplit_once('^') {
+                Some((path, outputs)) => (path, Some(outputs)),
+                None => (s, None),
+            };
+
+            if Path::new(path).starts_with(&*STORE_PREFIX) {
+                return Self::from_store_path(StorePath::from_path(path)?, outputs);
+            }
+            // other paths are store paths only if they resolve into the store
+            if path.contains('/') {
+                if let Ok(store_path) = StorePath::from_path(path) {
+                    return Self::from_store_path(store_path, outputs);
+                }
+            }
+        }
+        Ok(Installable::FlakeAttribute(s.parse()?))
+    }
+}
+
+/// Serialized form of an [Installable]
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum SerializedInstallable {
+    Expr { expr: String, attr_path: AttrPath },
+    File { file: PathBuf, attr_path: AttrPath },
+    Installable(String),
+}
+
+impl From<Installable> for SerializedInstallable {
+    fn from(installable: Installable) -> Self {
+        match installable {
+            Installable::Expr { expr, attr_path } => {
+                SerializedInstallable::Expr { expr, attr_path }
+            },
+            Installable::File { file, attr_path } => {
+                SerializedInstallable::File { file, attr_path }
+            },
+            other => SerializedInstallable::Installable(other.to_string()),
+        }
+    }
+}
+
+impl TryFrom<SerializedInstallable> for Installable {
+    type Error = ParseInstallableError;
+
+    fn try_from(serialized: SerializedInstallable) -> Result<Self, Self::Error> {
+        match serialized {
+            SerializedInstallable::Expr { expr, attr_path } => {
+                Ok(Installable::Expr { expr, attr_path })
+            },
+            SerializedInstallable::File { file, attr_path } => {
+                Ok(Installable::File { file, attr_path })
+            },
+            SerializedInstallable::Installable(installable) => installable.parse(),
+        }
+    }
+}
+
+impl ToArgs for Installable {
+    /// Arguments passing the installable to nix
+    ///
+    /// A single argument, except for [Installable::Expr] and [Installable::File]
+    /// which add `--expr <expr>` or `--file <file>` in front of their attrpath.
+    ///
+    /// ```
+    /// # use runix::command_line::ToArgs;
+    /// # use runix::installable::Installable;
+    /// let installable = Installable::Expr {
+    ///     expr: "import <nixpkgs> {}".to_string(),
+    ///     attr_path: "python3.pkgs.requests".parse().unwrap(),
+    /// };
+    /// assert_eq!(installable.to_args(), [
+    ///     "--expr",
+    ///     "import <nixpkgs> {}",
+    ///     "python3.pkgs.requests"
+    /// ]);
+    /// ```
+    fn to_args(&self) -> Vec<String> {
+        match self {
+            Installable::Expr { expr, attr_path } => {
+                vec!["--expr".to_string(), expr.clone(), attr_path.to_string()]
+            },
+            Installable::File { file, attr_path } => vec![
+                "--file".to_string(),
+                file.to_string_lossy().into_owned(),
+                attr_path.to_string(),
+            ],
+            other => vec![other.to_string()],
+        }
+    }
+}
+
+impl Display for Installable {
+    /// formats the installable as passed to nix on the command line
+    ///
+    /// [Installable::Expr] and [Installable::File] are formatted
+    /// as shell escaped list of their [ToArgs::to_args]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Installable::FlakeAttribute(flake_attribute) => write!(f, "{flake_attribute}"),
+            Installable::StorePath(store_path) => write!(f, "{store_path}"),
+            Installable::DerivationOutputs(derivation) => write!(f, "{derivation}"),
+            Installable::Expr { .. } | Installable::File { .. } => {
+                let args = self
+                    .to_args()
+                    .into_iter()
+                    .map(|arg| shell_escape::escape(arg.into()))
+                    .collect::<Vec<_>>();
+                write!(f, "{}", args.join(" "))
+            },
         }
     }
 }
@@ -271,7 +745,7 @@ impl Display for FlakeAttribute {
         if !self.attr_path.is_empty() {
             write!(f, "#{}", self.attr_path)?;
         }
-        write!(f, "{}", self.outputs.as_url_suffix())?;
+        write!(f, "{}", self.outputs)?;
 
         Ok(())
     }
@@ -291,12 +765,47 @@ pub enum ParseInstallableError {
     InvalidAttr(String),
     #[error("failed to parse URI")]
     URLParser(#[from] UrlParseError),
+    #[error("Invalid output selection '{0}'")]
+    InvalidOutputs(String),
+    #[error("Can not select outputs of '{0}', which is not a derivation")]
+    NotADerivation(String),
+    #[error("Can not select outputs of '{0}'")]
+    UnsupportedOutputs(String),
+    #[error(transparent)]
+    StorePath(#[from] StorePathError),
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn serde_installable() {
+        let installables: Vec<Installable> = serde_json::from_str(
+            r#"[
+                "github:flox/runix#runix^bin",
+                { "expr": "import <nixpkgs> {}", "attr_path": ["hello"] },
+                { "file": "./default.nix", "attr_path": [] }
+            ]"#,
+        )
+        .unwrap();
+        assert!(matches!(installables[0], Installable::FlakeAttribute(_)));
+        assert_eq!(installables[1], Installable::Expr {
+            expr: "import <nixpkgs> {}".to_string(),
+            attr_path: ["hello"].try_into().unwrap(),
+        });
+        assert!(matches!(installables[2], Installable::File { .. }));
+
+        let json = serde_json::to_value(&installables).unwrap();
+        assert_eq!(json[0], "github:flox/runix#runix^bin");
+        assert_eq!(
+            serde_json::from_value::<Vec<Installable>>(json).unwrap(),
+            installables
+        );
+
+        assert!(serde_json::from_str::<Installable>(r#""github:flox/runix#runix^""#).is_err());
+    }
+
     fn assert_parse_as(input: &str, expected: &str, description: &str) {
         let output = input.parse::<AttrPath>().expect(description);
         assert_eq!(output.to_string(), expected);
@@ -351,6 +860,13 @@ mod tests {
         assert_parse("''a''", "parse '' quoted single");
         assert_parse("2", "parse number");
         assert_parse("\"a.b\"", "parse quoted dot");
+        assert_parse("a.\"\".b", "parse quoted empty");
+        assert_parse("a.\"\"", "parse trailing quoted empty");
+        assert_parse_as(
+            "legacyPackages.\"x86_64-linux\".hello",
+            "legacyPackages.x86_64-linux.hello",
+            "parse unnecessarily quoted",
+        );
 
         assert_fail("\"${abc}\"", "interpolated");
         assert_fail("a.\"${asdf}\".c", "interpolated inside");
@@ -361,7 +877,7 @@ mod tests {
     #[test]
     fn attr_path_from_list() {
         assert_parse_components(["a"], "a", "parse single attribute");
-        assert_parse_components([""], "", "parse empty");
+        assert_parse_components([""], "\"\"", "parse empty");
         assert_parse_components(["a", "b", "c"], "a.b.c", "parse nested path");
         assert_parse_components(["a.b"], "\"a.b\"", "should parse quoted single attribute");
 
@@ -371,6 +887,32 @@ mod tests {
         AttrPath::try_from(["x.${asdf}", "c"]).expect_err("should not parse with dynamic element");
     }
 
+    #[test]
+    fn attr_path_operations() {
+        let mut attr_path: AttrPath = "packages.x86_64-linux".parse().unwrap();
+        let package: AttrPath = "\"hello.world\"".parse().unwrap();
+
+        let joined = attr_path.join(&package);
+        assert_eq!(joined.to_string(), "packages.x86_64-linux.\"hello.world\"");
+        assert_eq!(joined.len(), 3);
+        assert!(joined.starts_with(&attr_path));
+        assert_eq!(joined.parent().unwrap(), attr_path);
+        assert_eq!(joined.last().unwrap().as_str(), "hello.world");
+        assert_eq!(AttrPath::default().parent(), None);
+
+        assert_eq!(attr_path.pop().unwrap().as_str(), "x86_64-linux");
+        attr_path.push("aarch64-darwin".parse().unwrap());
+        assert_eq!(attr_path.to_string(), "packages.aarch64-darwin");
+
+        let json = serde_json::to_value(&joined).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!(["packages", "x86_64-linux", "hello.world"])
+        );
+        assert_eq!(serde_json::from_value::<AttrPath>(json).unwrap(), joined);
+        serde_json::from_value::<AttrPath>(serde_json::json!(["${x}"])).unwrap_err();
+    }
+
     #[test]
     fn parse_flake_outputs() {
         assert_outputs(
@@ -398,6 +940,160 @@ mod tests {
             InstallableOutputs::All,
             "Selecting all outputs is captured",
         );
+        assert_outputs(
+            "github:a/b^out,dev",
+            InstallableOutputs::Selected(["out", "dev"].map(ToString::to_string).to_vec()),
+            "Outputs are selected without attributes",
+        );
+    }
+
+    #[test]
+    fn parse_installable() {
+        let installable: FlakeAttribute = "github:flox/runix#a.\"b.c\".e^out,dev".parse().unwrap();
+        assert_eq!(
+            installable.flakeref,
+            "github:flox/runix".parse::<FlakeRef>().unwrap()
+        );
+        assert_eq!(
+            installable.attr_path,
+            AttrPath::try_from(["a", "b.c", "e"]).unwrap()
+        );
+        assert_eq!(
+            installable.outputs,
+            InstallableOutputs::Selected(["out", "dev"].map(ToString::to_string).to_vec())
+        );
+
+        assert!(matches!(
+            "flake:xyz#a.\"b^c\"".parse::<FlakeAttribute>(),
+            Err(ParseInstallableError::InvalidAttr(attr)) if attr == "b^c"
+        ));
+        "flake:xyz#a^".parse::<FlakeAttribute>().unwrap_err();
+        "flake:xyz#a^out,".parse::<FlakeAttribute>().unwrap_err();
+        "flake:xyz#a.\"b^out".parse::<FlakeAttribute>().unwrap_err();
+
+        assert!(matches!(
+            "/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-python3-3.10.10"
+                .parse::<Installable>()
+                .unwrap(),
+            Installable::StorePath(_)
+        ));
+        assert!(matches!(
+            "github:flox/runix".parse::<Installable>().unwrap(),
+            Installable::FlakeAttribute(_)
+        ));
+
+        let Installable::FlakeAttribute(installable) = ".^out".parse::<Installable>().unwrap()
+        else {
+            panic!("expected a flake attribute")
+        };
+        assert_eq!(installable.flakeref, ".".parse::<FlakeRef>().unwrap());
+        assert_eq!(
+            installable.outputs,
+            InstallableOutputs::Selected(vec!["out".to_string()])
+        );
+
+        let installable: FlakeAttribute = "github:a/b^out,dev".parse().unwrap();
+        assert_eq!(
+            installable.flakeref,
+            "github:a/b".parse::<FlakeRef>().unwrap()
+        );
+        assert_eq!(installable.to_string(), "github:a/b^out,dev");
+    }
+
+    #[test]
+    fn derivation_outputs() {
+        let drv = "/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-hello-2.12.1.drv";
+
+        let installable: Installable = format!("{drv}^*").parse().unwrap();
+        let Installable::DerivationOutputs(ref derivation) = installable else {
+            panic!("expected derivation outputs")
+        };
+        assert_eq!(derivation.drv_path().to_string(), drv);
+        assert_eq!(installable.outputs(), Some(&InstallableOutputs::All));
+        assert_eq!(installable.to_string(), format!("{drv}^*"));
+
+        let installable = installable
+            .with_outputs(InstallableOutputs::Default)
+            .unwrap();
+        assert!(matches!(installable, Installable::StorePath(_)));
+        assert_eq!(installable.outputs(), None);
+
+        format!("{drv}^").parse::<Installable>().unwrap_err();
+        assert!(matches!(
+            "/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-hello-2.12.1^out".parse::<Installable>(),
+            Err(ParseInstallableError::NotADerivation(_))
+        ));
+        "/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-hello-2.12.1"
+            .parse::<Installable>()
+            .unwrap()
+            .with_outputs(InstallableOutputs::All)
+            .unwrap_err();
+
+        let installable: Installable = "github:flox/runix#runix".parse().unwrap();
+        let installable = installable
+            .with_outputs("^out,dev".parse().unwrap())
+            .unwrap();
+        assert_eq!(installable.to_string(), "github:flox/runix#runix^out,dev");
+
+        "out".parse::<InstallableOutputs>().unwrap_err();
+        "^".parse::<InstallableOutputs>().unwrap_err();
+    }
+
+    #[test]
+    fn expr_installable() {
+        let installable = Installable::Expr {
+            expr: "with import <nixpkgs> {}; hello".to_string(),
+            attr_path: AttrPath::default(),
+        };
+        assert_eq!(installable.to_args(), [
+            "--expr",
+            "with import <nixpkgs> {}; hello",
+            ""
+        ]);
+        assert_eq!(
+            installable.to_string(),
+            "--expr 'with import <nixpkgs> {}; hello' ''"
+        );
+        assert_eq!(installable.outputs(), None);
+        assert!(matches!(
+            installable.with_outputs(InstallableOutputs::All),
+            Err(ParseInstallableError::UnsupportedOutputs(_))
+        ));
+    }
+
+    #[test]
+    fn parse_installable_syntaxes() {
+        let drv = "/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-hello-2.12.1.drv";
+        assert!(matches!(
+            format!("{drv}^out").parse::<Installable>().unwrap(),
+            Installable::DerivationOutputs(_)
+        ));
+        assert!(matches!(
+            drv.parse::<Installable>().unwrap(),
+            Installable::StorePath(_)
+        ));
+        "/nix/store/".parse::<Installable>().unwrap_err();
+
+        for flake in [
+            "flake:nixpkgs",
+            "flake:nixpkgs#hello",
+            "github:flox/runix",
+            "git+https://github.com/flox/runix#hello",
+        ] {
+            let installable = flake.parse::<Installable>().unwrap();
+            assert!(
+                matches!(installable, Installable::FlakeAttribute(_)),
+                "{flake} should parse as flake attribute"
+            );
+        }
+
+        let installable = Installable::from_file_attr("release.nix", "\"a.b\".c").unwrap();
+        assert_eq!(installable.to_args(), [
+            "--file",
+            "release.nix",
+            "\"a.b\".c"
+        ]);
+        Installable::from_expr_attr("{}", "a.\"${b}\"").unwrap_err();
     }
 
     #[test]
@@ -194,26 +194,50 @@
 //! we plan to approach native bindings to Nix commands and concepts.
 
 use std::error::Error;
-use std::path::PathBuf;
+use std::process::ExitStatus;
 
 /// Rust abstraction over the nix command line
 /// Candidate for a standalone library to build arbitrary Nix commands in a safe manner
 use arguments::NixArgs;
 use async_trait::async_trait;
+use log_event::LogEvent;
+use tokio::io::AsyncRead;
 
 pub mod arguments;
+pub mod blocking;
+pub mod build_report;
 pub mod command;
 pub mod command_line;
+pub mod content_address;
+pub mod daemon;
+pub mod derivation;
+pub mod dev_env;
+pub mod expr;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod flake_check;
 pub mod flake_metadata;
+pub mod flake_nix;
 pub mod flake_ref;
+pub mod gcroots;
+pub mod hash;
 pub mod installable;
+pub mod log_event;
+pub mod nar;
 pub mod narinfo;
+pub mod nix_config;
+pub mod nix_error;
+pub mod profile;
+pub mod progress;
 pub mod registry;
+pub mod search;
+pub mod signature;
+#[cfg(feature = "sqlite")]
+pub mod store_db;
+pub mod store_info;
 pub mod store_path;
 pub mod url_parser;
-
-// TODO drop in favor of store_path::StorePath
-pub type DerivationPath = PathBuf;
+pub mod why_depends;
 
 pub use command_line as default;
 use serde_json::Value;
@@ -278,6 +302,109 @@ pub trait RunJson<B: NixBackend>: Run<B> {
     async fn run_json(&self, backend: &B, nix_args: &NixArgs) -> Result<Value, Self::JsonError>;
 }
 
+/// Specialized version of [RunJson] that yields the output as it is parsed
+///
+/// For commands printing a json list or object,
+/// `on_item` is called for every element or entry without buffering the whole document,
+/// e.g. for `nix path-info --json --recursive` on large closures.
+#[async_trait]
+pub trait RunJsonStream<B: NixBackend>: Run<B> {
+    type JsonStreamError: 'static + Error + Send + Sync;
+    async fn run_json_stream(
+        &self,
+        backend: &B,
+        nix_args: &NixArgs,
+        on_item: &mut (dyn FnMut(Value) + Send),
+    ) -> Result<(), Self::JsonStreamError>;
+}
+
+/// Specialized version of [Run] that returns the output as a reader
+///
+/// Output of up to `spill_threshold` bytes is held in memory,
+/// larger outputs are written to a temporary file while the command runs,
+/// e.g. the NAR printed by `nix store dump-path` or evaluations printing gigabytes.
+#[async_trait]
+pub trait RunOutput<B: NixBackend>: Run<B> {
+    type Reader: AsyncRead + Send + Unpin;
+    type OutputError: 'static + Error + Send + Sync;
+    async fn run_output(
+        &self,
+        backend: &B,
+        nix_args: &NixArgs,
+        spill_threshold: usize,
+    ) -> Result<Self::Reader, Self::OutputError>;
+}
+
+/// A line of output of a running command, see [RunStreaming]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputLine {
+    Stdout(String),
+    Stderr(String),
+}
+
+/// Specialized version of [Run] that forwards output lines as they arrive
+///
+/// Instead of buffering or passing through the output of the command,
+/// `on_line` is called for every line printed to stdout or stderr.
+/// Useful to forward the logs of long running builds.
+#[async_trait]
+pub trait RunStreaming<B: NixBackend>: Run<B> {
+    type StreamingError: 'static + Error + Send + Sync;
+    async fn run_streaming(
+        &self,
+        backend: &B,
+        nix_args: &NixArgs,
+        on_line: &mut (dyn FnMut(OutputLine) + Send),
+    ) -> Result<(), Self::StreamingError>;
+}
+
+/// Specialized version of [Run] that reports structured log events
+///
+/// `on_event` is called for every [LogEvent] as it arrives,
+/// e.g. to report the progress of builds and downloads.
+#[async_trait]
+pub trait RunLogged<B: NixBackend>: Run<B> {
+    type LoggedError: 'static + Error + Send + Sync;
+    async fn run_logged(
+        &self,
+        backend: &B,
+        nix_args: &NixArgs,
+        on_event: &mut (dyn FnMut(LogEvent) + Send),
+    ) -> Result<(), Self::LoggedError>;
+}
+
+/// How the command of [RunInteractive] is connected to the terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Terminal {
+    /// Inherit stdin, stdout and stderr of the host process
+    #[default]
+    Inherit,
+    /// Run the command in a new pseudo terminal,
+    /// relaying the input and output of the host process
+    ///
+    /// Programs see a terminal even if the host process is not attached to one.
+    Pty,
+}
+
+/// Specialized version of [Run] for interactive programs
+///
+/// The command is connected to the terminal of the host process without capturing its output,
+/// e.g. to enter a shell with `nix develop`, to use `nix repl`
+/// or to run an interactive program with `nix run`.
+///
+/// Returns the exit status of the command rather than failing if it is unsuccessful,
+/// as it usually reflects the program run by nix, e.g. the last command in a shell.
+#[async_trait]
+pub trait RunInteractive<B: NixBackend>: Run<B> {
+    type InteractiveError: 'static + Error + Send + Sync;
+    async fn run_interactive(
+        &self,
+        backend: &B,
+        nix_args: &NixArgs,
+        terminal: Terminal,
+    ) -> Result<ExitStatus, Self::InteractiveError>;
+}
+
 /// Specialized version of [Run] that guarantees an associated type as output
 #[async_trait]
 pub trait RunTyped<B: NixBackend>: Run<B> {
@@ -0,0 +1,344 @@
+//! Structured nix logs, as printed with `--log-format internal-json`
+//!
+//! With this log format, nix prints one json object per line to stderr,
+//! prefixed with `@nix `.
+//! Objects describe activities (e.g. builds or downloads) starting and stopping,
+//! results of running activities (e.g. progress or build log lines) and plain messages.
+//!
+//! Reference:
+//! [libutil/logging.cc](https://github.com/NixOS/nix/blob/master/src/libutil/logging.cc)
+
+use std::str::FromStr;
+
+use serde::Deserialize;
+use thiserror::Error;
+
+/// Prefix of structured log lines
+pub const LOG_PREFIX: &str = "@nix ";
+
+/// Verbosity level of a log event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[serde(from = "u64")]
+pub enum Verbosity {
+    Error,
+    Warn,
+    Notice,
+    Info,
+    Talkative,
+    Chatty,
+    Debug,
+    Vomit,
+}
+
+impl From<u64> for Verbosity {
+    /// Levels above [Verbosity::Vomit] are treated as [Verbosity::Vomit]
+    fn from(level: u64) -> Self {
+        match level {
+            0 => Verbosity::Error,
+            1 => Verbosity::Warn,
+            2 => Verbosity::Notice,
+            3 => Verbosity::Info,
+            4 => Verbosity::Talkative,
+            5 => Verbosity::Chatty,
+            6 => Verbosity::Debug,
+            _ => Verbosity::Vomit,
+        }
+    }
+}
+
+/// The kind of a started activity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(from = "u64")]
+pub enum ActivityType {
+    Unknown,
+    CopyPath,
+    FileTransfer,
+    Realise,
+    CopyPaths,
+    Builds,
+    Build,
+    OptimiseStore,
+    VerifyPaths,
+    Substitute,
+    QueryPathInfo,
+    PostBuildHook,
+    BuildWaiting,
+    FetchTree,
+    /// An activity type not known to runix
+    Other(u64),
+}
+
+impl From<u64> for ActivityType {
+    fn from(activity: u64) -> Self {
+        match activity {
+            0 => ActivityType::Unknown,
+            100 => ActivityType::CopyPath,
+            101 => ActivityType::FileTransfer,
+            102 => ActivityType::Realise,
+            103 => ActivityType::CopyPaths,
+            104 => ActivityType::Builds,
+            105 => ActivityType::Build,
+            106 => ActivityType::OptimiseStore,
+            107 => ActivityType::VerifyPaths,
+            108 => ActivityType::Substitute,
+            109 => ActivityType::QueryPathInfo,
+            110 => ActivityType::PostBuildHook,
+            111 => ActivityType::BuildWaiting,
+            112 => ActivityType::FetchTree,
+            other => ActivityType::Other(other),
+        }
+    }
+}
+
+/// The kind of a result reported by an activity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(from = "u64")]
+pub enum ResultType {
+    FileLinked,
+    BuildLogLine,
+    UntrustedPath,
+    CorruptedPath,
+    SetPhase,
+    Progress,
+    SetExpected,
+    PostBuildLogLine,
+    FetchStatus,
+    /// A result type not known to runix
+    Other(u64),
+}
+
+impl From<u64> for ResultType {
+    fn from(result: u64) -> Self {
+        match result {
+            100 => ResultType::FileLinked,
+            101 => ResultType::BuildLogLine,
+            102 => ResultType::UntrustedPath,
+            103 => ResultType::CorruptedPath,
+            104 => ResultType::SetPhase,
+            105 => ResultType::Progress,
+            106 => ResultType::SetExpected,
+            107 => ResultType::PostBuildLogLine,
+            108 => ResultType::FetchStatus,
+            other => ResultType::Other(other),
+        }
+    }
+}
+
+/// A field of an activity or result
+///
+/// The meaning of fields depends on the [ActivityType] or [ResultType],
+/// e.g. the store path of a build or the bytes done and expected of a download.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(untagged)]
+pub enum Field {
+    Int(u64),
+    String(String),
+}
+
+impl Field {
+    pub fn as_int(&self) -> Option<u64> {
+        match self {
+            Field::Int(int) => Some(*int),
+            Field::String(_) => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Field::Int(_) => None,
+            Field::String(string) => Some(string),
+        }
+    }
+}
+
+/// Identifier of an activity, unique within a nix invocation
+pub type ActivityId = u64;
+
+/// A structured log event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogEvent {
+    /// An activity was started
+    Start {
+        id: ActivityId,
+        /// The activity this activity is part of, `0` for none
+        parent: ActivityId,
+        level: Verbosity,
+        activity: ActivityType,
+        text: String,
+        fields: Vec<Field>,
+    },
+    /// An activity was stopped
+    Stop { id: ActivityId },
+    /// A running activity reported a result
+    Result {
+        id: ActivityId,
+        result: ResultType,
+        fields: Vec<Field>,
+    },
+    /// A log message
+    Message { level: Verbosity, msg: String },
+    /// An error message
+    ///
+    /// `msg` is formatted for display, including traces and positions,
+    /// `raw_msg` only contains the error itself.
+    Error {
+        msg: String,
+        raw_msg: Option<String>,
+    },
+}
+
+/// The json representation of [LogEvent]s
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "camelCase")]
+enum RawLogEvent {
+    Start {
+        id: ActivityId,
+        #[serde(default)]
+        parent: ActivityId,
+        level: Verbosity,
+        #[serde(rename = "type")]
+        activity: ActivityType,
+        #[serde(default)]
+        text: String,
+        #[serde(default)]
+        fields: Vec<Field>,
+    },
+    Stop {
+        id: ActivityId,
+    },
+    Result {
+        id: ActivityId,
+        #[serde(rename = "type")]
+        result: ResultType,
+        #[serde(default)]
+        fields: Vec<Field>,
+    },
+    Msg {
+        level: Verbosity,
+        msg: String,
+        raw_msg: Option<String>,
+    },
+}
+
+impl From<RawLogEvent> for LogEvent {
+    fn from(raw: RawLogEvent) -> Self {
+        match raw {
+            RawLogEvent::Start {
+                id,
+                parent,
+                level,
+                activity,
+                text,
+                fields,
+            } => LogEvent::Start {
+                id,
+                parent,
+                level,
+                activity,
+                text,
+                fields,
+            },
+            RawLogEvent::Stop { id } => LogEvent::Stop { id },
+            RawLogEvent::Result { id, result, fields } => LogEvent::Result { id, result, fields },
+            RawLogEvent::Msg {
+                level: Verbosity::Error,
+                msg,
+                raw_msg,
+            } => LogEvent::Error { msg, raw_msg },
+            RawLogEvent::Msg { level, msg, .. } => LogEvent::Message { level, msg },
+        }
+    }
+}
+
+impl FromStr for LogEvent {
+    type Err = ParseLogEventError;
+
+    /// Parse a line printed by nix with `--log-format internal-json`
+    ///
+    /// ```
+    /// # use runix::log_event::{ActivityType, LogEvent};
+    /// let event: LogEvent = r#"@nix {"action":"start","id":1,"level":3,"parent":0,"text":"building '/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-hello-2.12.1.drv'","type":105,"fields":["/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-hello-2.12.1.drv","",1,1]}"#
+    ///     .parse()
+    ///     .unwrap();
+    /// assert!(matches!(event, LogEvent::Start {
+    ///     activity: ActivityType::Build,
+    ///     ..
+    /// }));
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let json = s
+            .strip_prefix(LOG_PREFIX)
+            .ok_or_else(|| ParseLogEventError::NotALogEvent(s.to_string()))?;
+        Ok(serde_json::from_str::<RawLogEvent>(json)?.into())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ParseLogEventError {
+    #[error("Not a structured log line: '{0}'")]
+    NotALogEvent(String),
+    #[error("Invalid log event: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_log_events() {
+        let events = [
+            r#"@nix {"action":"start","id":2,"level":4,"parent":1,"text":"downloading 'https://cache.nixos.org/nar/x.nar.xz'","type":101,"fields":["https://cache.nixos.org/nar/x.nar.xz"]}"#,
+            r#"@nix {"action":"result","id":2,"type":105,"fields":[1024,4096,0,0]}"#,
+            r#"@nix {"action":"result","id":3,"type":104,"fields":["buildPhase"]}"#,
+            r#"@nix {"action":"stop","id":2}"#,
+            r#"@nix {"action":"msg","level":1,"msg":"warning: Git tree is dirty"}"#,
+            r#"@nix {"action":"msg","level":0,"msg":"error: builder failed","raw_msg":"builder failed"}"#,
+            r#"@nix {"action":"start","id":4,"level":0,"type":999}"#,
+        ]
+        .map(|line| line.parse::<LogEvent>().unwrap());
+
+        assert_eq!(events[0], LogEvent::Start {
+            id: 2,
+            parent: 1,
+            level: Verbosity::Talkative,
+            activity: ActivityType::FileTransfer,
+            text: "downloading 'https://cache.nixos.org/nar/x.nar.xz'".to_string(),
+            fields: vec![Field::String(
+                "https://cache.nixos.org/nar/x.nar.xz".to_string()
+            )],
+        });
+        assert_eq!(events[1], LogEvent::Result {
+            id: 2,
+            result: ResultType::Progress,
+            fields: [1024, 4096, 0, 0].map(Field::Int).to_vec(),
+        });
+        assert!(matches!(&events[2], LogEvent::Result {
+            result: ResultType::SetPhase,
+            fields,
+            ..
+        } if fields[0].as_str() == Some("buildPhase")));
+        assert_eq!(events[3], LogEvent::Stop { id: 2 });
+        assert_eq!(events[4], LogEvent::Message {
+            level: Verbosity::Warn,
+            msg: "warning: Git tree is dirty".to_string()
+        });
+        assert_eq!(events[5], LogEvent::Error {
+            msg: "error: builder failed".to_string(),
+            raw_msg: Some("builder failed".to_string())
+        });
+        assert!(matches!(events[6], LogEvent::Start {
+            activity: ActivityType::Other(999),
+            ..
+        }));
+
+        assert!(matches!(
+            "building...".parse::<LogEvent>(),
+            Err(ParseLogEventError::NotALogEvent(_))
+        ));
+        assert!(matches!(
+            r#"@nix {"action":"unknown"}"#.parse::<LogEvent>(),
+            Err(ParseLogEventError::Json(_))
+        ));
+    }
+}
@@ -0,0 +1,571 @@
+//! The NAR (Nix ARchive) format, in which nix serializes store paths
+//!
+//! Unlike tar, NARs are canonical: they only record file contents, whether files are executable,
+//! symlink targets and directory entries sorted by name, so equal trees serialize to equal bytes.
+//! [dump] and [restore] stream between a filesystem and NAR bytes,
+//! [NarNode] holds a tree in memory, e.g. to inspect NARs downloaded from a binary cache.
+//!
+//! ```
+//! # use std::collections::BTreeMap;
+//! # use runix::nar::NarNode;
+//! let tree = NarNode::Directory(BTreeMap::from([
+//!     ("hello".to_string(), NarNode::Regular {
+//!         executable: true,
+//!         contents: b"#!/bin/sh\necho hello\n".to_vec(),
+//!     }),
+//!     ("greet".to_string(), NarNode::Symlink {
+//!         target: "hello".to_string(),
+//!     }),
+//! ]));
+//!
+//! let mut nar = Vec::new();
+//! tree.encode(&mut nar).unwrap();
+//! assert_eq!(NarNode::decode(nar.as_slice()).unwrap(), tree);
+//! ```
+//!
+//! Symlink targets and entry names are restricted to UTF-8.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::Path;
+
+use thiserror::Error;
+
+/// The magic string NARs start with
+const NAR_VERSION_MAGIC: &str = "nix-archive-1";
+
+/// Upper bound of the length of tokens, names and symlink targets
+const MAX_TOKEN_LENGTH: u64 = 4096;
+
+#[derive(Debug, Error)]
+pub enum NarError {
+    #[error("Could not read or write the NAR: {0}")]
+    Io(#[from] io::Error),
+    #[error("Invalid NAR: expected {expected}, found '{found}'")]
+    UnexpectedToken {
+        expected: &'static str,
+        found: String,
+    },
+    #[error("Invalid NAR: invalid entry name '{0}'")]
+    InvalidName(String),
+    #[error("Invalid NAR: entries not sorted by name at '{0}'")]
+    UnsortedEntries(String),
+    #[error("Unsupported file type at '{0}'")]
+    UnsupportedFileType(String),
+}
+
+/// A file system object serialized in a NAR
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NarNode {
+    Regular {
+        executable: bool,
+        contents: Vec<u8>,
+    },
+    Symlink {
+        target: String,
+    },
+    /// Entries by name
+    Directory(BTreeMap<String, NarNode>),
+}
+
+impl NarNode {
+    /// Read the tree at `path` into memory, without following a symlink at `path`
+    pub fn read_path(path: impl AsRef<Path>) -> Result<Self, NarError> {
+        let path = path.as_ref();
+        let metadata = fs::symlink_metadata(path)?;
+        let file_type = metadata.file_type();
+
+        let node = if file_type.is_file() {
+            NarNode::Regular {
+                executable: is_executable(&metadata),
+                contents: fs::read(path)?,
+            }
+        } else if file_type.is_symlink() {
+            NarNode::Symlink {
+                target: symlink_target(path)?,
+            }
+        } else if file_type.is_dir() {
+            let mut entries = BTreeMap::new();
+            for entry in fs::read_dir(path)? {
+                let entry = entry?;
+                let name = entry_name(&entry)?;
+                entries.insert(name, NarNode::read_path(entry.path())?);
+            }
+            NarNode::Directory(entries)
+        } else {
+            Err(NarError::UnsupportedFileType(path.display().to_string()))?
+        };
+        Ok(node)
+    }
+
+    /// Create the tree at `path`, which must not exist yet
+    pub fn write_path(&self, path: impl AsRef<Path>) -> Result<(), NarError> {
+        let path = path.as_ref();
+        match self {
+            NarNode::Regular {
+                executable,
+                contents,
+            } => {
+                fs::write(path, contents)?;
+                set_executable(path, *executable)?;
+            },
+            NarNode::Symlink { target } => symlink(target, path)?,
+            NarNode::Directory(entries) => {
+                fs::create_dir(path)?;
+                for (name, node) in entries {
+                    check_name(name)?;
+                    node.write_path(path.join(name))?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Serialize the tree as NAR to `writer`
+    pub fn encode(&self, mut writer: impl Write) -> Result<(), NarError> {
+        write_str(&mut writer, NAR_VERSION_MAGIC)?;
+        self.encode_node(&mut writer)
+    }
+
+    fn encode_node(&self, writer: &mut impl Write) -> Result<(), NarError> {
+        write_str(writer, "(")?;
+        write_str(writer, "type")?;
+        match self {
+            NarNode::Regular {
+                executable,
+                contents,
+            } => {
+                write_str(writer, "regular")?;
+                if *executable {
+                    write_str(writer, "executable")?;
+                    write_str(writer, "")?;
+                }
+                write_str(writer, "contents")?;
+                write_bytes(writer, contents)?;
+            },
+            NarNode::Symlink { target } => {
+                write_str(writer, "symlink")?;
+                write_str(writer, "target")?;
+                write_str(writer, target)?;
+            },
+            NarNode::Directory(entries) => {
+                write_str(writer, "directory")?;
+                for (name, node) in entries {
+                    check_name(name)?;
+                    write_str(writer, "entry")?;
+                    write_str(writer, "(")?;
+                    write_str(writer, "name")?;
+                    write_str(writer, name)?;
+                    write_str(writer, "node")?;
+                    node.encode_node(writer)?;
+                    write_str(writer, ")")?;
+                }
+            },
+        }
+        write_str(writer, ")")?;
+        Ok(())
+    }
+
+    /// Parse a NAR read from `reader` into memory
+    pub fn decode(mut reader: impl Read) -> Result<Self, NarError> {
+        expect(&mut reader, NAR_VERSION_MAGIC)?;
+        decode_node(&mut reader, &mut MemorySink)
+    }
+}
+
+/// Serialize the tree at `path` as NAR to `writer`, streaming file contents
+pub fn dump(path: impl AsRef<Path>, mut writer: impl Write) -> Result<(), NarError> {
+    write_str(&mut writer, NAR_VERSION_MAGIC)?;
+    dump_node(path.as_ref(), &mut writer)
+}
+
+fn dump_node(path: &Path, writer: &mut impl Write) -> Result<(), NarError> {
+    let metadata = fs::symlink_metadata(path)?;
+    let file_type = metadata.file_type();
+
+    write_str(writer, "(")?;
+    write_str(writer, "type")?;
+    if file_type.is_file() {
+        write_str(writer, "regular")?;
+        if is_executable(&metadata) {
+            write_str(writer, "executable")?;
+            write_str(writer, "")?;
+        }
+        write_str(writer, "contents")?;
+        let len = metadata.len();
+        write_u64(writer, len)?;
+        let copied = io::copy(&mut fs::File::open(path)?.take(len), writer)?;
+        if copied != len {
+            Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("'{}' changed while reading it", path.display()),
+            ))?
+        }
+        write_padding(writer, len)?;
+    } else if file_type.is_symlink() {
+        write_str(writer, "symlink")?;
+        write_str(writer, "target")?;
+        write_str(writer, &symlink_target(path)?)?;
+    } else if file_type.is_dir() {
+        write_str(writer, "directory")?;
+        let mut entries = BTreeMap::new();
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            entries.insert(entry_name(&entry)?, entry.path());
+        }
+        for (name, path) in entries {
+            write_str(writer, "entry")?;
+            write_str(writer, "(")?;
+            write_str(writer, "name")?;
+            write_str(writer, &name)?;
+            write_str(writer, "node")?;
+            dump_node(&path, writer)?;
+            write_str(writer, ")")?;
+        }
+    } else {
+        Err(NarError::UnsupportedFileType(path.display().to_string()))?
+    }
+    write_str(writer, ")")?;
+    Ok(())
+}
+
+/// Create the tree serialized as NAR in `reader` at `path`, which must not exist yet
+///
+/// File contents are streamed to disk rather than held in memory.
+pub fn restore(mut reader: impl Read, path: impl AsRef<Path>) -> Result<(), NarError> {
+    expect(&mut reader, NAR_VERSION_MAGIC)?;
+    decode_node(&mut reader, &mut FsSink(path.as_ref().to_path_buf()))
+}
+
+/// The next entry of a directory, [None] after the last one
+type NextEntry<T> = Result<Option<(String, T)>, NarError>;
+
+/// Where [decode_node] puts the decoded file system objects
+trait Sink {
+    type Output;
+
+    fn regular(
+        &mut self,
+        executable: bool,
+        contents: &mut dyn Read,
+        len: u64,
+    ) -> Result<Self::Output, NarError>;
+    fn symlink(&mut self, target: String) -> Result<Self::Output, NarError>;
+    fn directory(
+        &mut self,
+        entries: &mut dyn FnMut(&mut Self) -> NextEntry<Self::Output>,
+    ) -> Result<Self::Output, NarError>;
+    fn entry(&mut self, name: &str) -> Self;
+}
+
+/// Decodes into a [NarNode]
+struct MemorySink;
+
+impl Sink for MemorySink {
+    type Output = NarNode;
+
+    fn regular(
+        &mut self,
+        executable: bool,
+        contents: &mut dyn Read,
+        len: u64,
+    ) -> Result<NarNode, NarError> {
+        let mut buf = Vec::new();
+        contents.take(len).read_to_end(&mut buf)?;
+        Ok(NarNode::Regular {
+            executable,
+            contents: buf,
+        })
+    }
+
+    fn symlink(&mut self, target: String) -> Result<NarNode, NarError> {
+        Ok(NarNode::Symlink { target })
+    }
+
+    fn directory(
+        &mut self,
+        entries: &mut dyn FnMut(&mut Self) -> NextEntry<NarNode>,
+    ) -> Result<NarNode, NarError> {
+        let mut nodes = BTreeMap::new();
+        while let Some((name, node)) = entries(self)? {
+            nodes.insert(name, node);
+        }
+        Ok(NarNode::Directory(nodes))
+    }
+
+    fn entry(&mut self, _name: &str) -> Self {
+        MemorySink
+    }
+}
+
+/// Decodes onto the filesystem at the contained path
+struct FsSink(std::path::PathBuf);
+
+impl Sink for FsSink {
+    type Output = ();
+
+    fn regular(
+        &mut self,
+        executable: bool,
+        contents: &mut dyn Read,
+        len: u64,
+    ) -> Result<(), NarError> {
+        let mut file = fs::File::create_new(&self.0)?;
+        let copied = io::copy(&mut contents.take(len), &mut file)?;
+        if copied != len {
+            Err(io::Error::from(io::ErrorKind::UnexpectedEof))?
+        }
+        set_executable(&self.0, executable)?;
+        Ok(())
+    }
+
+    fn symlink(&mut self, target: String) -> Result<(), NarError> {
+        Ok(symlink(target, &self.0)?)
+    }
+
+    fn directory(
+        &mut self,
+        entries: &mut dyn FnMut(&mut Self) -> NextEntry<()>,
+    ) -> Result<(), NarError> {
+        fs::create_dir(&self.0)?;
+        while entries(self)?.is_some() {}
+        Ok(())
+    }
+
+    fn entry(&mut self, name: &str) -> Self {
+        FsSink(self.0.join(name))
+    }
+}
+
+fn decode_node<S: Sink>(reader: &mut impl Read, sink: &mut S) -> Result<S::Output, NarError> {
+    expect(reader, "(")?;
+    expect(reader, "type")?;
+    let output = match read_token(reader)?.as_str() {
+        "regular" => {
+            let mut token = read_token(reader)?;
+            let executable = token == "executable";
+            if executable {
+                expect(reader, "")?;
+                token = read_token(reader)?;
+            }
+            if token != "contents" {
+                return Err(unexpected("contents", token));
+            }
+            let len = read_u64(reader)?;
+            let output = sink.regular(executable, reader, len)?;
+            read_padding(reader, len)?;
+            output
+        },
+        "symlink" => {
+            expect(reader, "target")?;
+            sink.symlink(read_token(reader)?)?
+        },
+        "directory" => {
+            let mut previous: Option<String> = None;
+            let mut entries = |sink: &mut S| {
+                match read_token(reader)?.as_str() {
+                    "entry" => {},
+                    ")" => return Ok(None),
+                    token => return Err(unexpected("entry", token.to_string())),
+                }
+                expect(reader, "(")?;
+                expect(reader, "name")?;
+                let name = read_token(reader)?;
+                check_name(&name)?;
+                if previous.as_ref().is_some_and(|previous| *previous >= name) {
+                    return Err(NarError::UnsortedEntries(name));
+                }
+                expect(reader, "node")?;
+                let node = decode_node(reader, &mut sink.entry(&name))?;
+                expect(reader, ")")?;
+                previous = Some(name.clone());
+                Ok(Some((name, node)))
+            };
+            // the closing parenthesis of the directory is consumed by `entries`
+            return sink.directory(&mut entries);
+        },
+        token => return Err(unexpected("file type", token.to_string())),
+    };
+    expect(reader, ")")?;
+    Ok(output)
+}
+
+/// Entry names may not be empty, `.` or `..` and may not contain `/` or NUL
+fn check_name(name: &str) -> Result<(), NarError> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\0']) {
+        return Err(NarError::InvalidName(name.to_string()));
+    }
+    Ok(())
+}
+
+fn is_executable(metadata: &fs::Metadata) -> bool {
+    metadata.permissions().mode() & 0o100 != 0
+}
+
+fn set_executable(path: &Path, executable: bool) -> io::Result<()> {
+    if executable {
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
+
+fn symlink_target(path: &Path) -> Result<String, NarError> {
+    let target = fs::read_link(path)?;
+    target
+        .to_str()
+        .map(ToString::to_string)
+        .ok_or_else(|| NarError::UnsupportedFileType(path.display().to_string()))
+}
+
+fn entry_name(entry: &fs::DirEntry) -> Result<String, NarError> {
+    entry
+        .file_name()
+        .into_string()
+        .map_err(|name| NarError::InvalidName(name.to_string_lossy().into_owned()))
+}
+
+fn unexpected(expected: &'static str, found: String) -> NarError {
+    NarError::UnexpectedToken { expected, found }
+}
+
+fn padding(len: u64) -> usize {
+    ((8 - len % 8) % 8) as usize
+}
+
+fn write_u64(writer: &mut impl Write, n: u64) -> io::Result<()> {
+    writer.write_all(&n.to_le_bytes())
+}
+
+fn write_padding(writer: &mut impl Write, len: u64) -> io::Result<()> {
+    writer.write_all(&[0; 8][..padding(len)])
+}
+
+fn write_bytes(writer: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    write_u64(writer, bytes.len() as u64)?;
+    writer.write_all(bytes)?;
+    write_padding(writer, bytes.len() as u64)
+}
+
+fn write_str(writer: &mut impl Write, s: &str) -> io::Result<()> {
+    write_bytes(writer, s.as_bytes())
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_padding(reader: &mut impl Read, len: u64) -> Result<(), NarError> {
+    let mut buf = [0; 8];
+    let padding = &mut buf[..padding(len)];
+    reader.read_exact(padding)?;
+    if padding.iter().any(|&b| b != 0) {
+        return Err(unexpected("zero padding", format!("{padding:?}")));
+    }
+    Ok(())
+}
+
+/// Read a string that is not file contents, e.g. a keyword or a name
+fn read_token(reader: &mut impl Read) -> Result<String, NarError> {
+    let len = read_u64(reader)?;
+    if len > MAX_TOKEN_LENGTH {
+        return Err(unexpected("token", format!("<{len} bytes>")));
+    }
+    let mut buf = vec![0; len as usize];
+    reader.read_exact(&mut buf)?;
+    read_padding(reader, len)?;
+    String::from_utf8(buf).map_err(|e| unexpected("UTF-8 token", e.to_string()))
+}
+
+fn expect(reader: &mut impl Read, expected: &'static str) -> Result<(), NarError> {
+    let token = read_token(reader)?;
+    if token != expected {
+        return Err(unexpected(expected, token));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_regular_file() {
+        let mut nar = Vec::new();
+        NarNode::Regular {
+            executable: false,
+            contents: b"hi".to_vec(),
+        }
+        .encode(&mut nar)
+        .unwrap();
+
+        let mut expected = Vec::new();
+        for token in [
+            NAR_VERSION_MAGIC,
+            "(",
+            "type",
+            "regular",
+            "contents",
+            "hi",
+            ")",
+        ] {
+            write_str(&mut expected, token).unwrap();
+        }
+        assert_eq!(nar, expected);
+        assert_eq!(nar.len() % 8, 0);
+    }
+
+    #[test]
+    fn dumps_and_restores_trees() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        fs::create_dir_all(source.join("bin")).unwrap();
+        fs::write(source.join("bin/hello"), "#!/bin/sh\necho hello\n").unwrap();
+        fs::set_permissions(source.join("bin/hello"), fs::Permissions::from_mode(0o755)).unwrap();
+        fs::write(source.join("README"), "").unwrap();
+        symlink("bin/hello", source.join("hello")).unwrap();
+
+        let mut nar = Vec::new();
+        dump(&source, &mut nar).unwrap();
+
+        let tree = NarNode::read_path(&source).unwrap();
+        let mut encoded = Vec::new();
+        tree.encode(&mut encoded).unwrap();
+        assert_eq!(encoded, nar);
+        assert_eq!(NarNode::decode(nar.as_slice()).unwrap(), tree);
+
+        let restored = dir.path().join("restored");
+        restore(nar.as_slice(), &restored).unwrap();
+        assert_eq!(NarNode::read_path(&restored).unwrap(), tree);
+        assert_eq!(
+            fs::read_link(restored.join("hello")).unwrap(),
+            Path::new("bin/hello")
+        );
+
+        let written = dir.path().join("written");
+        tree.write_path(&written).unwrap();
+        assert_eq!(NarNode::read_path(&written).unwrap(), tree);
+    }
+
+    #[test]
+    fn rejects_invalid_nars() {
+        let mut nar = Vec::new();
+        for token in [NAR_VERSION_MAGIC, "(", "type", "directory", "entry", "("] {
+            write_str(&mut nar, token).unwrap();
+        }
+        write_str(&mut nar, "name").unwrap();
+        write_str(&mut nar, "..").unwrap();
+        assert!(matches!(
+            NarNode::decode(nar.as_slice()),
+            Err(NarError::InvalidName(name)) if name == ".."
+        ));
+
+        assert!(matches!(
+            NarNode::decode(&b"not a nar"[..]),
+            Err(NarError::UnexpectedToken { .. })
+        ));
+    }
+}
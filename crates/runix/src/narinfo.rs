@@ -1,24 +1,459 @@
 use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::Path;
+use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use thiserror::Error;
 
-use crate::DerivationPath;
+use crate::flake_ref::lock::NarHash;
+use crate::hash::HashFormat;
+use crate::signature::{self, FingerprintError, PublicKey, SecretKey};
+use crate::store_path::{DrvPath, StorePath, StorePathError};
+
+/// The compression of NARs in binary caches that do not specify one
+const DEFAULT_COMPRESSION: &str = "bzip2";
+
+/// The deriver written for paths whose deriver is not known
+const UNKNOWN_DERIVER: &str = "unknown-deriver";
 
 fn default_true() -> bool {
     true
 }
 
 /// Narinfo stores information output by `nix path-info --json`
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
 pub struct Narinfo {
-    pub path: DerivationPath,
+    pub path: StorePath,
     // TODO remove this
     // https://github.com/NixOS/nix/pull/7924 made it into 2.15.0, but keep this
     // a bit longer to support older Nix versions
     #[serde(default = "default_true")]
     pub valid: bool,
-    // TODO add other fields
+    /// Hash of the serialized path, absent for invalid paths
+    pub nar_hash: Option<NarHash>,
+    /// Size of the serialized path in bytes, absent for invalid paths
+    pub nar_size: Option<u64>,
+    /// Store paths referenced by the path
+    #[serde(default)]
+    pub references: Vec<StorePath>,
+    /// Sum of the nar sizes of the path's closure
+    ///
+    /// Only present if `--closure-size` is passed
+    pub closure_size: Option<u64>,
+    /// Signatures of the path, e.g. `cache.nixos.org-1:<base64>`
+    #[serde(default)]
+    pub sigs: Vec<String>,
+    /// The derivation that produced the path, if known
+    pub deriver: Option<DrvPath>,
+    /// Unix time at which the path was registered in the store
+    pub registration_time: Option<i64>,
+    /// Whether the path was built locally rather than copied from another store
+    #[serde(default)]
+    pub ultimate: bool,
+    /// Content address of the path for content-addressed paths
+    pub ca: Option<String>,
+    /// Hash of the compressed nar in a binary cache
+    ///
+    /// Only present for paths in remote stores
+    pub download_hash: Option<NarHash>,
+    /// Size of the compressed nar in a binary cache in bytes
+    ///
+    /// Only present for paths in remote stores
+    pub download_size: Option<u64>,
     #[serde(flatten)]
-    _other: HashMap<String, Value>,
+    pub(crate) _other: HashMap<String, Value>,
+}
+
+impl Narinfo {
+    /// The data signed by [Narinfo::sigs],
+    /// [None] for invalid paths and NAR hashes other than sha256
+    pub fn fingerprint(&self) -> Option<String> {
+        signature::fingerprint(
+            &self.path,
+            self.nar_hash.as_ref()?,
+            self.nar_size?,
+            &self.references,
+        )
+        .ok()
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ParseNarinfoError {
+    #[error("Invalid narinfo line '{0}', expected 'Key: value'")]
+    InvalidLine(String),
+    #[error("Narinfo is missing {0}")]
+    MissingField(&'static str),
+    #[error("Invalid {field} in narinfo: '{value}'")]
+    InvalidValue { field: &'static str, value: String },
+    #[error("Invalid store path in narinfo: {0}")]
+    StorePath(#[from] StorePathError),
+}
+
+/// The `.narinfo` files served by binary caches, describing a store path and its NAR
+///
+/// Unlike [Narinfo], references and the deriver are written relative to the store directory.
+///
+/// ```
+/// # use runix::narinfo::NarinfoFile;
+/// let narinfo: NarinfoFile = "\
+/// StorePath: /nix/store/sbldylj3clbkc0aqvjjzfa6slp4zdvlj-hello-2.12.1
+/// URL: nar/1mk1amnrf7i7lqq7gwyh62m0ngr8dcfdgb4d7jcmp6gy3s8r28hz.nar.xz
+/// Compression: xz
+/// FileHash: sha256:1mk1amnrf7i7lqq7gwyh62m0ngr8dcfdgb4d7jcmp6gy3s8r28hz
+/// FileSize: 56196
+/// NarHash: sha256:0c6sgmk4pimf1qn9vycqbafphh0b4a3dyvcswjpy48rcx3qqbhgw
+/// NarSize: 226560
+/// References: sbldylj3clbkc0aqvjjzfa6slp4zdvlj-hello-2.12.1
+/// Deriver: 1k6ymb5x2x6i5ys5zvmwbwl4xkr1b2jf-hello-2.12.1.drv
+/// Sig: cache.nixos.org-1:ahW5ZXBq7+cJFnHSb9ZVCdWNbnUhd6w8n8NzKPpfx4qIfPLbUuWqHP7zF5a7ZwKTNqKxZ/hT9CdVrS2c9j+aDQ==
+/// "
+/// .parse()
+/// .unwrap();
+///
+/// assert_eq!(narinfo.compression, "xz");
+/// assert_eq!(narinfo.references, [narinfo.store_path.clone()]);
+/// assert_eq!(narinfo.to_string().parse::<NarinfoFile>().unwrap(), narinfo);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NarinfoFile {
+    pub store_path: StorePath,
+    /// The location of the compressed NAR, relative to the binary cache
+    pub url: String,
+    /// The compression of the NAR, e.g. `xz`, `zstd` or `none`
+    pub compression: String,
+    /// Hash of the compressed NAR
+    pub file_hash: Option<NarHash>,
+    /// Size of the compressed NAR in bytes
+    pub file_size: Option<u64>,
+    pub nar_hash: NarHash,
+    pub nar_size: u64,
+    pub references: Vec<StorePath>,
+    pub deriver: Option<DrvPath>,
+    /// The system the path was built for, written by older versions of nix
+    pub system: Option<String>,
+    /// Signatures of the path, e.g. `cache.nixos.org-1:<base64>`
+    pub sigs: Vec<String>,
+    /// Content address of the path for content-addressed paths
+    pub ca: Option<String>,
+}
+
+impl NarinfoFile {
+    /// The data signed by [NarinfoFile::sigs], see [signature::fingerprint]
+    pub fn fingerprint(&self) -> Result<String, FingerprintError> {
+        signature::fingerprint(
+            &self.store_path,
+            &self.nar_hash,
+            self.nar_size,
+            &self.references,
+        )
+    }
+
+    /// Add a signature made with `key`, replacing previous signatures by keys of the same name
+    pub fn sign(&mut self, key: &SecretKey) -> Result<(), FingerprintError> {
+        let sig = key.sign(&self.fingerprint()?);
+        let prefix = format!("{}:", key.name);
+        self.sigs.retain(|sig| !sig.starts_with(&prefix));
+        self.sigs.push(sig);
+        Ok(())
+    }
+
+    /// Whether any of the signatures was made by one of the `trusted` keys
+    ///
+    /// Paths with NAR hashes other than sha256 have no valid signatures.
+    ///
+    /// ```
+    /// # use runix::narinfo::NarinfoFile;
+    /// # use runix::signature::SecretKey;
+    /// let mut narinfo: NarinfoFile = "\
+    /// StorePath: /nix/store/sbldylj3clbkc0aqvjjzfa6slp4zdvlj-hello-2.12.1
+    /// URL: nar/1mk1amnrf7i7lqq7gwyh62m0ngr8dcfdgb4d7jcmp6gy3s8r28hz.nar.xz
+    /// NarHash: sha256:0c6sgmk4pimf1qn9vycqbafphh0b4a3dyvcswjpy48rcx3qqbhgw
+    /// NarSize: 226560
+    /// References: sbldylj3clbkc0aqvjjzfa6slp4zdvlj-hello-2.12.1
+    /// "
+    /// .parse()
+    /// .unwrap();
+    /// let key: SecretKey = "cache.example.org-1:\
+    ///     xFs6r1S3lsqZEi3MpiKMfUCz8yB2+SnHIRb74QCJCCry02vbNAhm8hKU6QQvA+0ZPNdLO6fa9zEj6hEa0I4G+Q=="
+    ///     .parse()
+    ///     .unwrap();
+    ///
+    /// assert!(!narinfo.verify(&[key.public_key()]));
+    /// narinfo.sign(&key).unwrap();
+    /// assert!(narinfo.verify(&[key.public_key()]));
+    ///
+    /// narinfo.nar_size += 1;
+    /// assert!(!narinfo.verify(&[key.public_key()]));
+    /// ```
+    pub fn verify(&self, trusted: &[PublicKey]) -> bool {
+        let Ok(fingerprint) = self.fingerprint() else {
+            return false;
+        };
+        self.sigs
+            .iter()
+            .any(|sig| trusted.iter().any(|key| key.verify(&fingerprint, sig)))
+    }
+}
+
+impl FromStr for NarinfoFile {
+    type Err = ParseNarinfoError;
+
+    /// Parse a narinfo, resolving references in the store directory of its `StorePath`
+    ///
+    /// Unknown keys are ignored.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields: HashMap<&str, &str> = HashMap::new();
+        let mut sigs = Vec::new();
+        for line in s.lines().filter(|line| !line.is_empty()) {
+            let (key, value) = line
+                .split_once(':')
+                .ok_or_else(|| ParseNarinfoError::InvalidLine(line.to_string()))?;
+            let value = value.trim_start();
+            match key {
+                "Sig" => sigs.push(value.to_string()),
+                key => {
+                    fields.insert(key, value);
+                },
+            }
+        }
+        let required = |field: &'static str| {
+            fields
+                .get(field)
+                .copied()
+                .ok_or(ParseNarinfoError::MissingField(field))
+        };
+
+        let store_path = required("StorePath")?;
+        let store_dir = Path::new(store_path)
+            .parent()
+            .ok_or_else(|| invalid("StorePath", store_path))?;
+        let store_path = StorePath::from_path_in(store_dir, store_path)?;
+        let in_store =
+            |basename: &str| StorePath::from_path_in(store_dir, store_dir.join(basename));
+
+        Ok(NarinfoFile {
+            url: required("URL")?.to_string(),
+            compression: fields
+                .get("Compression")
+                .unwrap_or(&DEFAULT_COMPRESSION)
+                .to_string(),
+            file_hash: fields
+                .get("FileHash")
+                .map(|hash| parse_value("FileHash", hash))
+                .transpose()?,
+            file_size: fields
+                .get("FileSize")
+                .map(|size| parse_value("FileSize", size))
+                .transpose()?,
+            nar_hash: parse_value("NarHash", required("NarHash")?)?,
+            nar_size: parse_value("NarSize", required("NarSize")?)?,
+            references: fields
+                .get("References")
+                .map_or("", |references| references)
+                .split_whitespace()
+                .map(in_store)
+                .collect::<Result<_, _>>()?,
+            deriver: match fields.get("Deriver") {
+                None | Some(&UNKNOWN_DERIVER) => None,
+                Some(deriver) => Some(in_store(deriver)?.try_into()?),
+            },
+            system: fields.get("System").map(ToString::to_string),
+            sigs,
+            ca: fields.get("CA").map(ToString::to_string),
+            store_path,
+        })
+    }
+}
+
+impl Display for NarinfoFile {
+    /// Write the narinfo in the order nix writes it
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "StorePath: {}", self.store_path)?;
+        writeln!(f, "URL: {}", self.url)?;
+        writeln!(f, "Compression: {}", self.compression)?;
+        if let Some(ref file_hash) = self.file_hash {
+            writeln!(f, "FileHash: {}", nix_hash(file_hash))?;
+        }
+        if let Some(file_size) = self.file_size {
+            writeln!(f, "FileSize: {file_size}")?;
+        }
+        writeln!(f, "NarHash: {}", nix_hash(&self.nar_hash))?;
+        writeln!(f, "NarSize: {}", self.nar_size)?;
+        let references: Vec<_> = self.references.iter().map(StorePath::basename).collect();
+        writeln!(f, "References: {}", references.join(" "))?;
+        if let Some(ref deriver) = self.deriver {
+            writeln!(f, "Deriver: {}", deriver.basename())?;
+        }
+        if let Some(ref system) = self.system {
+            writeln!(f, "System: {system}")?;
+        }
+        for sig in &self.sigs {
+            writeln!(f, "Sig: {sig}")?;
+        }
+        if let Some(ref ca) = self.ca {
+            writeln!(f, "CA: {ca}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The information about the path in the format of `nix path-info --json`
+impl From<NarinfoFile> for Narinfo {
+    fn from(narinfo: NarinfoFile) -> Self {
+        Narinfo {
+            path: narinfo.store_path,
+            valid: true,
+            nar_hash: Some(narinfo.nar_hash),
+            nar_size: Some(narinfo.nar_size),
+            references: narinfo.references,
+            closure_size: None,
+            sigs: narinfo.sigs,
+            deriver: narinfo.deriver,
+            registration_time: None,
+            ultimate: false,
+            ca: narinfo.ca,
+            download_hash: narinfo.file_hash,
+            download_size: narinfo.file_size,
+            _other: HashMap::from([
+                ("url".to_string(), Value::String(narinfo.url)),
+                (
+                    "compression".to_string(),
+                    Value::String(narinfo.compression),
+                ),
+            ]),
+        }
+    }
+}
+
+/// The `<algorithm>:<nix base32>` representation of hashes in narinfos
+fn nix_hash(hash: &NarHash) -> String {
+    hash.hash().format(HashFormat::NixBase32)
+}
+
+fn parse_value<T: FromStr>(field: &'static str, value: &str) -> Result<T, ParseNarinfoError> {
+    value.parse().map_err(|_| invalid(field, value))
+}
+
+fn invalid(field: &'static str, value: &str) -> ParseNarinfoError {
+    ParseNarinfoError::InvalidValue {
+        field,
+        value: value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_path_info() {
+        let narinfo_list: Vec<Narinfo> = serde_json::from_str(
+            r#"
+[
+  {
+    "ca": null,
+    "closureSize": 31245680,
+    "deriver": "/nix/store/1k6ymb5x2x6i5ys5zvmwbwl4xkr1b2jf-hello-2.12.1.drv",
+    "narHash": "sha256-GGaJUxHHQ3Pw8dAQNG5/vXQVJ8KrUbTfI1wSXoQmQBk=",
+    "narSize": 226560,
+    "path": "/nix/store/sbldylj3clbkc0aqvjjzfa6slp4zdvlj-hello-2.12.1",
+    "references": [
+      "/nix/store/sbldylj3clbkc0aqvjjzfa6slp4zdvlj-hello-2.12.1"
+    ],
+    "registrationTime": 1688730350,
+    "signatures": [],
+    "sigs": [
+      "cache.nixos.org-1:ahW5ZXBq7+cJFnHSb9ZVCdWNbnUhd6w8n8NzKPpfx4qIfPLbUuWqHP7zF5a7ZwKTNqKxZ/hT9CdVrS2c9j+aDQ=="
+    ],
+    "ultimate": false,
+    "valid": true
+  },
+  {
+    "compression": "xz",
+    "downloadHash": "sha256:1mk1amnrf7i7lqq7gwyh62m0ngr8dcfdgb4d7jcmp6gy3s8r28hz",
+    "downloadSize": 56196,
+    "narHash": "sha256-GGaJUxHHQ3Pw8dAQNG5/vXQVJ8KrUbTfI1wSXoQmQBk=",
+    "narSize": 226560,
+    "path": "/nix/store/sbldylj3clbkc0aqvjjzfa6slp4zdvlj-hello-2.12.1",
+    "references": [],
+    "url": "nar/1mk1amnrf7i7lqq7gwyh62m0ngr8dcfdgb4d7jcmp6gy3s8r28hz.nar.xz"
+  }
+]
+            "#,
+        )
+        .expect("should parse");
+
+        let narinfo = &narinfo_list[0];
+        assert_eq!(
+            narinfo
+                .nar_hash
+                .as_ref()
+                .map(|hash| hash.to_base16())
+                .as_deref(),
+            Some("1866895311c74373f0f1d010346e7fbd741527c2ab51b4df235c125e84264019")
+        );
+        assert_eq!(narinfo.nar_size, Some(226560));
+        assert_eq!(narinfo.closure_size, Some(31245680));
+        assert_eq!(narinfo.references, std::slice::from_ref(&narinfo.path));
+        assert_eq!(narinfo.sigs.len(), 1);
+        assert_eq!(
+            narinfo.deriver.as_ref().map(|deriver| deriver.basename()),
+            Some("1k6ymb5x2x6i5ys5zvmwbwl4xkr1b2jf-hello-2.12.1.drv")
+        );
+        assert_eq!(narinfo.registration_time, Some(1688730350));
+        assert!(!narinfo.ultimate);
+        assert_eq!(narinfo.ca, None);
+        assert_eq!(narinfo.download_hash, None);
+
+        let remote = &narinfo_list[1];
+        assert_eq!(remote.download_size, Some(56196));
+        assert_eq!(
+            remote
+                .download_hash
+                .as_ref()
+                .map(|hash| hash.to_nix_base32())
+                .as_deref(),
+            Some("1mk1amnrf7i7lqq7gwyh62m0ngr8dcfdgb4d7jcmp6gy3s8r28hz")
+        );
+    }
+
+    #[test]
+    fn parses_narinfo_files() {
+        let narinfo: NarinfoFile = "\
+StorePath: /nix/store/sbldylj3clbkc0aqvjjzfa6slp4zdvlj-hello-2.12.1
+URL: nar/1mk1amnrf7i7lqq7gwyh62m0ngr8dcfdgb4d7jcmp6gy3s8r28hz.nar
+NarHash: sha256:0c6sgmk4pimf1qn9vycqbafphh0b4a3dyvcswjpy48rcx3qqbhgw
+NarSize: 226560
+References:
+Deriver: unknown-deriver
+Sig: a:b
+Sig: c:d
+"
+        .parse()
+        .unwrap();
+
+        assert_eq!(narinfo.compression, DEFAULT_COMPRESSION);
+        assert!(narinfo.references.is_empty());
+        assert_eq!(narinfo.deriver, None);
+        assert_eq!(narinfo.sigs, ["a:b", "c:d"]);
+        assert_eq!(narinfo.file_hash, None);
+
+        let path_info = Narinfo::from(narinfo.clone());
+        assert_eq!(path_info.nar_hash.as_ref(), Some(&narinfo.nar_hash));
+        assert_eq!(path_info._other["compression"], "bzip2");
+
+        assert!(matches!(
+            "StorePath: /nix/store/sbldylj3clbkc0aqvjjzfa6slp4zdvlj-hello-2.12.1"
+                .parse::<NarinfoFile>(),
+            Err(ParseNarinfoError::MissingField("URL"))
+        ));
+        assert!(matches!(
+            "no colon".parse::<NarinfoFile>(),
+            Err(ParseNarinfoError::InvalidLine(_))
+        ));
+    }
 }
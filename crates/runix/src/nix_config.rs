@@ -0,0 +1,463 @@
+//! The effective nix configuration as shown by `nix config show --json`,
+//! and `nix.conf` files, see [NixConf]
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+
+/// The prefix of settings appending to a setting rather than replacing it
+const EXTRA_PREFIX: &str = "extra-";
+
+/// A nix setting, see [NixConfigValues]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NixSetting {
+    /// The effective value
+    pub value: Value,
+    pub default_value: Value,
+    #[serde(default)]
+    pub description: String,
+    /// Alternative names of the setting, e.g. `build-max-jobs` for `max-jobs`
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// The experimental feature that has to be enabled to use the setting
+    #[serde(default)]
+    pub experimental_feature: Option<String>,
+}
+
+impl NixSetting {
+    /// Whether the value differs from the default value
+    pub fn is_set(&self) -> bool {
+        self.value != self.default_value
+    }
+}
+
+/// The nix settings by name
+///
+/// ```
+/// # use runix::nix_config::NixConfigValues;
+/// let config: NixConfigValues = serde_json::from_str(
+///     r#"{
+///         "experimental-features": {
+///             "aliases": [],
+///             "defaultValue": [],
+///             "description": "Experimental features that are enabled.",
+///             "experimentalFeature": null,
+///             "value": ["flakes", "nix-command"]
+///         },
+///         "max-jobs": {
+///             "aliases": ["build-max-jobs"],
+///             "defaultValue": 1,
+///             "description": "Maximum number of jobs that Nix will try to build in parallel.",
+///             "experimentalFeature": null,
+///             "value": 8
+///         },
+///         "auto-allocate-uids": {
+///             "aliases": [],
+///             "defaultValue": false,
+///             "description": "Whether to select UIDs for builds automatically.",
+///             "experimentalFeature": "auto-allocate-uids",
+///             "value": false
+///         }
+///     }"#,
+/// )
+/// .unwrap();
+///
+/// assert_eq!(config.value::<u32>("build-max-jobs"), Some(8));
+/// assert!(config.is_experimental_feature_enabled("flakes"));
+/// assert!(!config.is_available("auto-allocate-uids"));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct NixConfigValues(pub BTreeMap<String, NixSetting>);
+
+impl NixConfigValues {
+    /// The setting called `name` or one of its aliases
+    pub fn get(&self, name: &str) -> Option<&NixSetting> {
+        self.0.get(name).or_else(|| {
+            self.0
+                .values()
+                .find(|setting| setting.aliases.iter().any(|alias| alias == name))
+        })
+    }
+
+    /// The effective value of the setting `name`, [None] if unknown or of a different type
+    pub fn value<T: DeserializeOwned>(&self, name: &str) -> Option<T> {
+        self.get(name)
+            .and_then(|setting| T::deserialize(&setting.value).ok())
+    }
+
+    /// The enabled experimental features, e.g. `flakes`
+    pub fn experimental_features(&self) -> Vec<String> {
+        self.value("experimental-features").unwrap_or_default()
+    }
+
+    pub fn is_experimental_feature_enabled(&self, feature: &str) -> bool {
+        self.experimental_features()
+            .iter()
+            .any(|enabled| enabled == feature)
+    }
+
+    /// Whether the setting `name` is known and its experimental feature, if any, is enabled
+    pub fn is_available(&self, name: &str) -> bool {
+        match self.get(name) {
+            Some(setting) => setting
+                .experimental_feature
+                .as_deref()
+                .is_none_or(|feature| self.is_experimental_feature_enabled(feature)),
+            None => false,
+        }
+    }
+
+    /// The effective values by name
+    pub fn values(&self) -> BTreeMap<String, Value> {
+        self.0
+            .iter()
+            .map(|(name, setting)| (name.clone(), setting.value.clone()))
+            .collect()
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum NixConfError {
+    #[error("Could not read '{0}': {1}")]
+    Read(PathBuf, #[source] std::io::Error),
+    #[error("Could not write nix.conf: {0}")]
+    Write(#[source] std::io::Error),
+    #[error("Invalid nix.conf line {0}: '{1}'")]
+    InvalidLine(usize, String),
+    #[error("'{0}' includes itself")]
+    IncludeCycle(PathBuf),
+}
+
+/// A line of a [NixConf]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NixConfLine {
+    /// `name = value ...`, values are separated by whitespace
+    Setting { name: String, values: Vec<String> },
+    /// `include path`, or `!include path` if `optional`
+    ///
+    /// Relative paths are relative to the directory of the including file.
+    Include { path: PathBuf, optional: bool },
+    /// Comments and blank lines, retained to write the file back unchanged
+    Other(String),
+}
+
+impl Display for NixConfLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NixConfLine::Setting { name, values } => write!(f, "{name} = {}", values.join(" ")),
+            NixConfLine::Include { path, optional } => {
+                let directive = if *optional { "!include" } else { "include" };
+                write!(f, "{directive} {}", path.display())
+            },
+            NixConfLine::Other(line) => write!(f, "{line}"),
+        }
+    }
+}
+
+/// The contents of a `nix.conf` file
+///
+/// Edits keep comments and the order of other lines,
+/// but comments trailing settings are dropped when writing the file.
+///
+/// ```
+/// # use runix::nix_config::NixConf;
+/// let mut conf: NixConf = "\
+/// ## managed by the installer
+/// experimental-features = nix-command
+/// extra-experimental-features = flakes # for flox
+/// substituters = https://cache.nixos.org
+/// "
+/// .parse()
+/// .unwrap();
+///
+/// conf.extend("substituters", ["https://cache.example.org"]);
+/// conf.set("max-jobs", ["auto"]);
+///
+/// let settings = conf.settings();
+/// assert_eq!(settings.get("experimental-features").unwrap(), [
+///     "nix-command",
+///     "flakes"
+/// ]);
+/// assert_eq!(settings.get("substituters").unwrap().len(), 2);
+/// assert_eq!(
+///     conf.to_string(),
+///     "\
+/// ## managed by the installer
+/// experimental-features = nix-command
+/// extra-experimental-features = flakes
+/// substituters = https://cache.nixos.org
+/// extra-substituters = https://cache.example.org
+/// max-jobs = auto
+/// "
+/// );
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NixConf {
+    pub lines: Vec<NixConfLine>,
+}
+
+impl NixConf {
+    /// Read the nix.conf at `path`, without following includes
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, NixConfError> {
+        let path = path.as_ref();
+        fs::read_to_string(path)
+            .map_err(|e| NixConfError::Read(path.to_path_buf(), e))?
+            .parse()
+    }
+
+    /// Read the nix.conf at `path` and the files it includes, returning the resulting settings
+    ///
+    /// Missing files included with `!include` are skipped, as nix does.
+    pub fn load(path: impl AsRef<Path>) -> Result<NixConfSettings, NixConfError> {
+        let mut settings = NixConfSettings::default();
+        Self::load_into(path.as_ref(), &mut settings, &mut Vec::new())?;
+        Ok(settings)
+    }
+
+    fn load_into(
+        path: &Path,
+        settings: &mut NixConfSettings,
+        including: &mut Vec<PathBuf>,
+    ) -> Result<(), NixConfError> {
+        if including.iter().any(|parent| parent == path) {
+            return Err(NixConfError::IncludeCycle(path.to_path_buf()));
+        }
+        let conf = Self::read(path)?;
+        including.push(path.to_path_buf());
+
+        for line in conf.lines {
+            match line {
+                NixConfLine::Setting { name, values } => settings.apply(name, values),
+                NixConfLine::Include {
+                    path: include,
+                    optional,
+                } => {
+                    let include = path.parent().unwrap_or(Path::new("")).join(include);
+                    if optional && !include.exists() {
+                        continue;
+                    }
+                    Self::load_into(&include, settings, including)?;
+                },
+                NixConfLine::Other(_) => {},
+            }
+        }
+
+        including.pop();
+        Ok(())
+    }
+
+    /// Write the file to `path`, replacing it atomically
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), NixConfError> {
+        let path = path.as_ref();
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let tmp_path = path.with_file_name(format!(".{file_name}.{}.tmp", std::process::id()));
+
+        fs::write(&tmp_path, self.to_string())
+            .and_then(|_| fs::rename(&tmp_path, path))
+            .map_err(|e| {
+                let _ = fs::remove_file(&tmp_path);
+                NixConfError::Write(e)
+            })
+    }
+
+    /// The settings of this file, ignoring includes, see [NixConf::load]
+    pub fn settings(&self) -> NixConfSettings {
+        let mut settings = NixConfSettings::default();
+        for line in &self.lines {
+            if let NixConfLine::Setting { name, values } = line {
+                settings.apply(name.clone(), values.clone());
+            }
+        }
+        settings
+    }
+
+    /// Set `name` to `values`, replacing the setting and any `extra-` setting of the same name
+    ///
+    /// The first of the replaced lines is updated in place, new settings are appended.
+    pub fn set<V: Into<String>>(&mut self, name: &str, values: impl IntoIterator<Item = V>) {
+        let setting = NixConfLine::Setting {
+            name: name.to_string(),
+            values: values.into_iter().map(Into::into).collect(),
+        };
+        match self.position(name) {
+            Some(index) => {
+                self.lines[index] = setting;
+                let mut current = 0;
+                self.lines.retain(|line| {
+                    current += 1;
+                    current - 1 <= index || !Self::sets(line, name)
+                });
+            },
+            None => self.lines.push(setting),
+        }
+    }
+
+    /// Append `values` to the setting `name` with an `extra-` setting
+    pub fn extend<V: Into<String>>(&mut self, name: &str, values: impl IntoIterator<Item = V>) {
+        let extra = format!("{EXTRA_PREFIX}{name}");
+        let values = values.into_iter().map(Into::into);
+        let existing = self.lines.iter_mut().find_map(|line| match line {
+            NixConfLine::Setting { name, values } if *name == extra => Some(values),
+            _ => None,
+        });
+        match existing {
+            Some(existing) => existing.extend(values),
+            None => self.lines.push(NixConfLine::Setting {
+                name: extra,
+                values: values.collect(),
+            }),
+        }
+    }
+
+    /// Remove the setting `name` and any `extra-` setting of the same name
+    pub fn remove(&mut self, name: &str) {
+        self.lines.retain(|line| !Self::sets(line, name));
+    }
+
+    /// The index of the first line setting `name`
+    fn position(&self, name: &str) -> Option<usize> {
+        self.lines.iter().position(|line| Self::sets(line, name))
+    }
+
+    /// Whether `line` sets or extends `name`
+    fn sets(line: &NixConfLine, name: &str) -> bool {
+        match line {
+            NixConfLine::Setting { name: setting, .. } => {
+                setting == name || setting.strip_prefix(EXTRA_PREFIX) == Some(name)
+            },
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for NixConf {
+    type Err = NixConfError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = Vec::new();
+        for (number, raw) in s.lines().enumerate() {
+            let line = raw.split_once('#').map_or(raw, |(line, _)| line);
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+
+            let line = match tokens[..] {
+                [] => NixConfLine::Other(raw.to_string()),
+                [directive @ ("include" | "!include"), path] => NixConfLine::Include {
+                    path: path.into(),
+                    optional: directive == "!include",
+                },
+                [name, "=", ref values @ ..] => NixConfLine::Setting {
+                    name: name.to_string(),
+                    values: values.iter().map(ToString::to_string).collect(),
+                },
+                _ => return Err(NixConfError::InvalidLine(number + 1, raw.to_string())),
+            };
+            lines.push(line);
+        }
+        Ok(NixConf { lines })
+    }
+}
+
+impl Display for NixConf {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for line in &self.lines {
+            writeln!(f, "{line}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The values of the settings of a [NixConf] by name, with `extra-` settings applied
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NixConfSettings(pub BTreeMap<String, Vec<String>>);
+
+impl NixConfSettings {
+    pub fn get(&self, name: &str) -> Option<&[String]> {
+        self.0.get(name).map(Vec::as_slice)
+    }
+
+    /// The value of the setting `name` parsed as `T`, e.g. `max-jobs` as [u32],
+    /// [None] if not set or not a `T`
+    ///
+    /// Boolean settings are parsed from `true` and `false`.
+    pub fn value<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.get(name)?.join(" ").parse().ok()
+    }
+
+    /// Set `name` like a line of a nix.conf
+    fn apply(&mut self, name: String, values: Vec<String>) {
+        match name.strip_prefix(EXTRA_PREFIX) {
+            Some(name) => self.0.entry(name.to_string()).or_default().extend(values),
+            None => {
+                self.0.insert(name, values);
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edits_settings() {
+        let mut conf: NixConf = "\
+substituters = a
+extra-substituters = b
+# keep me
+extra-substituters = c
+"
+        .parse()
+        .unwrap();
+
+        conf.set("substituters", ["d"]);
+        assert_eq!(conf.to_string(), "substituters = d\n# keep me\n");
+
+        conf.remove("substituters");
+        assert_eq!(conf.to_string(), "# keep me\n");
+
+        assert!(matches!(
+            "no equals sign".parse::<NixConf>(),
+            Err(NixConfError::InvalidLine(1, _))
+        ));
+    }
+
+    #[test]
+    fn loads_includes() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("nix.conf"),
+            "max-jobs = 4\nsandbox = true\ninclude extra.conf\n!include missing.conf\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("extra.conf"),
+            "max-jobs = 8\nextra-trusted-users = alice\n",
+        )
+        .unwrap();
+
+        let settings = NixConf::load(dir.path().join("nix.conf")).unwrap();
+        assert_eq!(settings.value::<u32>("max-jobs"), Some(8));
+        assert_eq!(settings.value::<bool>("sandbox"), Some(true));
+        assert_eq!(settings.get("trusted-users").unwrap(), ["alice"]);
+
+        fs::write(dir.path().join("extra.conf"), "include nix.conf\n").unwrap();
+        assert!(matches!(
+            NixConf::load(dir.path().join("nix.conf")),
+            Err(NixConfError::IncludeCycle(_))
+        ));
+
+        let mut conf = NixConf::read(dir.path().join("nix.conf")).unwrap();
+        conf.set("sandbox", ["false"]);
+        conf.write(dir.path().join("nix.conf")).unwrap();
+        assert_eq!(NixConf::read(dir.path().join("nix.conf")).unwrap(), conf);
+    }
+}
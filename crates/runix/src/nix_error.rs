@@ -0,0 +1,615 @@
+//! Errors reported by nix, parsed from its stderr
+//!
+//! Nix does not report errors in a machine readable format,
+//! [NixError::from_stderr] recognizes common errors by their messages.
+//! Errors that are not recognized are reported as [NixError::Other],
+//! the raw stderr is retained by [NixExitError] either way.
+//! Build failures are additionally classified by the exit code of nix, see [ExitKind].
+
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use thiserror::Error;
+
+use crate::flake_ref::lock::NarHash;
+use crate::store_path::DrvPath;
+
+pub(crate) static ANSI_ESCAPE: Lazy<Regex> =
+    Lazy::new(|| Regex::new("\x1b\\[[0-9;]*[a-zA-Z]").unwrap());
+
+static HASH_MISMATCH: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"hash mismatch in [^\n]*?(/nix/store/[^\s':]+)?'?:\s*(?:specified|wanted):\s*(\S+)\s*got:\s*(\S+)",
+    )
+    .unwrap()
+});
+
+static INPUT_HASH_MISMATCH: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"hash mismatch in input '[^']+' \([^)]*\), expected '([^']+)', got '([^']+)'")
+        .unwrap()
+});
+
+/// The position of a trace frame or error, printed on a line of its own
+static POSITION: Lazy<Regex> = Lazy::new(|| Regex::new(r"^at (.+):(\d+):(\d+):?$").unwrap());
+
+static MISSING_FLAKE_ATTRIBUTE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"flake '([^']+)' does not provide attribute '([^']+)'").unwrap());
+
+static MISSING_ATTRIBUTE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"attribute '([^']+)' missing").unwrap());
+
+static BUILD_FAILURE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?:builder for|[Cc]annot build|dependencies of derivation) '(/nix/store/[^']+\.drv)'",
+    )
+    .unwrap()
+});
+
+static HTTP_STATUS: Lazy<Regex> = Lazy::new(|| Regex::new(r"HTTP error (\d{3})").unwrap());
+
+const NETWORK_ERRORS: &[&str] = &[
+    "unable to download",
+    "Could not resolve host",
+    "Couldn't resolve host",
+    "Failed to connect",
+    "Connection refused",
+    "Timeout was reached",
+    "HTTP error",
+];
+
+const EVALUATION_ERRORS: &[&str] = &[
+    "while evaluating",
+    "while calling",
+    "undefined variable",
+    "syntax error",
+    "infinite recursion",
+    "assertion",
+    "called with unexpected argument",
+    "called without required argument",
+    "was expected",
+    "cannot coerce",
+    "evaluation aborted",
+];
+
+const PERMISSION_ERRORS: &[&str] = &[
+    "Permission denied",
+    "Operation not permitted",
+    "not privileged",
+    "not a trusted user",
+];
+
+/// The kind of error that made nix fail
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum NixError {
+    /// A nix expression failed to evaluate
+    #[error("Evaluation failed: {message}")]
+    Evaluation {
+        message: String,
+        /// Where the error occurred, if reported
+        position: Option<Position>,
+        /// The frames of the trace in the order printed by nix,
+        /// outermost first since Nix 2.13
+        ///
+        /// Nix prints more frames if run with `--show-trace`,
+        /// see [crate::arguments::common::LoggingArgs].
+        trace: Vec<TraceFrame>,
+    },
+
+    /// A derivation failed to build
+    #[error("Build failed: {message}")]
+    Build {
+        /// The derivation that failed to build, if reported
+        drv_path: Option<DrvPath>,
+        message: String,
+    },
+
+    /// The output of a fixed-output derivation or a fetched source
+    /// did not match the expected hash
+    ///
+    /// Hashes are converted to SRI format, even if nix printed them in base16 or base32,
+    /// so that `got` can replace the hash in the nix expression.
+    #[error("Hash mismatch: specified {specified}, got {got}")]
+    HashMismatch {
+        /// The fixed-output derivation, [None] for fetched sources such as flake inputs
+        drv_path: Option<DrvPath>,
+        specified: NarHash,
+        got: NarHash,
+    },
+
+    /// An attribute does not exist
+    #[error("Missing attribute '{attr_path}'")]
+    MissingAttribute {
+        /// The attribute, for flakes the first of the attributes that were tried
+        attr_path: String,
+        /// The flake that does not provide the attribute
+        flake: Option<String>,
+    },
+
+    /// A download or connection to a remote failed
+    #[error("Network error: {message}")]
+    Network { message: String },
+
+    /// Nix lacks permissions, e.g. to access a file or the nix daemon
+    #[error("Permission denied: {message}")]
+    Permission { message: String },
+
+    /// Any other error
+    #[error("{message}")]
+    Other { message: String },
+}
+
+impl NixError {
+    /// Recognize the error reported in the `stderr` of a failed nix invocation
+    ///
+    /// ```
+    /// # use runix::nix_error::NixError;
+    /// let stderr = "error: flake 'github:flox/runix' does not provide attribute 'packages.x86_64-linux.foo' or 'foo'";
+    /// assert_eq!(NixError::from_stderr(stderr), NixError::MissingAttribute {
+    ///     attr_path: "packages.x86_64-linux.foo".to_string(),
+    ///     flake: Some("github:flox/runix".to_string()),
+    /// });
+    /// ```
+    pub fn from_stderr(stderr: &str) -> Self {
+        let stderr = ANSI_ESCAPE.replace_all(stderr, "");
+        let message = error_message(&stderr);
+
+        if let Some(error) = HASH_MISMATCH.captures(&stderr).and_then(|captures| {
+            hash_mismatch(
+                captures.get(1).map(|m| m.as_str()),
+                &captures[2],
+                &captures[3],
+            )
+        }) {
+            return error;
+        }
+
+        if let Some(error) = INPUT_HASH_MISMATCH
+            .captures(&stderr)
+            .and_then(|captures| hash_mismatch(None, &captures[1], &captures[2]))
+        {
+            return error;
+        }
+
+        if let Some(captures) = MISSING_FLAKE_ATTRIBUTE.captures(&stderr) {
+            return NixError::MissingAttribute {
+                attr_path: captures[2].to_string(),
+                flake: Some(captures[1].to_string()),
+            };
+        }
+
+        if let Some(captures) = MISSING_ATTRIBUTE.captures(&stderr) {
+            return NixError::MissingAttribute {
+                attr_path: captures[1].to_string(),
+                flake: None,
+            };
+        }
+
+        if let Some(captures) = BUILD_FAILURE.captures(&stderr) {
+            return NixError::Build {
+                drv_path: captures[1].parse().ok(),
+                message,
+            };
+        }
+
+        if NETWORK_ERRORS
+            .iter()
+            .any(|pattern| stderr.contains(pattern))
+            && !client_error(&stderr)
+        {
+            return NixError::Network { message };
+        }
+
+        if PERMISSION_ERRORS
+            .iter()
+            .any(|pattern| stderr.contains(pattern))
+        {
+            return NixError::Permission { message };
+        }
+
+        if EVALUATION_ERRORS
+            .iter()
+            .any(|pattern| stderr.contains(pattern))
+        {
+            let (position, trace) = parse_trace(&stderr);
+            return NixError::Evaluation {
+                message,
+                position,
+                trace,
+            };
+        }
+
+        NixError::Other { message }
+    }
+
+    /// Whether the error is likely to go away when trying again,
+    /// i.e. network errors reaching substituters or fetching sources
+    pub fn is_transient(&self) -> bool {
+        matches!(self, NixError::Network { .. })
+    }
+}
+
+/// Whether a download failed with an HTTP status that will not change when retried,
+/// e.g. 404 Not Found, unlike 408 Request Timeout and 429 Too Many Requests
+fn client_error(stderr: &str) -> bool {
+    HTTP_STATUS.captures_iter(stderr).any(|captures| {
+        let status: u16 = captures[1].parse().unwrap_or_default();
+        (400..500).contains(&status) && status != 408 && status != 429
+    })
+}
+
+/// A [NixError::HashMismatch], unless the hashes are not recognized
+fn hash_mismatch(drv_path: Option<&str>, specified: &str, got: &str) -> Option<NixError> {
+    Some(NixError::HashMismatch {
+        drv_path: drv_path.and_then(|drv_path| drv_path.parse().ok()),
+        specified: specified.parse().ok()?,
+        got: got.parse().ok()?,
+    })
+}
+
+/// A location in a nix file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Position {
+    /// The path of the file, or a placeholder such as `«string»` for expressions not read from files
+    pub file: String,
+    pub line: u32,
+    pub column: u32,
+}
+
+/// A frame of an evaluation trace, e.g. `while evaluating the attribute 'packages'`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceFrame {
+    pub message: String,
+    pub position: Option<Position>,
+}
+
+/// Parse the trace of an evaluation error and the position of the error itself
+///
+/// Frames start with `…`, the error with `error:`,
+/// either may be followed by a line `at <file>:<line>:<column>:`.
+fn parse_trace(stderr: &str) -> (Option<Position>, Vec<TraceFrame>) {
+    /// The item a following position belongs to
+    enum Positioned {
+        Frame,
+        Error,
+    }
+
+    let mut trace: Vec<TraceFrame> = Vec::new();
+    let mut error_position = None;
+    let mut positioned = None;
+
+    for line in stderr.lines().map(str::trim) {
+        if let Some(message) = line.strip_prefix('…') {
+            trace.push(TraceFrame {
+                message: message.trim().to_string(),
+                position: None,
+            });
+            positioned = Some(Positioned::Frame);
+        } else if let Some(message) = line.strip_prefix("error:") {
+            // an empty header is followed by the trace and the actual error
+            positioned = None;
+            if !message.trim().is_empty() {
+                error_position = None;
+                positioned = Some(Positioned::Error);
+            }
+        } else if let Some(captures) = POSITION.captures(line) {
+            let position = Position {
+                file: captures[1].to_string(),
+                line: captures[2].parse().unwrap_or_default(),
+                column: captures[3].parse().unwrap_or_default(),
+            };
+            match positioned.take() {
+                Some(Positioned::Frame) => {
+                    if let Some(frame) = trace.last_mut() {
+                        frame.position = Some(position);
+                    }
+                },
+                Some(Positioned::Error) => error_position = Some(position),
+                None => {},
+            }
+        }
+    }
+
+    (error_position, trace)
+}
+
+/// The failure indicated by the exit status of nix
+///
+/// Nix exits with `100` if builds failed, adding `1`, `2` and `4`
+/// if any build timed out, produced an output with the wrong hash,
+/// or was found not to be deterministic with `--check` respectively.
+/// Any other failure exits with `1`.
+///
+/// ```
+/// # use runix::nix_error::ExitKind;
+/// assert_eq!(ExitKind::from_code(102), ExitKind::HashMismatch);
+/// assert_eq!(ExitKind::from_code(103), ExitKind::MultipleBuildFailures {
+///     timed_out: true,
+///     hash_mismatch: true,
+///     not_deterministic: false,
+/// });
+/// assert_eq!(ExitKind::from_code(1), ExitKind::Failure(1));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitKind {
+    /// `100`: builds failed
+    BuildFailure,
+    /// `101`: a build timed out
+    TimedOut,
+    /// `102`: the output of a fixed-output derivation did not match its hash
+    HashMismatch,
+    /// `104`: a build repeated with `--check` produced a different output
+    NotDeterministic,
+    /// `103`, `105` to `107`: builds failed for several of the reasons above
+    MultipleBuildFailures {
+        timed_out: bool,
+        hash_mismatch: bool,
+        not_deterministic: bool,
+    },
+    /// Any other exit code, usually `1`
+    Failure(i32),
+    /// Nix was killed by a signal
+    Signal(Option<i32>),
+}
+
+impl ExitKind {
+    const BUILD_FAILURE: i32 = 100;
+    const HASH_MISMATCH: i32 = 2;
+    const NOT_DETERMINISTIC: i32 = 4;
+    const TIMED_OUT: i32 = 1;
+
+    pub fn from_code(code: i32) -> Self {
+        if !(Self::BUILD_FAILURE..Self::BUILD_FAILURE + 8).contains(&code) {
+            return ExitKind::Failure(code);
+        }
+
+        match code - Self::BUILD_FAILURE {
+            0 => ExitKind::BuildFailure,
+            Self::TIMED_OUT => ExitKind::TimedOut,
+            Self::HASH_MISMATCH => ExitKind::HashMismatch,
+            Self::NOT_DETERMINISTIC => ExitKind::NotDeterministic,
+            flags => ExitKind::MultipleBuildFailures {
+                timed_out: flags & Self::TIMED_OUT != 0,
+                hash_mismatch: flags & Self::HASH_MISMATCH != 0,
+                not_deterministic: flags & Self::NOT_DETERMINISTIC != 0,
+            },
+        }
+    }
+
+    pub fn from_status(status: ExitStatus) -> Self {
+        match status.code() {
+            Some(code) => Self::from_code(code),
+            None => ExitKind::Signal(status.signal()),
+        }
+    }
+
+    /// Whether nix reported failed builds
+    pub fn is_build_failure(&self) -> bool {
+        !matches!(self, ExitKind::Failure(_) | ExitKind::Signal(_))
+    }
+}
+
+/// A nix invocation that exited unsuccessfully
+#[derive(Debug, Error)]
+#[error("{error} ({status})")]
+pub struct NixExitError {
+    pub status: ExitStatus,
+    /// The failure indicated by [NixExitError::status]
+    pub kind: ExitKind,
+    /// The error parsed from [NixExitError::stderr]
+    pub error: NixError,
+    /// The raw stderr of nix
+    pub stderr: String,
+}
+
+impl NixExitError {
+    pub fn new(status: ExitStatus, stderr: String) -> Self {
+        NixExitError {
+            status,
+            kind: ExitKind::from_status(status),
+            error: NixError::from_stderr(&stderr),
+            stderr,
+        }
+    }
+}
+
+/// The message of the last error in `stderr`
+///
+/// Falls back to the last line if nix did not print an error.
+fn error_message(stderr: &str) -> String {
+    let mut lines = stderr.lines().rev().map(str::trim);
+    lines
+        .clone()
+        .filter_map(|line| line.strip_prefix("error:"))
+        .map(str::trim)
+        .find(|message| !message.is_empty())
+        .or_else(|| lines.find(|line| !line.is_empty()))
+        .unwrap_or_default()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_errors() {
+        let hash_mismatch = "\
+error: hash mismatch in fixed-output derivation '/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-source.drv':
+         specified: sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=
+            got:    sha256-SUO2nHJJRNEOmnE/DLf1ukQ/SO7NK1BXHNDVDo+v+nk=
+error: 1 dependencies of derivation '/nix/store/3dsk2kh0m6xyi5rhjqzwxd4jc4hy6r2s-hello.drv' failed to build";
+        assert_eq!(
+            NixError::from_stderr(hash_mismatch),
+            NixError::HashMismatch {
+                drv_path: Some(
+                    "/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-source.drv"
+                        .parse()
+                        .unwrap()
+                ),
+                specified: "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="
+                    .parse()
+                    .unwrap(),
+                got: "sha256-SUO2nHJJRNEOmnE/DLf1ukQ/SO7NK1BXHNDVDo+v+nk="
+                    .parse()
+                    .unwrap(),
+            }
+        );
+
+        // printed in base32 by older versions of nix
+        let base32 = "\
+error: hash mismatch in fixed-output derivation '/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-source.drv':
+  wanted: sha256:0000000000000000000000000000000000000000000000000000
+  got:    sha256:1b3mzrpfdbzqv2xv1wm7kjmynkm6zvsz6g5a0zbp0lw7v5j5kz5d";
+        let NixError::HashMismatch { got, .. } = NixError::from_stderr(base32) else {
+            panic!("expected a hash mismatch");
+        };
+        assert_eq!(got.algorithm(), "sha256");
+        assert!(got.to_string().starts_with("sha256-"));
+
+        let build = "\
+this derivation will be built:
+  /nix/store/3dsk2kh0m6xyi5rhjqzwxd4jc4hy6r2s-hello.drv
+error: builder for '/nix/store/3dsk2kh0m6xyi5rhjqzwxd4jc4hy6r2s-hello.drv' failed with exit code 1;
+       last 1 log lines:
+       > make: *** No targets specified and no makefile found.  Stop.
+       For full logs, run 'nix log /nix/store/3dsk2kh0m6xyi5rhjqzwxd4jc4hy6r2s-hello.drv'.";
+        assert_eq!(NixError::from_stderr(build), NixError::Build {
+            drv_path: Some(
+                "/nix/store/3dsk2kh0m6xyi5rhjqzwxd4jc4hy6r2s-hello.drv"
+                    .parse()
+                    .unwrap()
+            ),
+            message: "builder for '/nix/store/3dsk2kh0m6xyi5rhjqzwxd4jc4hy6r2s-hello.drv' failed with exit code 1;".to_string(),
+        });
+
+        let evaluation = "\
+\x1b[31;1merror:\x1b[0m
+       … while evaluating the attribute 'packages'
+
+       error: undefined variable 'hello'";
+        assert_eq!(NixError::from_stderr(evaluation), NixError::Evaluation {
+            message: "undefined variable 'hello'".to_string(),
+            position: None,
+            trace: vec![TraceFrame {
+                message: "while evaluating the attribute 'packages'".to_string(),
+                position: None,
+            }],
+        });
+
+        let input_hash_mismatch = "error: NAR hash mismatch in input 'github:flox/runix/7c3c1f2d0b3f1a4e5d6c7b8a9f0e1d2c3b4a5f6e' (/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-source), expected 'sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=', got 'sha256-SUO2nHJJRNEOmnE/DLf1ukQ/SO7NK1BXHNDVDo+v+nk='";
+        assert!(matches!(
+            NixError::from_stderr(input_hash_mismatch),
+            NixError::HashMismatch { drv_path: None, .. }
+        ));
+
+        let missing = "error: attribute 'hello' missing";
+        assert_eq!(NixError::from_stderr(missing), NixError::MissingAttribute {
+            attr_path: "hello".to_string(),
+            flake: None
+        });
+
+        let network = "warning: error: unable to download 'https://api.github.com/repos/flox/runix/commits/HEAD': Couldn't resolve host name (6)\nerror: cannot find flake 'github:flox/runix'";
+        assert!(matches!(
+            NixError::from_stderr(network),
+            NixError::Network { .. }
+        ));
+
+        let server_error =
+            "error: unable to download 'https://cache.nixos.org/nix-cache-info': HTTP error 503";
+        assert!(NixError::from_stderr(server_error).is_transient());
+
+        let not_found = "error: unable to download 'https://cache.nixos.org/nar/0000.nar.xz': HTTP error 404 (curl error: HTTP response code said error)";
+        assert!(!NixError::from_stderr(not_found).is_transient());
+        assert!(matches!(
+            NixError::from_stderr(not_found),
+            NixError::Other { .. }
+        ));
+
+        let permission = "error: opening lock file '/nix/var/nix/db/big-lock': Permission denied";
+        assert_eq!(NixError::from_stderr(permission), NixError::Permission {
+            message: "opening lock file '/nix/var/nix/db/big-lock': Permission denied".to_string()
+        });
+
+        assert_eq!(
+            NixError::from_stderr("something went wrong"),
+            NixError::Other {
+                message: "something went wrong".to_string()
+            }
+        );
+    }
+    #[test]
+    fn parse_traces() {
+        let stderr = "\
+error:
+       … while calling the 'import' builtin
+
+         at «string»:1:1:
+
+            1| import ./flake.nix
+             | ^
+
+       … while evaluating the attribute 'packages.x86_64-linux.default'
+
+         at /home/user/project/flake.nix:12:5:
+
+           11|   outputs = { self }: {
+           12|     packages.x86_64-linux.default = hello;
+             |     ^
+           13|   };
+
+       error: undefined variable 'hello'
+
+       at /home/user/project/flake.nix:12:37:
+
+           11|   outputs = { self }: {
+           12|     packages.x86_64-linux.default = hello;
+             |                                     ^";
+
+        let NixError::Evaluation {
+            message,
+            position,
+            trace,
+        } = NixError::from_stderr(stderr)
+        else {
+            panic!("expected an evaluation error");
+        };
+        assert_eq!(message, "undefined variable 'hello'");
+        assert_eq!(
+            position,
+            Some(Position {
+                file: "/home/user/project/flake.nix".to_string(),
+                line: 12,
+                column: 37,
+            })
+        );
+        assert_eq!(trace.len(), 2);
+        assert_eq!(trace[0].position.as_ref().unwrap().file, "«string»");
+        assert_eq!(
+            trace[1].message,
+            "while evaluating the attribute 'packages.x86_64-linux.default'"
+        );
+        assert_eq!(trace[1].position.as_ref().unwrap().line, 12);
+    }
+
+    #[test]
+    fn exit_kinds() {
+        let error = |code: i32| NixExitError::new(ExitStatus::from_raw(code << 8), String::new());
+
+        assert_eq!(error(100).kind, ExitKind::BuildFailure);
+        assert_eq!(error(101).kind, ExitKind::TimedOut);
+        assert_eq!(error(104).kind, ExitKind::NotDeterministic);
+        assert_eq!(error(106).kind, ExitKind::MultipleBuildFailures {
+            timed_out: false,
+            hash_mismatch: true,
+            not_deterministic: true,
+        });
+        assert_eq!(error(1).kind, ExitKind::Failure(1));
+        assert!(!error(1).kind.is_build_failure());
+        assert!(error(107).kind.is_build_failure());
+
+        let killed = NixExitError::new(ExitStatus::from_raw(9), String::new());
+        assert_eq!(killed.kind, ExitKind::Signal(Some(9)));
+    }
+}
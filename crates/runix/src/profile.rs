@@ -0,0 +1,433 @@
+//! Nix profiles, as listed by `nix profile list --json` and stored in their `manifest.json`
+//!
+//! ```no_run
+//! # use runix::profile::ProfileManifest;
+//! let manifest = ProfileManifest::read("/home/user/.nix-profile").unwrap();
+//! for element in manifest.elements.iter().filter(|element| element.active) {
+//!     println!("{}: {:?}", element.index, element.attr_path);
+//! }
+//! ```
+//!
+//! Manifests can be edited and written back in the format of their version,
+//! e.g. to assemble a profile without running `nix profile`:
+//!
+//! ```
+//! # use runix::profile::{ProfileElement, ProfileManifest};
+//! let mut manifest: ProfileManifest =
+//!     serde_json::from_str(r#"{"version": 3, "elements": {}}"#).unwrap();
+//! let name = manifest.add(ProfileElement {
+//!     attr_path: Some("legacyPackages.x86_64-linux.hello".to_string()),
+//!     ..ProfileElement::new(vec![
+//!         "/nix/store/3dsk2kh0m6xyi5rhjqzwxd4jc4hy6r2s-hello-2.12.1"
+//!             .parse()
+//!             .unwrap(),
+//!     ])
+//! });
+//! assert_eq!(name.as_deref(), Some("hello"));
+//!
+//! let json = serde_json::to_value(&manifest).unwrap();
+//! assert_eq!(json["elements"]["hello"]["priority"], 5);
+//! ```
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use thiserror::Error;
+
+use crate::flake_ref::FlakeRef;
+use crate::store_path::StorePath;
+
+/// The name of the manifest in a profile
+pub const MANIFEST: &str = "manifest.json";
+
+/// The priority of elements installed without `--priority`
+pub const DEFAULT_PRIORITY: u32 = 5;
+
+#[derive(Debug, Error)]
+pub enum ProfileError {
+    #[error("Could not read the profile manifest: {0}")]
+    Read(#[from] std::io::Error),
+    #[error("Could not parse the profile manifest: {0}")]
+    Parse(#[from] serde_json::Error),
+    #[error("Could not write the profile manifest: {0}")]
+    Write(#[source] std::io::Error),
+}
+
+/// A package installed in a profile
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileElement {
+    /// The position of the element in the profile,
+    /// used by versions of nix prior to 2.20 to refer to elements
+    pub index: usize,
+    /// The name used by nix 2.20 and later to refer to the element
+    pub name: Option<String>,
+    /// Whether the element is linked into the profile
+    pub active: bool,
+    /// Resolves conflicts between elements, lower values take precedence
+    pub priority: u32,
+    /// The flake the element was installed from, if installed from a flake
+    pub original: Option<FlakeRef>,
+    /// [ProfileElement::original], locked to the revision that was installed
+    pub locked: Option<FlakeRef>,
+    /// The flake output the element was installed from, e.g. `legacyPackages.x86_64-linux.hello`
+    pub attr_path: Option<String>,
+    /// The installed outputs, [None] for the default outputs
+    pub outputs: Option<Vec<String>>,
+    pub store_paths: Vec<StorePath>,
+}
+
+impl ProfileElement {
+    /// An active element of default priority linking `store_paths`,
+    /// not installed from a flake
+    pub fn new(store_paths: Vec<StorePath>) -> Self {
+        ProfileElement {
+            index: 0,
+            name: None,
+            active: true,
+            priority: DEFAULT_PRIORITY,
+            original: None,
+            locked: None,
+            attr_path: None,
+            outputs: None,
+            store_paths,
+        }
+    }
+
+    /// The name nix gives an element when installing it, the last component of its attribute path
+    /// or the name of its first store path without version, e.g. `hello` for `hello-2.12.1`
+    pub fn default_name(&self) -> Option<String> {
+        self.attr_path
+            .as_deref()
+            .and_then(|attr_path| attr_path.rsplit('.').next())
+            .map(ToString::to_string)
+            .or_else(|| {
+                self.store_paths
+                    .first()
+                    .map(|path| package_name(path.name()).to_string())
+            })
+    }
+}
+
+/// The elements of a profile, see [crate::command::ProfileList]
+///
+/// Parses the list of elements of manifest versions 1 and 2 and the elements by name of version 3,
+/// and serializes in the format of [ProfileManifest::version].
+/// Manifests of version 1 are written as version 2, as nix does.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "SerializedManifest", into = "SerializedManifest")]
+pub struct ProfileManifest {
+    pub version: u32,
+    pub elements: Vec<ProfileElement>,
+}
+
+impl ProfileManifest {
+    /// Read the manifest of the profile at `profile`, e.g. `~/.nix-profile`
+    pub fn read(profile: impl AsRef<Path>) -> Result<Self, ProfileError> {
+        let manifest = std::fs::read_to_string(profile.as_ref().join(MANIFEST))?;
+        Ok(serde_json::from_str(&manifest)?)
+    }
+
+    /// Write the manifest to the profile at `profile`, replacing an existing manifest atomically
+    ///
+    /// Note that profiles in the nix store are read-only,
+    /// this is meant for assembling new profile generations.
+    pub fn write(&self, profile: impl AsRef<Path>) -> Result<(), ProfileError> {
+        let path = profile.as_ref().join(MANIFEST);
+        let tmp_path = path.with_file_name(format!(".{MANIFEST}.{}.tmp", std::process::id()));
+
+        fs::write(&tmp_path, serde_json::to_string(self)?)
+            .and_then(|_| fs::rename(&tmp_path, &path))
+            .map_err(|e| {
+                let _ = fs::remove_file(&tmp_path);
+                ProfileError::Write(e)
+            })
+    }
+
+    /// Add `element` to the profile, returning its name
+    ///
+    /// From version 3 on, elements without a name are named by [ProfileElement::default_name],
+    /// with a numeric suffix if the name is taken.
+    pub fn add(&mut self, mut element: ProfileElement) -> Option<String> {
+        element.index = self.elements.len();
+        if self.version >= 3 && element.name.is_none() {
+            let base = element
+                .default_name()
+                .unwrap_or_else(|| "package".to_string());
+            let mut name = base.clone();
+            let mut suffix = 1;
+            while self.get(&name).is_some() {
+                name = format!("{base}-{suffix}");
+                suffix += 1;
+            }
+            element.name = Some(name);
+        }
+        let name = element.name.clone();
+        self.elements.push(element);
+        name
+    }
+
+    /// Remove the element called `name` or at index `name`,
+    /// the indices of the following elements shift accordingly
+    pub fn remove(&mut self, name: &str) -> Option<ProfileElement> {
+        let position = self.elements.iter().position(|element| {
+            element.name.as_deref() == Some(name) || element.index.to_string() == name
+        })?;
+        let removed = self.elements.remove(position);
+        for (index, element) in self.elements.iter_mut().enumerate() {
+            element.index = index;
+        }
+        Some(removed)
+    }
+
+    /// The element called `name` or at index `name`, to edit it
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut ProfileElement> {
+        self.elements.iter_mut().find(|element| {
+            element.name.as_deref() == Some(name) || element.index.to_string() == name
+        })
+    }
+
+    /// The element called `name` or at index `name`
+    pub fn get(&self, name: &str) -> Option<&ProfileElement> {
+        self.elements.iter().find(|element| {
+            element.name.as_deref() == Some(name) || element.index.to_string() == name
+        })
+    }
+
+    /// The elements installed from `flake_ref`
+    pub fn installed_from<'a>(
+        &'a self,
+        flake_ref: &'a FlakeRef,
+    ) -> impl Iterator<Item = &'a ProfileElement> {
+        self.elements
+            .iter()
+            .filter(move |element| element.original.as_ref() == Some(flake_ref))
+    }
+}
+
+/// The name of a package without its version, which starts at the first `-` not followed by a letter
+fn package_name(name: &str) -> &str {
+    name.match_indices('-')
+        .find(|(index, _)| !name[index + 1..].starts_with(|c: char| c.is_ascii_alphabetic()))
+        .map_or(name, |(index, _)| &name[..index])
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializedManifest {
+    elements: SerializedElements,
+    version: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum SerializedElements {
+    List(Vec<SerializedElement>),
+    Named(BTreeMap<String, SerializedElement>),
+}
+
+/// An element as stored in the manifest,
+/// version 1 used `originalUri` and `uri` rather than `originalUrl` and `url`
+#[serde_as]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SerializedElement {
+    #[serde(default = "active")]
+    active: bool,
+    #[serde(default = "default_priority")]
+    priority: u32,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(
+        default,
+        alias = "originalUri",
+        skip_serializing_if = "Option::is_none"
+    )]
+    original_url: Option<FlakeRef>,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default, alias = "uri", skip_serializing_if = "Option::is_none")]
+    url: Option<FlakeRef>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    attr_path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    outputs: Option<Vec<String>>,
+    store_paths: Vec<StorePath>,
+}
+
+fn active() -> bool {
+    true
+}
+
+fn default_priority() -> u32 {
+    DEFAULT_PRIORITY
+}
+
+impl SerializedElement {
+    fn into_element(self, index: usize, name: Option<String>) -> ProfileElement {
+        ProfileElement {
+            index,
+            name,
+            active: self.active,
+            priority: self.priority,
+            original: self.original_url,
+            locked: self.url,
+            attr_path: self.attr_path.filter(|attr_path| !attr_path.is_empty()),
+            outputs: self.outputs,
+            store_paths: self.store_paths,
+        }
+    }
+}
+
+impl From<ProfileElement> for SerializedElement {
+    fn from(element: ProfileElement) -> Self {
+        SerializedElement {
+            active: element.active,
+            priority: element.priority,
+            original_url: element.original,
+            url: element.locked,
+            attr_path: element.attr_path,
+            outputs: element.outputs,
+            store_paths: element.store_paths,
+        }
+    }
+}
+
+impl From<ProfileManifest> for SerializedManifest {
+    fn from(manifest: ProfileManifest) -> Self {
+        if manifest.version < 3 {
+            return SerializedManifest {
+                elements: SerializedElements::List(
+                    manifest.elements.into_iter().map(Into::into).collect(),
+                ),
+                version: manifest.version.max(2),
+            };
+        }
+
+        let elements = manifest
+            .elements
+            .into_iter()
+            .map(|element| {
+                let name = element
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| element.index.to_string());
+                (name, element.into())
+            })
+            .collect();
+        SerializedManifest {
+            elements: SerializedElements::Named(elements),
+            version: manifest.version,
+        }
+    }
+}
+
+impl From<SerializedManifest> for ProfileManifest {
+    fn from(manifest: SerializedManifest) -> Self {
+        let elements = match manifest.elements {
+            SerializedElements::List(elements) => elements
+                .into_iter()
+                .enumerate()
+                .map(|(index, element)| element.into_element(index, None))
+                .collect(),
+            SerializedElements::Named(elements) => elements
+                .into_iter()
+                .enumerate()
+                .map(|(index, (name, element))| element.into_element(index, Some(name)))
+                .collect(),
+        };
+
+        ProfileManifest {
+            version: manifest.version,
+            elements,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_manifests() {
+        let element = r#"{
+            "active": true,
+            "attrPath": "legacyPackages.x86_64-linux.hello",
+            "originalUrl": "flake:nixpkgs",
+            "outputs": null,
+            "priority": 5,
+            "storePaths": ["/nix/store/3dsk2kh0m6xyi5rhjqzwxd4jc4hy6r2s-hello-2.12.1"],
+            "url": "github:NixOS/nixpkgs/ea4c80b39be4c09702b0cb3b42eab59e2ba4f24b"
+        }"#;
+
+        let v2: ProfileManifest =
+            serde_json::from_str(&format!(r#"{{"version": 2, "elements": [{element}]}}"#)).unwrap();
+        let v3: ProfileManifest = serde_json::from_str(&format!(
+            r#"{{"version": 3, "elements": {{"hello": {element}}}}}"#
+        ))
+        .unwrap();
+
+        assert_eq!(v2.get("0"), v2.elements.first());
+        assert_eq!(v3.get("hello").unwrap().name.as_deref(), Some("hello"));
+        assert_eq!(v3.get("0"), v3.get("hello"));
+
+        let element = &v2.elements[0];
+        assert!(element.active);
+        assert_eq!(element.outputs, None);
+        assert_eq!(
+            element.original.as_ref().unwrap().to_string(),
+            "flake:nixpkgs"
+        );
+        assert_eq!(
+            v2.installed_from(element.original.as_ref().unwrap())
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn edits_manifests() {
+        let v1: ProfileManifest = serde_json::from_str(
+            r#"{"version": 1, "elements": [{
+                "active": true,
+                "attrPath": "packages.x86_64-linux.default",
+                "originalUri": "github:flox/runix",
+                "uri": "github:flox/runix/ea4c80b39be4c09702b0cb3b42eab59e2ba4f24b",
+                "storePaths": ["/nix/store/3dsk2kh0m6xyi5rhjqzwxd4jc4hy6r2s-runix-0.1.0"]
+            }]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            v1.elements[0].original.as_ref().unwrap().to_string(),
+            "github:flox/runix"
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        v1.write(dir.path()).unwrap();
+        let v2 = ProfileManifest::read(dir.path()).unwrap();
+        assert_eq!(v2.version, 2);
+        assert_eq!(v2.elements, v1.elements);
+
+        let mut v3 = ProfileManifest { version: 3, ..v2 };
+        let hello = ProfileElement::new(vec![
+            "/nix/store/3dsk2kh0m6xyi5rhjqzwxd4jc4hy6r2s-hello-2.12.1"
+                .parse()
+                .unwrap(),
+        ]);
+        assert_eq!(v3.add(hello.clone()).as_deref(), Some("hello"));
+        assert_eq!(v3.add(hello).as_deref(), Some("hello-1"));
+
+        v3.get_mut("hello").unwrap().priority = 4;
+        assert_eq!(
+            v3.remove("0").unwrap().attr_path.as_deref(),
+            Some("packages.x86_64-linux.default")
+        );
+        assert_eq!(v3.get("0").unwrap().priority, 4);
+
+        let json = serde_json::to_value(&v3).unwrap();
+        assert_eq!(json["version"], 3);
+        assert_eq!(json["elements"]["hello-1"]["active"], true);
+        assert!(json["elements"]["hello"].get("attrPath").is_none());
+        assert_eq!(serde_json::from_value::<ProfileManifest>(json).unwrap(), v3);
+    }
+}
@@ -0,0 +1,300 @@
+//! Build and download progress, aggregated from [LogEvent]s
+//!
+//! [ProgressTracker] follows the events reported by [crate::RunLogged]
+//! and maintains a [Progress] snapshot suitable to render progress bars,
+//! similar to the progress bar of the nix CLI.
+//!
+//! ```no_run
+//! # use runix::arguments::NixArgs;
+//! # use runix::command::Build;
+//! # use runix::command_line::NixCommandLine;
+//! # use runix::progress;
+//! # use runix::RunLogged;
+//! # #[tokio::main]
+//! # async fn main() {
+//! let (mut tracker, mut progress) = progress::channel();
+//! tokio::spawn(async move {
+//!     while progress.changed().await.is_ok() {
+//!         let builds = progress.borrow().builds.clone();
+//!         println!("built {}/{}", builds.done, builds.expected);
+//!     }
+//! });
+//!
+//! Build::default()
+//!     .run_logged(
+//!         &NixCommandLine::default(),
+//!         &NixArgs::default(),
+//!         &mut |event| {
+//!             tracker.handle(&event);
+//!         },
+//!     )
+//!     .await
+//!     .unwrap();
+//! # }
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+
+use tokio::sync::watch;
+
+use crate::log_event::{ActivityId, ActivityType, Field, LogEvent, ResultType};
+use crate::store_path::DrvPath;
+
+/// Progress of a set of activities, e.g. all builds of a nix invocation
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Counter {
+    pub done: u64,
+    pub expected: u64,
+    pub running: u64,
+    pub failed: u64,
+}
+
+impl Counter {
+    /// The fraction of done activities, [None] if none are expected
+    pub fn fraction(&self) -> Option<f64> {
+        (self.expected > 0).then(|| self.done as f64 / self.expected as f64)
+    }
+}
+
+/// Progress of data transfers in bytes
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Bytes {
+    pub done: u64,
+    pub expected: u64,
+}
+
+/// A running build
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildProgress {
+    /// The store path of the derivation being built
+    pub drv_path: DrvPath,
+    /// The current phase of the build, if reported by the builder
+    pub phase: Option<String>,
+}
+
+/// A snapshot of the progress of a nix invocation
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// Derivations built
+    pub builds: Counter,
+    /// Store paths copied, including paths substituted from binary caches
+    pub copied_paths: Counter,
+    /// Bytes downloaded or copied
+    pub downloads: Bytes,
+    /// Builds currently running
+    pub running_builds: BTreeMap<ActivityId, BuildProgress>,
+}
+
+/// Maintains a [Progress] snapshot from [LogEvent]s
+#[derive(Debug, Default)]
+pub struct ProgressTracker {
+    progress: Progress,
+    activities: HashMap<ActivityId, ActivityType>,
+    /// Bytes of running transfers
+    transfers: HashMap<ActivityId, Bytes>,
+    /// Bytes of stopped transfers
+    transferred: Bytes,
+}
+
+impl ProgressTracker {
+    /// The current progress
+    pub fn progress(&self) -> &Progress {
+        &self.progress
+    }
+
+    /// Update the progress with `event`
+    ///
+    /// Returns whether the progress changed.
+    pub fn handle(&mut self, event: &LogEvent) -> bool {
+        let before = self.progress.clone();
+
+        match event {
+            LogEvent::Start {
+                id,
+                activity,
+                fields,
+                ..
+            } => {
+                self.activities.insert(*id, *activity);
+                match activity {
+                    ActivityType::Build => {
+                        if let Some(drv_path) = field_str(fields, 0).and_then(|p| p.parse().ok()) {
+                            self.progress.running_builds.insert(*id, BuildProgress {
+                                drv_path,
+                                phase: None,
+                            });
+                        }
+                    },
+                    ActivityType::FileTransfer | ActivityType::CopyPath => {
+                        self.transfers.insert(*id, Bytes::default());
+                    },
+                    _ => {},
+                }
+            },
+            LogEvent::Stop { id } => {
+                self.activities.remove(id);
+                self.progress.running_builds.remove(id);
+                if let Some(bytes) = self.transfers.remove(id) {
+                    self.transferred.done += bytes.done;
+                    self.transferred.expected += bytes.expected;
+                }
+            },
+            LogEvent::Result {
+                id,
+                result: ResultType::Progress,
+                fields,
+            } => {
+                let counter = Counter {
+                    done: field_int(fields, 0).unwrap_or_default(),
+                    expected: field_int(fields, 1).unwrap_or_default(),
+                    running: field_int(fields, 2).unwrap_or_default(),
+                    failed: field_int(fields, 3).unwrap_or_default(),
+                };
+                match self.activities.get(id) {
+                    Some(ActivityType::Builds) => self.progress.builds = counter,
+                    Some(ActivityType::CopyPaths) => self.progress.copied_paths = counter,
+                    Some(ActivityType::FileTransfer | ActivityType::CopyPath) => {
+                        self.transfers.insert(*id, Bytes {
+                            done: counter.done,
+                            expected: counter.expected,
+                        });
+                    },
+                    _ => {},
+                }
+            },
+            LogEvent::Result {
+                id,
+                result: ResultType::SetPhase,
+                fields,
+            } => {
+                if let Some(build) = self.progress.running_builds.get_mut(id) {
+                    build.phase = field_str(fields, 0).map(ToString::to_string);
+                }
+            },
+            _ => {},
+        }
+
+        self.progress.downloads =
+            self.transfers
+                .values()
+                .fold(self.transferred.clone(), |acc, bytes| Bytes {
+                    done: acc.done + bytes.done,
+                    expected: acc.expected + bytes.expected,
+                });
+
+        self.progress != before
+    }
+}
+
+/// A [ProgressTracker] publishing its progress to a [watch] channel
+#[derive(Debug)]
+pub struct ProgressSender {
+    tracker: ProgressTracker,
+    sender: watch::Sender<Progress>,
+}
+
+impl ProgressSender {
+    /// Update the progress with `event` and notify receivers if it changed
+    pub fn handle(&mut self, event: &LogEvent) {
+        if self.tracker.handle(event) {
+            self.sender.send_replace(self.tracker.progress().clone());
+        }
+    }
+}
+
+/// Create a [ProgressSender] and a receiver of its progress updates
+pub fn channel() -> (ProgressSender, watch::Receiver<Progress>) {
+    let (sender, receiver) = watch::channel(Progress::default());
+    let sender = ProgressSender {
+        tracker: ProgressTracker::default(),
+        sender,
+    };
+    (sender, receiver)
+}
+
+fn field_int(fields: &[Field], n: usize) -> Option<u64> {
+    fields.get(n).and_then(Field::as_int)
+}
+
+fn field_str(fields: &[Field], n: usize) -> Option<&str> {
+    fields.get(n).and_then(Field::as_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(lines: &[&str]) -> Vec<LogEvent> {
+        lines.iter().map(|line| line.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn track_progress() {
+        let drv = "/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-hello-2.12.1.drv";
+        let mut tracker = ProgressTracker::default();
+
+        for event in events(&[
+            r#"@nix {"action":"start","id":1,"level":0,"type":104}"#,
+            r#"@nix {"action":"start","id":2,"level":0,"type":103}"#,
+            r#"@nix {"action":"result","id":1,"type":105,"fields":[0,2,1,0]}"#,
+            r#"@nix {"action":"result","id":2,"type":105,"fields":[1,3,0,0]}"#,
+            r#"@nix {"action":"start","id":3,"level":4,"parent":2,"type":101,"fields":["https://cache.nixos.org/nar/a.nar.xz"]}"#,
+            r#"@nix {"action":"result","id":3,"type":105,"fields":[100,400,0,0]}"#,
+            r#"@nix {"action":"start","id":4,"level":4,"parent":2,"type":101,"fields":["https://cache.nixos.org/nar/b.nar.xz"]}"#,
+            r#"@nix {"action":"result","id":4,"type":105,"fields":[50,50,0,0]}"#,
+            r#"@nix {"action":"stop","id":4}"#,
+        ]) {
+            tracker.handle(&event);
+        }
+
+        let build = format!(
+            r#"@nix {{"action":"start","id":5,"level":3,"parent":1,"type":105,"fields":["{drv}","",1,1]}}"#
+        );
+        assert!(tracker.handle(&build.parse().unwrap()));
+        let phase = r#"@nix {"action":"result","id":5,"type":104,"fields":["buildPhase"]}"#;
+        assert!(tracker.handle(&phase.parse().unwrap()));
+        assert!(!tracker.handle(&phase.parse().unwrap()));
+
+        let progress = tracker.progress();
+        assert_eq!(progress.builds, Counter {
+            done: 0,
+            expected: 2,
+            running: 1,
+            failed: 0
+        });
+        assert_eq!(progress.copied_paths.fraction(), Some(1.0 / 3.0));
+        assert_eq!(progress.downloads, Bytes {
+            done: 150,
+            expected: 450
+        });
+        assert_eq!(progress.running_builds[&5], BuildProgress {
+            drv_path: drv.parse().unwrap(),
+            phase: Some("buildPhase".to_string()),
+        });
+
+        tracker.handle(&r#"@nix {"action":"stop","id":5}"#.parse().unwrap());
+        assert!(tracker.progress().running_builds.is_empty());
+    }
+
+    #[test]
+    fn progress_channel() {
+        let (mut sender, receiver) = channel();
+        sender.handle(&LogEvent::Start {
+            id: 1,
+            parent: 0,
+            level: crate::log_event::Verbosity::Info,
+            activity: ActivityType::Builds,
+            text: String::new(),
+            fields: vec![],
+        });
+        assert!(!receiver.has_changed().unwrap());
+
+        sender.handle(&LogEvent::Result {
+            id: 1,
+            result: ResultType::Progress,
+            fields: [1, 1, 0, 0].map(Field::Int).to_vec(),
+        });
+        assert!(receiver.has_changed().unwrap());
+        assert_eq!(receiver.borrow().builds.done, 1);
+    }
+}
@@ -1,20 +1,66 @@
 //! A rust implementaiton of the `registry` file format
+//!
+//! [Registry] reads and writes `registry.json` files (version 2),
+//! [Registries] combines the user, system and global registries like nix does.
+//!
+//! ```
+//! # use runix::flake_ref::git_service::GitServiceRef;
+//! # use runix::registry::Registry;
+//! let dir = tempfile::tempdir().unwrap();
+//! let path = dir.path().join("registry.json");
+//!
+//! Registry::update(&path, |registry| {
+//!     registry.set("runix", GitServiceRef::github("flox", "runix").into())
+//! })
+//! .unwrap();
+//!
+//! let registry = Registry::from_path(&path).unwrap();
+//! assert_eq!(
+//!     registry.get("runix").unwrap().to.to_string(),
+//!     "github:flox/runix"
+//! );
+//! ```
 
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
-use serde_with::skip_serializing_none;
 use thiserror::Error;
 
 use crate::flake_ref::indirect::IndirectRef;
-use crate::flake_ref::FlakeRef;
+use crate::flake_ref::lock::Rev;
+use crate::flake_ref::{FlakeRef, FlakeRefAttributes};
+
+/// The location of the system registry
+pub const SYSTEM_REGISTRY: &str = "/etc/nix/registry.json";
+
+/// The version of the `registry.json` format read and written by [Registry]
+const REGISTRY_VERSION: u8 = 2;
 
 #[derive(Error, Debug)]
-pub enum RegistryError {}
+pub enum RegistryError {
+    #[error("Could not read registry {0:?}: {1}")]
+    Read(PathBuf, std::io::Error),
+    #[error("Could not parse registry {0:?}: {1}")]
+    Parse(PathBuf, serde_json::Error),
+    #[error("Unsupported version {1} of registry {0:?}")]
+    UnsupportedVersion(PathBuf, u8),
+    #[error("Could not write registry {0:?}: {1}")]
+    Write(PathBuf, std::io::Error),
+    #[error("Cannot resolve '{0}': no matching registry entry")]
+    NotFound(IndirectRef),
+    #[error("Cannot resolve '{0}': registry entries form a cycle")]
+    Cycle(IndirectRef),
+    #[error("Cannot override '{0}' of '{1}'")]
+    UnsupportedOverride(&'static str, Box<FlakeRef>),
+    #[error("Could not parse registry entry '{0}'")]
+    InvalidEntry(String),
+}
 
 #[derive(Debug, Clone, Deserialize, Serialize, Default, PartialEq, Eq)]
 pub struct Registry {
-    version: Version,
     /// Uses BTree implmentation to guarantee stable outputs
     /// [BTreeSet] unlike [std::collections::HashSet] guarantees
     /// that reading the set from a file and writing it back unchanged
@@ -22,26 +68,310 @@ pub struct Registry {
     /// Hash Sets employ stochastic methods, that may change this order
     /// at the benefit of O(1) access (rather than O(log n) with BTree)
     flakes: BTreeSet<RegistryEntry>,
+    version: Version,
 }
 
 impl Registry {
     pub fn set(&mut self, name: impl ToString, to: FlakeRef) {
-        let entry = RegistryEntry {
-            from: IndirectRef::new(name.to_string(), Default::default()),
-            to,
-            exact: None,
-        };
-        self.flakes.replace(entry);
+        let entry = RegistryEntry::new(IndirectRef::new(name.to_string(), Default::default()), to);
+        self.flakes.retain(|existing| existing.from != entry.from);
+        self.flakes.insert(entry);
     }
 
-    #[allow(unused)]
-    /// Todo: more functions such as remove, get, etc
-    pub fn remove(&mut self, _name: impl ToString) {}
+    /// Remove the entries for `name`, as `nix registry remove` does
+    ///
+    /// Returns whether an entry was removed.
+    pub fn remove(&mut self, name: impl ToString) -> bool {
+        let from = FlakeRef::from(IndirectRef::new(name.to_string(), Default::default()));
+        let len = self.flakes.len();
+        self.flakes.retain(|existing| existing.from != from);
+        self.flakes.len() != len
+    }
+
+    /// The entry for `name`, as set by [Registry::set]
+    ///
+    /// Use [Registry::lookup] to find the entry matching a flake ref with attributes.
+    pub fn get(&self, name: &str) -> Option<&RegistryEntry> {
+        self.entries()
+            .find(|entry| matches!(&entry.from, FlakeRef::Indirect(from) if from.id == name))
+    }
 
     /// Iterate over the entries in the registry
     pub fn entries(&self) -> impl Iterator<Item = &RegistryEntry> {
         self.flakes.iter()
     }
+
+    /// Read a registry from a `registry.json` file
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, RegistryError> {
+        let path = path.as_ref();
+        let contents =
+            std::fs::read(path).map_err(|e| RegistryError::Read(path.to_path_buf(), e))?;
+        let registry: Registry = serde_json::from_slice(&contents)
+            .map_err(|e| RegistryError::Parse(path.to_path_buf(), e))?;
+        if registry.version.0 != REGISTRY_VERSION {
+            return Err(RegistryError::UnsupportedVersion(
+                path.to_path_buf(),
+                registry.version.0,
+            ));
+        }
+        Ok(registry)
+    }
+
+    /// Write the registry to `path`, replacing it atomically
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), RegistryError> {
+        let path = path.as_ref();
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let tmp_path = path.with_file_name(format!(".{file_name}.{}.tmp", std::process::id()));
+
+        let mut contents =
+            serde_json::to_string_pretty(self).expect("registries serialize to json");
+        contents.push('\n');
+
+        std::fs::write(&tmp_path, contents)
+            .and_then(|_| std::fs::rename(&tmp_path, path))
+            .map_err(|e| {
+                let _ = std::fs::remove_file(&tmp_path);
+                RegistryError::Write(path.to_path_buf(), e)
+            })
+    }
+
+    /// Apply `edit` to the registry at `path` and write it back,
+    /// starting from an empty registry if the file does not exist
+    pub fn update<T>(
+        path: impl AsRef<Path>,
+        edit: impl FnOnce(&mut Registry) -> T,
+    ) -> Result<T, RegistryError> {
+        let path = path.as_ref();
+        let mut registry = read_optional_registry(path)?.unwrap_or_default();
+        let result = edit(&mut registry);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| RegistryError::Write(path.to_path_buf(), e))?;
+        }
+        registry.write(path)?;
+        Ok(result)
+    }
+
+    /// Attribute the entries to the registry `scope`
+    pub fn with_scope(self, scope: RegistryScope) -> Self {
+        self.flakes
+            .into_iter()
+            .map(|entry| RegistryEntry {
+                scope: Some(scope),
+                ..entry
+            })
+            .collect()
+    }
+
+    /// Find the entry matching an indirect flake ref
+    ///
+    /// Mirrors nix' matching rules:
+    /// an `exact` entry matches only if all attributes (except `dir`) are equal,
+    /// other entries also match refs that additionally specify a `ref` or `rev`.
+    pub fn lookup(&self, indirect: &IndirectRef) -> Option<&RegistryEntry> {
+        let exact_attributes = match_attributes(indirect, &["dir"]);
+        let attributes = match_attributes(indirect, &["dir", "ref", "rev"]);
+
+        self.entries().find(|entry| {
+            let FlakeRef::Indirect(ref from) = entry.from else {
+                return false;
+            };
+            if from.id != indirect.id {
+                return false;
+            }
+            let from_attributes = match_attributes(from, &[]);
+            if entry.exact {
+                from_attributes == exact_attributes
+            } else {
+                from_attributes == exact_attributes || from_attributes == attributes
+            }
+        })
+    }
+}
+
+/// Attributes of an indirect flake ref relevant for matching registry entries
+fn match_attributes<'a>(indirect: &'a IndirectRef, ignore: &[&str]) -> BTreeMap<&'a str, &'a str> {
+    indirect
+        .attributes
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .filter(|(k, _)| !["id", "type"].contains(k) && !ignore.contains(k))
+        .collect()
+}
+
+/// The registries consulted to resolve indirect flake refs
+///
+/// Entries are looked up in the `user`, `system` and `global` registry, in that order.
+/// Resolution only reads registry files and does not require nix or network access.
+/// Note that nix fetches the global registry from a remote location by default,
+/// a local copy can be provided with [Registries::with_global].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Registries {
+    pub user: Option<Registry>,
+    pub system: Option<Registry>,
+    pub global: Option<Registry>,
+}
+
+impl Registries {
+    /// Read the user and system registries from their default locations
+    ///
+    /// * user: `$XDG_CONFIG_HOME/nix/registry.json` (or `~/.config/nix/registry.json`)
+    /// * system: `/etc/nix/registry.json`
+    ///
+    /// Missing registry files are skipped.
+    pub fn from_default_locations() -> Result<Self, RegistryError> {
+        Ok(Registries {
+            user: Self::user_registry_path()
+                .as_deref()
+                .map(read_optional_registry)
+                .transpose()?
+                .flatten()
+                .map(|registry| registry.with_scope(RegistryScope::User)),
+            system: read_optional_registry(Path::new(SYSTEM_REGISTRY))?
+                .map(|registry| registry.with_scope(RegistryScope::System)),
+            global: None,
+        })
+    }
+
+    /// The location of the user registry, written by `nix registry add`
+    pub fn user_registry_path() -> Option<PathBuf> {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+            .map(|config_dir| config_dir.join("nix/registry.json"))
+    }
+
+    /// Use the registry at `path` as global registry
+    pub fn with_global(mut self, path: impl AsRef<Path>) -> Result<Self, RegistryError> {
+        self.global = Some(Registry::from_path(path)?.with_scope(RegistryScope::Global));
+        Ok(self)
+    }
+
+    /// The entries of all registries in order of precedence,
+    /// without the entries shadowed by an entry of a preceding registry
+    ///
+    /// Entries shadow each other if they replace the same flake ref and are equally `exact`.
+    pub fn merged(&self) -> Vec<&RegistryEntry> {
+        let mut merged: Vec<&RegistryEntry> = Vec::new();
+        for entry in [&self.user, &self.system, &self.global]
+            .into_iter()
+            .flatten()
+            .flat_map(Registry::entries)
+        {
+            let shadowed = merged
+                .iter()
+                .any(|existing| existing.from == entry.from && existing.exact == entry.exact);
+            if !shadowed {
+                merged.push(entry);
+            }
+        }
+        merged
+    }
+
+    /// Find the first entry matching an indirect flake ref
+    pub fn lookup(&self, indirect: &IndirectRef) -> Option<&RegistryEntry> {
+        [&self.user, &self.system, &self.global]
+            .into_iter()
+            .flatten()
+            .find_map(|registry| registry.lookup(indirect))
+    }
+
+    /// Resolve an indirect flake ref to a concrete flake ref
+    ///
+    /// Unless the matching entry is `exact`,
+    /// the `ref` and `rev` of `indirect` override those of the registry entry's target,
+    /// if the target does not pin them already.
+    /// A `dir` of `indirect` is applied if the target does not specify one.
+    /// Targets that are indirect flake refs themselves are resolved recursively.
+    pub fn resolve(&self, indirect: &IndirectRef) -> Result<FlakeRef, RegistryError> {
+        let mut visited = BTreeSet::new();
+        let mut current = indirect.clone();
+
+        loop {
+            if !visited.insert(current.id.clone()) {
+                return Err(RegistryError::Cycle(indirect.clone()));
+            }
+
+            let entry = self
+                .lookup(&current)
+                .ok_or_else(|| RegistryError::NotFound(current.clone()))?;
+
+            let resolved = if entry.exact {
+                apply_overrides(entry.to.clone(), None, None, current.dir())?
+            } else {
+                apply_overrides(
+                    entry.to.clone(),
+                    current.ref_(),
+                    current.rev(),
+                    current.dir(),
+                )?
+            };
+
+            match resolved {
+                FlakeRef::Indirect(next) => current = next,
+                resolved => return Ok(resolved),
+            }
+        }
+    }
+}
+
+fn read_optional_registry(path: &Path) -> Result<Option<Registry>, RegistryError> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    Registry::from_path(path).map(Some)
+}
+
+/// Apply the `ref`, `rev` and `dir` of an indirect flake ref to the target of a registry entry
+///
+/// Attributes already set on the target take precedence.
+fn apply_overrides(
+    to: FlakeRef,
+    reference: Option<String>,
+    rev: Option<Rev>,
+    dir: Option<PathBuf>,
+) -> Result<FlakeRef, RegistryError> {
+    let reference = reference.filter(|_| to.ref_().is_none() && to.rev().is_none());
+    let rev = rev.filter(|_| to.rev().is_none());
+    let dir = dir.filter(|_| to.dir().is_none());
+
+    macro_rules! with {
+        ($flake_ref:expr) => {{
+            let mut flake_ref = $flake_ref;
+            if let Some(reference) = reference {
+                flake_ref = flake_ref.with_ref(reference);
+            }
+            if let Some(rev) = rev {
+                flake_ref = flake_ref.with_rev(rev);
+            }
+            FlakeRef::from(flake_ref)
+        }};
+    }
+
+    let resolved = match to {
+        FlakeRef::Github(r) => with!(r),
+        FlakeRef::Gitlab(r) => with!(r),
+        FlakeRef::Sourcehut(r) => with!(r),
+        FlakeRef::GitPath(r) => with!(r),
+        FlakeRef::GitSsh(r) => with!(r),
+        FlakeRef::GitHttps(r) => with!(r),
+        FlakeRef::GitHttp(r) => with!(r),
+        FlakeRef::Indirect(r) => with!(r),
+        other => {
+            if reference.is_some() {
+                return Err(RegistryError::UnsupportedOverride("ref", Box::new(other)));
+            }
+            if rev.is_some() {
+                return Err(RegistryError::UnsupportedOverride("rev", Box::new(other)));
+            }
+            other
+        },
+    };
+
+    match dir {
+        Some(dir) => Ok(resolved.with_dir(dir)),
+        None => Ok(resolved),
+    }
 }
 
 impl FromIterator<RegistryEntry> for Registry {
@@ -62,23 +392,120 @@ impl Default for Version {
     }
 }
 
-#[skip_serializing_none]
+/// The registry an entry was read from, as shown by `nix registry list`
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum RegistryScope {
+    /// Entries passed with `--override-flake`
+    Flags,
+    User,
+    System,
+    Global,
+}
+
+impl fmt::Display for RegistryScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistryScope::Flags => write!(f, "flags"),
+            RegistryScope::User => write!(f, "user"),
+            RegistryScope::System => write!(f, "system"),
+            RegistryScope::Global => write!(f, "global"),
+        }
+    }
+}
+
+impl FromStr for RegistryScope {
+    type Err = RegistryError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "flags" => Ok(RegistryScope::Flags),
+            "user" => Ok(RegistryScope::User),
+            "system" => Ok(RegistryScope::System),
+            "global" => Ok(RegistryScope::Global),
+            _ => Err(RegistryError::InvalidEntry(s.to_string())),
+        }
+    }
+}
+
+/// An entry of a `registry.json` file
+///
+/// Serializes like nix, i.e. `exact` is only written if set.
+///
+/// ```
+/// # use runix::flake_ref::git_service::GitServiceRef;
+/// # use runix::flake_ref::indirect::IndirectRef;
+/// # use runix::registry::RegistryEntry;
+/// let entry = RegistryEntry::new(
+///     IndirectRef::from_id("runix"),
+///     GitServiceRef::github("flox", "runix").into(),
+/// );
+/// assert_eq!(
+///     serde_json::to_string(&entry).unwrap(),
+///     r#"{"from":{"id":"runix","type":"indirect"},"to":{"owner":"flox","repo":"runix","type":"github"}}"#
+/// );
+/// ```
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub struct RegistryEntry {
-    pub from: IndirectRef, // TODO merge into single flakeRef type @notgne2?
+    /// The registry the entry was read from, if known
+    #[serde(skip)]
+    pub scope: Option<RegistryScope>,
+    /// Whether the entry only matches flake refs with exactly the attributes of [RegistryEntry::from]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub exact: bool,
+    /// The flake ref replaced by the entry, usually an [IndirectRef]
+    pub from: FlakeRef,
     pub to: FlakeRef,
-    pub exact: Option<bool>,
 }
 
+impl RegistryEntry {
+    pub fn new(from: impl Into<FlakeRef>, to: FlakeRef) -> Self {
+        RegistryEntry {
+            scope: None,
+            exact: false,
+            from: from.into(),
+            to,
+        }
+    }
+
+    /// Parse a line printed by `nix registry list`, e.g.
+    /// `global flake:nixpkgs github:NixOS/nixpkgs/nixpkgs-unstable`
+    pub fn from_list_line(line: &str) -> Result<Self, RegistryError> {
+        let invalid = || RegistryError::InvalidEntry(line.to_string());
+
+        let mut fields = line.split_whitespace();
+        let (Some(scope), Some(from), Some(to), None) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            return Err(invalid());
+        };
+
+        Ok(RegistryEntry {
+            scope: Some(scope.parse()?),
+            ..RegistryEntry::new(
+                from.parse::<FlakeRef>().map_err(|_| invalid())?,
+                to.parse().map_err(|_| invalid())?,
+            )
+        })
+    }
+}
+
+/// Entries are ordered by [RegistryEntry::from], compared by their url
+///
+/// Entries with the same `from`, e.g. an exact and an inexact one, are distinct.
 impl Ord for RegistryEntry {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.from.cmp(&other.from)
+        (self.from.to_string(), self.exact, self.to.to_string()).cmp(&(
+            other.from.to_string(),
+            other.exact,
+            other.to.to_string(),
+        ))
     }
 }
 
 impl PartialOrd for RegistryEntry {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.from.partial_cmp(&other.from)
+        Some(self.cmp(other))
     }
 }
 
@@ -94,9 +521,189 @@ mod tests {
     use std::fs::File;
 
     use super::*;
+    use crate::flake_ref::git_service::GitServiceRef;
+
     #[test]
     fn parses_nix_registry() {
         serde_json::from_reader::<_, Registry>(File::open("./test/registry.test.json").unwrap())
             .expect("should parse");
     }
+
+    #[test]
+    fn resolves_from_registries() {
+        let registries = Registries {
+            user: Some(Registry::from_iter([RegistryEntry::new(
+                IndirectRef::from_id("runix"),
+                GitServiceRef::github("flox", "runix").into(),
+            )])),
+            system: None,
+            global: Some(Registry::from_path("./test/registry.test.json").unwrap()),
+        };
+
+        // user registry
+        assert_eq!(
+            registries
+                .resolve(
+                    &IndirectRef::from_id("runix")
+                        .with_ref("main")
+                        .with_dir("crates")
+                )
+                .unwrap()
+                .to_string(),
+            "github:flox/runix/main?dir=crates"
+        );
+
+        // global registry, pinned refs are not overridden
+        assert_eq!(
+            registries
+                .resolve(&IndirectRef::from_id("flox").with_ref("main"))
+                .unwrap()
+                .to_string(),
+            "github:flox/floxpkgs/master"
+        );
+
+        assert!(matches!(
+            registries.resolve(&IndirectRef::from_id("unknown")),
+            Err(RegistryError::NotFound(_))
+        ));
+
+        // paths do not support overrides
+        assert!(matches!(
+            registries.resolve(&IndirectRef::from_id("local").with_ref("main")),
+            Err(RegistryError::UnsupportedOverride("ref", _))
+        ));
+    }
+
+    #[test]
+    fn resolves_recursively() {
+        let registries = Registries {
+            user: Some(Registry::from_iter([
+                RegistryEntry::new(IndirectRef::from_id("a"), IndirectRef::from_id("b").into()),
+                RegistryEntry::new(
+                    IndirectRef::from_id("b"),
+                    GitServiceRef::github("flox", "runix").into(),
+                ),
+                RegistryEntry::new(
+                    IndirectRef::from_id("cycle"),
+                    IndirectRef::from_id("cycle").into(),
+                ),
+            ])),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            registries
+                .resolve(&IndirectRef::from_id("a").with_ref("main"))
+                .unwrap()
+                .to_string(),
+            "github:flox/runix/main"
+        );
+        assert!(matches!(
+            registries.resolve(&IndirectRef::from_id("cycle")),
+            Err(RegistryError::Cycle(_))
+        ));
+    }
+
+    #[test]
+    fn exact_entries() {
+        let registry = Registry::from_iter([RegistryEntry {
+            exact: true,
+            ..RegistryEntry::new(
+                IndirectRef::from_id("nixpkgs").with_ref("nixos-23.05"),
+                GitServiceRef::github("NixOS", "nixpkgs")
+                    .with_ref("nixos-23.05")
+                    .into(),
+            )
+        }]);
+
+        assert!(registry
+            .lookup(&IndirectRef::from_id("nixpkgs").with_ref("nixos-23.05"))
+            .is_some());
+        assert!(registry.lookup(&IndirectRef::from_id("nixpkgs")).is_none());
+    }
+
+    #[test]
+    fn edits_registry_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nix/registry.json");
+
+        let removed = Registry::update(&path, |registry| {
+            registry.set("a", GitServiceRef::github("flox", "a").into());
+            registry.set("b", GitServiceRef::github("flox", "b").into());
+            registry.set("a", GitServiceRef::github("flox", "runix").into());
+            registry.remove("b")
+        })
+        .unwrap();
+        assert!(removed);
+
+        let registry = Registry::from_path(&path).unwrap();
+        assert_eq!(registry.entries().count(), 1);
+        assert_eq!(
+            registry.get("a").unwrap().to.to_string(),
+            "github:flox/runix"
+        );
+        assert!(registry.get("b").is_none());
+
+        std::fs::write(&path, r#"{"flakes": [], "version": 1}"#).unwrap();
+        assert!(matches!(
+            Registry::from_path(&path),
+            Err(RegistryError::UnsupportedVersion(_, 1))
+        ));
+    }
+
+    #[test]
+    fn merges_scopes() {
+        let registries = Registries {
+            user: Some(
+                Registry::from_iter([RegistryEntry::new(
+                    IndirectRef::from_id("flox"),
+                    GitServiceRef::github("flox", "flox").into(),
+                )])
+                .with_scope(RegistryScope::User),
+            ),
+            system: None,
+            global: Some(
+                Registry::from_path("./test/registry.test.json")
+                    .unwrap()
+                    .with_scope(RegistryScope::Global),
+            ),
+        };
+
+        let merged = registries.merged();
+        let flox: Vec<_> = merged
+            .iter()
+            .filter(|entry| entry.from == IndirectRef::from_id("flox").into())
+            .collect();
+        assert_eq!(flox.len(), 1);
+        assert_eq!(flox[0].scope, Some(RegistryScope::User));
+        assert_eq!(
+            merged.len(),
+            registries.global.as_ref().unwrap().entries().count()
+        );
+    }
+
+    #[test]
+    fn parses_list_lines() {
+        let entry = RegistryEntry::from_list_line(
+            "global flake:nixpkgs github:NixOS/nixpkgs/nixpkgs-unstable",
+        )
+        .unwrap();
+        assert_eq!(entry.scope, Some(RegistryScope::Global));
+        assert_eq!(entry.from, IndirectRef::from_id("nixpkgs").into());
+        assert_eq!(
+            entry.to.to_string(),
+            "github:NixOS/nixpkgs/nixpkgs-unstable"
+        );
+
+        assert!(RegistryEntry::from_list_line("user flake:nixpkgs").is_err());
+        assert!(RegistryEntry::from_list_line("remote flake:a flake:b").is_err());
+
+        let registry = Registry::from_path("./test/registry.test.json")
+            .unwrap()
+            .with_scope(RegistryScope::System);
+        assert!(registry
+            .entries()
+            .all(|entry| entry.scope == Some(RegistryScope::System)));
+        assert!(registry.entries().any(|entry| entry.exact));
+    }
 }
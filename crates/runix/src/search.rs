@@ -0,0 +1,116 @@
+//! Packages found by `nix search --json`, see [crate::command::Search]
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+/// Output sets of a flake whose second attribute is a system
+const PER_SYSTEM_OUTPUTS: &[&str] = &["packages", "legacyPackages"];
+
+/// A package found by [crate::command::Search]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SearchItem {
+    pub pname: String,
+    pub version: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+/// The packages found by [crate::command::Search] by attribute path,
+/// e.g. `legacyPackages.x86_64-linux.hello`
+///
+/// Nixpkgs provides many packages under several attribute paths,
+/// e.g. `python3Packages.requests` and `python311Packages.requests`.
+/// [SearchResult::dedup] keeps one of them:
+///
+/// ```
+/// # use runix::search::SearchResult;
+/// let result: SearchResult = serde_json::from_str(
+///     r#"{
+///         "legacyPackages.x86_64-linux.python311Packages.requests": {
+///             "description": "HTTP library for Python",
+///             "pname": "python3.11-requests",
+///             "version": "2.31.0"
+///         },
+///         "legacyPackages.x86_64-linux.python3Packages.requests": {
+///             "description": "HTTP library for Python",
+///             "pname": "python3.11-requests",
+///             "version": "2.31.0"
+///         },
+///         "legacyPackages.aarch64-darwin.python3Packages.requests": {
+///             "description": "HTTP library for Python",
+///             "pname": "python3.11-requests",
+///             "version": "2.31.0"
+///         }
+///     }"#,
+/// )
+/// .unwrap();
+///
+/// let deduplicated = result.for_system("x86_64-linux").dedup();
+/// assert_eq!(deduplicated.0.keys().collect::<Vec<_>>(), [
+///     "legacyPackages.x86_64-linux.python3Packages.requests"
+/// ]);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SearchResult(pub BTreeMap<String, SearchItem>);
+
+impl SearchResult {
+    /// The system of the package at `attr_path`,
+    /// [None] if it is not part of a per system output, e.g. when searching a file
+    pub fn system(attr_path: &str) -> Option<&str> {
+        let mut components = attr_path.split('.');
+        match (components.next(), components.next()) {
+            (Some(output), Some(system)) if PER_SYSTEM_OUTPUTS.contains(&output) => Some(system),
+            _ => None,
+        }
+    }
+
+    /// The packages built for `system`
+    pub fn for_system(&self, system: &str) -> SearchResult {
+        SearchResult(
+            self.0
+                .iter()
+                .filter(|(attr_path, _)| Self::system(attr_path) == Some(system))
+                .map(|(attr_path, item)| (attr_path.clone(), item.clone()))
+                .collect(),
+        )
+    }
+
+    /// Keep a single attribute path of packages found under several aliases
+    ///
+    /// Packages of the same system with equal name, version and description are considered
+    /// the same, of which the shortest attribute path is kept.
+    pub fn dedup(&self) -> SearchResult {
+        let mut kept: HashMap<(Option<&str>, &SearchItem), &str> = HashMap::new();
+        for (attr_path, item) in &self.0 {
+            let shortest = kept
+                .entry((Self::system(attr_path), item))
+                .or_insert(attr_path);
+            if attr_path.len() < shortest.len() {
+                *shortest = attr_path;
+            }
+        }
+
+        SearchResult(
+            kept.into_iter()
+                .map(|((_, item), attr_path)| (attr_path.to_string(), item.clone()))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn system_of_attr_path() {
+        assert_eq!(
+            SearchResult::system("packages.aarch64-linux.hello"),
+            Some("aarch64-linux")
+        );
+        assert_eq!(SearchResult::system("hello"), None);
+        assert_eq!(SearchResult::system("python3Packages.requests"), None);
+    }
+}
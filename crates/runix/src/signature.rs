@@ -0,0 +1,262 @@
+//! Ed25519 signatures of store paths, as created by `nix store sign` and checked by nix
+//! against its `trusted-public-keys`
+//!
+//! Nix signs the [fingerprint] of a store path, which covers its path, NAR hash,
+//! NAR size and references. Keys and signatures are written as `<key name>:<base64>`.
+//!
+//! ```
+//! # use runix::signature::{PublicKey, SecretKey};
+//! let key: SecretKey = "cache.example.org-1:\
+//!     xFs6r1S3lsqZEi3MpiKMfUCz8yB2+SnHIRb74QCJCCry02vbNAhm8hKU6QQvA+0ZPNdLO6fa9zEj6hEa0I4G+Q=="
+//!     .parse()
+//!     .unwrap();
+//! let fingerprint = "1;/nix/store/sbldylj3clbkc0aqvjjzfa6slp4zdvlj-hello-2.12.1;\
+//!     sha256:0c6sgmk4pimf1qn9vycqbafphh0b4a3dyvcswjpy48rcx3qqbhgw;226560;";
+//!
+//! let sig = key.sign(fingerprint);
+//! assert!(sig.starts_with("cache.example.org-1:"));
+//! assert!(key.public_key().verify(fingerprint, &sig));
+//! assert!(!key.public_key().verify("1;/nix/store/...", &sig));
+//! ```
+
+use std::fmt::Display;
+use std::path::Path;
+use std::str::FromStr;
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use thiserror::Error;
+
+use crate::flake_ref::lock::NarHash;
+use crate::hash::{HashAlgorithm, HashFormat};
+use crate::store_path::StorePath;
+
+#[derive(Debug, Error)]
+pub enum KeyError {
+    #[error("Could not read key file: {0}")]
+    Read(#[from] std::io::Error),
+    #[error("Key '{0}' is not of the form '<name>:<base64>'")]
+    Format(String),
+    #[error("Key '{0}' is not a valid ed25519 key")]
+    InvalidKey(String),
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum FingerprintError {
+    #[error("NAR hash '{0}' is not a sha256 hash")]
+    NarHashAlgorithm(NarHash),
+}
+
+/// A key signing store paths, e.g. read from the file passed to `nix store sign --key-file`
+#[derive(Debug, Clone)]
+pub struct SecretKey {
+    pub name: String,
+    key: SigningKey,
+}
+
+impl SecretKey {
+    /// Read a key file written by `nix key generate-secret`
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, KeyError> {
+        std::fs::read_to_string(path)?.parse()
+    }
+
+    /// The public key verifying signatures made with this key
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey {
+            name: self.name.clone(),
+            key: self.key.verifying_key(),
+        }
+    }
+
+    /// Sign `fingerprint`, returning a signature in the format of narinfo `Sig` entries
+    pub fn sign(&self, fingerprint: &str) -> String {
+        let signature = self.key.sign(fingerprint.as_bytes());
+        format!("{}:{}", self.name, base64::encode(signature.to_bytes()))
+    }
+}
+
+impl FromStr for SecretKey {
+    type Err = KeyError;
+
+    /// Parse a secret key as written by `nix key generate-secret`,
+    /// whose 64 bytes are the seed followed by the public key
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, bytes) = split_key(s)?;
+        let bytes: [u8; 64] = bytes
+            .try_into()
+            .map_err(|_| KeyError::InvalidKey(name.to_string()))?;
+        let key = SigningKey::from_keypair_bytes(&bytes)
+            .map_err(|_| KeyError::InvalidKey(name.to_string()))?;
+        Ok(SecretKey {
+            name: name.to_string(),
+            key,
+        })
+    }
+}
+
+impl Display for SecretKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}",
+            self.name,
+            base64::encode(self.key.to_keypair_bytes())
+        )
+    }
+}
+
+/// A key verifying signatures of store paths, e.g. an entry of `trusted-public-keys`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicKey {
+    pub name: String,
+    key: VerifyingKey,
+}
+
+impl PublicKey {
+    /// Whether `sig` is a valid signature of `fingerprint` made by this key
+    ///
+    /// Signatures by keys of a different name are not valid.
+    pub fn verify(&self, fingerprint: &str, sig: &str) -> bool {
+        let Some((name, signature)) = sig.split_once(':') else {
+            return false;
+        };
+        let Some(signature) = base64::decode(signature)
+            .ok()
+            .and_then(|bytes| Signature::from_slice(&bytes).ok())
+        else {
+            return false;
+        };
+        name == self.name && self.key.verify(fingerprint.as_bytes(), &signature).is_ok()
+    }
+}
+
+impl FromStr for PublicKey {
+    type Err = KeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, bytes) = split_key(s)?;
+        let key = bytes
+            .try_into()
+            .ok()
+            .and_then(|bytes: [u8; 32]| VerifyingKey::from_bytes(&bytes).ok())
+            .ok_or_else(|| KeyError::InvalidKey(name.to_string()))?;
+        Ok(PublicKey {
+            name: name.to_string(),
+            key,
+        })
+    }
+}
+
+impl Display for PublicKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.name, base64::encode(self.key.as_bytes()))
+    }
+}
+
+/// Split `<name>:<base64>` into the name and the decoded bytes
+fn split_key(s: &str) -> Result<(&str, Vec<u8>), KeyError> {
+    let format = || KeyError::Format(s.split(':').next().unwrap_or_default().to_string());
+    let (name, key) = s.trim().split_once(':').ok_or_else(format)?;
+    if name.is_empty() {
+        return Err(format());
+    }
+    let bytes = base64::decode(key).map_err(|_| format())?;
+    Ok((name, bytes))
+}
+
+/// The data nix signs for a store path
+///
+/// `1;<store path>;<sha256 NAR hash in nix base32>;<NAR size>;<comma separated references>`
+///
+/// References are sorted, like nix sorts them, regardless of the order they are passed in.
+/// Fails for NAR hashes other than sha256, which nix does not sign.
+pub fn fingerprint(
+    path: &StorePath,
+    nar_hash: &NarHash,
+    nar_size: u64,
+    references: &[StorePath],
+) -> Result<String, FingerprintError> {
+    let hash = nar_hash.hash();
+    if hash.algo != HashAlgorithm::Sha256 {
+        return Err(FingerprintError::NarHashAlgorithm(nar_hash.clone()));
+    }
+
+    let mut references: Vec<_> = references.iter().map(ToString::to_string).collect();
+    references.sort();
+    Ok(format!(
+        "1;{path};{};{nar_size};{}",
+        hash.format(HashFormat::NixBase32),
+        references.join(",")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET_KEY: &str = "cache.example.org-1:xFs6r1S3lsqZEi3MpiKMfUCz8yB2+SnHIRb74QCJCCry02vbNAhm8hKU6QQvA+0ZPNdLO6fa9zEj6hEa0I4G+Q==";
+
+    #[test]
+    fn parses_keys() {
+        let key: SecretKey = SECRET_KEY.parse().unwrap();
+        assert_eq!(key.to_string(), SECRET_KEY);
+
+        let public_key = key.public_key();
+        assert_eq!(
+            public_key.to_string().parse::<PublicKey>().unwrap(),
+            public_key
+        );
+
+        assert!(matches!(
+            "no-colon".parse::<PublicKey>(),
+            Err(KeyError::Format(_))
+        ));
+        assert!(matches!(
+            "name:AAAA".parse::<PublicKey>(),
+            Err(KeyError::InvalidKey(_))
+        ));
+    }
+
+    #[test]
+    fn verifies_signatures_by_name() {
+        let key: SecretKey = SECRET_KEY.parse().unwrap();
+        let sig = key.sign("fingerprint");
+
+        let mut renamed = key.public_key();
+        renamed.name = "other-1".to_string();
+        assert!(!renamed.verify("fingerprint", &sig));
+        assert!(!key.public_key().verify("fingerprint", "garbage"));
+    }
+
+    #[test]
+    fn computes_fingerprints() {
+        let path: StorePath = "/nix/store/sbldylj3clbkc0aqvjjzfa6slp4zdvlj-hello-2.12.1"
+            .parse()
+            .unwrap();
+        let glibc: StorePath = "/nix/store/9l06v7fc38c1x3r2iydl15ksgz0ysb82-glibc-2.37-8"
+            .parse()
+            .unwrap();
+        let nar_hash: NarHash = "sha256-GGaJUxHHQ3Pw8dAQNG5/vXQVJ8KrUbTfI1wSXoQmQBk="
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            fingerprint(&path, &nar_hash, 226560, &[glibc.clone(), path.clone()]).unwrap(),
+            format!(
+                "1;{path};sha256:{};226560;/nix/store/9l06v7fc38c1x3r2iydl15ksgz0ysb82-glibc-2.37-8,{path}",
+                nar_hash.to_nix_base32()
+            )
+        );
+        assert_eq!(
+            fingerprint(&path, &nar_hash, 226560, &[path.clone(), glibc.clone()]),
+            fingerprint(&path, &nar_hash, 226560, &[glibc.clone(), path.clone()])
+        );
+
+        let sha512: NarHash = format!("sha512-{}", base64::encode([0u8; 64]))
+            .parse()
+            .unwrap();
+        assert_eq!(
+            fingerprint(&path, &sha512, 226560, &[glibc]),
+            Err(FingerprintError::NarHashAlgorithm(sha512.clone()))
+        );
+    }
+}
@@ -0,0 +1,327 @@
+//! Read-only queries of the database of the local store, `/nix/var/nix/db/db.sqlite`
+//!
+//! Looking up paths in the database is much faster than running `nix path-info`
+//! or connecting to the daemon, but only works for the local store
+//! and requires read access to the database.
+//! The database is opened read-only and never modified.
+//!
+//! Requires the `sqlite` feature.
+//!
+//! ```no_run
+//! # use runix::store_db::StoreDb;
+//! # use runix::store_path::StorePath;
+//! let db = StoreDb::open_default().unwrap();
+//! let path =
+//!     StorePath::from_path("/nix/store/3dsk2kh0m6xyi5rhjqzwxd4jc4hy6r2s-hello-2.12.1").unwrap();
+//!
+//! if db.is_valid(&path).unwrap() {
+//!     for reference in db.references(&path).unwrap() {
+//!         println!("{reference}");
+//!     }
+//! }
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use thiserror::Error;
+
+use crate::flake_ref::lock::{InvalidNarHash, NarHash};
+use crate::narinfo::Narinfo;
+use crate::store_path::{DrvPath, StorePath, StorePathError, STORE_PREFIX};
+
+/// The default location of the database
+pub const STORE_DB: &str = "/nix/var/nix/db/db.sqlite";
+
+const REFERENCES: &str =
+    "SELECT path FROM Refs JOIN ValidPaths ON reference = id WHERE referrer = ?";
+const REFERRERS: &str =
+    "SELECT path FROM Refs JOIN ValidPaths ON referrer = id WHERE reference = ?";
+
+#[derive(Debug, Error)]
+pub enum StoreDbError {
+    #[error("Could not open the store database '{0}': {1}")]
+    Open(PathBuf, #[source] rusqlite::Error),
+    #[error("Could not query the store database: {0}")]
+    Query(#[from] rusqlite::Error),
+    #[error(transparent)]
+    StorePath(#[from] StorePathError),
+    #[error(transparent)]
+    NarHash(#[from] InvalidNarHash),
+}
+
+/// A read-only connection to the database of a local store
+#[derive(Debug)]
+pub struct StoreDb {
+    connection: Connection,
+    /// The store the database belongs to, [STORE_PREFIX] by default
+    pub store_dir: PathBuf,
+}
+
+impl StoreDb {
+    /// Open the database at `path` of the store at `store_dir`
+    pub fn open(
+        path: impl AsRef<Path>,
+        store_dir: impl Into<PathBuf>,
+    ) -> Result<Self, StoreDbError> {
+        let path = path.as_ref();
+        let connection = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )
+        .map_err(|e| StoreDbError::Open(path.to_path_buf(), e))?;
+
+        Ok(StoreDb {
+            connection,
+            store_dir: store_dir.into(),
+        })
+    }
+
+    /// Open the database of the default store at [STORE_DB]
+    pub fn open_default() -> Result<Self, StoreDbError> {
+        Self::open(STORE_DB, &*STORE_PREFIX)
+    }
+
+    /// Whether `path` is registered in the store
+    pub fn is_valid(&self, path: &StorePath) -> Result<bool, StoreDbError> {
+        Ok(self.id(path)?.is_some())
+    }
+
+    /// Metadata of `path`, [None] if the path is not valid
+    ///
+    /// Like [crate::daemon::NixDaemon::query_path_info], without `closure_size`.
+    pub fn query_path_info(&self, path: &StorePath) -> Result<Option<Narinfo>, StoreDbError> {
+        let row = self
+            .connection
+            .query_row(
+                "SELECT id, hash, registrationTime, deriver, narSize, ultimate, sigs, ca \
+                 FROM ValidPaths WHERE path = ?",
+                [path.out_path().to_string_lossy()],
+                |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, i64>(2)?,
+                        row.get::<_, Option<String>>(3)?,
+                        row.get::<_, Option<i64>>(4)?,
+                        row.get::<_, Option<bool>>(5)?,
+                        row.get::<_, Option<String>>(6)?,
+                        row.get::<_, Option<String>>(7)?,
+                    ))
+                },
+            )
+            .optional()?;
+        let Some((id, hash, registration_time, deriver, nar_size, ultimate, sigs, ca)) = row else {
+            return Ok(None);
+        };
+
+        Ok(Some(Narinfo {
+            path: StorePath::new_unchecked(path.prefix(), path.basename(), None::<PathBuf>),
+            valid: true,
+            nar_hash: Some(hash.parse::<NarHash>()?),
+            nar_size: nar_size.map(|size| size as u64),
+            references: self.query_paths(REFERENCES, id)?,
+            closure_size: None,
+            sigs: sigs
+                .unwrap_or_default()
+                .split_whitespace()
+                .map(ToString::to_string)
+                .collect(),
+            deriver: deriver
+                .filter(|deriver| !deriver.is_empty())
+                .map(|deriver| self.drv_path(&deriver))
+                .transpose()?,
+            registration_time: Some(registration_time),
+            ultimate: ultimate.unwrap_or_default(),
+            ca: ca.filter(|ca| !ca.is_empty()),
+            download_hash: None,
+            download_size: None,
+            _other: HashMap::new(),
+        }))
+    }
+
+    /// The store paths referenced by `path`, empty if the path is not valid
+    pub fn references(&self, path: &StorePath) -> Result<Vec<StorePath>, StoreDbError> {
+        let Some(id) = self.id(path)? else {
+            return Ok(Vec::new());
+        };
+        self.query_paths(REFERENCES, id)
+    }
+
+    /// The valid store paths referencing `path`, as shown by `nix-store --query --referrers`
+    pub fn referrers(&self, path: &StorePath) -> Result<Vec<StorePath>, StoreDbError> {
+        let Some(id) = self.id(path)? else {
+            return Ok(Vec::new());
+        };
+        self.query_paths(REFERRERS, id)
+    }
+
+    /// The derivation that produced `path`, if known
+    pub fn deriver(&self, path: &StorePath) -> Result<Option<DrvPath>, StoreDbError> {
+        let deriver: Option<Option<String>> = self
+            .connection
+            .query_row(
+                "SELECT deriver FROM ValidPaths WHERE path = ?",
+                [path.out_path().to_string_lossy()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        deriver
+            .flatten()
+            .filter(|deriver| !deriver.is_empty())
+            .map(|deriver| self.drv_path(&deriver))
+            .transpose()
+    }
+
+    /// The outputs of the derivation at `drv_path` by name, empty if the derivation is not valid
+    ///
+    /// Outputs are registered when the derivation is added to the store,
+    /// they are not necessarily valid.
+    pub fn derivation_outputs(
+        &self,
+        drv_path: &DrvPath,
+    ) -> Result<BTreeMap<String, StorePath>, StoreDbError> {
+        let Some(id) = self.id(drv_path)? else {
+            return Ok(BTreeMap::new());
+        };
+        let mut statement = self
+            .connection
+            .prepare_cached("SELECT id, path FROM DerivationOutputs WHERE drv = ?")?;
+        let rows = statement.query_map(params![id], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+
+        let mut outputs = BTreeMap::new();
+        for row in rows {
+            let (name, path) = row?;
+            outputs.insert(name, self.store_path(&path)?);
+        }
+        Ok(outputs)
+    }
+
+    /// The id of the row of `path` in `ValidPaths`
+    fn id(&self, path: &StorePath) -> Result<Option<i64>, StoreDbError> {
+        Ok(self
+            .connection
+            .query_row(
+                "SELECT id FROM ValidPaths WHERE path = ?",
+                [path.out_path().to_string_lossy()],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /// Run `query` selecting paths with the parameter `id`, sorted like nix sorts them
+    fn query_paths(&self, query: &str, id: i64) -> Result<Vec<StorePath>, StoreDbError> {
+        let mut statement = self.connection.prepare_cached(query)?;
+        let rows = statement.query_map(params![id], |row| row.get::<_, String>(0))?;
+
+        let mut paths = Vec::new();
+        for row in rows {
+            paths.push(self.store_path(&row?)?);
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    fn store_path(&self, path: &str) -> Result<StorePath, StoreDbError> {
+        Ok(StorePath::from_path_in(&self.store_dir, path)?)
+    }
+
+    fn drv_path(&self, path: &str) -> Result<DrvPath, StoreDbError> {
+        Ok(self.store_path(path)?.try_into()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The tables of the nix database queried by [StoreDb]
+    const SCHEMA: &str = "
+        CREATE TABLE ValidPaths (
+            id integer primary key autoincrement not null,
+            path text unique not null,
+            hash text not null,
+            registrationTime integer not null,
+            deriver text,
+            narSize integer,
+            ultimate integer,
+            sigs text,
+            ca text
+        );
+        CREATE TABLE Refs (
+            referrer integer not null,
+            reference integer not null,
+            primary key (referrer, reference)
+        );
+        CREATE TABLE DerivationOutputs (
+            drv integer not null,
+            id text not null,
+            path text not null,
+            primary key (drv, id)
+        );
+    ";
+
+    const HASH: &str = "sha256:18669253d1f9d7b3ca3d16ee8fdde50ea1fd2d2f8b09edae4a9fcbb7e9e3b2f1";
+
+    #[test]
+    fn queries_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("db.sqlite");
+        let store = "/nix/store";
+        let hello = format!("{store}/3dsk2kh0m6xyi5rhjqzwxd4jc4hy6r2s-hello-2.12.1");
+        let glibc = format!("{store}/9l06v7fc38c1x3r2iydl15ksgz0ysb82-glibc-2.37-8");
+        let drv = format!("{store}/7rjqb838snvvxcmpvck1smfxhkwzqal5-hello-2.12.1.drv");
+
+        let connection = Connection::open(&db_path).unwrap();
+        connection.execute_batch(SCHEMA).unwrap();
+        connection
+            .execute(
+                "INSERT INTO ValidPaths (id, path, hash, registrationTime, deriver, narSize, ultimate, sigs) \
+                 VALUES (1, ?1, ?2, 1688730350, ?3, 226560, 1, 'cache.example.org-1:AAAA'), \
+                        (2, ?4, ?2, 1688730340, NULL, 29950360, 0, ''), \
+                        (3, ?3, ?2, 1688730300, '', 1480, 1, NULL)",
+                params![hello, HASH, drv, glibc],
+            )
+            .unwrap();
+        connection
+            .execute_batch("INSERT INTO Refs VALUES (1, 1), (1, 2), (2, 2)")
+            .unwrap();
+        connection
+            .execute("INSERT INTO DerivationOutputs VALUES (3, 'out', ?)", [
+                &hello,
+            ])
+            .unwrap();
+        drop(connection);
+
+        let db = StoreDb::open(&db_path, store).unwrap();
+        let hello: StorePath = hello.parse().unwrap();
+        let glibc: StorePath = glibc.parse().unwrap();
+        let drv: DrvPath = drv.parse().unwrap();
+
+        let info = db.query_path_info(&hello).unwrap().unwrap();
+        assert_eq!(info.nar_hash, Some(HASH.parse().unwrap()));
+        assert_eq!(info.nar_size, Some(226560));
+        assert_eq!(info.references, [hello.clone(), glibc.clone()]);
+        assert_eq!(info.sigs, ["cache.example.org-1:AAAA"]);
+        assert_eq!(info.deriver.as_ref(), Some(&drv));
+        assert!(info.ultimate);
+
+        assert_eq!(db.referrers(&glibc).unwrap(), [
+            hello.clone(),
+            glibc.clone()
+        ]);
+        assert_eq!(db.deriver(&glibc).unwrap(), None);
+        assert_eq!(db.deriver(&hello).unwrap(), Some(drv.clone()));
+        assert_eq!(db.derivation_outputs(&drv).unwrap()["out"], hello);
+
+        let missing: StorePath = "/nix/store/sbldylj3clbkc0aqvjjzfa6slp4zdvlj-hello-2.12.1"
+            .parse()
+            .unwrap();
+        assert!(!db.is_valid(&missing).unwrap());
+        assert!(db.query_path_info(&missing).unwrap().is_none());
+        assert!(db.references(&missing).unwrap().is_empty());
+    }
+}
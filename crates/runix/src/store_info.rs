@@ -0,0 +1,34 @@
+//! Information about a nix store, as shown by `nix store info --json`
+//!
+//! Reported by [crate::command::StoreInfo], by [crate::daemon::NixDaemon::store_info]
+//! and by [crate::command_line::remote::RemoteStore::check] to verify that a store is reachable.
+
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, BoolFromInt};
+
+/// A reachable nix store
+///
+/// ```
+/// # use runix::store_info::StoreInfo;
+/// let info: StoreInfo =
+///     serde_json::from_str(r#"{"trusted":1,"url":"daemon","version":"2.18.1"}"#).unwrap();
+/// assert_eq!(info.trusted, Some(true));
+/// assert_eq!(info.max_jobs, None);
+/// ```
+#[serde_as]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StoreInfo {
+    /// The URL of the store, e.g. `daemon` or `ssh-ng://builder`
+    pub url: String,
+    /// The version of nix serving the store, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Whether the store trusts the user, if known
+    #[serde_as(as = "Option<BoolFromInt>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trusted: Option<bool>,
+    /// The number of builds the store runs in parallel, if reported
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_jobs: Option<u32>,
+}
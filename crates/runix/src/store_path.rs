@@ -3,7 +3,9 @@ use std::fmt::Display;
 use std::path::{Component, Path, PathBuf};
 use std::str::FromStr;
 
+use derive_more::Deref;
 use once_cell::sync::Lazy;
+use serde_with::{DeserializeFromStr, SerializeDisplay};
 use thiserror::Error;
 
 /// Respect [NIX_STORE_DIR](https://nixos.org/manual/nix/stable/command-ref/env-common.html#env-NIX_STORE_DIR)
@@ -17,7 +19,19 @@ pub static STORE_PREFIX: Lazy<PathBuf> = Lazy::new(|| {
     .to_path_buf()
 });
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+/// The length of the hash part of store paths
+pub const HASH_PART_LEN: usize = 32;
+
+/// The characters of the base32 alphabet used by nix, which omits `e`, `o`, `u` and `t`
+const NIX_BASE32_CHARS: &str = "0123456789abcdfghijklmnpqrsvwxyz";
+
+/// The maximum length of the name of store paths
+const MAX_NAME_LEN: usize = 211;
+
+/// A path in the nix store, i.e. `/nix/store/<hash>-<name>` and optionally a path inside it
+#[derive(
+    Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, DeserializeFromStr, SerializeDisplay,
+)]
 pub struct StorePath {
     prefix: PathBuf,
     basename: String,
@@ -56,6 +70,30 @@ impl StorePath {
         &self.basename
     }
 
+    /// the hash part of the basename, identifying the store path
+    ///
+    /// ```
+    /// # use runix::store_path::StorePath;
+    /// let path = StorePath::from_path("/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-python3-3.10.10")
+    ///     .unwrap();
+    /// assert_eq!(path.hash_part(), "7rjqb838snvvxcmpvck1smfxhkwzqal5");
+    /// ```
+    pub fn hash_part(&self) -> &str {
+        &self.basename[..HASH_PART_LEN.min(self.basename.len())]
+    }
+
+    /// the name part of the basename, e.g. the name and version of a package
+    ///
+    /// ```
+    /// # use runix::store_path::StorePath;
+    /// let path = StorePath::from_path("/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-python3-3.10.10")
+    ///     .unwrap();
+    /// assert_eq!(path.name(), "python3-3.10.10");
+    /// ```
+    pub fn name(&self) -> &str {
+        self.basename.get(HASH_PART_LEN + 1..).unwrap_or_default()
+    }
+
     /// the package's path in the nix store
     ///
     /// drops all further components that might have been originally passed
@@ -110,6 +148,22 @@ impl StorePath {
         &mut self.package_path
     }
 
+    /// whether the store path is the path of a derivation
+    ///
+    /// ```
+    /// # use runix::store_path::StorePath;
+    /// let drv = StorePath::from_path("/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-hello-2.12.1.drv")
+    ///     .unwrap();
+    /// assert!(drv.is_derivation());
+    ///
+    /// let out =
+    ///     StorePath::from_path("/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-hello-2.12.1").unwrap();
+    /// assert!(!out.is_derivation());
+    /// ```
+    pub fn is_derivation(&self) -> bool {
+        self.basename.ends_with(".drv") && self.package_path.is_none()
+    }
+
     /// Combine components of store path into a native path type
     ///
     /// If parsed from a path, should return an equivalent path
@@ -137,6 +191,7 @@ impl StorePath {
     /// * if the path's prefix does not equal the [STORE_PREFIX]
     /// * if the path contains '..' components
     /// * if the path only contains the [STORE_PREFIX]
+    /// * if the basename is not a hash followed by a valid name
     /// ```
     /// # use runix::store_path::{StorePath, StorePathError, STORE_PREFIX};
     ///
@@ -147,7 +202,7 @@ impl StorePath {
     ///
     /// assert!(matches!(
     ///     StorePath::from_path("/var/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-python3-3.10.10"),
-    ///     Err(StorePathError::NotAStorePath(_))
+    ///     Err(StorePathError::NotAStorePath(..))
     /// ));
     ///
     /// assert!(matches!(
@@ -161,32 +216,43 @@ impl StorePath {
     ///     StorePath::from_path("/nix/store/"),
     ///     Err(StorePathError::NoPackage(_))
     /// ));
+    ///
+    /// assert!(matches!(
+    ///     StorePath::from_path("/nix/store/not-a-hash-python3-3.10.10"),
+    ///     Err(StorePathError::InvalidHashPart(_))
+    /// ));
     /// ```
     pub fn from_path(path: impl AsRef<Path>) -> Result<Self, StorePathError> {
         Self::try_from(path.as_ref().to_path_buf())
     }
 
-    /// Create a store path from its components
+    /// Try parsing a store path of the store at `store_dir` rather than [STORE_PREFIX]
     ///
-    /// Should be used with care.
-    /// Use [`StorePath::from_path`] instead to create a validated store path
-    pub fn new_unchecked(
-        prefix: impl AsRef<Path>,
-        name: impl AsRef<str>,
-        package_path: Option<impl AsRef<Path>>,
-    ) -> Self {
-        StorePath {
-            prefix: prefix.as_ref().to_path_buf(),
-            basename: name.as_ref().to_string(),
-            package_path: package_path.map(|p| p.as_ref().to_path_buf()),
-        }
-    }
-}
-
-impl TryFrom<PathBuf> for StorePath {
-    type Error = StorePathError;
-
-    fn try_from(mut value: PathBuf) -> Result<Self, Self::Error> {
+    /// Useful to inspect stores other than the one used by nix, e.g. `nix copy --to /mnt`.
+    ///
+    /// ```
+    /// # use std::path::Path;
+    /// # use runix::store_path::StorePath;
+    /// let path = StorePath::from_path_in(
+    ///     "/mnt/nix/store",
+    ///     "/mnt/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-python3-3.10.10",
+    /// )
+    /// .unwrap();
+    /// assert_eq!(path.prefix(), Path::new("/mnt/nix/store"));
+    /// assert_eq!(path.name(), "python3-3.10.10");
+    ///
+    /// StorePath::from_path_in(
+    ///     "/mnt/nix/store",
+    ///     "/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-python3-3.10.10",
+    /// )
+    /// .unwrap_err();
+    /// ```
+    pub fn from_path_in(
+        store_dir: impl AsRef<Path>,
+        path: impl AsRef<Path>,
+    ) -> Result<Self, StorePathError> {
+        let store_dir = store_dir.as_ref();
+        let mut value = path.as_ref().to_path_buf();
         // canonicalize relative path to resolve paths for `./result/` links
         if value.is_relative() {
             value = value
@@ -196,8 +262,8 @@ impl TryFrom<PathBuf> for StorePath {
 
         let mut components = value
             .as_path()
-            .strip_prefix(&*STORE_PREFIX)
-            .map_err(|_| StorePathError::NotAStorePath(value.clone()))?
+            .strip_prefix(store_dir)
+            .map_err(|_| StorePathError::NotAStorePath(value.clone(), store_dir.to_path_buf()))?
             .components()
             .peekable();
 
@@ -217,9 +283,10 @@ impl TryFrom<PathBuf> for StorePath {
             .as_os_str()
             .to_string_lossy()
             .into_owned();
+        Self::validate_basename(&basename, &value)?;
 
         let mut path = StorePath {
-            prefix: STORE_PREFIX.to_path_buf(),
+            prefix: store_dir.to_path_buf(),
             basename,
             package_path: None,
         };
@@ -230,6 +297,50 @@ impl TryFrom<PathBuf> for StorePath {
 
         Ok(path)
     }
+
+    /// Check that the basename of `path` consists of a nix base32 hash, a dash and a valid name
+    fn validate_basename(basename: &str, path: &Path) -> Result<(), StorePathError> {
+        let name = basename
+            .split_at_checked(HASH_PART_LEN)
+            .filter(|(hash_part, _)| hash_part.chars().all(|c| NIX_BASE32_CHARS.contains(c)))
+            .and_then(|(_, name)| name.strip_prefix('-'))
+            .ok_or_else(|| StorePathError::InvalidHashPart(path.to_path_buf()))?;
+
+        let valid_name = !name.is_empty()
+            && name.len() <= MAX_NAME_LEN
+            && !name.starts_with('.')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "+-._?=".contains(c));
+        if !valid_name {
+            return Err(StorePathError::InvalidName(path.to_path_buf()));
+        }
+        Ok(())
+    }
+
+    /// Create a store path from its components
+    ///
+    /// Should be used with care.
+    /// Use [`StorePath::from_path`] instead to create a validated store path
+    pub fn new_unchecked(
+        prefix: impl AsRef<Path>,
+        name: impl AsRef<str>,
+        package_path: Option<impl AsRef<Path>>,
+    ) -> Self {
+        StorePath {
+            prefix: prefix.as_ref().to_path_buf(),
+            basename: name.as_ref().to_string(),
+            package_path: package_path.map(|p| p.as_ref().to_path_buf()),
+        }
+    }
+}
+
+impl TryFrom<PathBuf> for StorePath {
+    type Error = StorePathError;
+
+    fn try_from(value: PathBuf) -> Result<Self, Self::Error> {
+        Self::from_path_in(&*STORE_PREFIX, value)
+    }
 }
 
 impl FromStr for StorePath {
@@ -246,14 +357,72 @@ impl Display for StorePath {
     }
 }
 
+/// The store path of a derivation, see [StorePath::is_derivation]
+///
+/// ```
+/// # use runix::store_path::DrvPath;
+/// let drv: DrvPath = "/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-hello-2.12.1.drv"
+///     .parse()
+///     .unwrap();
+/// assert_eq!(
+///     drv.basename(),
+///     "7rjqb838snvvxcmpvck1smfxhkwzqal5-hello-2.12.1.drv"
+/// );
+///
+/// "/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-hello-2.12.1"
+///     .parse::<DrvPath>()
+///     .unwrap_err();
+/// ```
+#[derive(
+    Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Deref, DeserializeFromStr, SerializeDisplay,
+)]
+pub struct DrvPath(StorePath);
+
+impl TryFrom<StorePath> for DrvPath {
+    type Error = StorePathError;
+
+    fn try_from(store_path: StorePath) -> Result<Self, Self::Error> {
+        if !store_path.is_derivation() {
+            return Err(StorePathError::NotADerivation(store_path.as_path()));
+        }
+        Ok(DrvPath(store_path))
+    }
+}
+
+impl From<DrvPath> for StorePath {
+    fn from(drv_path: DrvPath) -> Self {
+        drv_path.0
+    }
+}
+
+impl FromStr for DrvPath {
+    type Err = StorePathError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse::<StorePath>()?.try_into()
+    }
+}
+
+impl Display for DrvPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum StorePathError {
     #[error("'{0}' is a relative path and could not be canonicalized")]
     RelativePath(PathBuf, std::io::Error),
     #[error("'{0}' contains invalid components (e.g. '..')")]
     RelativeComponent(PathBuf),
-    #[error("'{0}' is not a store path (not a child of {:?})", &*STORE_PREFIX)]
-    NotAStorePath(PathBuf),
+    #[error("'{0}' is not a store path (not a child of {1:?})")]
+    NotAStorePath(PathBuf, PathBuf),
     #[error("'{0}' is mising a package directory")]
     NoPackage(PathBuf),
+    #[error("'{0}' does not start with a valid hash")]
+    InvalidHashPart(PathBuf),
+    #[error("'{0}' has an invalid name")]
+    InvalidName(PathBuf),
+    #[error("'{0}' is not the store path of a derivation")]
+    NotADerivation(PathBuf),
 }
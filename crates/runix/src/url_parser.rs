@@ -8,9 +8,16 @@ use serde::de::Error;
 use serde::{Deserialize, Deserializer};
 use serde_json::Value;
 
-use crate::flake_ref::lock::{InvalidRev, LastModified, NarHash, Rev, RevCount};
+use crate::flake_ref::lock::{InvalidNarHash, InvalidRev, LastModified, NarHash, Rev, RevCount};
 use crate::flake_ref::protocol::WrappedUrlParseError;
-use crate::flake_ref::{ParseTimeError, Timestamp, TimestampDeserialize};
+use crate::flake_ref::{
+    BoolReprs,
+    ParseFlakeRefError,
+    ParseTimeError,
+    Timestamp,
+    TimestampDeserialize,
+};
+use crate::installable::AttrPath;
 
 pub static PARSER_UTIL_BIN_PATH: &str = env!("PARSER_UTIL_BIN");
 
@@ -55,6 +62,8 @@ pub enum UrlParseError {
     BadTimestamp(#[from] ParseTimeError),
     #[error("bad revision")]
     BadRevision(#[from] InvalidRev),
+    #[error(transparent)]
+    BadNarHash(#[from] InvalidNarHash),
     #[error("unsupported protocol '{1}' for flake type '{0}'")]
     UnsupportedProtocol(String, String),
     #[error("unsupported service '{0}'")]
@@ -65,6 +74,8 @@ pub enum UrlParseError {
     AttributeType(&'static str, &'static str, Value),
     #[error("{0}")]
     Other(String),
+    #[error(transparent)]
+    FlakeRef(#[from] ParseFlakeRefError),
 }
 
 /// A flake reference URL that has been resolved by Nix, but has not been
@@ -139,7 +150,7 @@ impl TryFrom<GenericParsedURL> for LockedFlakeRef {
 #[derive(Debug, Clone)]
 pub struct InstallableFlakeRef {
     pub input: Input,
-    pub attr_path: Vec<String>,
+    pub attr_path: AttrPath,
     pub outputs: InstallableOutputs,
     pub r#ref: ParsedFlakeReference,
 }
@@ -185,7 +196,7 @@ struct GenericParsedURL {
     resolved_ref: Option<DeserializedFlakeRef>,
     locked_ref: Option<DeserializedFlakeRef>,
     r#ref: Option<DeserializedFlakeRef>,
-    attr_path: Option<Vec<String>>,
+    attr_path: Option<AttrPath>,
     outputs: Option<InstallableOutputs>,
     authority: Option<String>,
     base: Option<String>,
@@ -543,7 +554,7 @@ pub fn installable_flake_ref(
 /// Extracts the `narHash` flake attributes
 pub(crate) fn extract_nar_hash_attr(attrs: &Attrs) -> Result<Option<NarHash>, UrlParseError> {
     let nar_hash = match attrs.get("narHash") {
-        Some(Value::String(nar_hash)) => Some(nar_hash.clone()),
+        Some(Value::String(nar_hash)) => Some(nar_hash.parse()?),
         Some(v) => return Err(UrlParseError::AttributeType("narHash", "String", v.clone())),
         None => None,
     };
@@ -642,37 +653,37 @@ pub(crate) fn extract_rev_count_attr(attrs: &Attrs) -> Result<Option<RevCount>,
 /// Extracts the `shallow` flake attribute
 pub(crate) fn extract_shallow_attr(attrs: &Attrs) -> Result<Option<bool>, UrlParseError> {
     let shallow = match attrs.get("shallow") {
-        Some(Value::Bool(shallow)) => Some(shallow),
-        Some(v) => return Err(UrlParseError::AttributeType("shallow", "Bool", v.clone())),
+        Some(v) => Some(
+            BoolReprs::deserialize(v)
+                .map_err(|_| UrlParseError::AttributeType("shallow", "Bool", v.clone()))?,
+        ),
         None => None,
     };
-    Ok(shallow.copied())
+    Ok(shallow.map(bool::from))
 }
 
 /// Extracts the `submodules` flake attribute
 pub(crate) fn extract_submodules_attr(attrs: &Attrs) -> Result<Option<bool>, UrlParseError> {
     let submodules = match attrs.get("submodules") {
-        Some(Value::Bool(submodules)) => Some(submodules),
-        Some(v) => {
-            return Err(UrlParseError::AttributeType(
-                "submodules",
-                "Bool",
-                v.clone(),
-            ))
-        },
+        Some(v) => Some(
+            BoolReprs::deserialize(v)
+                .map_err(|_| UrlParseError::AttributeType("submodules", "Bool", v.clone()))?,
+        ),
         None => None,
     };
-    Ok(submodules.copied())
+    Ok(submodules.map(bool::from))
 }
 
 /// Extracts the `allRefs` flake attribute
 pub(crate) fn extract_all_refs_attr(attrs: &Attrs) -> Result<Option<bool>, UrlParseError> {
     let all_refs = match attrs.get("allRefs") {
-        Some(Value::Bool(all_refs)) => Some(all_refs),
-        Some(v) => return Err(UrlParseError::AttributeType("allRefs", "Bool", v.clone())),
+        Some(v) => Some(
+            BoolReprs::deserialize(v)
+                .map_err(|_| UrlParseError::AttributeType("allRefs", "Bool", v.clone()))?,
+        ),
         None => None,
     };
-    Ok(all_refs.copied())
+    Ok(all_refs.map(bool::from))
 }
 
 /// Extracts the `path` flake attribute
@@ -0,0 +1,262 @@
+//! The dependency graph printed by `nix why-depends`, parsed from its stdout
+//!
+//! Nix prints the chains of references from a package to a dependency as a tree:
+//!
+//! ```text
+//! /nix/store/3dsk2kh0m6xyi5rhjqzwxd4jc4hy6r2s-hello-2.12.1
+//! └───bin/hello: …...................../nix/store/9l06v7fc38c1x3r2iydl15ksgz0ysb82-glibc-2.37-8/lib/ld-linux-x86-64.so…
+//!     → /nix/store/9l06v7fc38c1x3r2iydl15ksgz0ysb82-glibc-2.37-8
+//! ```
+//!
+//! With `--precise`, every reference is annotated with the files that contain it.
+//! [WhyDependsGraph::from_stdout] turns the tree into nodes and edges, e.g. for audit tools.
+//! See [crate::command::WhyDepends::graph] to run the command and parse its output.
+
+use std::collections::VecDeque;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::nix_error::ANSI_ESCAPE;
+use crate::store_path::StorePath;
+
+/// The prefixes drawing the tree, each one level deep
+const TREE_PREFIXES: &[&str] = &["├───", "└───", "│   ", "    "];
+
+/// An occurrence of the hash of a dependency in a file of the depending store path
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reference {
+    /// The file containing the reference, relative to the depending store path,
+    /// `/` if the store path is a single file
+    pub file: PathBuf,
+    /// The text surrounding the reference
+    ///
+    /// Nix does not print the offset of the reference in the file,
+    /// only up to 32 bytes of context around it.
+    pub context: String,
+}
+
+/// A reference from [Edge::from] to [Edge::to], indices into [WhyDependsGraph::nodes]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    /// Where [Edge::from] refers to [Edge::to], empty if not run with `--precise`
+    pub references: Vec<Reference>,
+}
+
+/// The store paths printed by `nix why-depends` and the references between them
+///
+/// ```
+/// # use runix::store_path::StorePath;
+/// # use runix::why_depends::WhyDependsGraph;
+/// let graph = WhyDependsGraph::from_stdout(
+///     "\
+/// /nix/store/3dsk2kh0m6xyi5rhjqzwxd4jc4hy6r2s-hello-2.12.1
+/// └───bin/hello: …/nix/store/9l06v7fc38c1x3r2iydl15ksgz0ysb82-glibc-2.37-8/lib/ld-linux…
+///     → /nix/store/9l06v7fc38c1x3r2iydl15ksgz0ysb82-glibc-2.37-8
+/// ",
+/// );
+///
+/// let glibc =
+///     StorePath::from_path("/nix/store/9l06v7fc38c1x3r2iydl15ksgz0ysb82-glibc-2.37-8").unwrap();
+/// let chain = graph.chain_to(&glibc).unwrap();
+/// assert_eq!(chain.len(), 2);
+/// assert_eq!(
+///     graph.edges[0].references[0].file.to_str(),
+///     Some("bin/hello")
+/// );
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WhyDependsGraph {
+    /// The store paths in order of appearance, the first one being the package
+    pub nodes: Vec<StorePath>,
+    pub edges: Vec<Edge>,
+}
+
+impl WhyDependsGraph {
+    /// Parse the tree printed by `nix why-depends`, with or without `--precise` and `--all`
+    ///
+    /// Lines that are neither store paths nor references are ignored.
+    pub fn from_stdout(stdout: &str) -> Self {
+        let stdout = ANSI_ESCAPE.replace_all(stdout, "");
+        let mut graph = WhyDependsGraph::default();
+        // the node printed last at every depth
+        let mut ancestors: Vec<usize> = Vec::new();
+        let mut references = Vec::new();
+
+        for line in stdout.lines() {
+            let (depth, line) = strip_tree(line);
+            let line = line.trim_end();
+            if line.is_empty() {
+                continue;
+            }
+
+            let node = line.strip_prefix("→ ").unwrap_or(line);
+            // references start with a relative path, which is not parsed as store path
+            let path = node
+                .starts_with('/')
+                .then(|| node.parse::<StorePath>().ok())
+                .flatten();
+            let Some(path) = path else {
+                if let Some((file, context)) = line.split_once(": ") {
+                    references.push(Reference {
+                        file: file.into(),
+                        context: context.trim_matches('…').to_string(),
+                    });
+                }
+                continue;
+            };
+
+            let node = graph.node(path);
+            ancestors.truncate(depth);
+            if let Some(&parent) = ancestors.last() {
+                graph.edge(parent, node, std::mem::take(&mut references));
+            }
+            references.clear();
+            ancestors.push(node);
+        }
+
+        graph
+    }
+
+    /// The package whose dependencies are shown
+    pub fn root(&self) -> Option<&StorePath> {
+        self.nodes.first()
+    }
+
+    /// The index of `path` in [WhyDependsGraph::nodes]
+    pub fn index_of(&self, path: &StorePath) -> Option<usize> {
+        self.nodes.iter().position(|node| node == path)
+    }
+
+    /// The edges leaving the node at `index`
+    pub fn edges_from(&self, index: usize) -> impl Iterator<Item = &Edge> {
+        self.edges.iter().filter(move |edge| edge.from == index)
+    }
+
+    /// A shortest chain of references from [WhyDependsGraph::root] to `dependency`,
+    /// including both
+    pub fn chain_to(&self, dependency: &StorePath) -> Option<Vec<&StorePath>> {
+        let target = self.index_of(dependency)?;
+        let mut previous: Vec<Option<usize>> = vec![None; self.nodes.len()];
+        let mut queue = VecDeque::from([0]);
+        let mut visited = vec![false; self.nodes.len()];
+        visited[0] = true;
+
+        while let Some(index) = queue.pop_front() {
+            if index == target {
+                let mut chain = vec![&self.nodes[index]];
+                let mut current = index;
+                while let Some(parent) = previous[current] {
+                    chain.push(&self.nodes[parent]);
+                    current = parent;
+                }
+                chain.reverse();
+                return Some(chain);
+            }
+            for edge in self.edges_from(index) {
+                if !visited[edge.to] {
+                    visited[edge.to] = true;
+                    previous[edge.to] = Some(index);
+                    queue.push_back(edge.to);
+                }
+            }
+        }
+        None
+    }
+
+    fn node(&mut self, path: StorePath) -> usize {
+        self.index_of(&path).unwrap_or_else(|| {
+            self.nodes.push(path);
+            self.nodes.len() - 1
+        })
+    }
+
+    fn edge(&mut self, from: usize, to: usize, references: Vec<Reference>) {
+        match self
+            .edges
+            .iter_mut()
+            .find(|edge| edge.from == from && edge.to == to)
+        {
+            Some(edge) => edge.references.extend(references),
+            None => self.edges.push(Edge {
+                from,
+                to,
+                references,
+            }),
+        }
+    }
+}
+
+/// Split the prefix drawing the tree from `line`, returning its depth and the remainder
+fn strip_tree(mut line: &str) -> (usize, &str) {
+    let mut depth = 0;
+    while let Some(rest) = TREE_PREFIXES
+        .iter()
+        .find_map(|prefix| line.strip_prefix(prefix))
+    {
+        depth += 1;
+        line = rest;
+    }
+    (depth, line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_all_paths() {
+        let stdout = "\
+/nix/store/3dsk2kh0m6xyi5rhjqzwxd4jc4hy6r2s-hello-2.12.1
+├───bin/hello: …\x1b[31;1m9l06v7fc38c1x3r2iydl15ksgz0ysb82\x1b[0m-glibc-2.37-8/lib…
+│   → /nix/store/9l06v7fc38c1x3r2iydl15ksgz0ysb82-glibc-2.37-8
+│   └───lib/libc.so.6: …/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-libidn2-2.3.4/lib…
+│       → /nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-libidn2-2.3.4
+└───share/man: …/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-libidn2-2.3.4/share…
+    share/info: …/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-libidn2-2.3.4/share…
+    → \x1b[38;5;244m/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-libidn2-2.3.4
+";
+        let graph = WhyDependsGraph::from_stdout(stdout);
+        assert_eq!(graph.nodes.len(), 3);
+        assert_eq!(
+            graph
+                .edges
+                .iter()
+                .map(|edge| (edge.from, edge.to))
+                .collect::<Vec<_>>(),
+            [(0, 1), (1, 2), (0, 2)]
+        );
+        assert_eq!(graph.edges[0].references, [Reference {
+            file: "bin/hello".into(),
+            context: "9l06v7fc38c1x3r2iydl15ksgz0ysb82-glibc-2.37-8/lib".to_string(),
+        }]);
+        assert_eq!(graph.edges[2].references.len(), 2);
+
+        // the direct reference is shorter
+        let libidn2 = &graph.nodes[2];
+        assert_eq!(graph.chain_to(libidn2).unwrap(), [&graph.nodes[0], libidn2]);
+    }
+
+    #[test]
+    fn parses_imprecise_paths() {
+        let stdout = "\
+/nix/store/3dsk2kh0m6xyi5rhjqzwxd4jc4hy6r2s-hello-2.12.1
+└───/nix/store/9l06v7fc38c1x3r2iydl15ksgz0ysb82-glibc-2.37-8
+    └───/nix/store/7rjqb838snvvxcmpvck1smfxhkwzqal5-libidn2-2.3.4
+";
+        let graph = WhyDependsGraph::from_stdout(stdout);
+        assert_eq!(graph.root(), graph.nodes.first());
+        assert_eq!(
+            graph
+                .edges
+                .iter()
+                .map(|edge| (edge.from, edge.to))
+                .collect::<Vec<_>>(),
+            [(0, 1), (1, 2)]
+        );
+        assert!(graph.edges.iter().all(|edge| edge.references.is_empty()));
+        assert_eq!(graph.chain_to(&graph.nodes[2]).unwrap().len(), 3);
+    }
+}